@@ -0,0 +1,117 @@
+// Benchmarks the hot paths of the API, listing, creating, and searching todos, through the whole
+// router against an in-memory database, so they measure our code rather than the disk. Compare a
+// change against the main branch with
+//
+//     git stash && cargo bench --bench hot_paths -- --save-baseline main
+//     git stash pop && cargo bench --bench hot_paths -- --baseline main
+//
+// benches/BASELINE.md has the numbers of the last release, for a rough idea of what's normal.
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Method, Request};
+use axum::Router;
+use criterion::{criterion_group, criterion_main, Criterion};
+use http_rest_api_service::config::Config;
+use http_rest_api_service::i18n::Catalogs;
+use http_rest_api_service::migrations::{self, Phase};
+use http_rest_api_service::pool;
+use http_rest_api_service::router::create_router;
+use http_rest_api_service::state::AppState;
+use sqlx::sqlite::SqliteConnectOptions;
+use std::str::FromStr;
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+// How many todos the database holds when the benchmarks start, enough for the list and search to
+// have full pages to return.
+const TODOS: usize = 1_000;
+
+const WORDS: &[&str] = &[
+    "buy", "milk", "call", "plumber", "renew", "passport", "water", "plants", "book", "flights",
+    "review", "budget", "clean", "garage", "pay", "invoice",
+];
+
+async fn app() -> Router {
+    let url = "sqlite::memory:";
+    let dbpool = pool::in_memory(pool::options())
+        .connect_with(SqliteConnectOptions::from_str(url).expect("a valid database URL"))
+        .await
+        .expect("can open an in-memory database");
+    migrations::run(&dbpool, Phase::Expand)
+        .await
+        .expect("can migrate the database");
+    // The defaults, so the numbers don't depend on the environment the benchmarks run in.
+    let config = Config::from_vars([]).expect("the defaults are valid");
+    let catalogs = Catalogs::load(config.locales_dir.as_deref()).expect("can load the catalogs");
+    let app = create_router(AppState::new(dbpool, config, catalogs)).await;
+    for i in 0..TODOS {
+        let body = format!(
+            "{} {} {i}",
+            WORDS[i % WORDS.len()],
+            WORDS[(i / WORDS.len()) % WORDS.len()]
+        );
+        create(&app, &body).await;
+    }
+    app
+}
+
+async fn send(app: &Router, request: Request<Body>) {
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("the router is infallible");
+    let status = response.status();
+    // Reading the body is part of the work, since that's where JSON responses are serialized.
+    to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("can read the body");
+    assert!(status.is_success(), "the request failed with {status}");
+}
+
+async fn create(app: &Router, body: &str) {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/v1/todos")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!({ "body": body }).to_string()))
+        .expect("a valid request");
+    send(app, request).await;
+}
+
+async fn get(app: &Router, uri: &str) {
+    let request = Request::builder()
+        .uri(uri)
+        .body(Body::empty())
+        .expect("a valid request");
+    send(app, request).await;
+}
+
+fn hot_paths(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("can start a runtime");
+    let app = runtime.block_on(app());
+
+    c.bench_function("list", |b| {
+        b.to_async(&runtime)
+            .iter(|| get(&app, "/v1/todos?limit=50"))
+    });
+    c.bench_function("list_filtered", |b| {
+        b.to_async(&runtime)
+            .iter(|| get(&app, "/v1/todos?status=backlog&sort=-created_at&limit=50"))
+    });
+    c.bench_function("search", |b| {
+        b.to_async(&runtime)
+            .iter(|| get(&app, "/v1/todos/search?q=renew+passport"))
+    });
+    c.bench_function("suggest", |b| {
+        b.to_async(&runtime)
+            .iter(|| get(&app, "/v1/todos/suggest?q=water+pla"))
+    });
+    // Creating last keeps the todos it adds out of the other benchmarks.
+    c.bench_function("create", |b| {
+        b.to_async(&runtime)
+            .iter(|| create(&app, "benchmark a new todo"))
+    });
+}
+
+criterion_group!(benches, hot_paths);
+criterion_main!(benches);
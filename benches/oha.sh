@@ -0,0 +1,41 @@
+#!/bin/sh
+# Load-tests the hot paths of a release build with oha (https://github.com/hatoo/oha), for numbers
+# under concurrency that the criterion benchmarks in hot_paths.rs don't give. The server runs
+# against an in-memory database, seeded with TODOS todos, so the disk isn't what's measured.
+#
+#     cargo build --release && benches/oha.sh
+#
+# DURATION and CONNECTIONS change how long and how hard each path is loaded.
+set -eu
+
+ADDR=${ADDR:-127.0.0.1:3990}
+TODOS=${TODOS:-1000}
+DURATION=${DURATION:-10s}
+CONNECTIONS=${CONNECTIONS:-32}
+BASE="http://$ADDR/v1"
+
+DATABASE_URL=sqlite::memory: BIND_ADDR=$ADDR RUST_LOG=warn ./target/release/http-rest-api-service &
+server=$!
+trap 'kill $server' EXIT
+until curl -sf "http://$ADDR/ready" > /dev/null; do sleep 0.1; done
+
+words="buy milk call plumber renew passport water plants book flights review budget clean garage"
+i=0
+while [ $i -lt "$TODOS" ]; do
+    word=$(echo $words | cut -d' ' -f$((i % 14 + 1)))
+    curl -sf -o /dev/null -H 'content-type: application/json' \
+        -d "{\"body\": \"$word todo $i\"}" "$BASE/todos"
+    i=$((i + 1))
+done
+
+load() {
+    echo "== $1"
+    shift
+    oha --no-tui -z "$DURATION" -c "$CONNECTIONS" "$@"
+}
+
+load list "$BASE/todos?limit=50"
+load search "$BASE/todos/search?q=renew+passport"
+load suggest "$BASE/todos/suggest?q=water+pla"
+load create -m POST -H 'content-type: application/json' -d '{"body": "load test a new todo"}' \
+    "$BASE/todos"
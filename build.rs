@@ -0,0 +1,19 @@
+use std::process::Command;
+
+fn main() {
+    // Embed the git commit the binary was built from, so the admin API can report it. Builds from a
+    // source tarball without git fall back to "unknown".
+    let git_sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_SHA={git_sha}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    // sqlx::migrate!() embeds the migrations at compile time, so we rebuild when they change.
+    println!("cargo:rerun-if-changed=migrations");
+}
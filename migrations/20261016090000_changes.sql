@@ -0,0 +1,26 @@
+-- Every mutation of the todos table is recorded here with a monotonically increasing sequence number,
+-- which clients use as a cursor for incremental sync.
+CREATE TABLE IF NOT EXISTS changes (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    todo_id INTEGER NOT NULL,
+    op TEXT NOT NULL,
+    changed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS changes_todo_id ON changes (todo_id);
+
+-- We use triggers so that no code path can mutate a todo without bumping the sequence.
+CREATE TRIGGER IF NOT EXISTS todos_changes_insert AFTER INSERT ON todos
+BEGIN
+    INSERT INTO changes (todo_id, op) VALUES (NEW.id, 'insert');
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_changes_update AFTER UPDATE ON todos
+BEGIN
+    INSERT INTO changes (todo_id, op) VALUES (NEW.id, 'update');
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_changes_delete AFTER DELETE ON todos
+BEGIN
+    INSERT INTO changes (todo_id, op) VALUES (OLD.id, 'delete');
+END;
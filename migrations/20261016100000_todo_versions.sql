@@ -0,0 +1,3 @@
+-- Each todo carries a version that's bumped on every update, so sync clients can tell whether
+-- the copy they edited offline is still the latest one.
+ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,9 @@
+-- The service has a single owner, so the preferences table holds exactly one row.
+CREATE TABLE IF NOT EXISTS preferences (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    timezone TEXT NOT NULL DEFAULT 'UTC',
+    locale TEXT NOT NULL DEFAULT 'en',
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO preferences (id) VALUES (1);
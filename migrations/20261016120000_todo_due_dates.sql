@@ -0,0 +1,2 @@
+-- An optional due date, stored in UTC like the other timestamps.
+ALTER TABLE todos ADD COLUMN due_at TIMESTAMP;
@@ -0,0 +1,22 @@
+-- A full-text index over todo bodies. It's an external content table, so the text itself is only
+-- stored once, in todos, and the triggers below keep the index in sync.
+CREATE VIRTUAL TABLE IF NOT EXISTS todos_fts USING fts5(body, content='todos', content_rowid='id');
+
+-- Indexes todos created before this migration.
+INSERT INTO todos_fts (todos_fts) VALUES ('rebuild');
+
+CREATE TRIGGER IF NOT EXISTS todos_fts_insert AFTER INSERT ON todos
+BEGIN
+    INSERT INTO todos_fts (rowid, body) VALUES (NEW.id, NEW.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_fts_delete AFTER DELETE ON todos
+BEGIN
+    INSERT INTO todos_fts (todos_fts, rowid, body) VALUES ('delete', OLD.id, OLD.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS todos_fts_update AFTER UPDATE OF body ON todos
+BEGIN
+    INSERT INTO todos_fts (todos_fts, rowid, body) VALUES ('delete', OLD.id, OLD.body);
+    INSERT INTO todos_fts (rowid, body) VALUES (NEW.id, NEW.body);
+END;
@@ -0,0 +1,5 @@
+-- When each todo was last read, for the recently viewed listing. We only keep the latest view.
+CREATE TABLE IF NOT EXISTS todo_views (
+    todo_id INTEGER PRIMARY KEY NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    viewed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
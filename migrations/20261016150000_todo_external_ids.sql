@@ -0,0 +1,4 @@
+-- A key supplied by the client, e.g. the ID a todo has in the system it's imported from. NULLs
+-- don't conflict with each other, so todos created without one are unaffected.
+ALTER TABLE todos ADD COLUMN external_id TEXT;
+CREATE UNIQUE INDEX todos_external_id ON todos (external_id);
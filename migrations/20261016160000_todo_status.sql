@@ -0,0 +1,5 @@
+-- Where each todo is in its workflow. completed is kept in step with it, so existing completed todos
+-- start out done and everything else starts in the backlog.
+ALTER TABLE todos ADD COLUMN status TEXT NOT NULL DEFAULT 'backlog';
+UPDATE todos SET status = 'done' WHERE completed;
+CREATE INDEX todos_status ON todos (status);
@@ -0,0 +1,4 @@
+-- Archived todos are kept but left out of the default lists. archived_at orders the archive listing.
+ALTER TABLE todos ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE todos ADD COLUMN archived_at TIMESTAMP;
+CREATE INDEX todos_archived_at ON todos (archived_at) WHERE archived;
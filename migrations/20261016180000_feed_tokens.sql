@@ -0,0 +1,7 @@
+-- Secret tokens for the calendar feed at /feeds/:token/todos.ics. Each subscribing calendar can get
+-- its own token, so one can be rotated or revoked without breaking the others.
+CREATE TABLE IF NOT EXISTS feed_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    token TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
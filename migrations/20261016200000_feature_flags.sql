@@ -0,0 +1,8 @@
+-- Runtime switches for risky features. A flag is on for rollout_percent percent of the subjects it's
+-- checked for, so a feature can be rolled out gradually without a redeploy.
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name TEXT PRIMARY KEY NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    rollout_percent INTEGER NOT NULL DEFAULT 100,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
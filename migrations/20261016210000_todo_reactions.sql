@@ -0,0 +1,35 @@
+-- Emoji reactions on todos, for acknowledging a todo in a shared list without editing it. Each row
+-- is one reaction; removing a reaction deletes one row.
+CREATE TABLE IF NOT EXISTS todo_reactions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    emoji TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS todo_reactions_todo_id ON todo_reactions (todo_id, emoji);
+
+-- The counts per emoji as a JSON object, e.g. {"👍": 2}, kept on the todo so every query that
+-- returns todos returns them without a join. Updating it goes through the changes feed, so sync
+-- clients pick up new counts too.
+ALTER TABLE todos ADD COLUMN reactions TEXT NOT NULL DEFAULT '{}';
+
+CREATE TRIGGER IF NOT EXISTS todo_reactions_insert AFTER INSERT ON todo_reactions
+BEGIN
+    UPDATE todos SET reactions = (
+        SELECT json_group_object(emoji, n) FROM (
+            SELECT emoji, count(*) AS n FROM todo_reactions
+            WHERE todo_id = NEW.todo_id GROUP BY emoji ORDER BY emoji
+        )
+    ) WHERE id = NEW.todo_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS todo_reactions_delete AFTER DELETE ON todo_reactions
+BEGIN
+    UPDATE todos SET reactions = (
+        SELECT json_group_object(emoji, n) FROM (
+            SELECT emoji, count(*) AS n FROM todo_reactions
+            WHERE todo_id = OLD.todo_id GROUP BY emoji ORDER BY emoji
+        )
+    ) WHERE id = OLD.todo_id;
+END;
@@ -0,0 +1,8 @@
+-- Where a todo is to be done, for location-based reminders: WGS 84 coordinates in degrees, as GPS
+-- and map apps report them, and a free-text place name. All of them are optional.
+ALTER TABLE todos ADD COLUMN latitude REAL;
+ALTER TABLE todos ADD COLUMN longitude REAL;
+ALTER TABLE todos ADD COLUMN place TEXT;
+
+-- The ?near= filter looks todos up by a range of latitudes first.
+CREATE INDEX IF NOT EXISTS todos_location ON todos (latitude, longitude);
@@ -0,0 +1,7 @@
+-- The rate limit plan each tenant is on, e.g. "pro". The plans themselves and their limits are
+-- configured with RATE_LIMIT_PLANS; tenants without a row here are on the first plan.
+CREATE TABLE IF NOT EXISTS tenant_plans (
+    tenant TEXT PRIMARY KEY NOT NULL,
+    plan TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,15 @@
+-- Usage for billing, per hour. API calls are counted per tenant; requests without a tenant are
+-- counted under the empty tenant. Todos aren't split by tenant, so the storage used and the number
+-- of active todos are sampled for the instance as a whole.
+CREATE TABLE IF NOT EXISTS usage_meter (
+    hour TIMESTAMP NOT NULL,
+    tenant TEXT NOT NULL,
+    api_calls INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (hour, tenant)
+);
+
+CREATE TABLE IF NOT EXISTS usage_storage (
+    hour TIMESTAMP PRIMARY KEY NOT NULL,
+    storage_bytes INTEGER NOT NULL,
+    active_todos INTEGER NOT NULL
+);
@@ -0,0 +1,22 @@
+-- Throwaway lists for guests, reached through a capability URL holding the secret token. They're
+-- separate from the owner's todos, and are deleted once they expire.
+CREATE TABLE IF NOT EXISTS quicklists (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    token TEXT NOT NULL UNIQUE,
+    title TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS quicklists_expires_at ON quicklists (expires_at);
+
+CREATE TABLE IF NOT EXISTS quicklist_items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    quicklist_id INTEGER NOT NULL REFERENCES quicklists (id) ON DELETE CASCADE,
+    body TEXT NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS quicklist_items_quicklist ON quicklist_items (quicklist_id);
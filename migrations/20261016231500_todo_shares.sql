@@ -0,0 +1,11 @@
+-- Links giving people without access to the API a read-only view of one todo. Each link has its own
+-- secret token, so one can be revoked without breaking the others; deleting the todo revokes them
+-- all.
+CREATE TABLE IF NOT EXISTS todo_shares (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    token TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS todo_shares_todo ON todo_shares (todo_id);
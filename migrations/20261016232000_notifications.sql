@@ -0,0 +1,13 @@
+-- The notification channels the owner doesn't want to hear from, as a JSON array of channel names,
+-- e.g. ["slack"]. Routing rules decide which channels an event goes to; this only takes some away.
+ALTER TABLE preferences ADD COLUMN muted_channels TEXT NOT NULL DEFAULT '[]';
+
+-- Browsers subscribed to Web Push notifications, with the keys their push service gave them for
+-- encrypting messages to them.
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    endpoint TEXT NOT NULL UNIQUE,
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
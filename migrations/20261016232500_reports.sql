@@ -0,0 +1,11 @@
+-- Reports on the todos, generated on a schedule or on request, as Markdown. A scheduled report is
+-- generated once per scheduled time, even with several instances running.
+CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    kind TEXT NOT NULL,
+    scheduled_for TIMESTAMP,
+    todos INTEGER NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (kind, scheduled_for)
+);
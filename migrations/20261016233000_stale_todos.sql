@@ -0,0 +1,2 @@
+-- When an open todo was found untouched for a while. Every change to the todo clears it again.
+ALTER TABLE todos ADD COLUMN stale_at TIMESTAMP;
@@ -0,0 +1,12 @@
+-- The titles and descriptions of pages linked from todos, fetched in the background. Pages that
+-- couldn't be previewed are kept too, without a title, so they aren't fetched again every time.
+CREATE TABLE IF NOT EXISTS link_previews (
+    url TEXT PRIMARY KEY NOT NULL,
+    title TEXT,
+    description TEXT,
+    fetched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The previews of the links in the body as a JSON array, kept on the todo like its reactions so
+-- every query that returns todos returns them without a join.
+ALTER TABLE todos ADD COLUMN links TEXT NOT NULL DEFAULT '[]';
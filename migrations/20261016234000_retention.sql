@@ -0,0 +1,14 @@
+-- How long completed and archived todos and the change history are kept. Like the preferences,
+-- there's exactly one policy, since the service has a single owner. A null turns a rule off.
+CREATE TABLE IF NOT EXISTS retention_policy (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    archive_completed_after_days INTEGER,
+    purge_archived_after_days INTEGER,
+    max_changes INTEGER,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- The highest sequence number trimmed from the changes table, so clients syncing from before
+    -- it can be told to start over rather than miss deletes.
+    changes_trimmed_through INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT OR IGNORE INTO retention_policy (id) VALUES (1);
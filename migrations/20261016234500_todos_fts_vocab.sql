@@ -0,0 +1,4 @@
+-- The terms in the full-text index and the number of todos containing each, which searches use to
+-- estimate how many todos a query matches before running it. It reads the index itself, so there's
+-- nothing to keep in sync.
+CREATE VIRTUAL TABLE IF NOT EXISTS todos_fts_vocab USING fts5vocab(todos_fts, 'row');
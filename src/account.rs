@@ -0,0 +1,263 @@
+use crate::error::{Error, RequestError};
+use crate::geo;
+use crate::hooks::Hooks;
+use crate::i18n;
+use crate::preferences::{Preferences, UpdatePreferences};
+use crate::reactions::{self, Reactions};
+use crate::status::TodoStatus;
+use crate::todo::{format_due, resolve_due, CreateTodo, Todo};
+use crate::validation::BodyPolicy;
+use axum::http::StatusCode;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+
+// Identifies an account snapshot, so a JSON document meant for something else isn't imported.
+const FORMAT: &str = "todo-api-service/account-snapshot";
+// The version of the snapshot format. It only changes when older versions of the service would
+// misread a snapshot; adding optional fields doesn't need a new version. Newer versions of the
+// service keep reading older snapshots.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// Everything an account has on an instance, for moving it to another one with
+// GET and POST /v1/me/snapshot. Unlike the admin backups, a snapshot doesn't depend on the schema,
+// so it can move between instances running different versions of the service.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountSnapshot {
+    format: String,
+    version: u32,
+    exported_at: NaiveDateTime,
+    preferences: SnapshotPreferences,
+    todos: Vec<SnapshotTodo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct SnapshotPreferences {
+    timezone: String,
+    locale: String,
+}
+
+// A todo without its ID, which is specific to the instance and, with hashids, to its salt. The
+// external ID, if any, is what other systems know the todo by, so it's kept.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct SnapshotTodo {
+    body: String,
+    completed: bool,
+    status: TodoStatus,
+    created_at: NaiveDateTime,
+    due_at: Option<NaiveDateTime>,
+    external_id: Option<String>,
+    #[serde(default)]
+    archived: bool,
+    archived_at: Option<NaiveDateTime>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    place: Option<String>,
+    #[serde(default)]
+    reactions: Reactions,
+}
+
+impl SnapshotTodo {
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn external_id(&self) -> Option<&str> {
+        self.external_id.as_deref()
+    }
+
+    // Runs the before_create hooks on the todo and takes over what they changed.
+    async fn run_hooks(&mut self, dbpool: &SqlitePool, hooks: &Hooks) -> Result<(), Error> {
+        let mut draft = CreateTodo::new(self.body.clone()).with_status(self.status);
+        if let Some(due_at) = self.due_at {
+            draft = draft.with_due(format_due(due_at));
+        }
+        if let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) {
+            draft = draft.with_location(latitude, longitude);
+        }
+        if let Some(place) = &self.place {
+            draft = draft.with_place(place);
+        }
+        hooks.before_create(&mut draft).await?;
+        self.due_at = resolve_due(dbpool, draft.due()).await?;
+        self.body = draft.body().to_string();
+        self.status = draft.status();
+        self.completed = self.status == TodoStatus::Done;
+        self.latitude = draft.latitude();
+        self.longitude = draft.longitude();
+        self.place = draft.place().map(str::to_string);
+        Ok(())
+    }
+}
+
+impl AccountSnapshot {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn exported_at(&self) -> NaiveDateTime {
+        self.exported_at
+    }
+
+    pub fn todos(&self) -> &[SnapshotTodo] {
+        &self.todos
+    }
+
+    pub async fn export(dbpool: SqlitePool) -> Result<AccountSnapshot, Error> {
+        let preferences = query_as("select timezone, locale from preferences where id = 1")
+            .fetch_one(&dbpool)
+            .await?;
+        let todos = query_as("select * from todos order by id")
+            .fetch_all(&dbpool)
+            .await?;
+        Ok(AccountSnapshot {
+            format: FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+            exported_at: Utc::now().naive_utc().trunc_subsecs(0),
+            preferences,
+            todos,
+        })
+    }
+
+    // Adds the snapshot's todos to this instance's and takes over its preferences. Todos get new IDs
+    // here. The ones with an external ID this instance already has are skipped, so importing the
+    // same snapshot twice doesn't duplicate those; todos without one can't be told apart from new
+    // ones. Each todo goes through the before_create hooks and then the policy, like any new todo.
+    // Nothing is written unless the whole snapshot is valid. Returns the report and the todos that
+    // were created.
+    pub async fn import(
+        self,
+        dbpool: SqlitePool,
+        policy: &BodyPolicy,
+        hooks: &Hooks,
+    ) -> Result<(SnapshotImportReport, Vec<Todo>), Error> {
+        if self.format != FORMAT {
+            return Err(invalid_snapshot(
+                "format",
+                i18n::message("snapshot_format", &[("format", FORMAT)]),
+            ));
+        }
+        if self.version > SNAPSHOT_VERSION {
+            return Err(invalid_snapshot(
+                "version",
+                i18n::message(
+                    "snapshot_version",
+                    &[
+                        ("version", &self.version.to_string()),
+                        ("supported", &SNAPSHOT_VERSION.to_string()),
+                    ],
+                ),
+            ));
+        }
+        let mut todos = self.todos;
+        for (index, todo) in todos.iter_mut().enumerate() {
+            let field = format!("todos[{index}]");
+            todo.run_hooks(&dbpool, hooks)
+                .await
+                .map_err(|err| nest_field(err, &field))?;
+            todo.body = policy.apply(&todo.body, &format!("{field}.body"))?;
+            geo::check_location(todo.latitude, todo.longitude, todo.place.as_deref())
+                .map_err(|err| nest_field(err, &field))?;
+        }
+        let preferences = Preferences::update(
+            dbpool.clone(),
+            UpdatePreferences::default()
+                .with_timezone(self.preferences.timezone)
+                .with_locale(self.preferences.locale),
+        )
+        .await
+        .map_err(|err| nest_field(err, "preferences"))?;
+
+        let mut tx = dbpool.begin().await?;
+        let mut created = Vec::with_capacity(todos.len());
+        let mut skipped = Vec::new();
+        for todo in todos {
+            // `on conflict do nothing` returns no row for a todo whose external ID we have.
+            let row: Option<(i64,)> = query_as(
+                "insert into todos (body, completed, status, created_at, due_at, external_id,
+                 archived, archived_at, latitude, longitude, place)
+                 values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 on conflict (external_id) do nothing returning id",
+            )
+            .bind(&todo.body)
+            .bind(todo.completed)
+            .bind(todo.status)
+            .bind(todo.created_at)
+            .bind(todo.due_at)
+            .bind(&todo.external_id)
+            .bind(todo.archived)
+            .bind(todo.archived_at)
+            .bind(todo.latitude)
+            .bind(todo.longitude)
+            .bind(&todo.place)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some((id,)) = row else {
+                skipped.extend(todo.external_id);
+                continue;
+            };
+            for (emoji, count) in todo.reactions.iter() {
+                reactions::insert(&mut tx, id, emoji, count).await?;
+            }
+            created.push(
+                query_as("select * from todos where id = ?")
+                    .bind(id)
+                    .fetch_one(&mut *tx)
+                    .await?,
+            );
+        }
+        tx.commit().await?;
+
+        let report = SnapshotImportReport {
+            created: created.len(),
+            skipped,
+            preferences,
+        };
+        Ok((report, created))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotImportReport {
+    // The number of todos created.
+    created: usize,
+    // The external IDs of the todos skipped because this instance already has them.
+    skipped: Vec<String>,
+    // The preferences as they are after the import.
+    preferences: Preferences,
+}
+
+impl SnapshotImportReport {
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    pub fn preferences(&self) -> &Preferences {
+        &self.preferences
+    }
+}
+
+fn invalid_snapshot(field: &str, message: String) -> Error {
+    Error::BadRequest(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RequestError::new("invalid_snapshot", message).with_field(field),
+    )
+}
+
+// Prefixes the field of a validation error with where it is in the snapshot, e.g. "todos[3].place".
+fn nest_field(err: Error, parent: &str) -> Error {
+    match err {
+        Error::BadRequest(status, error) => {
+            let field = match error.field() {
+                Some(field) => format!("{parent}.{field}"),
+                None => parent.to_string(),
+            };
+            Error::BadRequest(status, error.with_field(field))
+        }
+        err => err,
+    }
+}
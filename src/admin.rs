@@ -0,0 +1,37 @@
+use crate::config::Config;
+use crate::error::Error;
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+// A middleware guarding the /v1/admin routes. Callers must present the configured admin token as a
+// bearer token; if no token is configured, the admin API is disabled altogether.
+pub async fn require_admin(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let expected = config.admin_token.as_deref().ok_or(Error::Forbidden)?;
+
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::Unauthorized)?;
+
+    // Comparing digests in constant time keeps the time a guess takes from revealing how much of it,
+    // or of its length, was right.
+    let matches: bool = Sha256::digest(provided)
+        .ct_eq(&Sha256::digest(expected))
+        .into();
+    if !matches {
+        return Err(Error::Unauthorized);
+    }
+
+    Ok(next.run(request).await)
+}
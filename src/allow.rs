@@ -0,0 +1,98 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::extract::{Request, State};
+use axum::http::header::{ACCESS_CONTROL_ALLOW_METHODS, ALLOW, VARY};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use tower_service::Service;
+
+// axum knows the methods of every route, and sets the Allow header from them when a request's method
+// isn't one of them. Those requests end up here: OPTIONS gets an empty 204, anything else a 405.
+pub async fn method_not_allowed(method: Method) -> Response {
+    if method == Method::OPTIONS {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    Error::BadRequest(
+        StatusCode::METHOD_NOT_ALLOWED,
+        RequestError::new(
+            "method_not_allowed",
+            i18n::message("method_not_allowed", &[("method", method.as_str())]),
+        ),
+    )
+    .into_response()
+}
+
+// A middleware which answers OPTIONS requests from the route table, so clients and CORS preflights
+// learn the methods a resource actually has, and an unknown path is a 404 rather than a preflight
+// that succeeds. It wraps the CORS layer, which would otherwise answer every OPTIONS request itself
+// without looking at the routes: we take the CORS headers from that answer and everything else from
+// the routes.
+pub async fn answer_options(
+    State(routes): State<Router>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::OPTIONS {
+        let mut response = next.run(request).await;
+        if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+            add_options(response.headers_mut());
+        }
+        return response;
+    }
+
+    let mut preflight = Request::new(axum::body::Body::empty());
+    *preflight.method_mut() = Method::OPTIONS;
+    *preflight.uri_mut() = request.uri().clone();
+    *preflight.headers_mut() = request.headers().clone();
+    let cors = next.run(preflight).await;
+
+    // Routers are always ready, and never fail.
+    let mut response = match routes.clone().call(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+    add_options(response.headers_mut());
+
+    for (name, value) in cors.headers() {
+        if name.as_str().starts_with("access-control-") || name == VARY {
+            response.headers_mut().insert(name, value.clone());
+        }
+    }
+    // The CORS layer allows any method, but a preflight should only promise the ones the route has.
+    match response.headers().get(ALLOW).cloned() {
+        Some(allow) => {
+            if response
+                .headers()
+                .contains_key(ACCESS_CONTROL_ALLOW_METHODS)
+            {
+                response
+                    .headers_mut()
+                    .insert(ACCESS_CONTROL_ALLOW_METHODS, allow);
+            }
+        }
+        None => {
+            response.headers_mut().remove(ACCESS_CONTROL_ALLOW_METHODS);
+        }
+    }
+    response
+}
+
+// axum leaves OPTIONS out of the Allow header, since no route has a handler for it, but we answer it
+// for every route.
+fn add_options(headers: &mut HeaderMap) {
+    let Some(allow) = headers.get(ALLOW).and_then(|allow| allow.to_str().ok()) else {
+        return;
+    };
+    if allow.split(',').any(|method| method.trim() == "OPTIONS") {
+        return;
+    }
+    let allow = match allow.is_empty() {
+        true => "OPTIONS".to_string(),
+        false => format!("{allow},OPTIONS"),
+    };
+    if let Ok(allow) = HeaderValue::from_str(&allow) {
+        headers.insert(ALLOW, allow);
+    }
+}
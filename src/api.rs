@@ -1,13 +1,63 @@
+use crate::account::{AccountSnapshot, SnapshotImportReport};
+use crate::cache::ResponseCache;
+use crate::cache_control::Streamed;
+use crate::change::{Change, ChangeFeed, ChangesQuery};
+use crate::config::Config;
+use crate::db_stats::{DbStats, DbStatsMonitor};
 use crate::error::Error;
-use crate::todo::{CreateTodo, Todo, UpdateTodo};
+use crate::export::{self, ExportFormat, ExportQuery};
+use crate::export_job::{CreateExportJob, ExportJob};
+use crate::extract::{Json, JsonContent, Query};
+use crate::feed::{self, FeedToken};
+use crate::flags::{FeatureFlag, FeatureFlags, FlagsQuery, UpdateFeatureFlag};
+use crate::health;
+use crate::hooks::Hooks;
+use crate::ids::{self, Id, IdEncoding};
+use crate::import::{self, ImportQuery, ImportReport};
+use crate::log_level::{LogLevel, LogLevelStatus};
+use crate::maintenance::{Maintenance, MaintenanceStatus};
+use crate::merge::{MergeRequest, MergeResponse};
+use crate::metering::{Metering, UsageFormat, UsageQuery};
+use crate::migrations::{self, MigrationStatus};
+use crate::params::ListParams;
+use crate::prefer::ReturnPreference;
+use crate::preferences::{Preferences, UpdatePreferences};
+use crate::quicklist::{
+    CreateQuicklist, CreateQuicklistItem, Quicklist, QuicklistItem, UpdateQuicklistItem,
+};
+use crate::rate_limit::{RateLimiter, RatePlan, SetTenantPlan, TenantPlan};
+use crate::reactions::{self, AddReaction};
+use crate::recent::RecentTodo;
+use crate::report::{CreateReport, Report};
+use crate::restore::{self, RestoreRequest, SnapshotReport};
+use crate::retention::{RetentionPolicy, UpdateRetentionPolicy};
+use crate::runtime::RuntimeInfo;
+use crate::search::{SearchHit, SuggestQuery, Suggestion};
+use crate::share::{SharedTodo, TodoShare};
+use crate::state::{AppState, MainDb};
+use crate::status::Board;
+use crate::sync::{SyncRequest, SyncResponse};
+use crate::tenant::Tenant;
+use crate::todo::{
+    CreateTodo, DuplicateOptions, PurgeQuery, PurgeResponse, Todo, TodoFilter, UpdateTodo,
+};
+use crate::triggers::Trigger;
+use crate::undo::{self, UndoLog, UndoRequest, UndoResponse};
+use crate::update_where::{UpdateWhere, UpdateWhereResponse};
+use crate::web_push::{CreatePushSubscription, PushKey, PushSubscription, WebPush};
 use axum::extract::{Path, State};
-use axum::Json;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, LOCATION};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
 use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub async fn ping(
     // The State extractor gives us the database connection pool from the axum state.
     State(dbpool): State<SqlitePool>,
-) -> Result<String, Error> {
+) -> Result<Response, Error> {
     use sqlx::Connection;
 
     // We need to acquire a connection from the database pool first.
@@ -15,52 +65,951 @@ pub async fn ping(
 
     // The ping() method will check if the database connection is OK
     // In the case of SQLite, this checks that the SQLite background threads are alive.
-    conn.ping()
-        .await
-        // Upon success, ping() returns unit, so we just map it to the string ok, which is returned as our response.
-        .map(|_| "ok".to_string())
-        // We use the `From` trait to map sqlx::Error to our error types.
-        .map_err(Into::into)
+    conn.ping().await?;
+    drop(conn);
+
+    // Operators can check which schema an instance sees without the admin token, e.g. while a
+    // deploy rolls out.
+    let mut response = "ok".into_response();
+    if let Some(version) = migrations::schema_version(&dbpool).await? {
+        response
+            .headers_mut()
+            .insert("x-schema-version", HeaderValue::from(version));
+    }
+    Ok(response)
 }
 
-pub async fn todo_list(State(dbpool): State<SqlitePool>) -> Result<Json<Vec<Todo>>, Error> {
+pub async fn todo_list(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    // The shared list parameters, validated against the configured limits.
+    params: ListParams,
+    // Filters only the todo list supports, like ?modified_since=<rfc3339> or ?status=done.
+    Query(filter): Query<TodoFilter>,
+) -> Result<Json<Vec<Todo>>, Error> {
     // Note how we're returning a JSON object of `Vec<Todo>` or, possibly, an error.
     // The `Todo::list()` method returns a plain `Vec<Todo>`, so we map that to a Json object using Json::from,
     // which relies on the Serialize trait we derived for `Todo`
-    Todo::list(dbpool).await.map(Json::from)
+    cache
+        .list(
+            &format!("{}&{}", params.fingerprint(), filter.fingerprint()),
+            Todo::list(dbpool, &params, &filter),
+        )
+        .await
+        .map(Arc::unwrap_or_clone)
+        .map(Json::from)
+}
+
+pub async fn todo_board(
+    State(dbpool): State<SqlitePool>,
+    params: ListParams,
+    Query(filter): Query<TodoFilter>,
+) -> Result<Json<Board>, Error> {
+    Board::load(dbpool, &params, &filter).await.map(Json::from)
+}
+
+pub async fn todo_archive_list(
+    State(dbpool): State<SqlitePool>,
+    params: ListParams,
+) -> Result<Json<Vec<Todo>>, Error> {
+    Todo::list_archived(dbpool, &params).await.map(Json::from)
+}
+
+pub async fn todo_export(
+    State(dbpool): State<SqlitePool>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let format = query.format();
+    let body = export::render(dbpool, format).await?;
+    Ok((
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.file_name()),
+            ),
+        ],
+        body,
+    ))
+}
+
+// Everything the account has, as a file to import into another instance.
+pub async fn snapshot_export(State(dbpool): State<SqlitePool>) -> Result<impl IntoResponse, Error> {
+    let snapshot = AccountSnapshot::export(dbpool).await?;
+    Ok((
+        [(
+            CONTENT_DISPOSITION,
+            "attachment; filename=\"account-snapshot.json\"",
+        )],
+        Json::from(snapshot),
+    ))
+}
+
+pub async fn snapshot_import(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Json(snapshot): Json<AccountSnapshot>,
+) -> Result<Json<SnapshotImportReport>, Error> {
+    let (report, created) = snapshot.import(dbpool, &config.body_policy, &hooks).await?;
+    cache.invalidate_all();
+    for todo in &created {
+        hooks.after_create(todo).await;
+    }
+    Ok(Json::from(report))
+}
+
+pub async fn todo_export_ndjson(State(dbpool): State<SqlitePool>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        Extension(Streamed),
+        export::ndjson(dbpool),
+    )
+}
+
+pub async fn export_job_create(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<CreateExportJob>,
+) -> Result<impl IntoResponse, Error> {
+    let job = ExportJob::create(dbpool, config.export_dir.clone(), request).await?;
+    // 202, since the export has only been queued; the Location is where to poll for it.
+    Ok((
+        StatusCode::ACCEPTED,
+        [(LOCATION, format!("/v1/exports/{}", ids::encode(job.id())))],
+        Json::from(job),
+    ))
+}
+
+pub async fn export_job_read(
+    State(dbpool): State<SqlitePool>,
+    Id(id): Id,
+) -> Result<Json<ExportJob>, Error> {
+    ExportJob::read(dbpool, id).await.map(Json::from)
+}
+
+pub async fn export_job_download(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    Id(id): Id,
+) -> Result<impl IntoResponse, Error> {
+    let (job, body) = ExportJob::download(dbpool, &config.export_dir, id).await?;
+    Ok((
+        [
+            (CONTENT_TYPE, job.format().content_type().to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", job.file_name()),
+            ),
+        ],
+        Extension(Streamed),
+        body,
+    ))
+}
+
+pub async fn reports_list(State(dbpool): State<SqlitePool>) -> Result<Json<Vec<Report>>, Error> {
+    Report::list(dbpool).await.map(Json::from)
+}
+
+pub async fn report_create(
+    State(dbpool): State<SqlitePool>,
+    Json(request): Json<CreateReport>,
+) -> Result<impl IntoResponse, Error> {
+    let report = Report::create(dbpool, request).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(
+            LOCATION,
+            format!("/v1/reports/{}", ids::encode(report.id())),
+        )],
+        Json::from(report),
+    ))
+}
+
+pub async fn report_read(
+    State(dbpool): State<SqlitePool>,
+    Id(id): Id,
+) -> Result<Json<Report>, Error> {
+    Report::read(dbpool, id).await.map(Json::from)
+}
+
+pub async fn report_download(
+    State(dbpool): State<SqlitePool>,
+    Id(id): Id,
+) -> Result<impl IntoResponse, Error> {
+    let (report, body) = Report::download(dbpool, id).await?;
+    Ok((
+        [
+            (
+                CONTENT_TYPE,
+                ExportFormat::Markdown.content_type().to_string(),
+            ),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", report.file_name()),
+            ),
+        ],
+        body,
+    ))
 }
 
 pub async fn todo_read(
     State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(maintenance): State<Arc<Maintenance>>,
     // A path parameter, which we access using the Path extractor. axum takes care of mapping the ID from the /v1/todos/:id router path
     // to the named parameter in a type-safe manner.
-    Path(id): Path<i64>,
+    Id(id): Id,
 ) -> Result<Json<Todo>, Error> {
-    Todo::read(dbpool, id).await.map(Json::from)
+    // Reads go through the response cache, which is a no-op unless it's enabled in the config.
+    let todo = cache.todo(id, Todo::read(dbpool.clone(), id)).await?;
+    // Views are recorded even when the todo came from the cache, but not in maintenance mode, which
+    // promises that nothing is written.
+    if !maintenance.status().enabled() {
+        RecentTodo::record_view(dbpool, id).await;
+    }
+    Ok(Json::from(todo))
 }
 
 pub async fn todo_create(
     State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    // Whether the client wants the created todo back or just its location (Prefer: return=minimal).
+    preference: ReturnPreference,
     // Here, we introduce the CreateTodo struct, which we're getting from the request body using
     // the Json extractor, which uses the Deserialize implementation we derived using the serde crate.
-    Json(new_todo): Json<CreateTodo>,
-) -> Result<Json<Todo>, Error> {
-    Todo::create(dbpool, new_todo).await.map(Json::from)
+    Json(mut new_todo): Json<CreateTodo>,
+) -> Result<Response, Error> {
+    hooks.before_create(&mut new_todo).await?;
+    // The policy runs after the hooks, so it also applies to what they changed.
+    new_todo.process(&config.text_pipeline);
+    new_todo.set_body(config.body_policy.apply(new_todo.body(), "body")?);
+    let todo = Todo::create(dbpool, new_todo).await?;
+    // A new todo shows up in lists, so any cached list is now stale.
+    cache.write_through(&todo);
+    hooks.after_create(&todo).await;
+    Ok(preference.respond(todo))
 }
 
+// Each extractor is a handler argument, so handlers that need a lot of state take a lot of them.
+#[allow(clippy::too_many_arguments)]
 pub async fn todo_update(
     State(dbpool): State<SqlitePool>,
-    Path(id): Path<i64>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    State(undo): State<Arc<UndoLog>>,
+    preference: ReturnPreference,
+    Id(id): Id,
     // The UpdateTodo struct which we're getting from the request body using the Json extractor,
     // which uses the Deserialize implementation we derived using the serde crate.
-    Json(updated_todo): Json<UpdateTodo>,
+    Json(mut updated_todo): Json<UpdateTodo>,
+) -> Result<Response, Error> {
+    hooks.before_update(id, &mut updated_todo).await?;
+    updated_todo.process(&config.text_pipeline);
+    updated_todo.set_body(config.body_policy.apply(updated_todo.body(), "body")?);
+    let before = Todo::read(dbpool.clone(), id).await?;
+    let todo = Todo::update(dbpool, &config.status_transitions, id, updated_todo).await?;
+    cache.write_through(&todo);
+    hooks.after_update(&todo).await;
+    let token = undo.record_update(before, &todo);
+    Ok(undo::with_token(preference.respond(todo), token))
+}
+
+pub async fn todo_upsert(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    preference: ReturnPreference,
+    Path(external_id): Path<String>,
+    Json(mut todo): Json<UpdateTodo>,
+) -> Result<Response, Error> {
+    let existing = Todo::find_external(&dbpool, &external_id).await?;
+    hooks.before_upsert(existing, &mut todo).await?;
+    todo.process(&config.text_pipeline);
+    todo.set_body(config.body_policy.apply(todo.body(), "body")?);
+    let (todo, created) = Todo::upsert(dbpool, &external_id, todo).await?;
+    cache.write_through(&todo);
+    // A todo can be created by another request in between, so whether this was a create is
+    // decided by the write itself.
+    if created {
+        hooks.after_create(&todo).await;
+    } else {
+        hooks.after_update(&todo).await;
+    }
+    let mut response = preference.respond(todo);
+    if created && response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::CREATED;
+    }
+    Ok(response)
+}
+
+pub async fn todo_archive(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Id(id): Id,
+    _: JsonContent,
+) -> Result<Json<Todo>, Error> {
+    let todo = Todo::archive(dbpool, id).await?;
+    // The todo leaves the cached lists as well.
+    cache.write_through(&todo);
+    hooks.after_update(&todo).await;
+    Ok(Json::from(todo))
+}
+
+pub async fn todo_unarchive(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Id(id): Id,
+    _: JsonContent,
+) -> Result<Json<Todo>, Error> {
+    let todo = Todo::unarchive(dbpool, id).await?;
+    cache.write_through(&todo);
+    hooks.after_update(&todo).await;
+    Ok(Json::from(todo))
+}
+
+pub async fn reaction_add(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    Id(id): Id,
+    Json(reaction): Json<AddReaction>,
+) -> Result<Json<Todo>, Error> {
+    let todo = reactions::add(dbpool, id, reaction).await?;
+    // Reactions don't change the todo itself, so the hooks aren't told, but cached copies have the
+    // old counts.
+    cache.write_through(&todo);
+    Ok(Json::from(todo))
+}
+
+pub async fn reaction_remove(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(ids): State<Arc<IdEncoding>>,
+    Path((id, emoji)): Path<(String, String)>,
 ) -> Result<Json<Todo>, Error> {
-    Todo::update(dbpool, id, updated_todo).await.map(Json::from)
+    let id = ids.decode(&id).ok_or(Error::NotFound)?;
+    let todo = reactions::remove(dbpool, id, &emoji).await?;
+    cache.write_through(&todo);
+    Ok(Json::from(todo))
+}
+
+pub async fn todo_import(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Query(query): Query<ImportQuery>,
+    // The export is taken as any JSON document, since strict JSON would reject the fields we don't
+    // use; the format adapters check its shape.
+    Json(export): Json<serde_json::Value>,
+) -> Result<(StatusCode, Json<ImportReport>), Error> {
+    let (report, written) =
+        import::import(dbpool, &config.body_policy, &hooks, &query, export).await?;
+    if !query.dry_run() {
+        cache.invalidate_all();
+    }
+    for (todo, created) in &written {
+        if *created {
+            hooks.after_create(todo).await;
+        } else {
+            hooks.after_update(todo).await;
+        }
+    }
+    // A 207 when some todos couldn't be imported; the report says which.
+    Ok((report.todos().status(), Json::from(report)))
+}
+
+// Each extractor is a handler argument, so handlers that need a lot of state take a lot of them.
+#[allow(clippy::too_many_arguments)]
+pub async fn todo_duplicate(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Id(id): Id,
+    Query(options): Query<DuplicateOptions>,
+    _: JsonContent,
+) -> Result<(StatusCode, Json<Todo>), Error> {
+    // The copy is a new todo as far as hooks are concerned, and like other new todos it has to
+    // pass the policy after them.
+    let mut copy = Todo::read(dbpool.clone(), id).await?.duplicate(&options);
+    hooks.before_create(&mut copy).await?;
+    copy.set_body(config.body_policy.apply(copy.body(), "body")?);
+    let todo = Todo::create(dbpool, copy).await?;
+    cache.write_through(&todo);
+    hooks.after_create(&todo).await;
+    Ok((StatusCode::CREATED, Json::from(todo)))
+}
+
+pub async fn todo_merge(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Json(request): Json<MergeRequest>,
+) -> Result<Json<MergeResponse>, Error> {
+    let response = request.apply(dbpool, &hooks).await?;
+    cache.invalidate_all();
+    hooks.after_update(response.todo()).await;
+    for id in response.merged() {
+        hooks.after_delete(*id).await;
+    }
+    Ok(Json::from(response))
+}
+
+pub async fn todo_update_where(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Json(request): Json<UpdateWhere>,
+) -> Result<Json<UpdateWhereResponse>, Error> {
+    let todos = request.apply(dbpool, &config.status_transitions).await?;
+    if !todos.is_empty() {
+        cache.invalidate_all();
+    }
+    for (todo, previous) in &todos {
+        hooks.after_bulk_update(todo, *previous).await;
+    }
+    Ok(Json::from(UpdateWhereResponse::new(todos.len())))
+}
+
+pub async fn todo_purge(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    State(undo): State<Arc<UndoLog>>,
+    Query(purge): Query<PurgeQuery>,
+    _: JsonContent,
+) -> Result<Json<PurgeResponse>, Error> {
+    let todos = Todo::purge_completed(dbpool, purge).await?;
+    cache.invalidate_all();
+    for todo in &todos {
+        hooks.after_delete(todo.id()).await;
+    }
+    let purged = todos.len();
+    let token = undo.record_delete(todos);
+    Ok(Json::from(
+        PurgeResponse::new(purged).with_undo_token(token),
+    ))
 }
 
 pub async fn todo_delete(
     State(dbpool): State<SqlitePool>,
-    Path(id): Path<i64>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    State(undo): State<Arc<UndoLog>>,
+    Id(id): Id,
+) -> Result<Response, Error> {
+    hooks.before_delete(id).await?;
+    let deleted = Todo::delete(dbpool, id).await?;
+    cache.invalidate_todo(id);
+    hooks.after_delete(id).await;
+    let token = deleted.and_then(|todo| undo.record_delete(vec![todo]));
+    Ok(undo::with_token(().into_response(), token))
+}
+
+// Undoes a delete, purge, or update with the token it returned, within the undo window. Like sync,
+// undo doesn't go through the hooks.
+pub async fn undo(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(undo): State<Arc<UndoLog>>,
+    Json(request): Json<UndoRequest>,
+) -> Result<Json<UndoResponse>, Error> {
+    let todos = undo.undo(dbpool, request.undo_token()).await?;
+    for todo in &todos {
+        cache.write_through(todo);
+    }
+    Ok(Json::from(UndoResponse::new(todos)))
+}
+
+pub async fn todo_search(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    params: ListParams,
+) -> Result<Json<Vec<SearchHit>>, Error> {
+    SearchHit::search(dbpool, params, &config)
+        .await
+        .map(Json::from)
+}
+
+pub async fn todo_recent(
+    State(dbpool): State<SqlitePool>,
+    params: ListParams,
+) -> Result<Json<Vec<RecentTodo>>, Error> {
+    RecentTodo::list(dbpool, params).await.map(Json::from)
+}
+
+pub async fn todo_suggest(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<SuggestQuery>,
+) -> Result<Json<Vec<Suggestion>>, Error> {
+    Suggestion::suggest(dbpool, params, &config)
+        .await
+        .map(Json::from)
+}
+
+pub async fn changes_list(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    // The Query extractor deserializes the ?since=<seq>&limit=<n> query string for us.
+    Query(params): Query<ChangesQuery>,
+) -> Result<Json<ChangeFeed>, Error> {
+    Change::since(dbpool, params.since(), params.limit(&config)?)
+        .await
+        .map(Json::from)
+}
+
+pub async fn sync(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    Json(request): Json<SyncRequest>,
+) -> Result<(StatusCode, Json<SyncResponse>), Error> {
+    // Conflicts and invalid changes are reported per change, with a 207 when there are any; only
+    // database errors fail the whole request.
+    let response = request.apply(dbpool, &config.body_policy).await?;
+    // A sync batch can touch any number of todos.
+    cache.invalidate_all();
+    Ok((response.status(), Json::from(response)))
+}
+
+pub async fn trigger_new_todo(
+    State(dbpool): State<SqlitePool>,
+    params: ListParams,
+) -> Result<Json<Vec<Todo>>, Error> {
+    Trigger::NewTodo.poll(dbpool, &params).await.map(Json::from)
+}
+
+pub async fn trigger_completed_todo(
+    State(dbpool): State<SqlitePool>,
+    params: ListParams,
+) -> Result<Json<Vec<Todo>>, Error> {
+    Trigger::CompletedTodo
+        .poll(dbpool, &params)
+        .await
+        .map(Json::from)
+}
+
+// The create action for automation platforms. It's todo_create without content negotiation: the
+// platforms always want the created item back, to pass its fields on to later steps.
+pub async fn action_create_todo(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<Arc<ResponseCache>>,
+    State(hooks): State<Arc<Hooks>>,
+    Json(mut new_todo): Json<CreateTodo>,
+) -> Result<(StatusCode, Json<Todo>), Error> {
+    hooks.before_create(&mut new_todo).await?;
+    new_todo.process(&config.text_pipeline);
+    new_todo.set_body(config.body_policy.apply(new_todo.body(), "body")?);
+    let todo = Todo::create(dbpool, new_todo).await?;
+    cache.write_through(&todo);
+    hooks.after_create(&todo).await;
+    Ok((StatusCode::CREATED, Json::from(todo)))
+}
+
+// Guest quicklists are authenticated only by the token in the path, like the calendar feed.
+pub async fn quicklist_create(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    Json(new): Json<CreateQuicklist>,
+) -> Result<Response, Error> {
+    let list = Quicklist::create(dbpool, Duration::from_secs(config.quicklist_ttl), new).await?;
+    let location = HeaderValue::from_str(list.path()).expect("tokens are hex");
+    Ok((
+        StatusCode::CREATED,
+        [(LOCATION, location)],
+        Json::from(list),
+    )
+        .into_response())
+}
+
+pub async fn quicklist_read(
+    State(dbpool): State<SqlitePool>,
+    Path(token): Path<String>,
+) -> Result<Json<Quicklist>, Error> {
+    Quicklist::read(dbpool, &token).await.map(Json::from)
+}
+
+pub async fn quicklist_delete(
+    State(dbpool): State<SqlitePool>,
+    Path(token): Path<String>,
 ) -> Result<(), Error> {
-    Todo::delete(dbpool, id).await
+    Quicklist::delete(dbpool, &token).await
+}
+
+pub async fn quicklist_item_create(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    Path(token): Path<String>,
+    Json(new): Json<CreateQuicklistItem>,
+) -> Result<(StatusCode, Json<QuicklistItem>), Error> {
+    let ttl = Duration::from_secs(config.quicklist_ttl);
+    let item = Quicklist::add_item(dbpool, ttl, &config.body_policy, &token, new).await?;
+    Ok((StatusCode::CREATED, Json::from(item)))
+}
+
+pub async fn quicklist_item_update(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(ids): State<Arc<IdEncoding>>,
+    Path((token, id)): Path<(String, String)>,
+    Json(update): Json<UpdateQuicklistItem>,
+) -> Result<Json<QuicklistItem>, Error> {
+    let id = ids.decode(&id).ok_or(Error::NotFound)?;
+    let ttl = Duration::from_secs(config.quicklist_ttl);
+    Quicklist::update_item(dbpool, ttl, &config.body_policy, &token, id, update)
+        .await
+        .map(Json::from)
+}
+
+pub async fn quicklist_item_delete(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    State(ids): State<Arc<IdEncoding>>,
+    Path((token, id)): Path<(String, String)>,
+) -> Result<(), Error> {
+    let id = ids.decode(&id).ok_or(Error::NotFound)?;
+    Quicklist::delete_item(
+        dbpool,
+        Duration::from_secs(config.quicklist_ttl),
+        &token,
+        id,
+    )
+    .await
+}
+
+pub async fn todo_share_create(
+    State(dbpool): State<SqlitePool>,
+    Id(id): Id,
+    _: JsonContent,
+) -> Result<Response, Error> {
+    let share = TodoShare::create(dbpool, id).await?;
+    let location = HeaderValue::from_str(share.path()).expect("tokens are hex");
+    Ok((
+        StatusCode::CREATED,
+        [(LOCATION, location)],
+        Json::from(share),
+    )
+        .into_response())
+}
+
+pub async fn todo_shares_list(
+    State(dbpool): State<SqlitePool>,
+    Id(id): Id,
+) -> Result<Json<Vec<TodoShare>>, Error> {
+    TodoShare::list(dbpool, id).await.map(Json::from)
+}
+
+pub async fn todo_share_revoke(
+    State(dbpool): State<SqlitePool>,
+    State(ids): State<Arc<IdEncoding>>,
+    Path((id, share_id)): Path<(String, String)>,
+) -> Result<(), Error> {
+    let id = ids.decode(&id).ok_or(Error::NotFound)?;
+    let share_id = ids.decode(&share_id).ok_or(Error::NotFound)?;
+    TodoShare::revoke(dbpool, id, share_id).await
+}
+
+// The shared view of a todo, authenticated only by the token in the path.
+pub async fn shared_todo_read(
+    State(dbpool): State<SqlitePool>,
+    Path(token): Path<String>,
+) -> Result<Json<SharedTodo>, Error> {
+    SharedTodo::read(dbpool, &token).await.map(Json::from)
+}
+
+// The key browsers subscribe to Web Push with. Without a VAPID key configured, there's none.
+pub async fn push_key(State(web_push): State<Arc<WebPush>>) -> Result<Json<PushKey>, Error> {
+    web_push.key().map(Json::from).ok_or(Error::NotFound)
+}
+
+pub async fn push_subscription_create(
+    State(dbpool): State<SqlitePool>,
+    Json(new): Json<CreatePushSubscription>,
+) -> Result<Response, Error> {
+    let subscription = PushSubscription::create(dbpool, new).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(
+            LOCATION,
+            format!("/v1/push/subscriptions/{}", ids::encode(subscription.id())),
+        )],
+        Json::from(subscription),
+    )
+        .into_response())
+}
+
+pub async fn push_subscriptions_list(
+    State(dbpool): State<SqlitePool>,
+) -> Result<Json<Vec<PushSubscription>>, Error> {
+    PushSubscription::list(dbpool).await.map(Json::from)
+}
+
+pub async fn push_subscription_delete(
+    State(dbpool): State<SqlitePool>,
+    Id(id): Id,
+) -> Result<(), Error> {
+    PushSubscription::delete(dbpool, id).await
+}
+
+// The tenant whose shard the request's feed tokens are in, which their paths have to name. Outside
+// database-per-tenant mode, there's only the one database, and feed paths don't name a tenant.
+fn feed_tenant(config: &Config, tenant: Option<Extension<Tenant>>) -> Option<Tenant> {
+    config.shard_dir.as_ref()?;
+    tenant.map(|Extension(tenant)| tenant)
+}
+
+pub async fn feed_tokens_list(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    tenant: Option<Extension<Tenant>>,
+) -> Result<Json<Vec<FeedToken>>, Error> {
+    let tenant = feed_tenant(&config, tenant);
+    FeedToken::list(dbpool, tenant.as_ref())
+        .await
+        .map(Json::from)
+}
+
+pub async fn feed_token_create(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    tenant: Option<Extension<Tenant>>,
+    _: JsonContent,
+) -> Result<(StatusCode, Json<FeedToken>), Error> {
+    let tenant = feed_tenant(&config, tenant);
+    let token = FeedToken::create(dbpool, tenant.as_ref()).await?;
+    Ok((StatusCode::CREATED, Json::from(token)))
+}
+
+pub async fn feed_token_rotate(
+    State(dbpool): State<SqlitePool>,
+    State(config): State<Arc<Config>>,
+    tenant: Option<Extension<Tenant>>,
+    Id(id): Id,
+    _: JsonContent,
+) -> Result<Json<FeedToken>, Error> {
+    let tenant = feed_tenant(&config, tenant);
+    FeedToken::rotate(dbpool, tenant.as_ref(), id)
+        .await
+        .map(Json::from)
+}
+
+pub async fn feed_token_revoke(State(dbpool): State<SqlitePool>, Id(id): Id) -> Result<(), Error> {
+    FeedToken::revoke(dbpool, id).await
+}
+
+// The calendar feed itself, authenticated only by the token in the path.
+pub async fn feed_calendar(
+    State(dbpool): State<SqlitePool>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let calendar = feed::calendar(dbpool, &token).await?;
+    Ok(([(CONTENT_TYPE, "text/calendar; charset=utf-8")], calendar))
+}
+
+pub async fn flags_enabled(
+    State(MainDb(dbpool)): State<MainDb>,
+    State(flags): State<Arc<FeatureFlags>>,
+    Query(query): Query<FlagsQuery>,
+) -> Result<Json<Vec<String>>, Error> {
+    flags
+        .enabled_for(&dbpool, query.subject())
+        .await
+        .map(Json::from)
+}
+
+pub async fn flags_list(
+    State(dbpool): State<SqlitePool>,
+    State(flags): State<Arc<FeatureFlags>>,
+) -> Result<Json<Vec<FeatureFlag>>, Error> {
+    flags.list(&dbpool).await.map(Json::from)
+}
+
+pub async fn flag_update(
+    State(dbpool): State<SqlitePool>,
+    State(flags): State<Arc<FeatureFlags>>,
+    Path(name): Path<String>,
+    Json(update): Json<UpdateFeatureFlag>,
+) -> Result<Json<FeatureFlag>, Error> {
+    flags.set(&dbpool, &name, update).await.map(Json::from)
+}
+
+pub async fn flag_delete(
+    State(dbpool): State<SqlitePool>,
+    State(flags): State<Arc<FeatureFlags>>,
+    Path(name): Path<String>,
+) -> Result<(), Error> {
+    flags.delete(&dbpool, &name).await
+}
+
+pub async fn rate_plans_list(State(limiter): State<Arc<RateLimiter>>) -> Json<Vec<RatePlan>> {
+    Json(limiter.plans())
+}
+
+pub async fn tenant_plans_list(
+    State(dbpool): State<SqlitePool>,
+    State(limiter): State<Arc<RateLimiter>>,
+) -> Result<Json<Vec<TenantPlan>>, Error> {
+    limiter.tenants(&dbpool).await.map(Json::from)
+}
+
+pub async fn tenant_plan_update(
+    State(dbpool): State<SqlitePool>,
+    State(limiter): State<Arc<RateLimiter>>,
+    Path(tenant): Path<String>,
+    Json(update): Json<SetTenantPlan>,
+) -> Result<Json<TenantPlan>, Error> {
+    limiter
+        .set_plan(&dbpool, &tenant, update)
+        .await
+        .map(Json::from)
+}
+
+pub async fn tenant_plan_delete(
+    State(dbpool): State<SqlitePool>,
+    State(limiter): State<Arc<RateLimiter>>,
+    Path(tenant): Path<String>,
+) -> Result<(), Error> {
+    limiter.reset_plan(&dbpool, &tenant).await
+}
+
+// The usage for invoicing, as JSON or as CSV with ?format=csv.
+pub async fn usage_export(
+    State(dbpool): State<SqlitePool>,
+    State(metering): State<Arc<Metering>>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, Error> {
+    let default_plan = config.rate_limit_plans.list().into_iter().next();
+    let report = metering
+        .report(
+            &dbpool,
+            &query,
+            default_plan.as_ref().map(|plan| plan.name()),
+        )
+        .await?;
+    let disposition = format!(
+        "attachment; filename=\"{}\"",
+        report.file_name(query.format())
+    );
+    Ok(match query.format() {
+        UsageFormat::Json => ([(CONTENT_DISPOSITION, disposition)], Json(report)).into_response(),
+        UsageFormat::Csv => (
+            [
+                (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (CONTENT_DISPOSITION, disposition),
+            ],
+            report.to_csv(),
+        )
+            .into_response(),
+    })
+}
+
+pub async fn maintenance_read(
+    State(maintenance): State<Arc<Maintenance>>,
+) -> Json<MaintenanceStatus> {
+    Json(maintenance.status())
+}
+
+pub async fn maintenance_update(
+    State(maintenance): State<Arc<Maintenance>>,
+    Json(status): Json<MaintenanceStatus>,
+) -> Json<MaintenanceStatus> {
+    maintenance.set(status);
+    Json(maintenance.status())
+}
+
+pub async fn log_level_read(
+    State(log_level): State<Arc<LogLevel>>,
+) -> Result<Json<LogLevelStatus>, Error> {
+    log_level.status().map(Json::from)
+}
+
+pub async fn log_level_update(
+    State(log_level): State<Arc<LogLevel>>,
+    Json(status): Json<LogLevelStatus>,
+) -> Result<Json<LogLevelStatus>, Error> {
+    log_level.set(status).map(Json::from)
+}
+
+pub async fn runtime_read(State(state): State<AppState>) -> Json<RuntimeInfo> {
+    Json(RuntimeInfo::collect(&state))
+}
+
+pub async fn database_read(
+    State(dbpool): State<SqlitePool>,
+    State(stats): State<Arc<DbStatsMonitor>>,
+) -> Result<Json<DbStats>, Error> {
+    stats.get(&dbpool).await.map(Json::from).map_err(Into::into)
+}
+
+pub async fn migrations_read(
+    State(dbpool): State<SqlitePool>,
+) -> Result<Json<MigrationStatus>, Error> {
+    Ok(Json::from(migrations::status(&dbpool).await?))
+}
+
+pub async fn restore_snapshot(
+    State(dbpool): State<SqlitePool>,
+    State(cache): State<Arc<ResponseCache>>,
+    Json(request): Json<RestoreRequest>,
+) -> Result<Json<SnapshotReport>, Error> {
+    let report = if request.verify_only() {
+        restore::verify(request.snapshot()).await
+    } else {
+        restore::restore(&dbpool, request.snapshot()).await
+    }
+    .map_err(restore::invalid_snapshot)?;
+    // Everything we had cached is from the database we just replaced.
+    cache.invalidate_all();
+    Ok(Json::from(report))
+}
+
+// The Prometheus scrape endpoint.
+pub async fn metrics_read(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render()
+            + &state.outbound.render()
+            + &state.deprecation_usage.render()
+            + &health::render(&state).await,
+    )
+}
+
+pub async fn retention_read(
+    State(dbpool): State<SqlitePool>,
+) -> Result<Json<RetentionPolicy>, Error> {
+    RetentionPolicy::read(dbpool).await.map(Json::from)
+}
+
+pub async fn retention_update(
+    State(dbpool): State<SqlitePool>,
+    Json(updated): Json<UpdateRetentionPolicy>,
+) -> Result<Json<RetentionPolicy>, Error> {
+    RetentionPolicy::update(dbpool, updated)
+        .await
+        .map(Json::from)
+}
+
+pub async fn preferences_read(
+    State(dbpool): State<SqlitePool>,
+) -> Result<Json<Preferences>, Error> {
+    Preferences::read(dbpool).await.map(Json::from)
+}
+
+pub async fn preferences_update(
+    State(dbpool): State<SqlitePool>,
+    Json(updated): Json<UpdatePreferences>,
+) -> Result<Json<Preferences>, Error> {
+    Preferences::update(dbpool, updated).await.map(Json::from)
 }
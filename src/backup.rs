@@ -0,0 +1,168 @@
+use axum::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, SqlitePool};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// Somewhere to ship database snapshots to, such as a directory or an object storage bucket.
+// Embedders can register their own sinks on the AppState; a directory sink is built in.
+#[async_trait]
+pub trait BackupSink: Send + Sync {
+    // Ships the snapshot at `snapshot`. `name` is unique per snapshot and sorts chronologically.
+    // The file is removed once every sink has been called, so sinks must copy it if they need it
+    // for longer.
+    async fn ship(&self, snapshot: &Path, name: &str) -> Result<(), String>;
+}
+
+// Copies snapshots into a directory, keeping the most recent `keep` of them. The directory can be a
+// mounted bucket or a volume that's replicated elsewhere.
+pub struct DirectorySink {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl DirectorySink {
+    pub fn new(dir: PathBuf, keep: usize) -> Self {
+        Self { dir, keep }
+    }
+}
+
+#[async_trait]
+impl BackupSink for DirectorySink {
+    async fn ship(&self, snapshot: &Path, name: &str) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|err| format!("can't create {}: {err}", self.dir.display()))?;
+        // Copying to a temporary name first means a half-written snapshot is never mistaken for a
+        // complete one.
+        let target = self.dir.join(name);
+        let partial = self.dir.join(format!("{name}.partial"));
+        tokio::fs::copy(snapshot, &partial)
+            .await
+            .map_err(|err| format!("can't copy to {}: {err}", partial.display()))?;
+        tokio::fs::rename(&partial, &target)
+            .await
+            .map_err(|err| format!("can't rename to {}: {err}", target.display()))?;
+
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .map_err(|err| format!("can't read {}: {err}", self.dir.display()))?;
+        let mut snapshots = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with(SNAPSHOT_PREFIX) && file_name.ends_with(".sqlite") {
+                snapshots.push(entry.path());
+            }
+        }
+        // The names sort chronologically, so the oldest ones come first.
+        snapshots.sort();
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for old in &snapshots[..excess] {
+            tokio::fs::remove_file(old).await.ok();
+        }
+        Ok(())
+    }
+}
+
+const SNAPSHOT_PREFIX: &str = "todos-";
+
+// How shipping has been going, reported by GET /v1/admin/runtime.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BackupStatus {
+    enabled: bool,
+    shipped: u64,
+    failures: u64,
+    last_success_at: Option<NaiveDateTime>,
+    last_error: Option<String>,
+}
+
+impl BackupStatus {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn last_success_at(&self) -> Option<NaiveDateTime> {
+        self.last_success_at
+    }
+}
+
+// The registered sinks and the status of the shipping task.
+#[derive(Default)]
+pub struct Backups {
+    sinks: Vec<Arc<dyn BackupSink>>,
+    status: RwLock<BackupStatus>,
+}
+
+impl Backups {
+    pub fn register(&mut self, sink: impl BackupSink + 'static) {
+        self.sinks.push(Arc::new(sink));
+        self.status
+            .write()
+            .expect("backup status lock poisoned")
+            .enabled = true;
+    }
+
+    pub fn status(&self) -> BackupStatus {
+        self.status
+            .read()
+            .expect("backup status lock poisoned")
+            .clone()
+    }
+
+    // Starts shipping a snapshot every `interval` in the background, if any sinks are registered.
+    pub fn spawn(self: Arc<Self>, dbpool: SqlitePool, interval: Duration) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                self.ship_once(&dbpool).await;
+            }
+        });
+    }
+
+    // Takes one snapshot and hands it to every sink.
+    pub async fn ship_once(&self, dbpool: &SqlitePool) {
+        let now = Utc::now().naive_utc();
+        let name = format!(
+            "{SNAPSHOT_PREFIX}{}.sqlite",
+            now.format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let snapshot = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+
+        // VACUUM INTO writes a consistent, compacted copy of the database without blocking writers
+        // for longer than it takes to read it.
+        let mut result = query("vacuum into ?")
+            .bind(snapshot.to_string_lossy().into_owned())
+            .execute(dbpool)
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("can't take a snapshot: {err}"));
+        if result.is_ok() {
+            for sink in &self.sinks {
+                if let Err(err) = sink.ship(&snapshot, &name).await {
+                    result = Err(err);
+                }
+            }
+        }
+        tokio::fs::remove_file(&snapshot).await.ok();
+
+        let mut status = self.status.write().expect("backup status lock poisoned");
+        match result {
+            Ok(()) => {
+                status.shipped += 1;
+                status.last_success_at = Some(now);
+                status.last_error = None;
+            }
+            Err(err) => {
+                tracing::warn!("backup failed: {err}");
+                status.failures += 1;
+                status.last_error = Some(err);
+            }
+        }
+    }
+}
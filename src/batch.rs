@@ -0,0 +1,143 @@
+use crate::error::{Error, RequestError};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+// What happened to one item of a batch request. Every batch endpoint reports its items this way, so
+// a client can tell exactly which ones to retry.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchItem<T> {
+    // The position of the item in the request.
+    index: usize,
+    // The status the item would have gotten as a request of its own, e.g. 201 for a todo that was
+    // created, 409 for a conflict or 422 for an invalid item.
+    status: u16,
+    // The client's own reference for the item, such as a sync change's client_ref or an imported
+    // todo's external ID, when it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RequestError>,
+    // The resulting entity. For a conflict, this is the server's current copy, so the client can
+    // resolve it without another request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity: Option<T>,
+}
+
+impl<T> BatchItem<T> {
+    pub fn succeeded(index: usize, status: StatusCode, entity: Option<T>) -> Self {
+        Self {
+            index,
+            status: status.as_u16(),
+            reference: None,
+            error: None,
+            entity,
+        }
+    }
+
+    pub fn failed(index: usize, status: StatusCode, error: RequestError) -> Self {
+        Self {
+            index,
+            status: status.as_u16(),
+            reference: None,
+            error: Some(error),
+            entity: None,
+        }
+    }
+
+    // Turns a validation error for the item into a failed item. Anything else, like a database
+    // error, fails the whole batch.
+    pub fn rejected(index: usize, error: Error) -> Result<Self, Error> {
+        match error {
+            Error::BadRequest(status, error) => Ok(Self::failed(index, status, error)),
+            error => Err(error),
+        }
+    }
+
+    pub fn with_reference(mut self, reference: Option<String>) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    pub fn with_entity(mut self, entity: Option<T>) -> Self {
+        self.entity = entity;
+        self
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    pub fn error(&self) -> Option<&RequestError> {
+        self.error.as_ref()
+    }
+
+    pub fn entity(&self) -> Option<&T> {
+        self.entity.as_ref()
+    }
+}
+
+// The items of a batch request, in request order, with how many of them succeeded and failed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchReport<T> {
+    succeeded: usize,
+    failed: usize,
+    items: Vec<BatchItem<T>>,
+}
+
+impl<T> Default for BatchReport<T> {
+    fn default() -> Self {
+        Self {
+            succeeded: 0,
+            failed: 0,
+            items: Vec::new(),
+        }
+    }
+}
+
+impl<T> BatchReport<T> {
+    pub fn push(&mut self, item: BatchItem<T>) {
+        match item.is_success() {
+            true => self.succeeded += 1,
+            false => self.failed += 1,
+        }
+        self.items.push(item);
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.succeeded
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    pub fn items(&self) -> &[BatchItem<T>] {
+        &self.items
+    }
+
+    // The items that failed, which are the ones worth retrying once the problem is fixed.
+    pub fn failures(&self) -> impl Iterator<Item = &BatchItem<T>> {
+        self.items.iter().filter(|item| !item.is_success())
+    }
+
+    // The status of the whole response: 200 when every item succeeded, and 207 Multi-Status when
+    // any failed, so clients that only look at the status still notice partial failures.
+    pub fn status(&self) -> StatusCode {
+        match self.failed {
+            0 => StatusCode::OK,
+            _ => StatusCode::MULTI_STATUS,
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::error::Error;
+use crate::todo::Todo;
+use moka::sync::Cache;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+enum Key {
+    Todo(i64),
+    // A list query, identified by a fingerprint of its parameters.
+    List(String),
+}
+
+#[derive(Clone)]
+enum Entry {
+    Todo(Box<Todo>),
+    List(Arc<Vec<Todo>>),
+}
+
+// An optional in-process cache for hot reads, so read-heavy deployments don't hit SQLite for every
+// request. Handlers that write invalidate the affected entries before they respond, so a client
+// always reads its own writes; the TTL bounds how stale an entry can get if a write path forgets to.
+pub struct ResponseCache {
+    // None when caching is disabled, in which case every read goes straight to the database.
+    cache: Option<Cache<Key, Entry>>,
+    // Bumped by every write. A read that raced with a write may have fetched the old data, so its
+    // result is only cached if the generation is still the one it started with. Holding the lock
+    // while checking and inserting keeps a write from slipping in between.
+    generation: Mutex<u64>,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: u64, ttl: Duration) -> Self {
+        Self {
+            cache: Some(
+                Cache::builder()
+                    .max_capacity(capacity)
+                    .time_to_live(ttl)
+                    .build(),
+            ),
+            generation: Mutex::new(0),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            cache: None,
+            generation: Mutex::new(0),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        *self
+            .generation
+            .lock()
+            .expect("cache generation lock poisoned")
+    }
+
+    // Caches an entry read from the database, unless a write happened since the read started.
+    fn insert_unless_stale(&self, cache: &Cache<Key, Entry>, started: u64, key: Key, entry: Entry) {
+        let generation = self
+            .generation
+            .lock()
+            .expect("cache generation lock poisoned");
+        if *generation == started {
+            cache.insert(key, entry);
+        }
+    }
+
+    // Runs an invalidation as a new generation, so reads that started before it don't cache what
+    // they fetched.
+    fn write(&self, invalidate: impl FnOnce(&Cache<Key, Entry>)) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        let mut generation = self
+            .generation
+            .lock()
+            .expect("cache generation lock poisoned");
+        *generation += 1;
+        invalidate(cache);
+    }
+
+    pub async fn todo<F>(&self, id: i64, fetch: F) -> Result<Todo, Error>
+    where
+        F: Future<Output = Result<Todo, Error>>,
+    {
+        let Some(cache) = &self.cache else {
+            return fetch.await;
+        };
+        if let Some(Entry::Todo(todo)) = cache.get(&Key::Todo(id)) {
+            return Ok(*todo);
+        }
+        let started = self.generation();
+        let todo = fetch.await?;
+        self.insert_unless_stale(
+            cache,
+            started,
+            Key::Todo(id),
+            Entry::Todo(Box::new(todo.clone())),
+        );
+        Ok(todo)
+    }
+
+    pub async fn list<F>(&self, fingerprint: &str, fetch: F) -> Result<Arc<Vec<Todo>>, Error>
+    where
+        F: Future<Output = Result<Vec<Todo>, Error>>,
+    {
+        let Some(cache) = &self.cache else {
+            return fetch.await.map(Arc::new);
+        };
+        let key = Key::List(fingerprint.to_string());
+        if let Some(Entry::List(todos)) = cache.get(&key) {
+            return Ok(todos);
+        }
+        let started = self.generation();
+        let todos = Arc::new(fetch.await?);
+        self.insert_unless_stale(cache, started, key, Entry::List(todos.clone()));
+        Ok(todos)
+    }
+
+    // Called after a todo was created or updated, with the todo as it is now. The todo is cached
+    // right away, so the next read doesn't need the database. Any list could include the todo, so
+    // we drop all of them.
+    //
+    // Concurrent updates can finish in any order, so a todo older than the cached one is left out
+    // rather than replacing it. Writes that don't bump the version, like reactions, stale flags,
+    // and link previews, can't be ordered that way, so a cached todo at the same version is
+    // dropped and the next read fetches it.
+    pub fn write_through(&self, todo: &Todo) {
+        self.write(|cache| {
+            invalidate_lists(cache);
+            let key = Key::Todo(todo.id());
+            match cache.get(&key) {
+                Some(Entry::Todo(cached)) if cached.version() > todo.version() => {}
+                Some(Entry::Todo(cached)) if cached.version() == todo.version() => {
+                    cache.invalidate(&key);
+                }
+                _ => cache.insert(key, Entry::Todo(Box::new(todo.clone()))),
+            }
+        });
+    }
+
+    // Called after a todo was deleted. Like write_through, this drops all lists.
+    pub fn invalidate_todo(&self, id: i64) {
+        self.write(|cache| {
+            cache.invalidate(&Key::Todo(id));
+            invalidate_lists(cache);
+        });
+    }
+
+    // Called after writes that can touch any number of todos, e.g. a sync batch.
+    pub fn invalidate_all(&self) {
+        self.write(|cache| cache.invalidate_all());
+    }
+}
+
+fn invalidate_lists(cache: &Cache<Key, Entry>) {
+    for (key, _) in cache.iter() {
+        if matches!(*key, Key::List(_)) {
+            cache.invalidate(&*key);
+        }
+    }
+}
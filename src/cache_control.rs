@@ -0,0 +1,95 @@
+use crate::config::Config;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+// A middleware applying our caching policy. Successful reads of the API get a short max-age and an
+// ETag, which also lets clients revalidate with If-None-Match. Everything else, including mutations,
+// errors, health checks, and the admin API, must not be stored by caches.
+pub async fn apply_policy(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let cacheable_route = matches!(*request.method(), Method::GET | Method::HEAD)
+        && request.uri().path().starts_with("/v1/")
+        && !request.uri().path().starts_with("/v1/admin");
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+
+    let response = next.run(request).await;
+
+    if !cacheable_route
+        || response.status() != StatusCode::OK
+        || response.extensions().get::<Streamed>().is_some()
+    {
+        return with_cache_control(response, HeaderValue::from_static("no-store"));
+    }
+
+    // We need the whole body to compute the ETag. Our read responses are small JSON documents, so
+    // buffering them is cheap.
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let etag = weak_etag(&bytes);
+
+    let cache_control = match config.cache_max_age {
+        0 => HeaderValue::from_static("private, no-cache"),
+        max_age => HeaderValue::from_str(&format!("private, max-age={max_age}"))
+            .expect("a number is a valid header value"),
+    };
+    parts.headers.insert(ETAG, etag.clone());
+    parts.headers.entry(CACHE_CONTROL).or_insert(cache_control);
+
+    // The client already has this representation, so we answer with an empty 304.
+    let matches = if_none_match
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .any(|candidate| candidate.trim() == "*" || candidate.trim() == etag)
+        })
+        .unwrap_or(false);
+    if matches {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// Marks a response whose body is streamed. Computing an ETag would mean buffering the whole body,
+// which is what streaming it avoids, so such responses are simply not cached.
+#[derive(Clone, Copy)]
+pub struct Streamed;
+
+// The ETag of a response body. Handlers that don't return a body, such as writes with
+// Prefer: return=minimal, use it on the serialized representation so the ETag matches a later GET.
+// It's a prefix of the body's SHA-256 rather than the standard library's hash, whose output can
+// change between Rust versions, so instances built with different toolchains agree on it during a
+// deploy.
+pub fn weak_etag(bytes: &[u8]) -> HeaderValue {
+    let digest = Sha256::digest(bytes);
+    let hex: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    // A weak ETag, because the same representation could be encoded differently by a compressing proxy.
+    HeaderValue::from_str(&format!("W/\"{hex}\"")).expect("a hex string is a valid header value")
+}
+
+fn with_cache_control(mut response: Response, value: HeaderValue) -> Response {
+    // Handlers that set their own Cache-Control header know better than our defaults.
+    if !response.headers().contains_key(CACHE_CONTROL) {
+        response.headers_mut().insert(CACHE_CONTROL, value);
+    }
+    response
+}
@@ -0,0 +1,163 @@
+use crate::config::Config;
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::params::page_limit;
+use crate::retention;
+use crate::todo::Todo;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+use std::collections::HashMap;
+
+// The query string for GET /v1/changes. Both parameters are optional, so a client syncing for the
+// first time can call the endpoint without any arguments.
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    #[serde(default)]
+    since: i64,
+    limit: Option<i64>,
+}
+
+impl ChangesQuery {
+    pub fn since(&self) -> i64 {
+        self.since
+    }
+
+    // The page size is capped, so a client that's far behind catches up in several requests.
+    pub fn limit(&self, config: &Config) -> Result<i64, Error> {
+        page_limit(self.limit, config)
+    }
+}
+
+// A row from the changes table, which the triggers in the changes migration populate for us.
+#[derive(sqlx::FromRow)]
+struct ChangeRow {
+    seq: i64,
+    todo_id: i64,
+    op: String,
+    changed_at: NaiveDateTime,
+}
+
+// The kind of change a client needs to apply locally. Inserts and updates are collapsed into an
+// upsert carrying the current state of the todo, while deletes become tombstones.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeOp {
+    Upsert,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Change {
+    seq: i64,
+    #[serde(with = "crate::ids::id")]
+    todo_id: i64,
+    op: ChangeOp,
+    changed_at: NaiveDateTime,
+    // The current todo for upserts; tombstones don't carry any data.
+    todo: Option<Todo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChangeFeed {
+    changes: Vec<Change>,
+    // The cursor the client should pass as `since` on its next call.
+    last_seq: i64,
+}
+
+impl ChangeFeed {
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn last_seq(&self) -> i64 {
+        self.last_seq
+    }
+}
+
+impl Change {
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    pub fn todo_id(&self) -> i64 {
+        self.todo_id
+    }
+
+    pub fn op(&self) -> ChangeOp {
+        self.op
+    }
+
+    pub fn todo(&self) -> Option<&Todo> {
+        self.todo.as_ref()
+    }
+
+    pub async fn since(dbpool: SqlitePool, since: i64, limit: i64) -> Result<ChangeFeed, Error> {
+        // The retention policy may have trimmed deletes the client hasn't seen, which it would
+        // never find out about, so it has to start over.
+        let trimmed_through = retention::changes_trimmed_through(&dbpool).await?;
+        if since > 0 && since < trimmed_through {
+            return Err(Error::BadRequest(
+                StatusCode::GONE,
+                RequestError::new(
+                    "changes_expired",
+                    i18n::message("changes_expired", &[("seq", &trimmed_through.to_string())]),
+                )
+                .with_field("since"),
+            ));
+        }
+        // We only return the latest change for each todo, because intermediate states are of no
+        // use to a client that's catching up.
+        let rows: Vec<ChangeRow> = query_as(
+            "select seq, todo_id, op, changed_at from changes
+             where seq in (select max(seq) from changes where seq > ? group by todo_id)
+             order by seq
+             limit ?",
+        )
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&dbpool)
+        .await?;
+
+        // Fetch the current state of every todo referenced by the page in one query, rather than
+        // reading them one by one.
+        let mut todos: HashMap<i64, Todo> = query_as(
+            "select * from todos where id in
+             (select todo_id from changes where seq > ? and seq <= ?)",
+        )
+        .bind(since)
+        .bind(rows.last().map(|row| row.seq).unwrap_or(since))
+        .fetch_all(&dbpool)
+        .await?
+        .into_iter()
+        .map(|todo: Todo| (todo.id(), todo))
+        .collect();
+
+        let last_seq = rows.last().map(|row| row.seq).unwrap_or(since);
+        let changes = rows
+            .into_iter()
+            .map(|row| {
+                // A todo that no longer exists is reported as a tombstone, even if the last change
+                // we have recorded for it within this page isn't the delete itself.
+                let todo = match row.op.as_str() {
+                    "delete" => None,
+                    _ => todos.remove(&row.todo_id),
+                };
+                Change {
+                    seq: row.seq,
+                    todo_id: row.todo_id,
+                    op: if todo.is_some() {
+                        ChangeOp::Upsert
+                    } else {
+                        ChangeOp::Delete
+                    },
+                    changed_at: row.changed_at,
+                    todo,
+                }
+            })
+            .collect();
+
+        Ok(ChangeFeed { changes, last_seq })
+    }
+}
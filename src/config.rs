@@ -0,0 +1,388 @@
+use crate::deprecation::Deprecations;
+use crate::envelope::EnvelopeMode;
+use crate::notify::NotificationRoutes;
+use crate::outbound::DestinationTimeouts;
+use crate::pipeline::Pipeline;
+use crate::rate_limit::RatePlans;
+use crate::report::ReportSchedules;
+use crate::status::StatusTransitions;
+use crate::validation::BodyPolicy;
+use crate::web_push::VapidKey;
+use axum::http::HeaderName;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// Runtime configuration, read from environment variables once at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    // The bearer token required by the /v1/admin routes. When it's not set, the admin API is disabled.
+    pub admin_token: Option<String>,
+    // Starts the service in read-only mode, e.g. while a migration or backup is running.
+    pub read_only: bool,
+    // Rejects JSON request bodies containing fields we don't know about, so typos don't go unnoticed.
+    pub strict_json: bool,
+    // Wraps responses in {"data": ..., "meta": ..., "errors": [...]}: off, always, or on_request
+    // for requests with a Prefer: envelope header.
+    pub response_envelope: EnvelopeMode,
+    // Routes and query parameters clients should move off, e.g. "GET /v1/todos/board
+    // deprecated=2026-10-01 sunset=2027-04-01 link=https://example.com/v2". Responses using them
+    // carry Deprecation and Sunset headers, and their use is counted in the metrics.
+    pub deprecations: Deprecations,
+    // A directory of <locale>.json message catalogs loaded at startup, in addition to English.
+    pub locales_dir: Option<PathBuf>,
+    // How long clients may cache successful reads, in seconds. 0 means they must always revalidate.
+    pub cache_max_age: u64,
+    // Enables the in-process cache for todo reads, holding up to response_cache_capacity entries
+    // for response_cache_ttl seconds each.
+    pub response_cache: bool,
+    pub response_cache_capacity: u64,
+    pub response_cache_ttl: u64,
+    // Collapses concurrent identical reads into one, so they share a single database query.
+    pub single_flight: bool,
+    // How long deletes and updates can be undone with the token they return, in seconds. 0 turns
+    // undo off.
+    pub undo_window: u64,
+    // How long a guest quicklist lives after it was last changed, in seconds.
+    pub quicklist_ttl: u64,
+    // The number of items returned by list endpoints when the client doesn't ask for a page size,
+    // and the largest page size a client may ask for.
+    pub default_page_size: i64,
+    pub max_page_size: i64,
+    // The most words a search query may contain, since every word adds to the cost of the query.
+    pub max_search_terms: usize,
+    // The most todos a search or suggestion may have to rank, estimated from the full-text index
+    // before running it. Common words and short prefixes match most todos, which all have to be
+    // sorted to return even one page. 0 turns the limit off.
+    pub max_search_cost: i64,
+    // Ships a snapshot of the database to this directory every backup_interval seconds, keeping
+    // the most recent backup_keep snapshots.
+    pub backup_dir: Option<PathBuf>,
+    pub backup_interval: u64,
+    pub backup_keep: usize,
+    // The service level objective: the fraction of requests which should succeed within
+    // slo_latency_ms milliseconds. The error budget burn rate is exported with the metrics.
+    pub slo_target: f64,
+    pub slo_latency_ms: u64,
+    // Labels the request metrics with the tenant named in the metrics_tenant_header request header,
+    // e.g. one set by the API gateway. To keep the number of series in check, only the tenants in
+    // metrics_tenants are labeled when it's set, and otherwise the first metrics_max_tenants seen;
+    // everyone else is counted as "other".
+    pub metrics_tenant_header: Option<HeaderName>,
+    pub metrics_tenants: Vec<String>,
+    pub metrics_max_tenants: usize,
+    // Limits the rate of API requests by plan, e.g.
+    // "free:reads=60,writes=20,burst=100;pro:reads=600,writes=200,burst=2000": reads and writes per
+    // minute, and burst credits per hour for going over them. Tenants are named by the
+    // rate_limit_tenant_header request header and put on a plan through the admin API; everyone else
+    // is on the first plan, and clients without a tenant are limited per address. Without plans,
+    // requests aren't limited.
+    pub rate_limit_plans: RatePlans,
+    pub rate_limit_tenant_header: Option<HeaderName>,
+    // The secret the API gateway signs the tenant header with (see tenant.rs). A tenant header is
+    // only believed with a valid signature, so the service won't start with a tenant header but no
+    // secret.
+    pub tenant_secret: Option<String>,
+    // Meters API calls per tenant, as signed in rate_limit_tenant_header, and samples the storage used,
+    // writing both to the database every metering_interval seconds for the billing export. 0 turns
+    // metering off.
+    pub metering_interval: u64,
+    // Pings heartbeat_url every heartbeat_interval seconds, so an external monitor notices when
+    // the service or its background tasks stop. Without a URL, there's no heartbeat.
+    pub heartbeat_url: Option<String>,
+    pub heartbeat_interval: u64,
+    // How often the database size and row counts are collected for the metrics and
+    // /v1/admin/database, in seconds.
+    pub db_stats_interval: u64,
+    // Keeps at least pool_min_idle database connections open, pinging the idle ones every
+    // pool_ping_interval seconds and replacing those that don't answer. The pool is filled before
+    // we start serving, so the first requests after a deploy don't wait for connections. 0 turns
+    // the keeper off.
+    pub pool_min_idle: u32,
+    pub pool_ping_interval: u64,
+    // How many prepared statements each database connection keeps, so the queries we run all the
+    // time are only prepared once per connection. 0 turns the cache off.
+    pub statement_cache_capacity: usize,
+    // Warns about requests running more than statement_budget statements, which usually means a
+    // query in a loop. With statement_budget_strict, such requests fail instead, to catch them in
+    // development and tests. 0 turns the counting off.
+    pub statement_budget: u32,
+    pub statement_budget_strict: bool,
+    // Flags open todos untouched for stale_after_days days as stale, for ?stale=true and the
+    // todo.stale notification. 0 turns it off.
+    pub stale_after_days: u64,
+    // The status changes allowed on update, e.g. "backlog:in_progress;in_progress:blocked|done".
+    // When it's not set, todos can move between any statuses.
+    pub status_transitions: StatusTransitions,
+    // How todo bodies are cleaned up and how long they may be: BODY_MAX_CHARS, BODY_NFC, and
+    // BODY_STRIP_CONTROL.
+    pub body_policy: BodyPolicy,
+    // The stages todo bodies go through on create and update before the body policy, e.g.
+    // "trim,linkify,due_phrases". When it's not set, bodies are stored as sent.
+    pub text_pipeline: Pipeline,
+    // Fetches the titles and descriptions of pages linked from todos, caching them for
+    // link_preview_ttl seconds. Off by default, since it makes the service fetch URLs users typed.
+    pub link_previews: bool,
+    pub link_preview_ttl: u64,
+    // Gives every tenant its own database in this directory, e.g. acme.sqlite, created with the
+    // provision command. The tenant is the one the gateway signs in rate_limit_tenant_header, so
+    // sharding needs both of those too. API requests without a tenant, or for a tenant that wasn't
+    // provisioned, are turned away; the admin API and the health checks use the main database. At
+    // most shard_max_open shards are kept open, and the least used are closed to make room.
+    pub shard_dir: Option<PathBuf>,
+    pub shard_max_open: u64,
+    // Where background export jobs write their files.
+    pub export_dir: PathBuf,
+    // Public listeners expect every connection to start with a PROXY protocol header from a load
+    // balancer, whose client address then stands in for the connection's.
+    pub proxy_protocol: bool,
+    // How the listeners handle connections. HTTP/2 is off unless http2 is set, and is then spoken
+    // to clients that open the connection with it (h2c with prior knowledge), such as gateways;
+    // each connection may then carry up to http2_max_concurrent_streams requests at a time. HTTP/2
+    // connections are pinged every http2_keep_alive_interval seconds, 0 meaning never, and closed
+    // if a ping isn't answered within http2_keep_alive_timeout seconds. HTTP/1 connections are
+    // kept open between requests unless http1_keep_alive is off, and closed if a request's headers
+    // take longer than header_read_timeout seconds. tcp_nodelay turns off Nagle's algorithm.
+    pub http2: bool,
+    pub http2_max_concurrent_streams: u32,
+    pub http2_keep_alive_interval: u64,
+    pub http2_keep_alive_timeout: u64,
+    pub http1_keep_alive: bool,
+    pub header_read_timeout: u64,
+    pub tcp_nodelay: bool,
+    // Calls to other services time out after outbound_timeout_ms milliseconds, unless
+    // outbound_timeouts has a timeout for the destination, e.g. "hooks.slack.com=3000". Failed
+    // calls are tried up to outbound_max_attempts times, waiting outbound_retry_base_ms
+    // milliseconds before the first retry and twice as long before every further one, up to
+    // outbound_retry_max_ms.
+    pub outbound_timeout_ms: u64,
+    pub outbound_timeouts: DestinationTimeouts,
+    pub outbound_max_attempts: u32,
+    pub outbound_retry_base_ms: u64,
+    pub outbound_retry_max_ms: u64,
+    // Encodes the IDs in the API as short opaque strings with Hashids, salted with hashids_salt
+    // and padded to at least hashids_min_length characters. Changing either changes every ID
+    // clients have stored, so pick them once.
+    pub hashids_salt: Option<String>,
+    pub hashids_min_length: usize,
+    // Which notification channels each event goes to, e.g. "todo.completed:email|web_push;*:log".
+    // Without rules, no notifications are sent. The log channel is always there; the others need
+    // their settings: notify_webhook_url, notify_slack_webhook_url (an incoming webhook),
+    // notify_email_from and notify_email_to (sent through the notify_sendmail program), and
+    // vapid_private_key for Web Push, with vapid_subject as the contact push services see.
+    pub notify_routes: NotificationRoutes,
+    pub notify_webhook_url: Option<String>,
+    pub notify_slack_webhook_url: Option<String>,
+    pub notify_email_from: Option<String>,
+    pub notify_email_to: Option<String>,
+    pub notify_sendmail: PathBuf,
+    pub vapid_private_key: Option<VapidKey>,
+    pub vapid_subject: String,
+    // The reports generated on a schedule, e.g. "open_by_status@mon 09:00;overdue@daily 08:00", at
+    // times in the owner's timezone. They're kept for download and sent to the channels routed for
+    // report.generated.
+    pub report_schedule: ReportSchedules,
+}
+
+impl Config {
+    // Reads the configuration, falling back to the default for any variable that can't be parsed.
+    pub fn from_env() -> Self {
+        Self::read(&mut Env::default())
+    }
+
+    // Reads the configuration, failing with a message per variable that can't be parsed, so a typo
+    // doesn't silently leave a setting at its default.
+    pub fn try_from_env() -> Result<Self, Vec<String>> {
+        Self::try_read(Env::default())
+    }
+
+    // Reads the configuration from the given variables alone, as if they were the whole
+    // environment, so tests and benchmarks get the same configuration wherever they run.
+    pub fn from_vars<'a>(
+        vars: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, Vec<String>> {
+        let vars = vars
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), OsString::from(value)))
+            .collect();
+        Self::try_read(Env {
+            vars: Some(vars),
+            problems: Vec::new(),
+        })
+    }
+
+    fn try_read(mut env: Env) -> Result<Self, Vec<String>> {
+        let config = Self::read(&mut env);
+        match env.problems.is_empty() {
+            true => Ok(config),
+            false => Err(env.problems),
+        }
+    }
+
+    fn read(env: &mut Env) -> Self {
+        Self {
+            admin_token: env.var("ADMIN_TOKEN").filter(|token| !token.is_empty()),
+            read_only: env.flag("READ_ONLY", false),
+            strict_json: env.flag("STRICT_JSON", true),
+            response_envelope: env.parse("RESPONSE_ENVELOPE", EnvelopeMode::Off),
+            deprecations: env.parse("DEPRECATIONS", Deprecations::default()),
+            locales_dir: env.var_os("LOCALES_DIR").map(PathBuf::from),
+            cache_max_age: env.parse("CACHE_MAX_AGE", 5),
+            response_cache: env.flag("RESPONSE_CACHE", false),
+            response_cache_capacity: env.parse("RESPONSE_CACHE_CAPACITY", 10_000),
+            response_cache_ttl: env.parse("RESPONSE_CACHE_TTL", 30),
+            single_flight: env.flag("SINGLE_FLIGHT", true),
+            undo_window: env.parse("UNDO_WINDOW", 30),
+            quicklist_ttl: env.parse("QUICKLIST_TTL", 7 * 24 * 3600),
+            default_page_size: env.parse("DEFAULT_PAGE_SIZE", 50),
+            max_page_size: env.parse("MAX_PAGE_SIZE", 500),
+            max_search_terms: env.parse("MAX_SEARCH_TERMS", 10),
+            max_search_cost: env.parse("MAX_SEARCH_COST", 100_000),
+            backup_dir: env.var_os("BACKUP_DIR").map(PathBuf::from),
+            backup_interval: env.parse("BACKUP_INTERVAL", 300),
+            backup_keep: env.parse("BACKUP_KEEP", 24),
+            slo_target: env.parse("SLO_TARGET", 0.99),
+            slo_latency_ms: env.parse("SLO_LATENCY_MS", 300),
+            metrics_tenant_header: env.optional("METRICS_TENANT_HEADER"),
+            metrics_tenants: env
+                .var("METRICS_TENANTS")
+                .map(|tenants| {
+                    tenants
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|tenant| !tenant.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            metrics_max_tenants: env.parse("METRICS_MAX_TENANTS", 50),
+            rate_limit_plans: env.parse("RATE_LIMIT_PLANS", RatePlans::default()),
+            rate_limit_tenant_header: env.optional("RATE_LIMIT_TENANT_HEADER"),
+            tenant_secret: env.var("TENANT_SECRET").filter(|secret| !secret.is_empty()),
+            metering_interval: env.parse("METERING_INTERVAL", 3600),
+            heartbeat_url: env.optional("HEARTBEAT_URL"),
+            heartbeat_interval: env.parse("HEARTBEAT_INTERVAL", 300),
+            db_stats_interval: env.parse("DB_STATS_INTERVAL", 300),
+            pool_min_idle: env.parse("POOL_MIN_IDLE", 2),
+            pool_ping_interval: env.parse("POOL_PING_INTERVAL", 30),
+            statement_cache_capacity: env.parse("STATEMENT_CACHE_CAPACITY", 100),
+            statement_budget: env.parse("STATEMENT_BUDGET", 50),
+            statement_budget_strict: env.flag("STATEMENT_BUDGET_STRICT", false),
+            stale_after_days: env.parse("STALE_AFTER_DAYS", 30),
+            status_transitions: env.parse("STATUS_TRANSITIONS", StatusTransitions::default()),
+            body_policy: BodyPolicy {
+                max_chars: env.parse("BODY_MAX_CHARS", BodyPolicy::default().max_chars),
+                normalize: env.flag("BODY_NFC", true),
+                strip_control: env.flag("BODY_STRIP_CONTROL", true),
+            },
+            text_pipeline: env.parse("TEXT_PIPELINE", Pipeline::default()),
+            link_previews: env.flag("LINK_PREVIEWS", false),
+            link_preview_ttl: env.parse("LINK_PREVIEW_TTL", 86400),
+            shard_dir: env.var_os("SHARD_DIR").map(PathBuf::from),
+            shard_max_open: env.parse("SHARD_MAX_OPEN", 100),
+            export_dir: env
+                .var_os("EXPORT_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("todo-exports")),
+            proxy_protocol: env.flag("PROXY_PROTOCOL", false),
+            http2: env.flag("HTTP2", false),
+            http2_max_concurrent_streams: env.parse("HTTP2_MAX_CONCURRENT_STREAMS", 200),
+            http2_keep_alive_interval: env.parse("HTTP2_KEEP_ALIVE_INTERVAL", 0),
+            http2_keep_alive_timeout: env.parse("HTTP2_KEEP_ALIVE_TIMEOUT", 20),
+            http1_keep_alive: env.flag("HTTP1_KEEP_ALIVE", true),
+            header_read_timeout: env.parse("HEADER_READ_TIMEOUT", 30),
+            tcp_nodelay: env.flag("TCP_NODELAY", false),
+            outbound_timeout_ms: env.parse("OUTBOUND_TIMEOUT_MS", 10_000),
+            outbound_timeouts: env.parse("OUTBOUND_TIMEOUTS", DestinationTimeouts::default()),
+            outbound_max_attempts: env.parse("OUTBOUND_MAX_ATTEMPTS", 3),
+            outbound_retry_base_ms: env.parse("OUTBOUND_RETRY_BASE_MS", 200),
+            outbound_retry_max_ms: env.parse("OUTBOUND_RETRY_MAX_MS", 10_000),
+            hashids_salt: env.var("HASHIDS_SALT").filter(|salt| !salt.is_empty()),
+            hashids_min_length: env.parse("HASHIDS_MIN_LENGTH", 8),
+            notify_routes: env.parse("NOTIFY_ROUTES", NotificationRoutes::default()),
+            notify_webhook_url: env.optional("NOTIFY_WEBHOOK_URL"),
+            notify_slack_webhook_url: env.optional("NOTIFY_SLACK_WEBHOOK_URL"),
+            notify_email_from: env.optional("NOTIFY_EMAIL_FROM"),
+            notify_email_to: env.optional("NOTIFY_EMAIL_TO"),
+            notify_sendmail: env
+                .var_os("NOTIFY_SENDMAIL")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/usr/sbin/sendmail")),
+            vapid_private_key: env.optional("VAPID_PRIVATE_KEY"),
+            vapid_subject: env.parse("VAPID_SUBJECT", "mailto:admin@localhost".to_string()),
+            report_schedule: env.parse("REPORT_SCHEDULE", ReportSchedules::default()),
+        }
+    }
+}
+
+// Reads environment variables, noting the ones that are set but can't be parsed. Variables come
+// from the process's environment unless they were given.
+#[derive(Default)]
+struct Env {
+    vars: Option<HashMap<String, OsString>>,
+    problems: Vec<String>,
+}
+
+impl Env {
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => std::env::var_os(name),
+        }
+    }
+
+    // Like std::env::var, variables that aren't valid Unicode count as unset.
+    fn var(&self, name: &str) -> Option<String> {
+        self.var_os(name)?.into_string().ok()
+    }
+
+    // Boolean flags accept the usual spellings. Anything else is a problem, and means false when
+    // problems are ignored; an unset variable gives the default.
+    fn flag(&mut self, name: &str, default: bool) -> bool {
+        let Some(value) = self.var(name) else {
+            return default;
+        };
+        match value.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => {
+                self.problems
+                    .push(format!("{name}: `{value}` should be true or false"));
+                false
+            }
+        }
+    }
+
+    // Parses a variable into any type implementing FromStr, falling back to the default when it's
+    // unset or can't be parsed.
+    fn parse<T: FromStr>(&mut self, name: &str, default: T) -> T
+    where
+        T::Err: Display,
+    {
+        let Some(value) = self.var(name) else {
+            return default;
+        };
+        value.parse().unwrap_or_else(|err| {
+            self.problems
+                .push(format!("{name}: `{value}` is invalid: {err}"));
+            default
+        })
+    }
+
+    // Like parse, for settings without a default. Unset and empty variables give None.
+    fn optional<T: FromStr>(&mut self, name: &str) -> Option<T>
+    where
+        T::Err: Display,
+    {
+        let value = self.var(name).filter(|value| !value.is_empty())?;
+        value
+            .parse()
+            .map_err(|err| {
+                self.problems
+                    .push(format!("{name}: `{value}` is invalid: {err}"));
+            })
+            .ok()
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// The size of the database and the number of rows per table, reported by GET /v1/admin/database
+// and as gauges on /v1/admin/metrics, for capacity planning.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbStats {
+    // The bytes in the database file, including free pages.
+    size_bytes: i64,
+    // The bytes in pages that are free for reuse; VACUUM would give them back.
+    free_bytes: i64,
+    // The bytes in the write-ahead log, which is folded into the database at checkpoints.
+    wal_bytes: u64,
+    tables: BTreeMap<String, i64>,
+    collected_at: NaiveDateTime,
+}
+
+impl DbStats {
+    pub fn size_bytes(&self) -> i64 {
+        self.size_bytes
+    }
+
+    pub fn free_bytes(&self) -> i64 {
+        self.free_bytes
+    }
+
+    pub fn wal_bytes(&self) -> u64 {
+        self.wal_bytes
+    }
+
+    // The number of rows per table.
+    pub fn tables(&self) -> &BTreeMap<String, i64> {
+        &self.tables
+    }
+
+    pub fn collected_at(&self) -> NaiveDateTime {
+        self.collected_at
+    }
+
+    // Counting rows reads every table, so we don't do it on every metrics scrape.
+    pub async fn collect(dbpool: &SqlitePool) -> Result<DbStats, sqlx::Error> {
+        let (size_bytes, free_bytes): (i64, i64) = query_as(
+            "select page_count * page_size, freelist_count * page_size
+             from pragma_page_count(), pragma_freelist_count(), pragma_page_size()",
+        )
+        .fetch_one(dbpool)
+        .await?;
+        // The migrations table is sqlx's own, and sqlite_ tables are SQLite's. Vocabulary tables
+        // are views of a full-text index, and counting their rows would read all of it.
+        let names: Vec<(String,)> = query_as(
+            "select name from sqlite_schema where type = 'table'
+             and name not like 'sqlite_%' and name not like '_sqlx_%'
+             and sql not like '%using fts5vocab%' order by name",
+        )
+        .fetch_all(dbpool)
+        .await?;
+        let mut tables = BTreeMap::new();
+        for (name,) in names {
+            let (rows,): (i64,) = query_as(&format!(
+                "select count(*) from \"{}\"",
+                name.replace('"', "\"\"")
+            ))
+            .fetch_one(dbpool)
+            .await?;
+            tables.insert(name, rows);
+        }
+        let mut wal = dbpool
+            .connect_options()
+            .as_ref()
+            .clone()
+            .get_filename()
+            .into_owned()
+            .into_os_string();
+        wal.push("-wal");
+        let wal_bytes = tokio::fs::metadata(&wal)
+            .await
+            .map_or(0, |metadata| metadata.len());
+        Ok(DbStats {
+            size_bytes,
+            free_bytes,
+            wal_bytes,
+            tables,
+            collected_at: Utc::now().naive_utc(),
+        })
+    }
+}
+
+// The latest statistics, collected in the background.
+#[derive(Default)]
+pub struct DbStatsMonitor {
+    latest: RwLock<Option<DbStats>>,
+}
+
+impl DbStatsMonitor {
+    pub fn latest(&self) -> Option<DbStats> {
+        self.latest
+            .read()
+            .expect("database stats lock poisoned")
+            .clone()
+    }
+
+    fn set(&self, stats: DbStats) {
+        *self.latest.write().expect("database stats lock poisoned") = Some(stats);
+    }
+
+    // The latest statistics, collecting them first if that hasn't happened yet.
+    pub async fn get(&self, dbpool: &SqlitePool) -> Result<DbStats, sqlx::Error> {
+        if let Some(stats) = self.latest() {
+            return Ok(stats);
+        }
+        let stats = DbStats::collect(dbpool).await?;
+        self.set(stats.clone());
+        Ok(stats)
+    }
+
+    // Starts collecting the statistics every `interval` in the background.
+    pub fn spawn(self: Arc<Self>, dbpool: SqlitePool, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if dbpool.is_closed() {
+                    break;
+                }
+                match DbStats::collect(&dbpool).await {
+                    Ok(stats) => self.set(stats),
+                    Err(err) => tracing::warn!(error = ?err, "failed to collect database stats"),
+                }
+            }
+        });
+    }
+}
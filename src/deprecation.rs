@@ -0,0 +1,267 @@
+use crate::config::Config;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header::LINK;
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{NaiveDate, NaiveTime};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+// A route, or a query parameter of a route, that clients should stop using. Responses to requests
+// using it carry a Deprecation header (RFC 9745) with the date it was deprecated, a Sunset header
+// (RFC 8594) with the date it goes away if there is one, and a Link to what to use instead.
+#[derive(Clone, Debug)]
+pub struct Deprecation {
+    // Every method of the route when None.
+    method: Option<Method>,
+    // The route as it's declared in the router, e.g. /v1/todos/:id.
+    route: String,
+    // Only requests with this query parameter, when set.
+    param: Option<String>,
+    deprecated_on: NaiveDate,
+    sunset_on: Option<NaiveDate>,
+    link: Option<String>,
+}
+
+impl Deprecation {
+    pub fn new(method: Option<Method>, route: impl Into<String>, deprecated_on: NaiveDate) -> Self {
+        Self {
+            method,
+            route: route.into(),
+            param: None,
+            deprecated_on,
+            sunset_on: None,
+            link: None,
+        }
+    }
+
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+
+    pub fn with_sunset(mut self, sunset_on: NaiveDate) -> Self {
+        self.sunset_on = Some(sunset_on);
+        self
+    }
+
+    pub fn with_link(mut self, link: impl Into<String>) -> Self {
+        self.link = Some(link.into());
+        self
+    }
+
+    fn applies(&self, method: &Method, route: &str, query: &str) -> bool {
+        self.method
+            .as_ref()
+            .is_none_or(|deprecated| deprecated == method)
+            && self.route == route
+            && self.param.as_deref().is_none_or(|param| {
+                query
+                    .split('&')
+                    .any(|pair| pair.split('=').next() == Some(param))
+            })
+    }
+}
+
+// Parses one deprecation like "GET /v1/todos/board deprecated=2026-10-01 sunset=2027-04-01
+// link=https://example.com/migrating". The method is optional, and a route ending in ?name
+// deprecates that query parameter rather than the route.
+impl FromStr for Deprecation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut words = value.split_whitespace().peekable();
+        let method = match words.peek() {
+            Some(word) if !word.starts_with('/') => {
+                let method = Method::from_str(&word.to_ascii_uppercase())
+                    .map_err(|_| format!("`{word}` isn't an HTTP method"))?;
+                words.next();
+                Some(method)
+            }
+            _ => None,
+        };
+        let route = words
+            .next()
+            .filter(|route| route.starts_with('/'))
+            .ok_or_else(|| format!("`{value}` should name a route like /v1/todos/:id"))?;
+        let (route, param) = match route.split_once('?') {
+            Some((route, param)) => (route, Some(param.to_string())),
+            None => (route, None),
+        };
+        let date = |date: &str| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format!("`{date}` should be a date like 2026-10-01"))
+        };
+        let (mut deprecated_on, mut sunset_on, mut link) = (None, None, None);
+        for word in words {
+            match word.split_once('=') {
+                Some(("deprecated", on)) => deprecated_on = Some(date(on)?),
+                Some(("sunset", on)) => sunset_on = Some(date(on)?),
+                Some(("link", url)) => {
+                    HeaderValue::from_str(url)
+                        .map_err(|_| format!("`{url}` isn't a valid link"))?;
+                    link = Some(url.to_string());
+                }
+                _ => {
+                    return Err(format!(
+                        "`{word}` should be deprecated=<date>, sunset=<date>, or link=<url>"
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            method,
+            route: route.to_string(),
+            param,
+            deprecated_on: deprecated_on
+                .ok_or_else(|| format!("`{value}` needs a deprecated=<date>"))?,
+            sunset_on,
+            link,
+        })
+    }
+}
+
+// The deprecated routes and parameters, separated by semicolons.
+#[derive(Clone, Debug, Default)]
+pub struct Deprecations {
+    deprecations: Vec<Deprecation>,
+}
+
+impl Deprecations {
+    pub fn with(mut self, deprecation: Deprecation) -> Self {
+        self.deprecations.push(deprecation);
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Deprecation> {
+        self.deprecations.iter()
+    }
+}
+
+impl FromStr for Deprecations {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .split(';')
+            .map(str::trim)
+            .filter(|deprecation| !deprecation.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(|deprecations| Self { deprecations })
+    }
+}
+
+// How often each deprecated route and parameter is still used, so we know when it's safe to remove.
+#[derive(Default)]
+pub struct DeprecationUsage {
+    // Keyed by method, route, and parameter.
+    counts: Mutex<BTreeMap<(String, String, String), u64>>,
+}
+
+impl DeprecationUsage {
+    fn count(&self, method: &Method, deprecation: &Deprecation) {
+        let key = (
+            method.to_string(),
+            deprecation.route.clone(),
+            deprecation.param.clone().unwrap_or_default(),
+        );
+        *self
+            .counts
+            .lock()
+            .expect("deprecation usage lock poisoned")
+            .entry(key)
+            .or_default() += 1;
+    }
+
+    // Renders the counts in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counts = self.counts.lock().expect("deprecation usage lock poisoned");
+        let mut out = String::new();
+        out.push_str(
+            "# HELP deprecated_requests_total Requests using a deprecated route or parameter.\n",
+        );
+        out.push_str("# TYPE deprecated_requests_total counter\n");
+        for ((method, route, param), count) in counts.iter() {
+            writeln!(
+                out,
+                "deprecated_requests_total{{method=\"{method}\",route=\"{route}\",param=\"{param}\"}} {count}"
+            )
+            .ok();
+        }
+        out
+    }
+}
+
+// A middleware which adds the deprecation headers to responses to requests using a deprecated route
+// or parameter, and counts them.
+pub async fn announce(
+    State(config): State<Arc<Config>>,
+    State(usage): State<Arc<DeprecationUsage>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(route) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let method = request.method().clone();
+    let query = request.uri().query().unwrap_or_default();
+    let applying: Vec<&Deprecation> = config
+        .deprecations
+        .iter()
+        .filter(|deprecation| deprecation.applies(&method, route.as_str(), query))
+        .collect();
+    if applying.is_empty() {
+        return next.run(request).await;
+    }
+    for deprecation in &applying {
+        usage.count(&method, deprecation);
+    }
+    // A request can use several deprecated parameters, but a response has one Deprecation and one
+    // Sunset header, so they carry the earliest dates.
+    let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
+    let deprecated_on = applying
+        .iter()
+        .map(|deprecation| deprecation.deprecated_on)
+        .min()
+        .expect("at least one deprecation applies");
+    let sunset_on = applying
+        .iter()
+        .filter_map(|deprecation| deprecation.sunset_on)
+        .min();
+    let links: Vec<String> = applying
+        .iter()
+        .filter_map(|deprecation| deprecation.link.clone())
+        .collect();
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        DEPRECATION,
+        HeaderValue::from_str(&format!("@{}", midnight(deprecated_on).timestamp()))
+            .expect("a timestamp is a valid header value"),
+    );
+    if let Some(sunset_on) = sunset_on {
+        headers.insert(
+            SUNSET,
+            HeaderValue::from_str(
+                &midnight(sunset_on)
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            )
+            .expect("a date is a valid header value"),
+        );
+    }
+    // Links were checked to be valid header values when they were configured.
+    for link in links {
+        if let Ok(value) = HeaderValue::from_str(&format!("<{link}>; rel=\"deprecation\"")) {
+            headers.append(LINK, value);
+        }
+    }
+    response
+}
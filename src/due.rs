@@ -0,0 +1,148 @@
+use chrono::{
+    DateTime, Datelike, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+    Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+// When a due date doesn't include a time, we assume the start of the working day.
+const DEFAULT_TIME: (u32, u32) = (9, 0);
+
+// Parses a due date like "tomorrow 5pm", "next friday", "in 3 days", or "2024-05-01 17:30" relative
+// to `now`, which is in the owner's timezone. RFC 3339 timestamps are accepted as they are. The
+// result is in UTC, which is how we store timestamps.
+pub fn parse(input: &str, now: DateTime<Tz>) -> Option<NaiveDateTime> {
+    let input = input.trim();
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(input) {
+        return Some(timestamp.naive_utc());
+    }
+
+    let lowercase = input.to_lowercase();
+    let words: Vec<&str> = lowercase
+        .split_whitespace()
+        .filter(|word| *word != "at" && *word != "on")
+        .collect();
+    let today = now.date_naive();
+
+    // "in 3 days", "in 2 hours", ... are relative to now rather than to a calendar day.
+    if let ["in", amount, unit] = words.as_slice() {
+        let amount: i64 = amount.parse().ok()?;
+        let offset = match unit.trim_end_matches('s') {
+            "minute" | "min" => Duration::try_minutes(amount)?,
+            "hour" => Duration::try_hours(amount)?,
+            "day" => Duration::try_days(amount)?,
+            "week" => Duration::try_weeks(amount)?,
+            _ => return None,
+        };
+        // Timestamps are stored with second precision.
+        return (now + offset).naive_utc().with_nanosecond(0);
+    }
+
+    let (date, rest) = parse_date(&words, today)?;
+    let time = match rest {
+        [] => None,
+        [time] => Some(parse_time(time)?),
+        // Allows a space between the time and the meridiem, as in "5 pm".
+        [time, meridiem @ ("am" | "pm")] => Some(parse_time(&format!("{time}{meridiem}"))?),
+        _ => return None,
+    };
+
+    let date = match (date, time) {
+        (Some(date), _) => date,
+        // A bare time means its next occurrence: today if it's still ahead of us, tomorrow otherwise.
+        (None, Some(time)) if today.and_time(time) > now.naive_local() => today,
+        (None, Some(_)) => today.succ_opt()?,
+        (None, None) => return None,
+    };
+    let time = time.unwrap_or_else(|| {
+        NaiveTime::from_hms_opt(DEFAULT_TIME.0, DEFAULT_TIME.1, 0).unwrap_or_default()
+    });
+
+    to_utc(now.timezone(), date.and_time(time))
+}
+
+// Parses the leading date words, returning the date (None when there aren't any) and the words
+// left over for the time.
+fn parse_date<'a, 'b>(
+    words: &'a [&'b str],
+    today: NaiveDate,
+) -> Option<(Option<NaiveDate>, &'a [&'b str])> {
+    let (date, consumed) = match words {
+        ["today", ..] => (today, 1),
+        ["tomorrow", ..] => (today.succ_opt()?, 1),
+        ["day", "after", "tomorrow", ..] => (today.checked_add_days(Days::new(2))?, 3),
+        ["next", "week", ..] => (today.checked_add_days(Days::new(7))?, 2),
+        // "next friday" is the first Friday after today, so it's never today itself.
+        ["next", day, ..] => (next_weekday(today, parse_weekday(day)?, false)?, 2),
+        ["this", day, ..] => (next_weekday(today, parse_weekday(day)?, true)?, 2),
+        [word, ..] => {
+            if let Some(weekday) = parse_weekday(word) {
+                (next_weekday(today, weekday, true)?, 1)
+            } else if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+                (date, 1)
+            } else {
+                return Some((None, words));
+            }
+        }
+        [] => return None,
+    };
+    Some((Some(date), &words[consumed..]))
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    // chrono accepts both the full names and the three-letter abbreviations.
+    word.parse().ok()
+}
+
+fn next_weekday(today: NaiveDate, weekday: Weekday, include_today: bool) -> Option<NaiveDate> {
+    let mut days_ahead =
+        (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+    if days_ahead == 0 && !include_today {
+        days_ahead = 7;
+    }
+    today.checked_add_days(Days::new(days_ahead.into()))
+}
+
+// Accepts "17:30", "5pm", "5:30pm", "noon", and "midnight".
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+
+    let (clock, offset) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (word, None)
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        // Without a meridiem, a bare number like "5" is too ambiguous to guess at.
+        None if offset.is_some() => (clock.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+
+    let hour = match offset {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        // 12am is midnight and 12pm is noon.
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+pub fn to_utc(timezone: Tz, local: NaiveDateTime) -> Option<NaiveDateTime> {
+    let resolved = timezone
+        .from_local_datetime(&local)
+        .earliest()
+        // A local time that falls into a DST gap doesn't exist, so we move it past the gap.
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })?;
+    Some(resolved.with_timezone(&Utc).naive_utc())
+}
@@ -0,0 +1,114 @@
+use crate::config::Config;
+use crate::prefer::{prefers, PREFERENCE_APPLIED};
+use crate::trace_context::TraceContext;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// When responses are wrapped in an envelope like
+// {"data": ..., "meta": {"status": 200}, "errors": []}, for gateways that require one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnvelopeMode {
+    // Responses are sent as they are, which is what our own client expects.
+    #[default]
+    Off,
+    // Every response is wrapped.
+    Always,
+    // Responses to requests with a Prefer: envelope header are wrapped.
+    OnRequest,
+}
+
+impl FromStr for EnvelopeMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(EnvelopeMode::Off),
+            "always" => Ok(EnvelopeMode::Always),
+            "on_request" => Ok(EnvelopeMode::OnRequest),
+            _ => Err("expected off, always, or on_request".to_string()),
+        }
+    }
+}
+
+// A middleware which wraps JSON responses and every error in the envelope. A successful response's
+// body becomes data, and an error's becomes the only entry of errors, so clients find the details in
+// the same place whatever produced the error. Responses without a body, like a 204 or a 304, and
+// other formats, like exports and calendar feeds, are sent as they are.
+pub async fn wrap(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    let wanted = match config.response_envelope {
+        EnvelopeMode::Off => false,
+        EnvelopeMode::Always => true,
+        EnvelopeMode::OnRequest => prefers(request.headers(), "envelope"),
+    };
+    if !wanted {
+        return next.run(request).await;
+    }
+    let trace_id = request
+        .extensions()
+        .get::<TraceContext>()
+        .map(|context| context.trace_id().to_string());
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !json && status.is_success() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to read a response to wrap it");
+            return status.into_response();
+        }
+    };
+    if bytes.is_empty() && (status.is_success() || status.is_redirection()) {
+        return Response::from_parts(parts, Body::empty());
+    }
+
+    // JSON bodies are spliced in as they are, which keeps their fields in order.
+    let body = String::from_utf8_lossy(&bytes);
+    let (data, errors) = match (status.is_success(), json) {
+        (true, _) => (body.as_ref(), "[]".to_string()),
+        // Our own errors are already objects with a code and a message. Plain-text ones get a code
+        // from the status.
+        (false, true) => ("null", format!("[{body}]")),
+        (false, false) => {
+            let reason = status.canonical_reason().unwrap_or("error");
+            let message = match body.is_empty() {
+                true => reason.to_lowercase(),
+                false => body.to_string(),
+            };
+            let code = reason.to_lowercase().replace([' ', '-'], "_");
+            let error = json!({"code": code, "message": message});
+            ("null", format!("[{error}]"))
+        }
+    };
+    let mut meta = json!({"status": status.as_u16()});
+    if let Some(trace_id) = trace_id {
+        meta["trace_id"] = Value::String(trace_id);
+    }
+    let envelope = format!(r#"{{"data":{data},"meta":{meta},"errors":{errors}}}"#);
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if config.response_envelope == EnvelopeMode::OnRequest {
+        parts
+            .headers
+            .append(PREFERENCE_APPLIED, HeaderValue::from_static("envelope"));
+    }
+    Response::from_parts(parts, Body::from(envelope))
+}
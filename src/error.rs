@@ -1,5 +1,7 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 #[derive(Debug)]
 pub enum Error {
@@ -7,6 +9,53 @@ pub enum Error {
     Sqlx(StatusCode, String),
     // Error::NotFound is what we'll use to conveniently map response to HTTP 404s.
     NotFound,
+    // Missing or wrong credentials for a protected route.
+    Unauthorized,
+    // The route exists but is disabled, e.g. the admin API without a configured token.
+    Forbidden,
+    // The request itself is invalid, e.g. a malformed JSON body. The details are returned to the
+    // client as a JSON object.
+    BadRequest(StatusCode, RequestError),
+    // The service can't accept the request right now, e.g. writes during maintenance mode.
+    ServiceUnavailable(String),
+}
+
+// Describes what's wrong with a request, so clients don't need to parse free-form text.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestError {
+    // A stable, machine-readable error code such as "malformed_json".
+    code: Cow<'static, str>,
+    message: String,
+    // The path to the offending field, e.g. "changes[1].base_version", when we know it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+}
+
+impl RequestError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code: Cow::Borrowed(code),
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    pub fn with_field(mut self, field: impl Into<String>) -> Self {
+        self.field = Some(field.into());
+        self
+    }
 }
 
 impl From<sqlx::Error> for Error {
@@ -31,6 +80,12 @@ impl IntoResponse for Error {
             Error::Sqlx(code, body) => (code, body).into_response(),
             // Call into_response() on StatusCode::NOT_FOUND, which gives us an empty HTTP 404 response
             Error::NotFound => StatusCode::NOT_FOUND.into_response(),
+            Error::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
+            Error::Forbidden => StatusCode::FORBIDDEN.into_response(),
+            Error::BadRequest(code, error) => (code, axum::Json(error)).into_response(),
+            Error::ServiceUnavailable(message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, message).into_response()
+            }
         }
     }
 }
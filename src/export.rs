@@ -0,0 +1,180 @@
+use crate::error::Error;
+use crate::ids;
+use crate::preferences::Preferences;
+use crate::status::TodoStatus;
+use crate::todo::Todo;
+use axum::body::{Body, Bytes};
+use chrono::{NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use futures_util::{stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+use std::fmt::Write;
+use tokio::sync::mpsc;
+
+// The plain-text formats todos can be exported in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    // GitHub-flavored task lists: "- [ ] body".
+    Markdown,
+    // Emacs org-mode headlines: "** TODO body".
+    Org,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    format: ExportFormat,
+}
+
+impl ExportQuery {
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+            ExportFormat::Org => "text/org; charset=utf-8",
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "todos.md",
+            ExportFormat::Org => "todos.org",
+        }
+    }
+}
+
+// Renders every todo that isn't archived as a checklist, with a section per status. Due dates are
+// shown in the owner's timezone, since the export is meant to be read by people.
+pub async fn render(dbpool: SqlitePool, format: ExportFormat) -> Result<String, Error> {
+    let timezone = Preferences::read(dbpool.clone()).await?.timezone();
+    let todos: Vec<Todo> = query_as("select * from todos where not archived order by id")
+        .fetch_all(&dbpool)
+        .await?;
+
+    let mut out = String::new();
+    match format {
+        ExportFormat::Markdown => out.push_str("# Todos\n"),
+        ExportFormat::Org => out.push_str("#+TITLE: Todos\n"),
+    }
+    for status in TodoStatus::ALL {
+        let section: Vec<&Todo> = todos
+            .iter()
+            .filter(|todo| todo.status() == status)
+            .collect();
+        if section.is_empty() {
+            continue;
+        }
+        match format {
+            ExportFormat::Markdown => writeln!(out, "\n## {}\n", heading(status)).ok(),
+            ExportFormat::Org => writeln!(out, "\n* {}", heading(status)).ok(),
+        };
+        for todo in section {
+            match format {
+                ExportFormat::Markdown => markdown_item(&mut out, todo, timezone),
+                ExportFormat::Org => org_item(&mut out, todo, timezone),
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn heading(status: TodoStatus) -> &'static str {
+    match status {
+        TodoStatus::Backlog => "Backlog",
+        TodoStatus::InProgress => "In progress",
+        TodoStatus::Blocked => "Blocked",
+        TodoStatus::Done => "Done",
+    }
+}
+
+fn local(timezone: Tz, utc: NaiveDateTime) -> NaiveDateTime {
+    timezone.from_utc_datetime(&utc).naive_local()
+}
+
+pub(crate) fn markdown_item(out: &mut String, todo: &Todo, timezone: Tz) {
+    let mut lines = todo.body().lines();
+    let checkbox = if todo.completed() { "x" } else { " " };
+    write!(out, "- [{checkbox}] {}", lines.next().unwrap_or_default()).ok();
+    if let Some(due_at) = todo.due_at() {
+        write!(
+            out,
+            " (due {})",
+            local(timezone, due_at).format("%Y-%m-%d %H:%M")
+        )
+        .ok();
+    }
+    out.push('\n');
+    // Indenting the rest of the body keeps it inside the list item.
+    for line in lines {
+        indented(out, "      ", line);
+    }
+}
+
+fn org_item(out: &mut String, todo: &Todo, timezone: Tz) {
+    let mut lines = todo.body().lines();
+    let keyword = if todo.completed() { "DONE" } else { "TODO" };
+    writeln!(out, "** {keyword} {}", lines.next().unwrap_or_default()).ok();
+    if let Some(due_at) = todo.due_at() {
+        writeln!(
+            out,
+            "   DEADLINE: <{}>",
+            local(timezone, due_at).format("%Y-%m-%d %a %H:%M")
+        )
+        .ok();
+    }
+    // Headlines have to start at the beginning of a line, so the indented body can't contain any.
+    for line in lines {
+        indented(out, "   ", line);
+    }
+}
+
+// Writes a line of a multi-line body, leaving blank lines without trailing whitespace.
+fn indented(out: &mut String, indent: &str, line: &str) {
+    if !line.trim().is_empty() {
+        out.push_str(indent);
+        out.push_str(line);
+    }
+    out.push('\n');
+}
+
+// Streams every todo, archived ones included, as newline-delimited JSON. Rows are sent as they're
+// read, so memory use doesn't grow with the number of todos, and the bounded channel stops us from
+// reading ahead of a slow client. A database error halfway through can't change the status any more,
+// so it aborts the response instead, which the client sees as a truncated transfer.
+pub fn ndjson(dbpool: SqlitePool) -> Body {
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, sqlx::Error>>(64);
+    tokio::spawn(ids::inherit(async move {
+        let mut rows = query_as::<_, Todo>("select * from todos order by id").fetch(&dbpool);
+        loop {
+            let line = match rows.try_next().await {
+                Ok(Some(todo)) => Ok(ndjson_line(&todo)),
+                Ok(None) => break,
+                Err(err) => {
+                    tracing::warn!(error = %err, "NDJSON export failed");
+                    Err(err)
+                }
+            };
+            let failed = line.is_err();
+            // When the client goes away, there's no one left to send the rest to.
+            if sender.send(line).await.is_err() || failed {
+                break;
+            }
+        }
+    }));
+    Body::from_stream(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|line| (line, receiver))
+    }))
+}
+
+// One line of an NDJSON export.
+pub fn ndjson_line(todo: &Todo) -> Bytes {
+    let mut line = serde_json::to_vec(todo).expect("a todo always serializes to JSON");
+    line.push(b'\n');
+    Bytes::from(line)
+}
@@ -0,0 +1,261 @@
+use crate::error::Error;
+use crate::export::{self, ExportFormat};
+use crate::ids;
+use crate::todo::Todo;
+use axum::body::Body;
+use chrono::NaiveDateTime;
+use futures_util::{stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+
+// The formats an export job can produce.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ExportJobFormat {
+    Ndjson,
+    Markdown,
+    Org,
+}
+
+impl ExportJobFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportJobFormat::Ndjson => "ndjson",
+            ExportJobFormat::Markdown => "md",
+            ExportJobFormat::Org => "org",
+        }
+    }
+
+    // The plain-text format rendered by the synchronous export, or None for NDJSON.
+    fn text_format(self) -> Option<ExportFormat> {
+        match self {
+            ExportJobFormat::Ndjson => None,
+            ExportJobFormat::Markdown => Some(ExportFormat::Markdown),
+            ExportJobFormat::Org => Some(ExportFormat::Org),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        self.text_format()
+            .map_or("application/x-ndjson", ExportFormat::content_type)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ExportJobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateExportJob {
+    format: ExportJobFormat,
+}
+
+impl CreateExportJob {
+    pub fn new(format: ExportJobFormat) -> Self {
+        Self { format }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct ExportJob {
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    format: ExportJobFormat,
+    status: ExportJobStatus,
+    // The number of todos exported, once the job has succeeded.
+    rows: Option<i64>,
+    error: Option<String>,
+    created_at: NaiveDateTime,
+    finished_at: Option<NaiveDateTime>,
+    // Where to download the export from, once the job has succeeded.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_path: Option<String>,
+}
+
+impl ExportJob {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn format(&self) -> ExportJobFormat {
+        self.format
+    }
+
+    pub fn status(&self) -> ExportJobStatus {
+        self.status
+    }
+
+    pub fn rows(&self) -> Option<i64> {
+        self.rows
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn download_path(&self) -> Option<&str> {
+        self.download_path.as_deref()
+    }
+
+    fn with_download_path(mut self) -> Self {
+        if self.status == ExportJobStatus::Succeeded {
+            self.download_path = Some(format!("/v1/exports/{}/download", self.id));
+        }
+        self
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("export-{}.{}", self.id, self.format.extension())
+    }
+
+    fn file(&self, dir: &Path) -> PathBuf {
+        dir.join(self.file_name())
+    }
+
+    // Queues an export and starts building it in the background. Clients poll the job until it has
+    // succeeded or failed.
+    pub async fn create(
+        dbpool: SqlitePool,
+        dir: PathBuf,
+        request: CreateExportJob,
+    ) -> Result<ExportJob, Error> {
+        let job: ExportJob = query_as("insert into export_jobs (format) values (?) returning *")
+            .bind(request.format)
+            .fetch_one(&dbpool)
+            .await?;
+        tokio::spawn(ids::inherit(job.clone().run(dbpool, dir)));
+        Ok(job)
+    }
+
+    pub async fn read(dbpool: SqlitePool, id: i64) -> Result<ExportJob, Error> {
+        query_as("select * from export_jobs where id = ?")
+            .bind(id)
+            .fetch_one(&dbpool)
+            .await
+            .map(ExportJob::with_download_path)
+            .map_err(Into::into)
+    }
+
+    // Jobs only run inside the process that queued them, so any job still queued or running when
+    // the service starts was cut short by a restart. They're marked as failed, so clients polling
+    // them don't wait forever.
+    pub async fn fail_interrupted(dbpool: SqlitePool, started_at: NaiveDateTime) {
+        let result = query(
+            "update export_jobs set status = 'failed', error = 'interrupted by a restart', finished_at = datetime('now')
+             where status in ('queued', 'running') and created_at < ?",
+        )
+        .bind(started_at)
+        .execute(&dbpool)
+        .await;
+        if let Err(err) = result {
+            tracing::warn!(error = %err, "failed to clean up interrupted export jobs");
+        }
+    }
+
+    async fn run(self, dbpool: SqlitePool, dir: PathBuf) {
+        let result = query("update export_jobs set status = 'running' where id = ?")
+            .bind(self.id)
+            .execute(&dbpool)
+            .await
+            .map_err(|err| err.to_string());
+        let result = match result {
+            Ok(_) => self.write(&dbpool, &dir).await,
+            Err(err) => Err(err),
+        };
+
+        let (status, rows, error) = match result {
+            Ok(rows) => (ExportJobStatus::Succeeded, Some(rows), None),
+            Err(err) => {
+                tracing::warn!(job = self.id, error = %err, "export job failed");
+                // Don't leave a partial file behind.
+                tokio::fs::remove_file(self.file(&dir)).await.ok();
+                (ExportJobStatus::Failed, None, Some(err))
+            }
+        };
+        let result = query(
+            "update export_jobs set status = ?, rows = ?, error = ?, finished_at = datetime('now') where id = ?",
+        )
+        .bind(status)
+        .bind(rows)
+        .bind(error)
+        .bind(self.id)
+        .execute(&dbpool)
+        .await;
+        if let Err(err) = result {
+            tracing::warn!(job = self.id, error = %err, "failed to record export job result");
+        }
+    }
+
+    // Writes the export file, returning the number of todos in it.
+    async fn write(&self, dbpool: &SqlitePool, dir: &Path) -> Result<i64, String> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|err| format!("can't create {}: {err}", dir.display()))?;
+        let path = self.file(dir);
+        let file = tokio::fs::File::create(&path)
+            .await
+            .map_err(|err| format!("can't create {}: {err}", path.display()))?;
+        let mut file = BufWriter::new(file);
+        let write_error = |err: std::io::Error| format!("can't write {}: {err}", path.display());
+
+        let rows = match self.format.text_format() {
+            // Like the streamed NDJSON export, rows are written as they're read.
+            None => {
+                let mut rows = query_as::<_, Todo>("select * from todos order by id").fetch(dbpool);
+                let mut count = 0;
+                while let Some(todo) = rows.try_next().await.map_err(|err| err.to_string())? {
+                    file.write_all(&export::ndjson_line(&todo))
+                        .await
+                        .map_err(write_error)?;
+                    count += 1;
+                }
+                count
+            }
+            Some(format) => {
+                let text = export::render(dbpool.clone(), format)
+                    .await
+                    .map_err(|err| format!("{err:?}"))?;
+                file.write_all(text.as_bytes()).await.map_err(write_error)?;
+                let (count,): (i64,) = query_as("select count(*) from todos where not archived")
+                    .fetch_one(dbpool)
+                    .await
+                    .map_err(|err| err.to_string())?;
+                count
+            }
+        };
+        file.flush().await.map_err(write_error)?;
+        Ok(rows)
+    }
+
+    // Streams the finished export file. Jobs that haven't succeeded have nothing to download.
+    pub async fn download(
+        dbpool: SqlitePool,
+        dir: &Path,
+        id: i64,
+    ) -> Result<(ExportJob, Body), Error> {
+        let job = Self::read(dbpool, id).await?;
+        if job.status != ExportJobStatus::Succeeded {
+            return Err(Error::NotFound);
+        }
+        let file = tokio::fs::File::open(job.file(dir))
+            .await
+            .map_err(|_| Error::NotFound)?;
+        let body = Body::from_stream(stream::try_unfold(file, |mut file| async move {
+            let mut chunk = vec![0; 64 * 1024];
+            let read = file.read(&mut chunk).await?;
+            chunk.truncate(read);
+            Ok::<_, std::io::Error>((read > 0).then_some((chunk, file)))
+        }));
+        Ok((job, body))
+    }
+}
@@ -0,0 +1,192 @@
+use crate::config::Config;
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRef, FromRequest, FromRequestParts, Request};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+// A drop-in replacement for axum's Json. Invalid request bodies are rejected with a structured error
+// naming the offending field, and unknown fields are rejected when strict JSON is enabled in the
+// config. As a response it behaves exactly like axum::Json.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+
+        check_content_type(req.headers()).map_err(IntoResponse::into_response)?;
+
+        // Failing to read the body (e.g. because it's too large) keeps axum's own response.
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        // We parse the JSON syntax first, so malformed bodies get a 400 while well-formed bodies
+        // with the wrong shape get a 422.
+        let value: serde_json::Value = serde_json::from_slice(&bytes).map_err(|err| {
+            Error::BadRequest(
+                StatusCode::BAD_REQUEST,
+                RequestError::new(
+                    "malformed_json",
+                    i18n::message("malformed_json", &[("detail", &err.to_string())]),
+                ),
+            )
+            .into_response()
+        })?;
+
+        let mut unknown = Vec::new();
+        let parsed = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+            value,
+            &mut |path: serde_ignored::Path| unknown.push(path.to_string()),
+        ));
+
+        // An unknown field is checked before any other error, because a typo like "bodyy" is the
+        // likely reason a required field is missing.
+        if config.strict_json {
+            if let Some(field) = unknown.first() {
+                return Err(Error::BadRequest(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    RequestError::new(
+                        "unknown_field",
+                        i18n::message("unknown_field", &[("field", field)]),
+                    )
+                    .with_field(field.clone()),
+                )
+                .into_response());
+            }
+        }
+
+        let parsed = parsed.map_err(|err| {
+            let path = err.path().to_string();
+            let detail = err.into_inner().to_string();
+            let mut error = RequestError::new(
+                "invalid_body",
+                i18n::message("invalid_body", &[("detail", &detail)]),
+            );
+            // The root of the document is reported as ".", which doesn't help anyone.
+            if path != "." {
+                error = error.with_field(path);
+            }
+            Error::BadRequest(StatusCode::UNPROCESSABLE_ENTITY, error).into_response()
+        })?;
+
+        Ok(Json(parsed))
+    }
+}
+
+// A replacement for axum's Query, which rejects query strings that don't fit the expected type with
+// a structured 400 naming the offending parameter.
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+
+        serde_path_to_error::deserialize(deserializer)
+            .map(Query)
+            .map_err(|err| {
+                let path = err.path().to_string();
+                let detail = err.into_inner().to_string();
+                let mut error = RequestError::new(
+                    "invalid_param",
+                    i18n::message("invalid_param", &[("detail", &detail)]),
+                );
+                // Missing parameters are reported at the root, which is ".".
+                if path != "." {
+                    error = error.with_field(path);
+                }
+                Error::BadRequest(StatusCode::BAD_REQUEST, error)
+            })
+    }
+}
+
+// Checks that the body of a write request, if it has one, is labeled as JSON. Handlers taking a Json
+// body get the check from Json; write handlers without a body take JsonContent, so a payload sent
+// to them gets a clear 415 instead of being ignored or tripping over an unrelated error.
+pub struct JsonContent;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for JsonContent {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let has_body = parts.headers.contains_key(TRANSFER_ENCODING)
+            || parts
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|length| length.trim() != "0");
+        match has_body {
+            true => check_content_type(&parts.headers).map(|_| JsonContent),
+            false => Ok(JsonContent),
+        }
+    }
+}
+
+// A 415 naming the Content-Type we got, since a mislabeled payload is easy to miss on the client.
+fn check_content_type(headers: &HeaderMap) -> Result<(), Error> {
+    if has_json_content_type(headers) {
+        return Ok(());
+    }
+    let message = match headers.get(CONTENT_TYPE).map(|value| value.to_str()) {
+        Some(Ok(content_type)) => {
+            i18n::message("unsupported_media_type", &[("content_type", content_type)])
+        }
+        Some(Err(_)) => i18n::message("unsupported_media_type", &[("content_type", "?")]),
+        None => i18n::message("missing_content_type", &[]),
+    };
+    Err(Error::BadRequest(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        RequestError::new("unsupported_media_type", message),
+    ))
+}
+
+// Accepts application/json as well as structured syntax suffixes like application/merge-patch+json.
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim().to_ascii_lowercase();
+            mime == "application/json"
+                || (mime.starts_with("application/") && mime.ends_with("+json"))
+        })
+        .unwrap_or(false)
+}
+
+impl<T> From<T> for Json<T> {
+    fn from(value: T) -> Self {
+        Json(value)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
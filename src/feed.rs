@@ -0,0 +1,197 @@
+use crate::error::Error;
+use crate::ids;
+use crate::tenant::Tenant;
+use crate::todo::Todo;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+
+// A secret token giving calendar apps read access to the due dates of the todos, without the
+// credentials the rest of the API may need.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct FeedToken {
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    token: String,
+    created_at: NaiveDateTime,
+    // The path of the feed, for pasting into a calendar app's subscription dialog.
+    #[sqlx(skip)]
+    path: String,
+}
+
+// In database-per-tenant mode, a feed's path names the tenant before the token, separated by this,
+// e.g. /feeds/acme~0f3a.../todos.ics. Calendar apps can't send the tenant header, so that's how
+// shard::dispatch finds the shard the token is in. Tenant names can't contain it.
+pub const TENANT_SEPARATOR: char = '~';
+
+// SQLite's randomblob() draws from its cryptographically strong generator, which is seeded from the
+// operating system, so we don't need a random number crate for the tokens.
+const NEW_TOKEN: &str = "lower(hex(randomblob(20)))";
+
+impl FeedToken {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn created_at(&self) -> NaiveDateTime {
+        self.created_at
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // The tenant is the one whose shard the token is in, in database-per-tenant mode.
+    fn with_path(mut self, tenant: Option<&Tenant>) -> Self {
+        self.path = match tenant {
+            Some(tenant) => format!(
+                "/feeds/{}{TENANT_SEPARATOR}{}/todos.ics",
+                tenant.as_str(),
+                self.token
+            ),
+            None => format!("/feeds/{}/todos.ics", self.token),
+        };
+        self
+    }
+
+    pub async fn list(
+        dbpool: SqlitePool,
+        tenant: Option<&Tenant>,
+    ) -> Result<Vec<FeedToken>, Error> {
+        query_as("select * from feed_tokens order by id")
+            .fetch_all(&dbpool)
+            .await
+            .map(|tokens: Vec<FeedToken>| {
+                tokens
+                    .into_iter()
+                    .map(|token| token.with_path(tenant))
+                    .collect()
+            })
+            .map_err(Into::into)
+    }
+
+    pub async fn create(dbpool: SqlitePool, tenant: Option<&Tenant>) -> Result<FeedToken, Error> {
+        query_as(&format!(
+            "insert into feed_tokens (token) values ({NEW_TOKEN}) returning *"
+        ))
+        .fetch_one(&dbpool)
+        .await
+        .map(|token: FeedToken| token.with_path(tenant))
+        .map_err(Into::into)
+    }
+
+    // Replaces the token with a new one. Calendars subscribed with the old one stop getting updates.
+    pub async fn rotate(
+        dbpool: SqlitePool,
+        tenant: Option<&Tenant>,
+        id: i64,
+    ) -> Result<FeedToken, Error> {
+        query_as(&format!(
+            "update feed_tokens set token = {NEW_TOKEN}, created_at = datetime('now') where id = ? returning *"
+        ))
+        .bind(id)
+        .fetch_one(&dbpool)
+        .await
+        .map(|token: FeedToken| token.with_path(tenant))
+        .map_err(Into::into)
+    }
+
+    pub async fn revoke(dbpool: SqlitePool, id: i64) -> Result<(), Error> {
+        let result = query("delete from feed_tokens where id = ?")
+            .bind(id)
+            .execute(&dbpool)
+            .await?;
+        match result.rows_affected() {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Renders the open todos with a due date as an iCalendar feed, after checking the token. Unknown
+// and revoked tokens get a 404, so they can't be told apart from a wrong path.
+pub async fn calendar(dbpool: SqlitePool, token: &str) -> Result<String, Error> {
+    query("select 1 from feed_tokens where token = ?")
+        .bind(token)
+        .fetch_one(&dbpool)
+        .await?;
+
+    let todos: Vec<Todo> = query_as(
+        "select * from todos where due_at is not null and not completed and not archived
+         order by due_at, id",
+    )
+    .fetch_all(&dbpool)
+    .await?;
+
+    let stamp = format_utc(Utc::now().naive_utc());
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//todo-api-service//todos//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:Todos".to_string(),
+    ];
+    for todo in todos {
+        let Some(due_at) = todo.due_at() else {
+            continue;
+        };
+        // Calendar apps generally ignore VTODO components, so each todo is a zero-length event
+        // at its due time.
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:todo-{}@todo-api-service", ids::encode(todo.id())),
+            format!("DTSTAMP:{stamp}"),
+            format!("DTSTART:{}", format_utc(due_at)),
+            format!("SUMMARY:{}", escape_text(todo.body())),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    Ok(lines
+        .iter()
+        .map(|line| fold(line))
+        .collect::<Vec<_>>()
+        .join(""))
+}
+
+fn format_utc(timestamp: NaiveDateTime) -> String {
+    timestamp.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// Escapes the characters that are special in iCalendar text values (RFC 5545, section 3.3.11).
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Terminates a content line with CRLF, folding it so no physical line is longer than 75 octets.
+// Continuation lines start with a space, and we never split a UTF-8 character.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
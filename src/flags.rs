@@ -0,0 +1,216 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// How long a lookup may serve flags read earlier. Writes through the admin API take effect
+// immediately in this process; other processes pick them up within this time.
+const CACHE_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct FeatureFlag {
+    name: String,
+    enabled: bool,
+    // The share of subjects the flag is on for, from 0 to 100, while it's enabled.
+    rollout_percent: i64,
+    updated_at: NaiveDateTime,
+}
+
+impl FeatureFlag {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn rollout_percent(&self) -> i64 {
+        self.rollout_percent
+    }
+
+    // Whether the flag is on for a subject, such as a client ID. Each subject falls into a fixed
+    // bucket per flag, so raising the percentage only ever adds subjects. Without a subject, a flag
+    // is only on once it's rolled out to everyone.
+    pub fn is_on_for(&self, subject: Option<&str>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        match subject {
+            _ if self.rollout_percent >= 100 => true,
+            Some(subject) => bucket(&self.name, subject) < self.rollout_percent,
+            None => false,
+        }
+    }
+}
+
+// A subject's bucket for a flag, from 0 to 99. We use FNV-1a rather than the standard library's
+// hasher, whose output isn't guaranteed to stay the same across Rust versions.
+fn bucket(flag: &str, subject: &str) -> i64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in flag.bytes().chain([b':']).chain(subject.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % 100) as i64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateFeatureFlag {
+    enabled: bool,
+    #[serde(default = "full_rollout")]
+    rollout_percent: i64,
+}
+
+fn full_rollout() -> i64 {
+    100
+}
+
+impl UpdateFeatureFlag {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            rollout_percent: full_rollout(),
+        }
+    }
+
+    pub fn with_rollout_percent(mut self, percent: i64) -> Self {
+        self.rollout_percent = percent;
+        self
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FlagsQuery {
+    // Who the flags are evaluated for, e.g. a client or installation ID.
+    subject: Option<String>,
+}
+
+impl FlagsQuery {
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+}
+
+// The flags the service and its embedders check at runtime. Lookups are served from an in-process
+// copy of the flags table, so checking a flag on every request is cheap.
+pub struct FeatureFlags {
+    cache: Cache<(), Arc<HashMap<String, FeatureFlag>>>,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(1)
+                .time_to_live(CACHE_TTL)
+                .build(),
+        }
+    }
+}
+
+impl FeatureFlags {
+    // Whether a flag is on for a subject. Unknown flags are off, and so is every flag when they
+    // can't be read, so a database hiccup falls back to the safe behavior.
+    pub async fn is_enabled(&self, dbpool: &SqlitePool, name: &str, subject: Option<&str>) -> bool {
+        match self.all(dbpool).await {
+            Ok(flags) => flags.get(name).is_some_and(|flag| flag.is_on_for(subject)),
+            Err(err) => {
+                tracing::warn!(flag = name, error = ?err, "failed to read feature flags");
+                false
+            }
+        }
+    }
+
+    // The names of the flags that are on for a subject, for clients that adapt their UI.
+    pub async fn enabled_for(
+        &self,
+        dbpool: &SqlitePool,
+        subject: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        let flags = self.all(dbpool).await?;
+        let mut names: Vec<String> = flags
+            .values()
+            .filter(|flag| flag.is_on_for(subject))
+            .map(|flag| flag.name.clone())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub async fn list(&self, dbpool: &SqlitePool) -> Result<Vec<FeatureFlag>, Error> {
+        let flags = self.all(dbpool).await?;
+        let mut flags: Vec<FeatureFlag> = flags.values().cloned().collect();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(flags)
+    }
+
+    pub async fn set(
+        &self,
+        dbpool: &SqlitePool,
+        name: &str,
+        update: UpdateFeatureFlag,
+    ) -> Result<FeatureFlag, Error> {
+        if !(0..=100).contains(&update.rollout_percent) {
+            return Err(Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "invalid_rollout",
+                    i18n::message(
+                        "invalid_rollout",
+                        &[("percent", &update.rollout_percent.to_string())],
+                    ),
+                )
+                .with_field("rollout_percent"),
+            ));
+        }
+        let flag = query_as(
+            "insert into feature_flags (name, enabled, rollout_percent) values (?, ?, ?)
+             on conflict (name) do update set enabled = excluded.enabled,
+             rollout_percent = excluded.rollout_percent, updated_at = datetime('now')
+             returning *",
+        )
+        .bind(name)
+        .bind(update.enabled)
+        .bind(update.rollout_percent)
+        .fetch_one(dbpool)
+        .await?;
+        self.cache.invalidate_all();
+        Ok(flag)
+    }
+
+    pub async fn delete(&self, dbpool: &SqlitePool, name: &str) -> Result<(), Error> {
+        let result = query("delete from feature_flags where name = ?")
+            .bind(name)
+            .execute(dbpool)
+            .await?;
+        self.cache.invalidate_all();
+        match result.rows_affected() {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn all(&self, dbpool: &SqlitePool) -> Result<Arc<HashMap<String, FeatureFlag>>, Error> {
+        if let Some(flags) = self.cache.get(&()) {
+            return Ok(flags);
+        }
+        let flags: Vec<FeatureFlag> = query_as("select * from feature_flags")
+            .fetch_all(dbpool)
+            .await?;
+        let flags = Arc::new(
+            flags
+                .into_iter()
+                .map(|flag| (flag.name.clone(), flag))
+                .collect::<HashMap<_, _>>(),
+        );
+        self.cache.insert((), flags.clone());
+        Ok(flags)
+    }
+}
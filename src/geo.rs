@@ -0,0 +1,152 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::params::invalid_param;
+use axum::http::StatusCode;
+
+// The mean radius of the Earth. Treating the Earth as a sphere is off by less than a percent, which
+// is plenty for finding todos near a place.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+// Half the Earth's circumference; every point is within this distance.
+const MAX_RADIUS_KM: f64 = 20_000.0;
+pub const DEFAULT_RADIUS_KM: f64 = 10.0;
+// The longest place name we accept, in characters.
+const MAX_PLACE_CHARS: usize = 200;
+
+// Checks the location a client sent with a todo: coordinates come in pairs of latitude and
+// longitude in degrees, as GPS and map apps report them. A place name can come without coordinates.
+pub fn check_location(
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    place: Option<&str>,
+) -> Result<(), Error> {
+    let invalid = |code: &'static str, field: &str, message: String| {
+        Error::BadRequest(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            RequestError::new(code, message).with_field(field),
+        )
+    };
+    match (latitude, longitude) {
+        (Some(_), None) => {
+            return Err(invalid(
+                "incomplete_location",
+                "longitude",
+                i18n::message("incomplete_location", &[]),
+            ))
+        }
+        (None, Some(_)) => {
+            return Err(invalid(
+                "incomplete_location",
+                "latitude",
+                i18n::message("incomplete_location", &[]),
+            ))
+        }
+        _ => {}
+    }
+    if let Some(latitude) = latitude.filter(|latitude| !(-90.0..=90.0).contains(latitude)) {
+        return Err(invalid(
+            "invalid_latitude",
+            "latitude",
+            i18n::message("invalid_latitude", &[("latitude", &latitude.to_string())]),
+        ));
+    }
+    if let Some(longitude) = longitude.filter(|longitude| !(-180.0..=180.0).contains(longitude)) {
+        return Err(invalid(
+            "invalid_longitude",
+            "longitude",
+            i18n::message(
+                "invalid_longitude",
+                &[("longitude", &longitude.to_string())],
+            ),
+        ));
+    }
+    if place.is_some_and(|place| place.chars().count() > MAX_PLACE_CHARS) {
+        return Err(invalid(
+            "place_too_long",
+            "place",
+            i18n::message("place_too_long", &[("max", &MAX_PLACE_CHARS.to_string())]),
+        ));
+    }
+    Ok(())
+}
+
+// The latitudes and longitudes, in degrees, of the smallest box around a circle on the Earth's
+// surface. Filtering on the box is a cheap indexed comparison, at the cost of also matching the
+// corners, which lie up to about 40% further out than the radius.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_latitude: f64,
+    pub max_latitude: f64,
+    // When the box crosses the antimeridian, min_longitude is greater than max_longitude, and the
+    // box covers the longitudes outside of the range between them.
+    pub min_longitude: f64,
+    pub max_longitude: f64,
+}
+
+impl BoundingBox {
+    // Parses the ?near=<latitude>,<longitude> and ?radius_km= list parameters.
+    pub fn from_params(near: &str, radius_km: Option<f64>) -> Result<Self, Error> {
+        let invalid_near = || {
+            invalid_param(
+                "invalid_near",
+                "near",
+                i18n::message("invalid_near", &[("near", near)]),
+            )
+        };
+        let (latitude, longitude) = near.split_once(',').ok_or_else(invalid_near)?;
+        let latitude: f64 = latitude.trim().parse().map_err(|_| invalid_near())?;
+        let longitude: f64 = longitude.trim().parse().map_err(|_| invalid_near())?;
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(invalid_near());
+        }
+        let radius_km = radius_km.unwrap_or(DEFAULT_RADIUS_KM);
+        if !(radius_km > 0.0 && radius_km <= MAX_RADIUS_KM) {
+            return Err(invalid_param(
+                "invalid_radius",
+                "radius_km",
+                i18n::message("invalid_radius", &[("max", &MAX_RADIUS_KM.to_string())]),
+            ));
+        }
+        Ok(Self::around(latitude, longitude, radius_km))
+    }
+
+    // See http://janmatuschek.de/LatitudeLongitudeBoundingCoordinates for the math.
+    pub fn around(latitude: f64, longitude: f64, radius_km: f64) -> Self {
+        let angle = radius_km / EARTH_RADIUS_KM;
+        let min_latitude = latitude - angle.to_degrees();
+        let max_latitude = latitude + angle.to_degrees();
+        // A circle around a pole covers every longitude.
+        if min_latitude <= -90.0 || max_latitude >= 90.0 {
+            return Self {
+                min_latitude: min_latitude.max(-90.0),
+                max_latitude: max_latitude.min(90.0),
+                min_longitude: -180.0,
+                max_longitude: 180.0,
+            };
+        }
+        let delta = (angle.sin() / latitude.to_radians().cos())
+            .asin()
+            .to_degrees();
+        // Close to a pole, the circle can still span every longitude.
+        if delta.is_nan() || delta >= 180.0 {
+            return Self {
+                min_latitude,
+                max_latitude,
+                min_longitude: -180.0,
+                max_longitude: 180.0,
+            };
+        }
+        let (mut min_longitude, mut max_longitude) = (longitude - delta, longitude + delta);
+        if min_longitude < -180.0 {
+            min_longitude += 360.0;
+        }
+        if max_longitude > 180.0 {
+            max_longitude -= 360.0;
+        }
+        Self {
+            min_latitude,
+            max_latitude,
+            min_longitude,
+            max_longitude,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::state::AppState;
+use chrono::Utc;
+use sqlx::query_as;
+use std::fmt::Write;
+
+// Renders the health of the service and its dependencies as Prometheus gauges, to go with the
+// request metrics. Unlike /ready, which is up or down, these let alerts fire on thresholds, such as
+// a backup that's a few hours old or a growing queue of export jobs.
+pub async fn render(state: &AppState) -> String {
+    let mut out = String::new();
+
+    // A query rather than a ping, so a database that accepts connections but can't answer counts as
+    // down. The queue depth comes from the same query.
+    let jobs: Result<(i64,), _> =
+        query_as("select count(*) from export_jobs where status in ('queued', 'running')")
+            .fetch_one(&state.dbpool)
+            .await;
+    gauge(
+        &mut out,
+        "db_up",
+        "Whether the database answers queries.",
+        jobs.is_ok() as u64,
+    );
+    if let Ok((depth,)) = jobs {
+        gauge(
+            &mut out,
+            "job_queue_depth",
+            "Export jobs queued or running.",
+            depth,
+        );
+    }
+    gauge(
+        &mut out,
+        "webhook_backlog",
+        "Outbound calls to webhooks and other integrations in progress, retries included.",
+        state.outbound.in_flight(),
+    );
+
+    // Without sinks there are no backups to be late. Until the first one ships, the backup is as
+    // old as the process.
+    let backups = state.backups.status();
+    if backups.enabled() {
+        let age = match backups.last_success_at() {
+            Some(at) => (Utc::now().naive_utc() - at).num_seconds().max(0) as u64,
+            None => state.started_at.elapsed().as_secs(),
+        };
+        gauge(
+            &mut out,
+            "last_backup_age_seconds",
+            "Seconds since the last backup was shipped.",
+            age,
+        );
+    }
+
+    // Collected in the background, since counting rows on every scrape would be too slow.
+    if let Some(stats) = state.db_stats.latest() {
+        gauge(
+            &mut out,
+            "db_size_bytes",
+            "The size of the database file, free pages included.",
+            stats.size_bytes(),
+        );
+        gauge(
+            &mut out,
+            "db_free_bytes",
+            "The bytes in free pages of the database file.",
+            stats.free_bytes(),
+        );
+        gauge(
+            &mut out,
+            "db_wal_bytes",
+            "The size of the write-ahead log.",
+            stats.wal_bytes(),
+        );
+        writeln!(out, "# HELP db_table_rows Rows per table.").ok();
+        writeln!(out, "# TYPE db_table_rows gauge").ok();
+        for (table, rows) in stats.tables() {
+            writeln!(out, "db_table_rows{{table=\"{table}\"}} {rows}").ok();
+        }
+    }
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} gauge").ok();
+    writeln!(out, "{name} {value}").ok();
+}
@@ -0,0 +1,40 @@
+use crate::outbound::Outbound;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Pings a dead man's switch, such as Healthchecks.io or Cronitor, every `interval`. The monitor
+// alerts when the pings stop, which catches what probing /ready from outside can't: a service that
+// still answers requests while its background tasks have stalled. The pings come from a background
+// task like the ones they vouch for, and are skipped while the database doesn't answer.
+pub fn spawn(dbpool: SqlitePool, outbound: Arc<Outbound>, url: String, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = sqlx::query("select 1").execute(&dbpool).await {
+                tracing::warn!(error = ?err, "skipped the heartbeat, the database doesn't answer");
+                continue;
+            }
+            if let Err(err) = ping(&outbound, &url).await {
+                tracing::warn!("failed to send the heartbeat: {err}");
+            }
+        }
+    });
+}
+
+async fn ping(outbound: &Outbound, url: &str) -> Result<(), String> {
+    let request = outbound
+        .client()
+        .get(url)
+        .build()
+        .map_err(|err| err.to_string())?;
+    let response = outbound
+        .send(request)
+        .await
+        .map_err(|err| err.to_string())?;
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(format!("the monitor answered {}", response.status())),
+    }
+}
@@ -0,0 +1,124 @@
+use crate::error::Error;
+use crate::status::TodoStatus;
+use crate::todo::{CreateTodo, Todo, UpdateTodo};
+use std::sync::Arc;
+
+// Re-exported so embedders can implement TodoHook without depending on async-trait themselves.
+pub use axum::async_trait;
+
+// Hooks into the lifecycle of todo mutations, for embedders of the library that need side effects
+// such as notifications, extra validation, or enrichment. Every method has a no-op default, so a
+// hook only implements the ones it cares about.
+//
+// The before_* hooks run before anything is written and can change the request or reject it by
+// returning an error, which is sent to the client as is. They run for every write of a todo's
+// content: upserts and imports run before_update for todos that exist and before_create for the
+// rest, copies run before_create, and merges run before_update for the todo merged into and
+// before_delete for the ones merged away. The after_* hooks run once the change is committed and
+// can't fail the request anymore. Bulk updates through /v1/todos/update-where are
+// described by a filter rather than an UpdateTodo, so they skip before_update and call
+// after_bulk_update for each todo they changed. Changes applied through /v1/sync or undone through
+// /v1/undo don't go through the hooks.
+#[async_trait]
+pub trait TodoHook: Send + Sync {
+    async fn before_create(&self, _todo: &mut CreateTodo) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn after_create(&self, _todo: &Todo) {}
+
+    async fn before_update(&self, _id: i64, _todo: &mut UpdateTodo) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn after_update(&self, _todo: &Todo) {}
+
+    // Like after_update, for a todo changed by a bulk update, with the status it had before. Hooks
+    // that care what a todo was before an update, like the notifications telling completing from
+    // updating, can't learn it from before_update here. It runs after_update unless overridden.
+    async fn after_bulk_update(&self, todo: &Todo, _previous: TodoStatus) {
+        self.after_update(todo).await;
+    }
+
+    async fn before_delete(&self, _id: i64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn after_delete(&self, _id: i64) {}
+}
+
+// The registered hooks, which run in the order they were registered. The first before_* hook to
+// return an error stops the rest.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Vec<Arc<dyn TodoHook>>,
+}
+
+impl Hooks {
+    pub fn register(&mut self, hook: impl TodoHook + 'static) {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    pub async fn before_create(&self, todo: &mut CreateTodo) -> Result<(), Error> {
+        for hook in &self.hooks {
+            hook.before_create(todo).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_create(&self, todo: &Todo) {
+        for hook in &self.hooks {
+            hook.after_create(todo).await;
+        }
+    }
+
+    pub async fn before_update(&self, id: i64, todo: &mut UpdateTodo) -> Result<(), Error> {
+        for hook in &self.hooks {
+            hook.before_update(id, todo).await?;
+        }
+        Ok(())
+    }
+
+    // The before_* hooks for writing a todo by its external ID: before_update if the todo
+    // `existing` is there, and before_create with the todo as a CreateTodo if not.
+    pub async fn before_upsert(
+        &self,
+        existing: Option<i64>,
+        todo: &mut UpdateTodo,
+    ) -> Result<(), Error> {
+        match existing {
+            Some(id) => self.before_update(id, todo).await,
+            None => {
+                let mut create = CreateTodo::from(todo.clone());
+                self.before_create(&mut create).await?;
+                *todo = create.into();
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn after_update(&self, todo: &Todo) {
+        for hook in &self.hooks {
+            hook.after_update(todo).await;
+        }
+    }
+
+    pub async fn after_bulk_update(&self, todo: &Todo, previous: TodoStatus) {
+        for hook in &self.hooks {
+            hook.after_bulk_update(todo, previous).await;
+        }
+    }
+
+    pub async fn before_delete(&self, id: i64) -> Result<(), Error> {
+        for hook in &self.hooks {
+            hook.before_delete(id).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_delete(&self, id: i64) {
+        for hook in &self.hooks {
+            hook.after_delete(id).await;
+        }
+    }
+}
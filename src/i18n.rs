@@ -0,0 +1,147 @@
+use axum::extract::{Request, State};
+use axum::http::header::ACCEPT_LANGUAGE;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// The English catalog is compiled into the binary, so there's always a message to fall back to.
+const FALLBACK_LOCALE: &str = "en";
+const FALLBACK_CATALOG: &str = include_str!("../locales/en.json");
+
+type Catalog = HashMap<String, String>;
+
+// Message catalogs keyed by lowercase language tag, e.g. "en" or "pt-br".
+pub struct Catalogs {
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Catalogs {
+    // Loads every <locale>.json file from the given directory on top of the built-in English catalog.
+    pub fn load(dir: Option<&Path>) -> Result<Self, String> {
+        let mut catalogs = HashMap::new();
+        let fallback: Catalog = serde_json::from_str(FALLBACK_CATALOG)
+            .map_err(|err| format!("built-in catalog is invalid: {err}"))?;
+        catalogs.insert(FALLBACK_LOCALE.to_string(), fallback);
+
+        if let Some(dir) = dir {
+            let entries = std::fs::read_dir(dir)
+                .map_err(|err| format!("can't read {}: {err}", dir.display()))?;
+            for entry in entries {
+                let path = entry.map_err(|err| err.to_string())?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                    continue;
+                }
+                let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("can't read {}: {err}", path.display()))?;
+                let catalog: Catalog = serde_json::from_str(&contents)
+                    .map_err(|err| format!("{} is invalid: {err}", path.display()))?;
+                // A catalog on disk extends the one for the same locale rather than replacing it,
+                // so an English catalog only needs to contain the messages it overrides.
+                catalogs
+                    .entry(locale.to_ascii_lowercase())
+                    .or_insert_with(Catalog::new)
+                    .extend(catalog);
+            }
+        }
+
+        Ok(Self { catalogs })
+    }
+
+    // Picks the best available locale for an Accept-Language header value.
+    fn negotiate(&self, accept_language: &str) -> String {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // A stable sort keeps the client's order for ranges with the same quality.
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| {
+                let tag = tag.to_ascii_lowercase();
+                // We try the full tag first (pt-br), then its primary language (pt).
+                let primary = tag.split('-').next().unwrap_or_default().to_string();
+                [tag, primary]
+                    .into_iter()
+                    .find(|candidate| self.catalogs.contains_key(candidate))
+            })
+            .unwrap_or_else(|| FALLBACK_LOCALE.to_string())
+    }
+
+    fn lookup(&self, locale: &str, key: &str) -> Option<&str> {
+        self.catalogs
+            .get(locale)
+            .and_then(|catalog| catalog.get(key))
+            .or_else(|| self.catalogs.get(FALLBACK_LOCALE)?.get(key))
+            .map(String::as_str)
+    }
+}
+
+#[derive(Clone)]
+struct Localizer {
+    catalogs: Arc<Catalogs>,
+    locale: String,
+}
+
+tokio::task_local! {
+    // The localizer for the request being handled. Errors are turned into responses without access
+    // to the request, so we make the negotiated locale available to them this way.
+    static LOCALIZER: Localizer;
+}
+
+// A middleware which negotiates the locale from the Accept-Language header for the rest of the request.
+pub async fn negotiate_language(
+    State(catalogs): State<Arc<Catalogs>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let locale = request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| catalogs.negotiate(value))
+        .unwrap_or_else(|| FALLBACK_LOCALE.to_string());
+
+    LOCALIZER
+        .scope(Localizer { catalogs, locale }, next.run(request))
+        .await
+}
+
+// Renders the message for `key` in the current request's locale, replacing {name} placeholders with
+// the given arguments. Outside of a request, or for unknown keys, we fall back to English or to the
+// key itself.
+pub fn message(key: &str, args: &[(&str, &str)]) -> String {
+    let template = LOCALIZER
+        .try_with(|localizer| {
+            localizer
+                .catalogs
+                .lookup(&localizer.locale, key)
+                .map(str::to_string)
+        })
+        .ok()
+        .flatten()
+        .or_else(|| {
+            serde_json::from_str::<Catalog>(FALLBACK_CATALOG)
+                .ok()?
+                .remove(key)
+        })
+        .unwrap_or_else(|| key.to_string());
+
+    args.iter().fold(template, |message, (name, value)| {
+        message.replace(&format!("{{{name}}}"), value)
+    })
+}
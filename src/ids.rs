@@ -0,0 +1,440 @@
+use crate::error::Error;
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, Path, Request, State};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serializer};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890";
+const SEPARATORS: &str = "cfhistuCFHISTU";
+
+// Turns integer IDs into short opaque strings like "Xk3vqA9b" and back, following the Hashids
+// algorithm (https://hashids.org) with its default alphabet, so other Hashids libraries given the
+// same salt and minimum length decode our IDs too. This hides how many todos there are and keeps
+// clients from walking through them by counting, but it's obfuscation, not encryption: anyone who
+// learns the salt can decode the IDs.
+pub struct Hashids {
+    salt: Vec<char>,
+    min_length: usize,
+    alphabet: Vec<char>,
+    separators: Vec<char>,
+    guards: Vec<char>,
+}
+
+impl Hashids {
+    pub fn new(salt: &str, min_length: usize) -> Self {
+        let salt: Vec<char> = salt.chars().collect();
+        let mut separators: Vec<char> = SEPARATORS.chars().collect();
+        let mut alphabet: Vec<char> = ALPHABET
+            .chars()
+            .filter(|c| !separators.contains(c))
+            .collect();
+        shuffle(&mut separators, &salt);
+
+        // There should be about one separator for every 3.5 letters.
+        let wanted = (alphabet.len() as f64 / 3.5).ceil() as usize;
+        if separators.len() < wanted {
+            let missing = wanted.max(2) - separators.len();
+            separators.extend(alphabet.drain(..missing));
+        } else {
+            separators.truncate(wanted.max(2));
+        }
+        shuffle(&mut alphabet, &salt);
+
+        let guard_count = alphabet.len().div_ceil(12);
+        let guards = alphabet.drain(..guard_count).collect();
+        Self {
+            salt,
+            min_length,
+            alphabet,
+            separators,
+            guards,
+        }
+    }
+
+    pub fn encode(&self, number: u64) -> String {
+        // The lottery character seeds the alphabet's shuffle, so consecutive numbers don't look alike.
+        let numbers_hash = (number % 100) as usize;
+        let mut alphabet = self.alphabet.clone();
+        let lottery = alphabet[numbers_hash % alphabet.len()];
+        let mut encoded = vec![lottery];
+        let key = self.buffer(lottery, &alphabet);
+        shuffle(&mut alphabet, &key);
+        encoded.extend(hash(number, &alphabet));
+
+        // Short IDs are padded up to the minimum length, first with guards and then with letters.
+        if encoded.len() < self.min_length {
+            let guard = (numbers_hash + encoded[0] as usize) % self.guards.len();
+            encoded.insert(0, self.guards[guard]);
+            if encoded.len() < self.min_length {
+                let guard = (numbers_hash + encoded[2] as usize) % self.guards.len();
+                encoded.push(self.guards[guard]);
+            }
+        }
+        let half = alphabet.len() / 2;
+        while encoded.len() < self.min_length {
+            let key = alphabet.clone();
+            shuffle(&mut alphabet, &key);
+            encoded = [&alphabet[half..], &encoded[..], &alphabet[..half]].concat();
+            let excess = encoded.len().saturating_sub(self.min_length);
+            if excess > 0 {
+                let start = excess / 2;
+                encoded = encoded[start..start + self.min_length].to_vec();
+            }
+        }
+        encoded.into_iter().collect()
+    }
+
+    // Returns None for anything that isn't exactly what we'd encode for the number it decodes to,
+    // which rules out most IDs encoded with another salt too.
+    pub fn decode(&self, encoded: &str) -> Option<u64> {
+        let chars: Vec<char> = encoded.chars().collect();
+        // The number sits between the guards, if there are any.
+        let parts: Vec<&[char]> = chars.split(|c| self.guards.contains(c)).collect();
+        let inner = match parts.len() {
+            2 | 3 => parts[1],
+            _ => parts[0],
+        };
+        let (&lottery, rest) = inner.split_first()?;
+        if rest.is_empty() || rest.iter().any(|c| self.separators.contains(c)) {
+            return None;
+        }
+        let mut alphabet = self.alphabet.clone();
+        let key = self.buffer(lottery, &alphabet);
+        shuffle(&mut alphabet, &key);
+        let number = unhash(rest, &alphabet)?;
+        (self.encode(number) == encoded).then_some(number)
+    }
+
+    fn buffer(&self, lottery: char, alphabet: &[char]) -> Vec<char> {
+        let mut buffer = vec![lottery];
+        buffer.extend(&self.salt);
+        buffer.extend(alphabet);
+        buffer.truncate(alphabet.len());
+        buffer
+    }
+}
+
+// The deterministic shuffle at the heart of Hashids, keyed by the salt.
+fn shuffle(alphabet: &mut [char], salt: &[char]) {
+    if salt.is_empty() {
+        return;
+    }
+    let (mut v, mut p) = (0, 0);
+    for i in (1..alphabet.len()).rev() {
+        v %= salt.len();
+        let integer = salt[v] as usize;
+        p += integer;
+        let j = (integer + v + p) % i;
+        alphabet.swap(i, j);
+        v += 1;
+    }
+}
+
+fn hash(mut number: u64, alphabet: &[char]) -> Vec<char> {
+    let base = alphabet.len() as u64;
+    let mut hashed = Vec::new();
+    loop {
+        hashed.insert(0, alphabet[(number % base) as usize]);
+        number /= base;
+        if number == 0 {
+            return hashed;
+        }
+    }
+}
+
+fn unhash(hashed: &[char], alphabet: &[char]) -> Option<u64> {
+    hashed.iter().try_fold(0u64, |number, c| {
+        let position = alphabet.iter().position(|a| a == c)? as u64;
+        number
+            .checked_mul(alphabet.len() as u64)?
+            .checked_add(position)
+    })
+}
+
+// How IDs appear in the API: as the integers they are in the database, or, when HASHIDS_SALT is set,
+// encoded with Hashids. The encoding covers every ID the API exposes: todos, export jobs, and feed
+// tokens, in response bodies, Location headers, request bodies, and paths.
+#[derive(Default)]
+pub enum IdEncoding {
+    #[default]
+    Plain,
+    Hashids(Hashids),
+}
+
+impl IdEncoding {
+    pub fn hashids(salt: &str, min_length: usize) -> Self {
+        IdEncoding::Hashids(Hashids::new(salt, min_length))
+    }
+
+    pub fn encode(&self, id: i64) -> String {
+        match (self, u64::try_from(id)) {
+            (IdEncoding::Hashids(hashids), Ok(id)) => hashids.encode(id),
+            _ => id.to_string(),
+        }
+    }
+
+    pub fn decode(&self, id: &str) -> Option<i64> {
+        match self {
+            IdEncoding::Plain => id.parse().ok(),
+            IdEncoding::Hashids(hashids) => hashids.decode(id)?.try_into().ok(),
+        }
+    }
+}
+
+tokio::task_local! {
+    // The encoding for the request being handled. IDs are serialized deep inside the models, without
+    // access to the state, so we make the encoding available to them this way, like the locale.
+    static ENCODING: Arc<IdEncoding>;
+}
+
+// A middleware which makes the configured encoding apply to the rest of the request.
+pub async fn scope_encoding(
+    State(encoding): State<Arc<IdEncoding>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    ENCODING.scope(encoding, next.run(request)).await
+}
+
+// Runs f with the encoding, for serializing outside a request, e.g. in the client.
+pub fn with_encoding<R>(encoding: Arc<IdEncoding>, f: impl FnOnce() -> R) -> R {
+    ENCODING.sync_scope(encoding, f)
+}
+
+// Carries the current request's encoding over to a task spawned to finish its work, such as
+// streaming an export.
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let encoding = ENCODING.try_with(Arc::clone).unwrap_or_default();
+    ENCODING.scope(encoding, future)
+}
+
+// Runs a background task with the encoding, so the IDs it sends out, e.g. in notifications, look
+// like the ones in the API.
+pub fn scope<F: Future>(encoding: Arc<IdEncoding>, future: F) -> impl Future<Output = F::Output> {
+    ENCODING.scope(encoding, future)
+}
+
+// Encodes an ID for use in a path or header.
+pub fn encode(id: i64) -> String {
+    ENCODING
+        .try_with(|encoding| encoding.encode(id))
+        .unwrap_or_else(|_| id.to_string())
+}
+
+fn is_plain() -> bool {
+    ENCODING
+        .try_with(|encoding| matches!(**encoding, IdEncoding::Plain))
+        .unwrap_or(true)
+}
+
+// The serde side of the encoding, for ID fields: #[serde(with = "crate::ids::id")]. Plain IDs are
+// numbers and encoded ones strings. Once IDs are encoded, plain numbers aren't accepted any more,
+// since that would let clients go back to counting.
+pub mod id {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(id: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        match is_plain() {
+            true => serializer.serialize_i64(*id),
+            false => serializer.serialize_str(&encode(*id)),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        deserializer.deserialize_any(IdVisitor)
+    }
+}
+
+// The same for optional IDs: #[serde(with = "crate::ids::option")].
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(id: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match id {
+            Some(id) => super::id::serialize(id, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<i64>, D::Error> {
+        Ok(Option::<Id>::deserialize(deserializer)?.map(|Id(id)| id))
+    }
+}
+
+// And for lists of IDs: #[serde(with = "crate::ids::list")].
+pub mod list {
+    use super::*;
+    use serde::ser::SerializeSeq;
+
+    pub fn serialize<S: Serializer>(ids: &[i64], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(ids.len()))?;
+        for id in ids {
+            seq.serialize_element(&Encoded(*id))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<i64>, D::Error> {
+        Ok(Vec::<Id>::deserialize(deserializer)?
+            .into_iter()
+            .map(|Id(id)| id)
+            .collect())
+    }
+
+    struct Encoded(i64);
+
+    impl serde::Serialize for Encoded {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            super::id::serialize(&self.0, serializer)
+        }
+    }
+}
+
+struct IdVisitor;
+
+impl Visitor<'_> for IdVisitor {
+    type Value = i64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match is_plain() {
+            true => f.write_str("an integer ID"),
+            false => f.write_str("an encoded ID"),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, id: i64) -> Result<i64, E> {
+        match is_plain() {
+            true => Ok(id),
+            false => Err(E::invalid_type(de::Unexpected::Signed(id), &self)),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, id: u64) -> Result<i64, E> {
+        match (is_plain(), i64::try_from(id)) {
+            (true, Ok(id)) => Ok(id),
+            _ => Err(E::invalid_type(de::Unexpected::Unsigned(id), &self)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, id: &str) -> Result<i64, E> {
+        let decoded = match is_plain() {
+            true => None,
+            false => ENCODING
+                .try_with(|encoding| encoding.decode(id))
+                .ok()
+                .flatten(),
+        };
+        decoded.ok_or_else(|| E::invalid_value(de::Unexpected::Str(id), &self))
+    }
+}
+
+// An ID taken from the path, e.g. /v1/todos/:id, in whichever encoding is configured. IDs that
+// don't decode are reported as not found, like IDs that don't exist.
+pub struct Id(pub i64);
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor).map(Id)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Id
+where
+    S: Send + Sync,
+    Arc<IdEncoding>: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(id) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| Error::NotFound)?;
+        Arc::<IdEncoding>::from_ref(state)
+            .decode(&id)
+            .map(Id)
+            .ok_or(Error::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference values from hashids.org and the JavaScript and Python Hashids libraries, so IDs we
+    // hand out stay decodable by them.
+    #[test]
+    fn matches_other_libraries() {
+        let cases = [
+            ("this is my salt", 0, 12345, "NkK9"),
+            ("this is my salt", 8, 1, "gB0NV05e"),
+            ("", 16, 1, "4q2VolejRejNmGQB"),
+            ("", 0, 0, "gY"),
+            ("", 0, 1, "jR"),
+            ("", 0, 928728, "R8ZN0"),
+        ];
+        for (salt, min_length, number, encoded) in cases {
+            let hashids = Hashids::new(salt, min_length);
+            assert_eq!(
+                hashids.encode(number),
+                encoded,
+                "{salt:?}, {min_length}, {number}"
+            );
+            assert_eq!(hashids.decode(encoded), Some(number), "{encoded}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for (salt, min_length) in [("", 0), ("pepper", 0), ("pepper", 8), ("pepper", 30)] {
+            let hashids = Hashids::new(salt, min_length);
+            let numbers = (0..2_000).chain([u64::from(u32::MAX), i64::MAX as u64, u64::MAX]);
+            for number in numbers {
+                let encoded = hashids.encode(number);
+                assert!(encoded.chars().count() >= min_length, "{encoded}");
+                assert_eq!(hashids.decode(&encoded), Some(number), "{encoded}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_did_not_encode() {
+        let hashids = Hashids::new("pepper", 8);
+        let other = Hashids::new("salt", 8);
+        // An ID encoded with another salt can still be one of ours for some other number, but it
+        // only decodes when it's exactly what we'd encode for that number, and most don't.
+        let mut decoded = 0;
+        for number in 0..2_000 {
+            let encoded = other.encode(number);
+            if let Some(ours) = hashids.decode(&encoded) {
+                assert_eq!(hashids.encode(ours), encoded);
+                decoded += 1;
+            }
+        }
+        assert!(
+            decoded < 100,
+            "{decoded} of 2000 IDs with another salt decoded"
+        );
+        let encoded = hashids.encode(42);
+        for garbage in [
+            "",
+            "1",
+            "!!!!!!!!",
+            "ööööööö",
+            &encoded[1..],
+            &format!("{encoded}a"),
+        ] {
+            assert_eq!(hashids.decode(garbage), None, "{garbage:?}");
+        }
+        assert_eq!(IdEncoding::hashids("pepper", 8).decode("42"), None);
+        assert_eq!(IdEncoding::Plain.decode("42"), Some(42));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::batch::{BatchItem, BatchReport};
+use crate::error::{Error, RequestError};
+use crate::hooks::Hooks;
+use crate::i18n;
+use crate::todo::{resolve_due, Todo, UpdateTodo};
+use crate::validation::BodyPolicy;
+use axum::http::StatusCode;
+use chrono::{DateTime, NaiveDateTime};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::fmt;
+
+// The export formats we can import from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    // Todoist's task list, either the REST API's array of tasks or a sync export with an items array.
+    Todoist,
+    // A Trello board exported as JSON.
+    Trello,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    format: ImportFormat,
+    // Reports what the import would do without writing anything.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+impl ImportQuery {
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+// One todo from the export, and what the import did or would do with it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportedTodo {
+    external_id: String,
+    body: String,
+    completed: bool,
+    due_at: Option<NaiveDateTime>,
+    // Whether the todo is new, as opposed to one an earlier import of the same export created.
+    created: bool,
+    // The todo's ID, unless this is a dry run.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::ids::option"
+    )]
+    id: Option<i64>,
+}
+
+impl ImportedTodo {
+    pub fn external_id(&self) -> &str {
+        &self.external_id
+    }
+
+    pub fn created(&self) -> bool {
+        self.created
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        self.id
+    }
+}
+
+// The report lists the todos in export order, each with the status it got: 201 for a new todo, 200
+// for one an earlier import created, and 422 for one we couldn't import, e.g. because of a due date
+// we can't understand. Each item's reference is the todo's external ID.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportReport {
+    dry_run: bool,
+    created: usize,
+    updated: usize,
+    // Entries we leave out, such as archived Trello cards.
+    skipped: usize,
+    #[serde(flatten)]
+    todos: BatchReport<ImportedTodo>,
+}
+
+impl ImportReport {
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    pub fn updated(&self) -> usize {
+        self.updated
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn todos(&self) -> &BatchReport<ImportedTodo> {
+        &self.todos
+    }
+}
+
+// A todo as the format adapters produce it, before we've looked at the database.
+struct Candidate {
+    external_id: String,
+    body: String,
+    completed: bool,
+    due: Option<String>,
+}
+
+// Imports the todos from an export. Each todo is written with an external ID derived from its ID in
+// the other service, so importing the same export again updates the todos rather than duplicating
+// them, and an import that failed halfway can simply be retried. Projects, lists, and labels have
+// no equivalent here and are dropped. Returns the report and, unless it's a dry run, the todos
+// that were written, with whether each was created.
+pub async fn import(
+    dbpool: SqlitePool,
+    policy: &BodyPolicy,
+    hooks: &Hooks,
+    query: &ImportQuery,
+    export: serde_json::Value,
+) -> Result<(ImportReport, Vec<(Todo, bool)>), Error> {
+    let (candidates, skipped) = match query.format {
+        ImportFormat::Todoist => todoist(parse(export)?),
+        ImportFormat::Trello => trello(parse(export)?),
+    };
+
+    let mut report = ImportReport {
+        dry_run: query.dry_run,
+        created: 0,
+        updated: 0,
+        skipped,
+        todos: BatchReport::default(),
+    };
+    let mut written = Vec::new();
+    for (index, mut candidate) in candidates.into_iter().enumerate() {
+        let existing = Todo::find_external(&dbpool, &candidate.external_id).await?;
+        let mut update = UpdateTodo::new(candidate.body.clone(), candidate.completed);
+        if let Some(due) = candidate.due.take() {
+            update = update.with_due(due);
+        }
+        let due_at = match prepare(&dbpool, policy, hooks, query, existing, &mut update).await {
+            Ok(due_at) => due_at,
+            Err(err) => {
+                // A todo we can't import doesn't stop the others, so fixing it and importing the
+                // export again only touches what failed.
+                report.todos.push(
+                    BatchItem::rejected(index, err)?.with_reference(Some(candidate.external_id)),
+                );
+                continue;
+            }
+        };
+        candidate.body = update.body().to_string();
+        candidate.completed = update.completed().unwrap_or(candidate.completed);
+
+        let (id, created) = if query.dry_run {
+            (None, existing.is_none())
+        } else {
+            let (todo, created) =
+                Todo::upsert(dbpool.clone(), &candidate.external_id, update).await?;
+            let id = todo.id();
+            written.push((todo, created));
+            (Some(id), created)
+        };
+        let status = if created {
+            report.created += 1;
+            StatusCode::CREATED
+        } else {
+            report.updated += 1;
+            StatusCode::OK
+        };
+        let reference = Some(candidate.external_id.clone());
+        let todo = ImportedTodo {
+            external_id: candidate.external_id,
+            body: candidate.body,
+            completed: candidate.completed,
+            due_at,
+            created,
+            id,
+        };
+        report
+            .todos
+            .push(BatchItem::succeeded(index, status, Some(todo)).with_reference(reference));
+    }
+    Ok((report, written))
+}
+
+// Gets a todo ready to import the way other writes are: through the hooks, then the policy.
+// Returns its due date. A dry run skips the hooks, which would be told about a write that never
+// happens.
+async fn prepare(
+    dbpool: &SqlitePool,
+    policy: &BodyPolicy,
+    hooks: &Hooks,
+    query: &ImportQuery,
+    existing: Option<i64>,
+    update: &mut UpdateTodo,
+) -> Result<Option<NaiveDateTime>, Error> {
+    if !query.dry_run {
+        hooks.before_upsert(existing, update).await?;
+    }
+    update.set_body(policy.apply(update.body(), "body")?);
+    resolve_due(dbpool, update.due()).await
+}
+
+// Exports are full of fields we don't use, so they're parsed leniently, but a document that isn't
+// the format the client named gets a 422 saying where it went wrong.
+fn parse<T: DeserializeOwned>(export: serde_json::Value) -> Result<T, Error> {
+    serde_path_to_error::deserialize(export).map_err(|err| {
+        let path = err.path().to_string();
+        let detail = err.into_inner().to_string();
+        let mut error = RequestError::new(
+            "invalid_export",
+            i18n::message("invalid_export", &[("detail", &detail)]),
+        );
+        if path != "." {
+            error = error.with_field(path);
+        }
+        Error::BadRequest(StatusCode::UNPROCESSABLE_ENTITY, error)
+    })
+}
+
+// Joins a title and a description into a todo body.
+fn body(title: &str, description: &str) -> String {
+    match description.trim() {
+        "" => title.trim().to_string(),
+        description => format!("{}\n\n{description}", title.trim()),
+    }
+}
+
+// IDs are strings in current exports and numbers in older ones.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExportId {
+    Text(String),
+    Number(i64),
+}
+
+impl fmt::Display for ExportId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportId::Text(id) => f.write_str(id),
+            ExportId::Number(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TodoistExport {
+    Sync { items: Vec<TodoistTask> },
+    Rest(Vec<TodoistTask>),
+}
+
+#[derive(Deserialize)]
+struct TodoistTask {
+    id: ExportId,
+    content: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default, alias = "is_completed")]
+    checked: bool,
+    due: Option<TodoistDue>,
+}
+
+#[derive(Deserialize)]
+struct TodoistDue {
+    // A date, a floating local time like "2024-05-01T17:30:00", or a UTC time ending in Z.
+    date: String,
+    // The REST API puts the UTC time here when the due date has a time.
+    datetime: Option<String>,
+}
+
+fn todoist(export: TodoistExport) -> (Vec<Candidate>, usize) {
+    let tasks = match export {
+        TodoistExport::Sync { items } => items,
+        TodoistExport::Rest(tasks) => tasks,
+    };
+    let candidates = tasks
+        .into_iter()
+        .map(|task| Candidate {
+            external_id: format!("todoist:{}", task.id),
+            body: body(&task.content, &task.description),
+            completed: task.checked,
+            due: task.due.map(|due| {
+                let due = due.datetime.unwrap_or(due.date);
+                if DateTime::parse_from_rfc3339(&due).is_ok() {
+                    return due;
+                }
+                // Floating times are in the owner's timezone, which is how we read "2024-05-01 17:30".
+                NaiveDateTime::parse_from_str(&due, "%Y-%m-%dT%H:%M:%S")
+                    .map(|local| local.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or(due)
+            }),
+        })
+        .collect();
+    (candidates, 0)
+}
+
+#[derive(Deserialize)]
+struct TrelloBoard {
+    #[serde(default)]
+    lists: Vec<TrelloList>,
+    cards: Vec<TrelloCard>,
+}
+
+#[derive(Deserialize)]
+struct TrelloList {
+    id: String,
+    #[serde(default)]
+    closed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrelloCard {
+    id: String,
+    name: String,
+    #[serde(default)]
+    desc: String,
+    #[serde(default)]
+    closed: bool,
+    // An RFC 3339 timestamp in UTC.
+    due: Option<String>,
+    #[serde(default)]
+    due_complete: bool,
+    id_list: String,
+}
+
+fn trello(board: TrelloBoard) -> (Vec<Candidate>, usize) {
+    let closed_lists: Vec<&str> = board
+        .lists
+        .iter()
+        .filter(|list| list.closed)
+        .map(|list| list.id.as_str())
+        .collect();
+    let mut skipped = 0;
+    let mut candidates = Vec::new();
+    for card in &board.cards {
+        // Archived cards, and cards in archived lists, are gone as far as the board's owner is
+        // concerned.
+        if card.closed || closed_lists.contains(&card.id_list.as_str()) {
+            skipped += 1;
+            continue;
+        }
+        candidates.push(Candidate {
+            external_id: format!("trello:{}", card.id),
+            body: body(&card.name, &card.desc),
+            completed: card.due_complete,
+            due: card.due.clone(),
+        });
+    }
+    (candidates, skipped)
+}
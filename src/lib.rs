@@ -0,0 +1,72 @@
+// The service as a library: the models, the storage code, and the router. The binary in main.rs
+// wires it up with configuration, tracing, and the database, and the todo-client crate shares the
+// model types from here.
+pub mod account;
+mod admin;
+mod allow;
+mod api;
+pub mod backup;
+pub mod batch;
+pub mod cache;
+mod cache_control;
+pub mod change;
+pub mod config;
+pub mod db_stats;
+pub mod deprecation;
+mod due;
+pub mod envelope;
+pub mod error;
+pub mod export;
+pub mod export_job;
+mod extract;
+pub mod feed;
+pub mod flags;
+pub mod geo;
+mod health;
+mod heartbeat;
+pub mod hooks;
+pub mod i18n;
+pub mod ids;
+pub mod import;
+pub mod links;
+pub mod listener;
+pub mod log_level;
+pub mod maintenance;
+pub mod merge;
+pub mod metering;
+pub mod metrics;
+pub mod migrations;
+pub mod notify;
+pub mod outbound;
+pub mod params;
+pub mod pipeline;
+pub mod pool;
+mod prefer;
+pub mod preferences;
+pub mod proxy_protocol;
+pub mod quicklist;
+pub mod rate_limit;
+pub mod reactions;
+pub mod recent;
+pub mod report;
+pub mod restore;
+pub mod retention;
+pub mod router;
+pub mod runtime;
+pub mod search;
+pub mod shard;
+pub mod share;
+pub mod single_flight;
+pub mod stale;
+pub mod state;
+pub mod statement_budget;
+pub mod status;
+pub mod sync;
+pub mod tenant;
+pub mod todo;
+pub mod trace_context;
+pub mod triggers;
+pub mod undo;
+pub mod update_where;
+pub mod validation;
+pub mod web_push;
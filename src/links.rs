@@ -0,0 +1,394 @@
+use crate::cache::ResponseCache;
+use crate::hooks::{async_trait, TodoHook};
+use crate::outbound::Outbound;
+use crate::pipeline::trim_url;
+use crate::todo::Todo;
+use chrono::{NaiveDateTime, Utc};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{SqliteTypeInfo, SqliteValueRef};
+use sqlx::{query, query_as, Decode, Sqlite, SqlitePool, Type};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Only the first few links in a body are previewed, so a pasted list of URLs doesn't turn into a
+// crawl.
+const MAX_LINKS: usize = 5;
+// Titles and descriptions are in a page's head, so there's no need to read much of it.
+const MAX_PAGE_BYTES: usize = 256 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 500;
+
+// A link in a todo's body, with the title and description of the page when we could fetch them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    url: String,
+    title: Option<String>,
+    description: Option<String>,
+}
+
+impl Link {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+// The links in a todo's body, in the order they appear. The previews are fetched in the
+// background after the todo is written, so a todo that was just created has none yet.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Links(Vec<Link>);
+
+impl Links {
+    pub fn iter(&self) -> impl Iterator<Item = &Link> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Type<Sqlite> for Links {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <String as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for Links {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let json = <&str as Decode<Sqlite>>::decode(value)?;
+        Ok(Links(serde_json::from_str(json)?))
+    }
+}
+
+// The http and https URLs in a body, without duplicates. URLs in angle brackets or Markdown links
+// count too.
+fn urls(body: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = body[offset..].find("http") {
+        let start = offset + found;
+        offset = start + 4;
+        let rest = &body[start..];
+        let separate = body[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        if !separate || !(rest.starts_with("http://") || rest.starts_with("https://")) {
+            continue;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+            .unwrap_or(rest.len());
+        let url = trim_url(&rest[..end]);
+        offset = start + url.len();
+        if reqwest::Url::parse(url).is_ok() && !urls.iter().any(|seen| seen == url) {
+            urls.push(url.to_string());
+        }
+        if urls.len() == MAX_LINKS {
+            break;
+        }
+    }
+    urls
+}
+
+// Fetches previews for the links in todos as they're created and updated. Previews are cached by
+// URL for a while, so the same link in many todos is fetched once.
+pub struct LinkPreviews {
+    dbpool: SqlitePool,
+    cache: Arc<ResponseCache>,
+    outbound: Arc<Outbound>,
+    ttl: Duration,
+}
+
+impl LinkPreviews {
+    pub fn new(
+        dbpool: SqlitePool,
+        cache: Arc<ResponseCache>,
+        outbound: Arc<Outbound>,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            dbpool,
+            cache,
+            outbound,
+            ttl,
+        }
+    }
+
+    fn spawn(&self, todo: &Todo) {
+        let dbpool = self.dbpool.clone();
+        let cache = self.cache.clone();
+        let outbound = self.outbound.clone();
+        let ttl = self.ttl;
+        let (id, body) = (todo.id(), todo.body().to_string());
+        tokio::spawn(async move {
+            if let Err(err) = refresh(&dbpool, &cache, &outbound, ttl, id, &body).await {
+                tracing::warn!(error = ?err, id, "failed to update link previews");
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl TodoHook for LinkPreviews {
+    async fn after_create(&self, todo: &Todo) {
+        self.spawn(todo);
+    }
+
+    async fn after_update(&self, todo: &Todo) {
+        self.spawn(todo);
+    }
+}
+
+// Stores the previews of the links in a todo's body on the todo, unless the body was changed in
+// the meantime, in which case the update that changed it stores its own. Previews leave the
+// version alone, since this runs after every write that adds a link and the client that made it
+// would otherwise conflict with itself on its next edit or undo. The cached copy goes all the same,
+// and the ETag changes with the links.
+async fn refresh(
+    dbpool: &SqlitePool,
+    cache: &ResponseCache,
+    outbound: &Outbound,
+    ttl: Duration,
+    id: i64,
+    body: &str,
+) -> Result<(), sqlx::Error> {
+    let mut links = Vec::new();
+    for url in urls(body) {
+        links.push(preview(dbpool, outbound, ttl, url).await?);
+    }
+    let links = serde_json::to_string(&links).expect("links always serialize");
+    // Leaving unchanged links alone keeps them out of the changes feed.
+    let updated: Option<Todo> = query_as(
+        "update todos set links = ?1
+         where id = ?2 and body = ?3 and links != ?1 returning *",
+    )
+    .bind(links)
+    .bind(id)
+    .bind(body)
+    .fetch_optional(dbpool)
+    .await?;
+    if let Some(todo) = updated {
+        cache.write_through(&todo);
+    }
+    Ok(())
+}
+
+async fn preview(
+    dbpool: &SqlitePool,
+    outbound: &Outbound,
+    ttl: Duration,
+    url: String,
+) -> Result<Link, sqlx::Error> {
+    let cached: Option<(Option<String>, Option<String>, NaiveDateTime)> =
+        query_as("select title, description, fetched_at from link_previews where url = ?")
+            .bind(&url)
+            .fetch_optional(dbpool)
+            .await?;
+    let fresh_after = Utc::now().naive_utc() - ttl;
+    if let Some((title, description, fetched_at)) = cached {
+        if fetched_at > fresh_after {
+            return Ok(Link {
+                url,
+                title,
+                description,
+            });
+        }
+    }
+    let (title, description) = fetch(outbound, &url).await.unwrap_or_else(|err| {
+        tracing::debug!(%url, error = %err, "couldn't preview a link");
+        (None, None)
+    });
+    query(
+        "insert into link_previews (url, title, description) values (?, ?, ?)
+         on conflict (url) do update set title = excluded.title,
+         description = excluded.description, fetched_at = excluded.fetched_at",
+    )
+    .bind(&url)
+    .bind(&title)
+    .bind(&description)
+    .execute(dbpool)
+    .await?;
+    Ok(Link {
+        url,
+        title,
+        description,
+    })
+}
+
+// The title and description of an HTML page. Other kinds of content have neither.
+async fn fetch(outbound: &Outbound, url: &str) -> Result<(Option<String>, Option<String>), String> {
+    let request = outbound
+        .client()
+        .get(url)
+        .header(ACCEPT, "text/html")
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut response = outbound.send_public(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("the page returned {}", response.status()));
+    }
+    let html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("html"));
+    if !html {
+        return Ok((None, None));
+    }
+    let mut page = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        page.extend_from_slice(&chunk);
+        if page.len() >= MAX_PAGE_BYTES {
+            page.truncate(MAX_PAGE_BYTES);
+            break;
+        }
+    }
+    Ok(parse(&String::from_utf8_lossy(&page)))
+}
+
+// Picks the title and description out of a page's head, preferring the Open Graph ones, which
+// sites write for previews like this. We don't need a full HTML parser for a few tags.
+fn parse(html: &str) -> (Option<String>, Option<String>) {
+    // ASCII lowercasing keeps byte offsets the same, so they apply to the page too.
+    let lowercase = html.to_ascii_lowercase();
+    let head = &html[..lowercase.find("</head").unwrap_or(html.len())];
+    let mut title = None;
+    let mut og_title = None;
+    let mut description = None;
+    let mut og_description = None;
+    let mut offset = 0;
+    while let Some(found) = lowercase[offset..head.len()].find('<') {
+        let start = offset + found + 1;
+        let Some(len) = lowercase[start..head.len()].find('>') else {
+            break;
+        };
+        let tag = &head[start..start + len];
+        offset = start + len + 1;
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "title" if title.is_none() => {
+                let end = lowercase[offset..head.len()]
+                    .find("</title")
+                    .map_or(head.len(), |end| offset + end);
+                title = Some(&head[offset..end]);
+            }
+            "meta" => {
+                let key = attribute(tag, "property").or_else(|| attribute(tag, "name"));
+                let content = attribute(tag, "content");
+                match key.map(|key| key.to_ascii_lowercase()).as_deref() {
+                    Some("og:title") => og_title = og_title.or(content),
+                    Some("og:description") => og_description = og_description.or(content),
+                    Some("description") => description = description.or(content),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    (
+        og_title
+            .or(title)
+            .and_then(|title| clean(title, MAX_TITLE_CHARS)),
+        og_description
+            .or(description)
+            .and_then(|description| clean(description, MAX_DESCRIPTION_CHARS)),
+    )
+}
+
+// The value of an attribute in a tag, quoted or not.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lowercase = tag.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(found) = lowercase[offset..].find(name) {
+        let start = offset + found;
+        offset = start + name.len();
+        let separate = lowercase[..start].ends_with(char::is_whitespace);
+        let rest = lowercase[offset..].trim_start();
+        if !separate || !rest.starts_with('=') {
+            continue;
+        }
+        let value_start = tag.len() - rest[1..].trim_start().len();
+        let value = &tag[value_start..];
+        return Some(match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                &value[..value.find(quote).unwrap_or(value.len())]
+            }
+            _ => &value[..value.find(char::is_whitespace).unwrap_or(value.len())],
+        });
+    }
+    None
+}
+
+// Decodes the common character references and collapses whitespace, leaving out empty values.
+fn clean(text: &str, max_chars: usize) -> Option<String> {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 8)
+            .map(|end| &rest[1..end + 1]);
+        let c = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => match reference
+                .strip_prefix("#x")
+                .or(reference.strip_prefix("#X"))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => reference
+                    .strip_prefix('#')
+                    .and_then(|decimal| decimal.parse().ok())
+                    .and_then(char::from_u32),
+            },
+        });
+        match (c, reference) {
+            (Some(c), Some(reference)) => {
+                decoded.push(c);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    let text: String = decoded
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(max_chars)
+        .collect();
+    (!text.is_empty()).then_some(text)
+}
@@ -0,0 +1,157 @@
+use crate::config::Config;
+use crate::proxy_protocol;
+use axum::body::Body;
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::{async_trait, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower_service::Service;
+
+// How long a load balancer gets to send the PROXY protocol header after connecting.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The address of the client a request came from: the peer of the connection, or, behind a load
+// balancer speaking the PROXY protocol, the client it connected on behalf of. Anything that needs
+// the client's IP, like logging, should take it from here rather than from the connection.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(SocketAddr);
+
+impl ClientAddr {
+    pub fn addr(self) -> SocketAddr {
+        self.0
+    }
+
+    pub fn ip(self) -> IpAddr {
+        self.0.ip()
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientAddr {
+    type Rejection = (StatusCode, &'static str);
+
+    // The address is only known when the router is served by serve() below.
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<ClientAddr>().copied().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the client address is unknown",
+        ))
+    }
+}
+
+// How a listener handles its connections. See Config for what the settings do.
+#[derive(Clone, Debug)]
+pub struct ConnectionSettings {
+    // Every connection has to start with a PROXY protocol header, version 1 or 2, and connections
+    // that don't are closed. Only turn it on for listeners that are exclusively reached through
+    // the load balancer.
+    pub proxy_protocol: bool,
+    pub http2: bool,
+    pub http2_max_concurrent_streams: u32,
+    pub http2_keep_alive_interval: Option<Duration>,
+    pub http2_keep_alive_timeout: Duration,
+    pub http1_keep_alive: bool,
+    pub header_read_timeout: Duration,
+    pub tcp_nodelay: bool,
+}
+
+impl ConnectionSettings {
+    fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(self.http1_keep_alive)
+            .header_read_timeout(self.header_read_timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.http2_max_concurrent_streams)
+            .keep_alive_interval(self.http2_keep_alive_interval)
+            .keep_alive_timeout(self.http2_keep_alive_timeout);
+        match self.http2 {
+            true => builder,
+            false => builder.http1_only(),
+        }
+    }
+}
+
+impl From<&Config> for ConnectionSettings {
+    fn from(config: &Config) -> Self {
+        Self {
+            proxy_protocol: config.proxy_protocol,
+            http2: config.http2,
+            http2_max_concurrent_streams: config.http2_max_concurrent_streams,
+            http2_keep_alive_interval: Some(config.http2_keep_alive_interval)
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs),
+            http2_keep_alive_timeout: Duration::from_secs(config.http2_keep_alive_timeout),
+            http1_keep_alive: config.http1_keep_alive,
+            header_read_timeout: Duration::from_secs(config.header_read_timeout),
+            tcp_nodelay: config.tcp_nodelay,
+        }
+    }
+}
+
+// Serves the router on a listener, like axum::serve, but recording each request's ClientAddr and
+// applying the connection settings. None of our routes upgrade connections (e.g. to WebSockets), so
+// they're served without upgrade support, which is what lets us turn HTTP/2 off.
+pub async fn serve(tcp: TcpListener, router: Router, settings: ConnectionSettings) {
+    let builder = Arc::new(settings.builder());
+    loop {
+        let (mut stream, peer) = match tcp.accept().await {
+            Ok(connection) => connection,
+            // Errors like running out of file descriptors usually pass, so we wait a moment
+            // rather than give up on the listener.
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to accept a connection");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        if settings.tcp_nodelay {
+            if let Err(err) = stream.set_nodelay(true) {
+                tracing::debug!(%peer, error = %err, "failed to set TCP_NODELAY");
+            }
+        }
+        let router = router.clone();
+        let builder = builder.clone();
+        let proxy_protocol = settings.proxy_protocol;
+        tokio::spawn(async move {
+            let client = if proxy_protocol {
+                let header =
+                    tokio::time::timeout(HEADER_TIMEOUT, proxy_protocol::read_header(&mut stream))
+                        .await;
+                match header {
+                    Ok(Ok(client)) => client.unwrap_or(peer),
+                    Ok(Err(err)) => {
+                        tracing::debug!(%peer, error = %err, "rejected a connection");
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!(%peer, "timed out waiting for the PROXY protocol header");
+                        return;
+                    }
+                }
+            } else {
+                peer
+            };
+
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ClientAddr(client));
+                router.clone().call(request.map(Body::new))
+            });
+            let connection = builder.serve_connection(TokioIo::new(stream), service);
+            if let Err(err) = connection.await {
+                tracing::debug!(%client, error = %err, "connection closed with an error");
+            }
+        });
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+
+// The log filter as reported and set through the admin API, in RUST_LOG syntax, e.g.
+// "sqlx=debug,info".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogLevelStatus {
+    filter: String,
+}
+
+impl LogLevelStatus {
+    pub fn new(filter: impl Into<String>) -> Self {
+        Self {
+            filter: filter.into(),
+        }
+    }
+
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+}
+
+// Parses a filter the way the binary does at startup: anything the directives don't mention is
+// logged at info.
+pub fn env_filter(directives: &str) -> Result<EnvFilter, String> {
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(directives)
+        .map_err(|err| err.to_string())
+}
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+// Swaps the log filter of a running service, e.g. to turn on sqlx=debug while diagnosing an
+// incident. The subscriber is set up by whoever embeds the service, so they hand us a function
+// that installs a new filter, typically wrapping a tracing_subscriber reload handle. Without one,
+// the log level can't be changed and the admin routes are disabled.
+#[derive(Default)]
+pub struct LogLevel {
+    // The filter currently installed, and the function installing a new one.
+    inner: Option<(RwLock<String>, Reload)>,
+}
+
+impl LogLevel {
+    pub fn new(
+        filter: impl Into<String>,
+        reload: impl Fn(EnvFilter) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner: Some((RwLock::new(filter.into()), Box::new(reload))),
+        }
+    }
+
+    pub fn status(&self) -> Result<LogLevelStatus, Error> {
+        let (filter, _) = self.inner.as_ref().ok_or(Error::Forbidden)?;
+        Ok(LogLevelStatus::new(filter.read().unwrap().clone()))
+    }
+
+    pub fn set(&self, status: LogLevelStatus) -> Result<LogLevelStatus, Error> {
+        let (filter, reload) = self.inner.as_ref().ok_or(Error::Forbidden)?;
+        let parsed = env_filter(&status.filter).map_err(|detail| {
+            Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "invalid_log_filter",
+                    i18n::message("invalid_log_filter", &[("detail", &detail)]),
+                )
+                .with_field("filter"),
+            )
+        })?;
+        // The lock is held while reloading, so concurrent updates can't leave the reported filter
+        // out of step with the installed one.
+        let mut current = filter.write().unwrap();
+        reload(parsed).map_err(Error::ServiceUnavailable)?;
+        tracing::info!(filter = %status.filter, "log filter changed");
+        *current = status.filter;
+        Ok(LogLevelStatus::new(current.clone()))
+    }
+}
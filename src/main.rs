@@ -1,15 +1,32 @@
-use router::create_router;
+use http_rest_api_service::config::Config;
+use http_rest_api_service::i18n::Catalogs;
+use http_rest_api_service::listener::{self, ConnectionSettings};
+use http_rest_api_service::log_level::LogLevel;
+use http_rest_api_service::migrations::{self, Phase};
+use http_rest_api_service::pool;
+use http_rest_api_service::restore;
+use http_rest_api_service::router::{create_router_for, Routes};
+use http_rest_api_service::shard::{self, Shards};
+use http_rest_api_service::state::AppState;
+use std::fmt::Display;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 
-mod api;
-mod error;
-mod router;
-mod todo;
+// Reports a problem that keeps the service from starting and exits, rather than panicking with a
+// message that only makes sense to someone who knows the code.
+fn exit_with(problem: impl Display) -> ! {
+    eprintln!("error: {problem}");
+    std::process::exit(1);
+}
 
-async fn init_dbpool() -> Result<sqlx::Pool<sqlx::Sqlite>, sqlx::Error> {
-    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+async fn init_dbpool(
+    phase: Phase,
+    statement_cache_capacity: usize,
+) -> Result<sqlx::Pool<sqlx::Sqlite>, String> {
+    use sqlx::sqlite::SqliteConnectOptions;
 
     // We'll try to read the DATABASE_URL environment variable or default sqlite:db.sqlite if not defined
     // (Which opens a file called db.sqlite in the current working directory)
@@ -17,65 +34,349 @@ async fn init_dbpool() -> Result<sqlx::Pool<sqlx::Sqlite>, sqlx::Error> {
         std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:db.sqlite".to_string());
 
     // When we connect to the database, we ask the driver to create the database if it doesn't already exit.
-    let db_pool = SqlitePoolOptions::new()
+    let options = SqliteConnectOptions::from_str(&db_connection_str).map_err(|err| {
+        format!(
+            "DATABASE_URL `{db_connection_str}` isn't a SQLite URL like sqlite:db.sqlite: {err}"
+        )
+    })?;
+    let mut pool_options = pool::options();
+    if pool::is_in_memory(&db_connection_str) {
+        tracing::warn!("using an in-memory database, which is lost when the service stops");
+        pool_options = pool::in_memory(pool_options);
+    }
+    let db_pool = pool_options
         .connect_with(
-            SqliteConnectOptions::from_str(&db_connection_str)?
+            options
                 // SQLx will generate a `CREATE DATABASE IF NOT EXISTS` for us
-                .create_if_missing(true),
+                .create_if_missing(true)
+                .statement_cache_capacity(statement_cache_capacity),
         )
         .await
-        .expect("can't connect to database");
+        .map_err(|err| {
+            format!(
+                "can't open the database at {db_connection_str}: {err}; \
+                 check that its directory exists and is writable"
+            )
+        })?;
 
-    // After we've connected to the DB, we run any necessary migrations.
-    sqlx::migrate!()
-        // We can pass our newly created DB pool directly to SQLx, which will obtain a connection from the pool.
-        .run(&db_pool)
+    // After we've connected to the DB, we run the migrations of the phase. At startup that's only
+    // the expand ones, which are safe while older instances share the database.
+    migrations::run(&db_pool, phase)
         .await
-        .expect("database migration failed");
+        .map_err(|err| format!("can't migrate the database at {db_connection_str}: {err}"))?;
     Ok(db_pool)
 }
 
-fn init_tracing() {
-    use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
+// The addresses to listen on, and the routes each of them serves. BIND_ADDR takes a comma-separated
+// list, e.g. "0.0.0.0:3000,[::]:3000" for IPv4 and IPv6. When ADMIN_BIND_ADDR is set, e.g. to a
+// private interface, the admin API is only served there, and BIND_ADDR serves everything else.
+// Earlier versions read BIND_ARRD by mistake, which we still accept so existing deployments keep
+// working.
+fn listeners() -> Result<Vec<(SocketAddr, Routes)>, Vec<String>> {
+    let (name, value) = match (std::env::var("BIND_ADDR"), std::env::var("BIND_ARRD")) {
+        (Ok(value), _) => ("BIND_ADDR", value),
+        (Err(_), Ok(value)) => {
+            tracing::warn!("BIND_ARRD is deprecated, set BIND_ADDR instead");
+            ("BIND_ARRD", value)
+        }
+        (Err(_), Err(_)) => ("BIND_ADDR", "127.0.0.1:3000".to_string()),
+    };
+    let admin = std::env::var("ADMIN_BIND_ADDR").ok();
+    let public_routes = match admin {
+        Some(_) => Routes::Public,
+        None => Routes::All,
+    };
+
+    let mut listeners = Vec::new();
+    let mut problems = Vec::new();
+    let lists = [
+        (name, Some(value), public_routes),
+        ("ADMIN_BIND_ADDR", admin, Routes::Admin),
+    ];
+    for (name, value, routes) in lists {
+        for addr in value
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+        {
+            match SocketAddr::from_str(addr) {
+                Ok(addr) => listeners.push((addr, routes)),
+                Err(_) => problems.push(format!(
+                    "{name}: `{addr}` isn't an address like 127.0.0.1:3000 or [::1]:3000"
+                )),
+            }
+        }
+    }
+    match problems.is_empty() {
+        true => Ok(listeners),
+        false => Err(problems),
+    }
+}
+
+// Makes sure we can write files to a directory the service needs, creating it if necessary, so a
+// misconfigured path is reported now rather than when the first export or backup fails.
+async fn check_writable(name: &str, dir: &Path) -> Result<(), String> {
+    let problem = |err: std::io::Error| format!("{name} {} isn't writable: {err}", dir.display());
+    tokio::fs::create_dir_all(dir).await.map_err(problem)?;
+    let probe = dir.join(".write-check");
+    tokio::fs::write(&probe, b"").await.map_err(problem)?;
+    tokio::fs::remove_file(&probe).await.map_err(problem)
+}
+
+// Checks everything the service needs before it starts serving, on top of the configuration
+// problems already found, so a misconfiguration stops it right away with all the problems listed,
+// instead of a panic halfway through startup or an error on the first request that happens to
+// need the broken piece.
+async fn self_check(
+    config: &Config,
+    mut problems: Vec<String>,
+) -> Result<(Vec<(SocketAddr, Routes)>, Catalogs), Vec<String>> {
+    let listeners = listeners().map_err(|found| problems.extend(found)).ok();
+    if let Err(problem) = check_writable("EXPORT_DIR", &config.export_dir).await {
+        problems.push(problem);
+    }
+    if let Some(dir) = &config.backup_dir {
+        if let Err(problem) = check_writable("BACKUP_DIR", dir).await {
+            problems.push(problem);
+        }
+    }
+    if let Some(dir) = &config.shard_dir {
+        if let Err(problem) = check_writable("SHARD_DIR", dir).await {
+            problems.push(problem);
+        }
+        if config.rate_limit_tenant_header.is_none() {
+            problems.push(
+                "SHARD_DIR is set, but RATE_LIMIT_TENANT_HEADER, which names the tenant, isn't"
+                    .to_string(),
+            );
+        }
+    }
+    if config.rate_limit_tenant_header.is_some() && config.tenant_secret.is_none() {
+        problems.push(
+            "RATE_LIMIT_TENANT_HEADER is set, but TENANT_SECRET, which the gateway signs it with, \
+             isn't"
+                .to_string(),
+        );
+    }
+    let catalogs = Catalogs::load(config.locales_dir.as_deref())
+        .map_err(|err| problems.push(format!("can't load the message catalogs: {err}")))
+        .ok();
+    match (listeners, catalogs) {
+        (Some(listeners), Some(catalogs)) if problems.is_empty() => Ok((listeners, catalogs)),
+        _ => Err(problems),
+    }
+}
+
+// `migrate [--phase=expand|cleanup]` applies the migrations of a phase and exits. Deploys run
+// `migrate --phase=cleanup` once every instance has been upgraded; see migrations.rs.
+async fn run_migrate(args: &[String]) {
+    let phase = match args {
+        [] => Ok(Phase::Expand),
+        [arg] => match arg.strip_prefix("--phase=") {
+            Some(phase) => phase.parse(),
+            None => Err(format!("unknown argument `{arg}`")),
+        },
+        _ => Err("too many arguments".to_string()),
+    };
+    let phase = phase.unwrap_or_else(|problem| {
+        eprintln!("{problem}\nusage: migrate [--phase=expand|cleanup]");
+        std::process::exit(2);
+    });
+    let statement_cache_capacity = Config::from_env().statement_cache_capacity;
+    init_dbpool(phase, statement_cache_capacity)
+        .await
+        .unwrap_or_else(|problem| exit_with(problem));
+    // The shards are migrated along with the main database, so none of them is left behind.
+    if let Some(dir) = std::env::var_os("SHARD_DIR") {
+        let shards = shard::list(Path::new(&dir))
+            .await
+            .unwrap_or_else(|err| exit_with(format!("can't list the shards in SHARD_DIR: {err}")));
+        for path in &shards {
+            shard::open(path, phase, statement_cache_capacity)
+                .await
+                .unwrap_or_else(|problem| exit_with(problem));
+        }
+        println!("migrated {} shards", shards.len());
+    }
+    println!("applied the {phase} migrations");
+}
+
+// `provision <tenant>...` creates the shards of tenants in SHARD_DIR, or migrates the ones that
+// exist already, and exits. Requests for a tenant are only served once it's provisioned.
+async fn run_provision(tenants: &[String]) {
+    if tenants.is_empty() {
+        eprintln!("usage: provision <tenant>...");
+        std::process::exit(2);
+    }
+    let Some(dir) = std::env::var_os("SHARD_DIR") else {
+        exit_with("SHARD_DIR isn't set, so tenants don't have databases of their own");
+    };
+    let statement_cache_capacity = Config::from_env().statement_cache_capacity;
+    for tenant in tenants {
+        let path = shard::provision(Path::new(&dir), tenant, statement_cache_capacity)
+            .await
+            .unwrap_or_else(|problem| exit_with(problem));
+        println!("provisioned {tenant} in {}", path.display());
+    }
+}
+
+// Reminds whoever reads the logs that a deploy isn't finished until its cleanup migrations ran.
+async fn warn_pending_cleanup(dbpool: &sqlx::SqlitePool) {
+    match migrations::pending_cleanup(dbpool).await {
+        Ok(pending) if !pending.is_empty() => {
+            let versions: Vec<i64> = pending.iter().map(|m| m.version).collect();
+            tracing::warn!(
+                ?versions,
+                "cleanup migrations are pending; run `migrate --phase=cleanup` once every instance is upgraded"
+            );
+        }
+        Ok(_) => {}
+        Err(err) => tracing::warn!(error = %err, "can't check for pending cleanup migrations"),
+    }
+}
+
+// `restore <snapshot> [--verify-only]` restores a backup snapshot into the database and exits,
+// the same as POST /v1/admin/restore does for a running service.
+async fn run_restore(dbpool: &sqlx::SqlitePool, args: &[String]) {
+    let Some(snapshot) = args.first().map(std::path::Path::new) else {
+        eprintln!("usage: restore <snapshot> [--verify-only]");
+        std::process::exit(2);
+    };
+    let result = if args.iter().any(|arg| arg == "--verify-only") {
+        restore::verify(snapshot).await
+    } else {
+        restore::restore(dbpool, snapshot).await
+    };
+    match result {
+        Ok(report) => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("the report is serializable")
+        ),
+        Err(err) => {
+            eprintln!("can't restore {}: {err}", snapshot.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+// Sets up tracing, returning the handle the admin API uses to change the log filter at runtime.
+fn init_tracing() -> LogLevel {
+    use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, reload, EnvFilter};
 
     // Fetches the RUST_LOG environment providing a default value if it's not defined
     let rust_log = std::env::var(EnvFilter::DEFAULT_ENV)
         .unwrap_or_else(|_| "sqlx=info,tower_http=debug,info".to_string());
 
+    // Constructs an environment filter, with the default log level set to info or using the value
+    // provided by RUST_LOG otherwise. It's wrapped in a reload layer so it can be swapped later.
+    let (filter, handle) = reload::Layer::new(
+        EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .parse_lossy(&rust_log),
+    );
+
     // Returns the default global registry
-    tracing_subscriber::registry()
-        // Adds a formatting layer, which provides human-readable trace formatting
-        .with(fmt::layer())
-        // Constructs an environment filter, with the default log level set to info or using the
-        // value provided by RUST_LOG otherwise
-        .with(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .parse_lossy(rust_log),
-        )
-        .init();
+    let registry = tracing_subscriber::registry()
+        // Adds a formatting layer, which provides human-readable trace formatting. The filter only
+        // applies to the formatting layer, so it doesn't hide the runtime's own spans from the
+        // console layer.
+        .with(fmt::layer().with_filter(filter));
+
+    // With the console feature, tokio-console can connect on 127.0.0.1:6669 (configurable with the
+    // TOKIO_CONSOLE_BIND variable) to inspect tasks and their wakeups.
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    registry.init();
+
+    LogLevel::new(rust_log, move |filter| {
+        handle.reload(filter).map_err(|err| err.to_string())
+    })
 }
 
 #[tokio::main]
 async fn main() {
     // Initializes the tracing and logging for our service and its dependencies
-    init_tracing();
+    let log_level = init_tracing();
 
-    // Initializes the DB pool
-    let dbpool = init_dbpool().await.expect("couldn't initialize DB pool");
+    // Subcommands run against the database instead of starting the server.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = args.split_first().map(|(cmd, rest)| (cmd.as_str(), rest));
+    if let Some(("migrate", args)) = command {
+        run_migrate(args).await;
+        return;
+    }
+    if let Some(("provision", args)) = command {
+        run_provision(args).await;
+        return;
+    }
 
-    // Creates the core application service and its routes
-    let router = create_router(dbpool).await;
+    // Reads the runtime configuration from the environment. It's checked along with everything
+    // else we need before starting anything below, once we know we're starting the server.
+    let (config, problems) = match Config::try_from_env() {
+        Ok(config) => (config, Vec::new()),
+        // We carry on with the defaults for now, so the rest of the problems are reported too.
+        Err(problems) => (Config::from_env(), problems),
+    };
 
-    // Fetches the binding address from the environment variable
-    // BIND_ADDR or uses the default value of 127.0.0.1:3000
-    let bind_addr = std::env::var("BIND_ARRD").unwrap_or_else(|_| "127.0.0.1:3000".to_string());
+    // Initializes the DB pool
+    let dbpool = init_dbpool(Phase::Expand, config.statement_cache_capacity)
+        .await
+        .unwrap_or_else(|problem| exit_with(problem));
+    warn_pending_cleanup(&dbpool).await;
 
-    let addr = SocketAddr::from_str(&bind_addr).unwrap();
-    let tcp = TcpListener::bind(&addr).await.unwrap();
+    if let Some(("restore", args)) = command {
+        run_restore(&dbpool, args).await;
+        return;
+    }
 
-    // Creates the service and starts the HTTP server
-    axum::serve(tcp, router.into_make_service())
+    // The message catalogs are used for localized error messages.
+    let (listeners, catalogs) = self_check(&config, problems)
         .await
-        .expect("unable to start server");
+        .unwrap_or_else(|problems| exit_with(problems.join("\n       ")));
+
+    // Binding before we start any background work means a port that's already taken stops us
+    // cleanly.
+    let mut bound = Vec::with_capacity(listeners.len());
+    for (addr, routes) in listeners {
+        let tcp = TcpListener::bind(&addr)
+            .await
+            .unwrap_or_else(|err| exit_with(format!("can't listen on {addr}: {err}")));
+        bound.push((tcp, addr, routes));
+    }
+
+    let warm = pool::warm_up(&dbpool, config.pool_min_idle).await;
+    tracing::info!(connections = warm, "warmed up the database pool");
+
+    let settings = ConnectionSettings::from(&config);
+    let state = AppState::new(dbpool, config, catalogs).with_log_level(log_level);
+    state.spawn_tasks();
+    let shards = state
+        .config
+        .shard_dir
+        .as_ref()
+        .map(|dir| Arc::new(Shards::new(dir.clone(), state.clone())));
+
+    // Creates the service for each listener, with the routes it serves, and starts the HTTP servers
+    let mut servers = tokio::task::JoinSet::new();
+    for (tcp, addr, routes) in bound {
+        let mut router = create_router_for(state.clone(), routes).await;
+        if let (Some(shards), false) = (&shards, routes == Routes::Admin) {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                shards.clone(),
+                shard::dispatch,
+            ));
+        }
+        tracing::info!(%addr, ?routes, "listening");
+        // The private admin listener is meant to be reached directly rather than through the load
+        // balancer.
+        let mut settings = settings.clone();
+        settings.proxy_protocol &= routes != Routes::Admin;
+        servers.spawn(listener::serve(tcp, router, settings));
+    }
+    // The servers run until the process is stopped, so one of them returning at all is fatal.
+    match servers.join_next().await {
+        Some(Err(err)) => exit_with(format!("a server crashed: {err}")),
+        Some(Ok(())) | None => exit_with("a server stopped"),
+    }
 }
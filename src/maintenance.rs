@@ -0,0 +1,83 @@
+use crate::error::Error;
+use crate::i18n;
+use axum::extract::{Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+// The maintenance status as reported and set through the admin API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaintenanceStatus {
+    enabled: bool,
+    // An optional message shown to clients whose writes are rejected. Without one, clients get the
+    // localized default message.
+    message: Option<String>,
+}
+
+impl MaintenanceStatus {
+    pub fn new(enabled: bool, message: Option<String>) -> Self {
+        Self { enabled, message }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+// Holds the current maintenance status. A plain RwLock is fine here because the lock is never
+// held across an await point.
+pub struct Maintenance {
+    status: RwLock<MaintenanceStatus>,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            status: RwLock::new(MaintenanceStatus {
+                enabled,
+                message: None,
+            }),
+        }
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn set(&self, status: MaintenanceStatus) {
+        *self.status.write().unwrap() = status;
+    }
+}
+
+// A middleware which rejects mutations with a 503 while maintenance mode is on. Reads keep working.
+pub async fn reject_writes(
+    State(maintenance): State<Arc<Maintenance>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let is_read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    // The admin routes stay writable, otherwise there'd be no way to switch maintenance mode off.
+    let is_admin = request.uri().path().starts_with("/v1/admin");
+
+    if !is_read && !is_admin {
+        let status = maintenance.status();
+        if status.enabled {
+            return Err(Error::ServiceUnavailable(
+                status
+                    .message
+                    .unwrap_or_else(|| i18n::message("maintenance", &[])),
+            ));
+        }
+    }
+
+    Ok(next.run(request).await)
+}
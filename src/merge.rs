@@ -0,0 +1,165 @@
+use crate::error::{Error, RequestError};
+use crate::geo;
+use crate::hooks::Hooks;
+use crate::i18n;
+use crate::ids;
+use crate::status::TodoStatus;
+use crate::todo::{format_due, resolve_due, Todo};
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+
+// Merges duplicates of a todo into it. The primary todo survives and the duplicates are deleted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MergeRequest {
+    #[serde(with = "crate::ids::id")]
+    primary_id: i64,
+    #[serde(with = "crate::ids::list")]
+    duplicate_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MergeResponse {
+    // The primary todo after the merge.
+    todo: Todo,
+    // The duplicates that were merged into it and deleted.
+    #[serde(with = "crate::ids::list")]
+    merged: Vec<i64>,
+}
+
+impl MergeRequest {
+    pub fn new(primary_id: i64, duplicate_ids: Vec<i64>) -> Self {
+        Self {
+            primary_id,
+            duplicate_ids,
+        }
+    }
+
+    // Appends the bodies of the duplicates to the primary's, skipping bodies it already has, and
+    // gives it the earliest due date if it doesn't have one. The primary also takes over the most
+    // recent view of any of them. The hooks see the merge as an update of the primary and a delete
+    // of each duplicate, and run before the transaction, since they may read the todos themselves.
+    // Everything is written in one transaction, so a failure leaves all the todos as they were.
+    pub async fn apply(self, dbpool: SqlitePool, hooks: &Hooks) -> Result<MergeResponse, Error> {
+        if self.duplicate_ids.is_empty() {
+            return Err(invalid_merge(
+                i18n::message("merge_empty", &[]),
+                "duplicate_ids",
+            ));
+        }
+        if self.duplicate_ids.contains(&self.primary_id) {
+            return Err(invalid_merge(
+                i18n::message("merge_self", &[]),
+                "duplicate_ids",
+            ));
+        }
+
+        let primary: Todo = query_as("select * from todos where id = ?")
+            .bind(self.primary_id)
+            .fetch_optional(&dbpool)
+            .await?
+            .ok_or_else(|| missing(self.primary_id, "primary_id"))?;
+
+        let mut bodies = vec![primary.body().to_string()];
+        let mut earliest_due: Option<NaiveDateTime> = None;
+        let mut merged = Vec::new();
+        for (index, id) in self.duplicate_ids.iter().enumerate() {
+            // Listing a duplicate twice is harmless.
+            if merged.contains(id) {
+                continue;
+            }
+            merged.push(*id);
+            let duplicate: Todo = query_as("select * from todos where id = ?")
+                .bind(id)
+                .fetch_optional(&dbpool)
+                .await?
+                .ok_or_else(|| missing(*id, &format!("duplicate_ids[{index}]")))?;
+            if !bodies.iter().any(|body| body == duplicate.body()) {
+                bodies.push(duplicate.body().to_string());
+            }
+            earliest_due = match (earliest_due, duplicate.due_at()) {
+                (Some(earliest), Some(due)) => Some(earliest.min(due)),
+                (earliest, due) => earliest.or(due),
+            };
+        }
+
+        let mut update = primary.to_update();
+        update.set_body(bodies.join("\n\n"));
+        if let (None, Some(due_at)) = (primary.due_at(), earliest_due) {
+            update = update.with_due(format_due(due_at));
+        }
+        for id in &merged {
+            hooks.before_delete(*id).await?;
+        }
+        hooks.before_update(self.primary_id, &mut update).await?;
+        let due_at = resolve_due(&dbpool, update.due()).await?;
+        let status = update.status_from(primary.status());
+        geo::check_location(update.latitude(), update.longitude(), update.place())?;
+
+        let mut tx = dbpool.begin().await?;
+
+        let todo: Todo = query_as(
+            "update todos set body = ?, due_at = ?, completed = ?, status = ?, latitude = ?, longitude = ?,
+             place = ?, stale_at = null, updated_at = datetime('now'), version = version + 1
+             where id = ? returning *",
+        )
+        .bind(update.body())
+        .bind(due_at)
+        .bind(status == TodoStatus::Done)
+        .bind(status)
+        .bind(update.latitude())
+        .bind(update.longitude())
+        .bind(update.place())
+        .bind(self.primary_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| missing(self.primary_id, "primary_id"))?;
+
+        for id in &merged {
+            query(
+                "insert into todo_views (todo_id, viewed_at)
+                 select ?1, viewed_at from todo_views where todo_id = ?2
+                 on conflict (todo_id) do update set viewed_at = max(viewed_at, excluded.viewed_at)",
+            )
+            .bind(self.primary_id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+            // Deleting the duplicate also deletes its views and records the deletion in the
+            // changes feed.
+            query("delete from todos where id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(MergeResponse { todo, merged })
+    }
+}
+
+impl MergeResponse {
+    pub fn todo(&self) -> &Todo {
+        &self.todo
+    }
+
+    pub fn merged(&self) -> &[i64] {
+        &self.merged
+    }
+}
+
+fn invalid_merge(message: String, field: &str) -> Error {
+    Error::BadRequest(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RequestError::new("invalid_merge", message).with_field(field),
+    )
+}
+
+fn missing(id: i64, field: &str) -> Error {
+    invalid_merge(
+        i18n::message("merge_missing", &[("id", &ids::encode(id))]),
+        field,
+    )
+}
@@ -0,0 +1,326 @@
+use crate::error::Error;
+use crate::i18n;
+use crate::params::invalid_param;
+use crate::tenant::Tenant;
+use axum::extract::{Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Counts API calls per tenant and hour, and writes them to the usage_meter table together with a
+// sample of the storage used and the active todos. Calls are counted in memory and written every
+// interval, so a crash loses at most one interval's worth of calls.
+pub struct Metering {
+    enabled: bool,
+    calls: Mutex<HashMap<(NaiveDateTime, String), i64>>,
+}
+
+impl Metering {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            calls: Mutex::default(),
+        }
+    }
+
+    fn count(&self, tenant: &str) {
+        let hour = current_hour();
+        let mut calls = self.calls.lock().unwrap();
+        *calls.entry((hour, tenant.to_string())).or_default() += 1;
+    }
+
+    // Writes the calls counted so far and samples the storage for the current hour. Calls that
+    // can't be written are counted again, so they're written with the next flush.
+    pub async fn flush(&self, dbpool: &SqlitePool) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let calls = std::mem::take(&mut *self.calls.lock().unwrap());
+        if let Err(err) = write_calls(dbpool, &calls).await {
+            let mut pending = self.calls.lock().unwrap();
+            for (key, count) in calls {
+                *pending.entry(key).or_default() += count;
+            }
+            return Err(err);
+        }
+        // The largest sample of the hour is kept, since storage is billed by its peak.
+        query(
+            "insert into usage_storage (hour, storage_bytes, active_todos)
+             select ?, (select page_count * page_size from pragma_page_count(), pragma_page_size()),
+             (select count(*) from todos where not completed and not archived)
+             where true
+             on conflict (hour) do update set
+             storage_bytes = max(storage_bytes, excluded.storage_bytes),
+             active_todos = max(active_todos, excluded.active_todos)",
+        )
+        .bind(current_hour())
+        .execute(dbpool)
+        .await?;
+        Ok(())
+    }
+
+    // Starts writing the meter every `interval` in the background.
+    pub fn spawn(self: Arc<Self>, dbpool: SqlitePool, interval: Duration) {
+        if !self.enabled {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = self.flush(&dbpool).await {
+                    tracing::warn!(error = ?err, "failed to write the usage meter");
+                }
+            }
+        });
+    }
+
+    // The usage between two dates for invoicing, with the calls counted since the last flush.
+    pub async fn report(
+        &self,
+        dbpool: &SqlitePool,
+        query: &UsageQuery,
+        default_plan: Option<&str>,
+    ) -> Result<UsageReport, Error> {
+        let today = Utc::now().date_naive();
+        let from = query
+            .from
+            .unwrap_or_else(|| today.with_day(1).expect("every month has a first day"));
+        let to = query.to.unwrap_or_else(|| today + ChronoDuration::days(1));
+        if from >= to {
+            return Err(invalid_param(
+                "invalid_period",
+                "to",
+                i18n::message("invalid_period", &[]),
+            ));
+        }
+        self.flush(dbpool).await?;
+
+        let (start, end) = (
+            from.and_time(Default::default()),
+            to.and_time(Default::default()),
+        );
+        let rows: Vec<(String, Option<String>, i64)> = query_as(
+            "select usage_meter.tenant, tenant_plans.plan, sum(api_calls) from usage_meter
+             left join tenant_plans on tenant_plans.tenant = usage_meter.tenant
+             where hour >= ? and hour < ? group by usage_meter.tenant order by usage_meter.tenant",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(dbpool)
+        .await?;
+        let (storage_bytes_peak, active_todos_peak): (i64, i64) = query_as(
+            "select coalesce(max(storage_bytes), 0), coalesce(max(active_todos), 0)
+             from usage_storage where hour >= ? and hour < ?",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_one(dbpool)
+        .await?;
+
+        let tenants = rows
+            .into_iter()
+            .map(|(tenant, plan, api_calls)| TenantUsage {
+                plan: match tenant.is_empty() {
+                    true => None,
+                    false => plan.or_else(|| default_plan.map(String::from)),
+                },
+                tenant: Some(tenant).filter(|tenant| !tenant.is_empty()),
+                api_calls,
+            })
+            .collect();
+        Ok(UsageReport {
+            from,
+            to,
+            tenants,
+            storage_bytes_peak,
+            active_todos_peak,
+        })
+    }
+}
+
+async fn write_calls(
+    dbpool: &SqlitePool,
+    calls: &HashMap<(NaiveDateTime, String), i64>,
+) -> Result<(), Error> {
+    let mut tx = dbpool.begin().await?;
+    for ((hour, tenant), count) in calls {
+        query(
+            "insert into usage_meter (hour, tenant, api_calls) values (?, ?, ?)
+             on conflict (hour, tenant) do update set api_calls = api_calls + excluded.api_calls",
+        )
+        .bind(hour)
+        .bind(tenant)
+        .bind(count)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn current_hour() -> NaiveDateTime {
+    let now = Utc::now().naive_utc();
+    now.date()
+        .and_hms_opt(now.hour(), 0, 0)
+        .expect("the current hour is a valid time")
+}
+
+// A middleware which counts API calls for billing, by the tenant the gateway vouched for (see
+// tenant.rs). Everyone else's calls are counted as calls without a tenant, whatever headers they
+// send, so clients can't bill another tenant or fill the table with made-up ones. The admin API and the health checks aren't billed, and requests turned away by the
+// rate limiter never get here.
+pub async fn meter(
+    State(metering): State<Arc<Metering>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if metering.enabled
+        && path.starts_with("/v1/")
+        && !path.starts_with("/v1/admin")
+        && request.method() != Method::OPTIONS
+    {
+        let tenant = request
+            .extensions()
+            .get::<Tenant>()
+            .map(Tenant::as_str)
+            .unwrap_or_default();
+        metering.count(tenant);
+    }
+    next.run(request).await
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+// The query of GET /v1/admin/usage. The period runs from the start of `from` up to, but not
+// including, `to`, and defaults to the current month so far.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UsageQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<NaiveDate>,
+    #[serde(default)]
+    format: UsageFormat,
+}
+
+impl UsageQuery {
+    pub fn with_period(mut self, from: NaiveDate, to: NaiveDate) -> Self {
+        self.from = Some(from);
+        self.to = Some(to);
+        self
+    }
+
+    pub fn with_format(mut self, format: UsageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> UsageFormat {
+        self.format
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UsageReport {
+    from: NaiveDate,
+    to: NaiveDate,
+    tenants: Vec<TenantUsage>,
+    // The instance's, since todos aren't split by tenant: the most storage used and the most active
+    // todos in any hour of the period.
+    storage_bytes_peak: i64,
+    active_todos_peak: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantUsage {
+    // None for requests without a tenant.
+    tenant: Option<String>,
+    plan: Option<String>,
+    api_calls: i64,
+}
+
+impl TenantUsage {
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn plan(&self) -> Option<&str> {
+        self.plan.as_deref()
+    }
+
+    pub fn api_calls(&self) -> i64 {
+        self.api_calls
+    }
+}
+
+impl UsageReport {
+    pub fn tenants(&self) -> &[TenantUsage] {
+        &self.tenants
+    }
+
+    pub fn storage_bytes_peak(&self) -> i64 {
+        self.storage_bytes_peak
+    }
+
+    pub fn active_todos_peak(&self) -> i64 {
+        self.active_todos_peak
+    }
+
+    pub fn file_name(&self, format: UsageFormat) -> String {
+        let extension = match format {
+            UsageFormat::Json => "json",
+            UsageFormat::Csv => "csv",
+        };
+        format!("usage-{}-{}.{extension}", self.from, self.to)
+    }
+
+    // One row per tenant, for spreadsheets and invoicing tools. The instance's peaks are repeated on
+    // every row, so each row stands on its own.
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("from,to,tenant,plan,api_calls,storage_bytes_peak,active_todos_peak\r\n");
+        for usage in &self.tenants {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{}\r",
+                self.from,
+                self.to,
+                csv_field(usage.tenant().unwrap_or_default()),
+                csv_field(usage.plan().unwrap_or_default()),
+                usage.api_calls,
+                self.storage_bytes_peak,
+                self.active_todos_peak,
+            )
+            .ok();
+        }
+        csv
+    }
+}
+
+// Quotes a field when it needs to be, as RFC 4180 has it. Fields starting with a formula character
+// get a leading quote mark, so spreadsheets don't evaluate tenant names.
+fn csv_field(value: &str) -> String {
+    let value = match value.starts_with(['=', '+', '-', '@']) {
+        true => format!("'{value}"),
+        false => value.to_string(),
+    };
+    match value.contains([',', '"', '\r', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value,
+    }
+}
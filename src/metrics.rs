@@ -0,0 +1,314 @@
+use crate::config::Config;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// The windows we report the error budget burn rate over, in minutes. A fast window catches sudden
+// outages and a slow one catches steady degradation.
+const BURN_WINDOWS: [(&str, u64); 2] = [("5m", 5), ("1h", 60)];
+
+#[derive(Default)]
+struct RouteStats {
+    // Counts per bucket; the last one is +Inf.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+    statuses: BTreeMap<u16, u64>,
+}
+
+// Good and bad requests in one minute, for the SLO.
+struct Minute {
+    minute: u64,
+    good: u64,
+    bad: u64,
+}
+
+// The labels of a route's series: the method, the route template, and the tenant when requests are
+// labeled by tenant.
+type RouteKey = (String, String, Option<String>);
+
+#[derive(Default)]
+struct Inner {
+    // Keyed by method and route template, so the label set stays small.
+    routes: BTreeMap<RouteKey, RouteStats>,
+    minutes: VecDeque<Minute>,
+    // The tenants that have their own label so far, when there's no allowlist.
+    tenants: BTreeSet<String>,
+}
+
+// Which tenants get a label of their own. Every tenant label multiplies the number of series, so
+// tenants beyond the allowlist or the limit share the "other" label, and requests without a tenant
+// are labeled "none".
+pub struct TenantLabels {
+    header: HeaderName,
+    allowlist: Vec<String>,
+    max_tenants: usize,
+}
+
+impl TenantLabels {
+    pub fn new(header: HeaderName, allowlist: Vec<String>, max_tenants: usize) -> Self {
+        Self {
+            header,
+            allowlist,
+            max_tenants,
+        }
+    }
+}
+
+// Tenant names longer than this are cut short rather than stored in full.
+const MAX_TENANT_LEN: usize = 64;
+
+// Request metrics for the Prometheus endpoint: latency histograms per route, and the burn rate of
+// the error budget for a latency and availability SLO. A request meets the SLO when it doesn't fail
+// with a server error and completes within the latency threshold.
+pub struct Metrics {
+    slo_target: f64,
+    slo_latency: Duration,
+    tenants: Option<TenantLabels>,
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    pub fn new(slo_target: f64, slo_latency: Duration) -> Self {
+        Self {
+            // A target of 100% would leave no error budget to burn, so we cap it.
+            slo_target: slo_target.clamp(0.0, 0.9999),
+            slo_latency,
+            tenants: None,
+            inner: Mutex::default(),
+        }
+    }
+
+    // Labels the request metrics by tenant.
+    pub fn with_tenants(mut self, tenants: TenantLabels) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    // The tenant label for a request, or None when we don't label by tenant.
+    fn tenant(&self, headers: &HeaderMap) -> Option<String> {
+        let labels = self.tenants.as_ref()?;
+        let Some(tenant) = headers
+            .get(&labels.header)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|tenant| !tenant.is_empty())
+        else {
+            return Some("none".to_string());
+        };
+        let tenant: String = tenant.chars().take(MAX_TENANT_LEN).collect();
+        if !labels.allowlist.is_empty() {
+            return Some(match labels.allowlist.contains(&tenant) {
+                true => tenant,
+                false => "other".to_string(),
+            });
+        }
+        let mut inner = self.inner.lock().expect("metrics lock poisoned");
+        if inner.tenants.contains(&tenant) || inner.tenants.len() < labels.max_tenants {
+            inner.tenants.insert(tenant.clone());
+            Some(tenant)
+        } else {
+            Some("other".to_string())
+        }
+    }
+
+    fn observe(&self, key: RouteKey, status: u16, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let minute = current_minute();
+        let good = status < 500 && elapsed <= self.slo_latency;
+
+        let mut inner = self.inner.lock().expect("metrics lock poisoned");
+        let stats = inner.routes.entry(key).or_default();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        stats.buckets[bucket] += 1;
+        stats.sum += seconds;
+        stats.count += 1;
+        *stats.statuses.entry(status).or_default() += 1;
+
+        if inner.minutes.back().map(|last| last.minute) != Some(minute) {
+            inner.minutes.push_back(Minute {
+                minute,
+                good: 0,
+                bad: 0,
+            });
+        }
+        let longest = BURN_WINDOWS.iter().map(|(_, minutes)| *minutes).max();
+        while inner.minutes.len() as u64 > longest.unwrap_or_default() {
+            inner.minutes.pop_front();
+        }
+        let current = inner.minutes.back_mut().expect("we just pushed a minute");
+        if good {
+            current.good += 1;
+        } else {
+            current.bad += 1;
+        }
+    }
+
+    // Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().expect("metrics lock poisoned");
+        let mut out = String::new();
+
+        out.push_str("# HELP http_request_duration_seconds Request latency by route.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (key, stats) in &inner.routes {
+            let labels = labels(key);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&stats.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                )
+                .ok();
+            }
+            writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.count
+            )
+            .ok();
+            writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                stats.sum
+            )
+            .ok();
+            writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                stats.count
+            )
+            .ok();
+        }
+
+        out.push_str("# HELP http_requests_total Requests by route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (key, stats) in &inner.routes {
+            let labels = labels(key);
+            for (status, count) in &stats.statuses {
+                writeln!(
+                    out,
+                    "http_requests_total{{{labels},status=\"{status}\"}} {count}"
+                )
+                .ok();
+            }
+        }
+
+        out.push_str("# HELP slo_target The fraction of requests that should meet the SLO.\n");
+        out.push_str("# TYPE slo_target gauge\n");
+        writeln!(out, "slo_target {}", self.slo_target).ok();
+        out.push_str(
+            "# HELP slo_latency_threshold_seconds Requests slower than this miss the SLO.\n",
+        );
+        out.push_str("# TYPE slo_latency_threshold_seconds gauge\n");
+        writeln!(
+            out,
+            "slo_latency_threshold_seconds {}",
+            self.slo_latency.as_secs_f64()
+        )
+        .ok();
+
+        // A burn rate of 1 uses up the error budget exactly over the SLO period; alerts typically
+        // fire at 14.4 over an hour or 6 over six hours.
+        out.push_str(
+            "# HELP slo_error_budget_burn_rate How fast the error budget is being used up.\n",
+        );
+        out.push_str("# TYPE slo_error_budget_burn_rate gauge\n");
+        let now = current_minute();
+        for (window, minutes) in BURN_WINDOWS {
+            let (good, bad) = inner
+                .minutes
+                .iter()
+                .filter(|minute| minute.minute + minutes > now)
+                .fold((0, 0), |(good, bad), minute| {
+                    (good + minute.good, bad + minute.bad)
+                });
+            let burn_rate = match good + bad {
+                0 => 0.0,
+                total => (bad as f64 / total as f64) / (1.0 - self.slo_target),
+            };
+            writeln!(
+                out,
+                "slo_error_budget_burn_rate{{window=\"{window}\"}} {burn_rate}"
+            )
+            .ok();
+        }
+
+        out
+    }
+}
+
+fn labels((method, route, tenant): &RouteKey) -> String {
+    let mut labels = format!("method=\"{method}\",route=\"{route}\"");
+    if let Some(tenant) = tenant {
+        // Tenants come from a request header, so they're escaped as the format requires.
+        let tenant = tenant
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        write!(labels, ",tenant=\"{tenant}\"").ok();
+    }
+    labels
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
+
+impl From<&Config> for Metrics {
+    fn from(config: &Config) -> Self {
+        let metrics = Metrics::new(
+            config.slo_target,
+            Duration::from_millis(config.slo_latency_ms),
+        );
+        match &config.metrics_tenant_header {
+            Some(header) => metrics.with_tenants(TenantLabels::new(
+                header.clone(),
+                config.metrics_tenants.clone(),
+                config.metrics_max_tenants,
+            )),
+            None => metrics,
+        }
+    }
+}
+
+// A middleware recording the latency and status of every request, labeled with the route template
+// (e.g. /v1/todos/:id) rather than the actual path, and the tenant if configured.
+pub async fn record(State(metrics): State<Arc<Metrics>>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        // Requests that didn't match a route would otherwise add a label per path.
+        .unwrap_or_else(|| "unmatched".to_string());
+    let tenant = metrics.tenant(request.headers());
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics.observe(
+        (method, route, tenant),
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
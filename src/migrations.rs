@@ -0,0 +1,211 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{MigrateError, Migration, Migrator};
+use sqlx::SqlitePool;
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+// Our migrations come in two kinds, so a rolling deploy can run the old and the new version of the
+// service against the same database.
+//
+// Expand migrations only add things, like tables, indexes, or columns with defaults, which the old
+// version doesn't notice. They're applied at startup.
+//
+// Cleanup migrations drop or rewrite things the old version still relies on. Their file names start
+// with "cleanup", e.g. 20261101090000_cleanup_drop_todo_legacy_flag.sql, and they only run from
+// `migrate --phase=cleanup` once every instance runs the version that no longer needs them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Expand,
+    Cleanup,
+}
+
+impl FromStr for Phase {
+    type Err = String;
+
+    fn from_str(phase: &str) -> Result<Self, Self::Err> {
+        match phase {
+            "expand" => Ok(Phase::Expand),
+            "cleanup" => Ok(Phase::Cleanup),
+            _ => Err(format!(
+                "unknown migration phase `{phase}`; use expand or cleanup"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Expand => "expand",
+            Phase::Cleanup => "cleanup",
+        })
+    }
+}
+
+// sqlx turns the underscores in file names into spaces.
+pub fn is_cleanup(migration: &Migration) -> bool {
+    migration.description.starts_with("cleanup ")
+}
+
+// The migrations a phase applies: the expand ones for Expand, and all of them for Cleanup.
+pub fn migrator(phase: Phase) -> Migrator {
+    let mut migrator = sqlx::migrate!();
+    if phase == Phase::Expand {
+        let expand: Vec<Migration> = migrator
+            .iter()
+            .filter(|m| !is_cleanup(m))
+            .cloned()
+            .collect();
+        migrator.migrations = Cow::Owned(expand);
+        // The database may have migrations we don't know about: the cleanup ones we just left
+        // out, or, while a deploy is rolling out, the expand ones of a newer version. Neither
+        // should stop this version from starting.
+        migrator.set_ignore_missing(true);
+    }
+    migrator
+}
+
+pub async fn run(dbpool: &SqlitePool, phase: Phase) -> Result<(), MigrateError> {
+    migrator(phase).run(dbpool).await
+}
+
+// The cleanup migrations that haven't been applied yet, so we can remind operators to run them.
+pub async fn pending_cleanup(dbpool: &SqlitePool) -> Result<Vec<Migration>, sqlx::Error> {
+    let applied: Vec<(i64,)> = sqlx::query_as("select version from _sqlx_migrations")
+        .fetch_all(dbpool)
+        .await?;
+    Ok(migrator(Phase::Cleanup)
+        .iter()
+        .filter(|m| is_cleanup(m) && !applied.contains(&(m.version,)))
+        .cloned()
+        .collect())
+}
+
+// A migration the database has run, as reported by GET /v1/admin/migrations.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AppliedMigration {
+    version: i64,
+    description: String,
+    installed_on: NaiveDateTime,
+    // A failed migration leaves the database in an unknown state and needs an operator.
+    success: bool,
+    // The SHA-384 of the migration as it was applied, in hex.
+    checksum: String,
+    // Whether the checksum matches this version's copy of the migration: false when the file was
+    // edited after it ran, and None for migrations this version doesn't know about, e.g. ones a
+    // newer version applied during a rolling deploy.
+    checksum_matches: Option<bool>,
+}
+
+// A migration this version has that the database hasn't run yet.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingMigration {
+    version: i64,
+    description: String,
+    phase: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MigrationStatus {
+    // The latest migration that ran successfully, i.e. the version of the schema.
+    schema_version: Option<i64>,
+    applied: Vec<AppliedMigration>,
+    pending: Vec<PendingMigration>,
+}
+
+impl MigrationStatus {
+    pub fn schema_version(&self) -> Option<i64> {
+        self.schema_version
+    }
+
+    pub fn applied(&self) -> &[AppliedMigration] {
+        &self.applied
+    }
+
+    pub fn pending(&self) -> &[PendingMigration] {
+        &self.pending
+    }
+}
+
+impl AppliedMigration {
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    pub fn checksum_matches(&self) -> Option<bool> {
+        self.checksum_matches
+    }
+}
+
+impl PendingMigration {
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn phase(&self) -> &str {
+        &self.phase
+    }
+}
+
+pub async fn status(dbpool: &SqlitePool) -> Result<MigrationStatus, sqlx::Error> {
+    let rows: Vec<(i64, String, NaiveDateTime, bool, Vec<u8>)> = sqlx::query_as(
+        "select version, description, installed_on, success, checksum from _sqlx_migrations
+         order by version",
+    )
+    .fetch_all(dbpool)
+    .await?;
+    let known = migrator(Phase::Cleanup);
+
+    let applied: Vec<AppliedMigration> = rows
+        .into_iter()
+        .map(
+            |(version, description, installed_on, success, checksum)| AppliedMigration {
+                checksum_matches: known
+                    .iter()
+                    .find(|m| m.version == version)
+                    .map(|m| *m.checksum == *checksum),
+                version,
+                description,
+                installed_on,
+                success,
+                checksum: checksum.iter().map(|byte| format!("{byte:02x}")).collect(),
+            },
+        )
+        .collect();
+    let pending = known
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .map(|m| PendingMigration {
+            version: m.version,
+            description: m.description.to_string(),
+            phase: match is_cleanup(m) {
+                true => Phase::Cleanup,
+                false => Phase::Expand,
+            }
+            .to_string(),
+        })
+        .collect();
+
+    Ok(MigrationStatus {
+        schema_version: applied
+            .iter()
+            .filter(|m| m.success)
+            .map(|m| m.version)
+            .max(),
+        applied,
+        pending,
+    })
+}
+
+// The version of the schema, for the readiness check.
+pub async fn schema_version(dbpool: &SqlitePool) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("select max(version) from _sqlx_migrations where success")
+        .fetch_one(dbpool)
+        .await
+}
@@ -0,0 +1,535 @@
+use crate::hooks::{async_trait, TodoHook};
+use crate::ids;
+use crate::outbound::Outbound;
+use crate::preferences::Preferences;
+use crate::report::Report;
+use crate::status::TodoStatus;
+use crate::todo::{Todo, UpdateTodo};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::io::AsyncWriteExt;
+
+// The events the owner can be notified about.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationEvent {
+    #[serde(rename = "todo.created")]
+    TodoCreated,
+    #[serde(rename = "todo.updated")]
+    TodoUpdated,
+    #[serde(rename = "todo.completed")]
+    TodoCompleted,
+    #[serde(rename = "todo.deleted")]
+    TodoDeleted,
+    #[serde(rename = "todo.stale")]
+    TodoStale,
+    #[serde(rename = "report.generated")]
+    ReportGenerated,
+}
+
+impl NotificationEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NotificationEvent::TodoCreated => "todo.created",
+            NotificationEvent::TodoUpdated => "todo.updated",
+            NotificationEvent::TodoCompleted => "todo.completed",
+            NotificationEvent::TodoDeleted => "todo.deleted",
+            NotificationEvent::TodoStale => "todo.stale",
+            NotificationEvent::ReportGenerated => "report.generated",
+        }
+    }
+}
+
+impl fmt::Display for NotificationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NotificationEvent {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "todo.created" => Ok(NotificationEvent::TodoCreated),
+            "todo.updated" => Ok(NotificationEvent::TodoUpdated),
+            "todo.completed" => Ok(NotificationEvent::TodoCompleted),
+            "todo.deleted" => Ok(NotificationEvent::TodoDeleted),
+            "todo.stale" => Ok(NotificationEvent::TodoStale),
+            "report.generated" => Ok(NotificationEvent::ReportGenerated),
+            _ => Err(format!("`{value}` isn't a notification event")),
+        }
+    }
+}
+
+// What a channel is asked to deliver.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Notification {
+    event: NotificationEvent,
+    // The todo the event is about, if any.
+    #[serde(with = "crate::ids::option", default)]
+    todo_id: Option<i64>,
+    // The todo as it is after the change. Deleted todos only have their ID.
+    todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    report: Option<Report>,
+    // A short line for channels that show text, e.g. "Completed: buy milk".
+    summary: String,
+    // The full text for channels with room for it, such as a report's Markdown for email.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    details: Option<String>,
+    at: NaiveDateTime,
+}
+
+impl Notification {
+    pub fn new(event: NotificationEvent, todo_id: i64, todo: Option<Todo>) -> Self {
+        let summary = match (&todo, event) {
+            (Some(todo), NotificationEvent::TodoCreated) => format!("New todo: {}", todo.body()),
+            (Some(todo), NotificationEvent::TodoCompleted) => format!("Completed: {}", todo.body()),
+            (Some(todo), NotificationEvent::TodoStale) => {
+                format!("Untouched for a while: {}", todo.body())
+            }
+            (Some(todo), _) => format!("Updated: {}", todo.body()),
+            (None, _) => format!("Deleted todo {}", ids::encode(todo_id)),
+        };
+        Self {
+            event,
+            todo_id: Some(todo_id),
+            details: todo.as_ref().map(|todo| todo.body().to_string()),
+            todo,
+            report: None,
+            summary,
+            at: Utc::now().naive_utc().trunc_subsecs(0),
+        }
+    }
+
+    // A report was generated. The summary says where to download it; the details are the report.
+    pub fn for_report(report: Report, markdown: String) -> Self {
+        Self {
+            event: NotificationEvent::ReportGenerated,
+            todo_id: None,
+            todo: None,
+            summary: format!(
+                "Report {}: {} todos, {}",
+                report.kind().as_str(),
+                report.todos(),
+                report.download_path()
+            ),
+            report: Some(report),
+            details: Some(markdown),
+            at: Utc::now().naive_utc().trunc_subsecs(0),
+        }
+    }
+
+    pub fn event(&self) -> NotificationEvent {
+        self.event
+    }
+
+    pub fn todo_id(&self) -> Option<i64> {
+        self.todo_id
+    }
+
+    pub fn todo(&self) -> Option<&Todo> {
+        self.todo.as_ref()
+    }
+
+    pub fn report(&self) -> Option<&Report> {
+        self.report.as_ref()
+    }
+
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    pub fn details(&self) -> Option<&str> {
+        self.details.as_deref()
+    }
+}
+
+// A way of reaching the owner, such as email or a chat. Embedders can add their own with
+// AppState::with_notifier; routing rules refer to a notifier by its channel name.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    // The name routing rules and the muted_channels preference use, e.g. "slack".
+    fn channel(&self) -> &str;
+
+    // Delivers a notification. Failures are logged and not retried beyond what the transport does,
+    // since a late notification is rarely better than none.
+    async fn notify(&self, notification: &Notification) -> Result<(), String>;
+}
+
+// Which channels each event goes to, e.g. "todo.completed:email|slack;*:log". A rule for "*"
+// applies to every event without a rule of its own. Without any rules, nothing is sent.
+#[derive(Clone, Debug, Default)]
+pub struct NotificationRoutes {
+    routes: HashMap<NotificationEvent, Vec<String>>,
+    fallback: Vec<String>,
+}
+
+impl NotificationRoutes {
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.fallback.is_empty()
+    }
+
+    pub fn channels_for(&self, event: NotificationEvent) -> &[String] {
+        self.routes.get(&event).unwrap_or(&self.fallback)
+    }
+}
+
+impl FromStr for NotificationRoutes {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut routes = Self::default();
+        for rule in value
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            let (event, channels) = rule
+                .split_once(':')
+                .ok_or_else(|| format!("`{rule}` should look like `event:channel|channel`"))?;
+            let channels: Vec<String> = channels
+                .split('|')
+                .map(str::trim)
+                .filter(|channel| !channel.is_empty())
+                .map(String::from)
+                .collect();
+            match event.trim() {
+                "*" => routes.fallback = channels,
+                event => {
+                    routes.routes.insert(event.parse()?, channels);
+                }
+            }
+        }
+        Ok(routes)
+    }
+}
+
+// The notifiers configured at startup, and the rules routing events to them.
+pub struct Notifiers {
+    routes: NotificationRoutes,
+    channels: RwLock<Vec<Arc<dyn Notifier>>>,
+}
+
+impl Notifiers {
+    pub fn new(routes: NotificationRoutes) -> Self {
+        Self {
+            routes,
+            channels: RwLock::default(),
+        }
+    }
+
+    // Adds a channel, replacing one with the same name.
+    pub fn register(&self, notifier: Arc<dyn Notifier>) {
+        let mut channels = self.channels.write().unwrap();
+        channels.retain(|channel| channel.channel() != notifier.channel());
+        channels.push(notifier);
+    }
+
+    pub fn routes(&self) -> &NotificationRoutes {
+        &self.routes
+    }
+
+    // The names of the registered channels, for reporting.
+    pub fn channels(&self) -> Vec<String> {
+        let channels = self.channels.read().unwrap();
+        channels
+            .iter()
+            .map(|channel| channel.channel().to_string())
+            .collect()
+    }
+
+    // Sends a notification to the channels its event is routed to, except the ones the owner muted.
+    pub async fn dispatch(&self, dbpool: &SqlitePool, notification: Notification) {
+        let routed = self.routes.channels_for(notification.event);
+        if routed.is_empty() {
+            return;
+        }
+        let muted: HashSet<String> = match Preferences::read(dbpool.clone()).await {
+            Ok(preferences) => preferences.muted_channels().iter().cloned().collect(),
+            Err(err) => {
+                tracing::warn!(error = ?err, "failed to read the muted notification channels");
+                HashSet::new()
+            }
+        };
+        let notifiers: Vec<Arc<dyn Notifier>> = {
+            let channels = self.channels.read().unwrap();
+            routed
+                .iter()
+                .filter(|name| !muted.contains(*name))
+                .filter_map(|name| {
+                    let notifier = channels.iter().find(|channel| channel.channel() == name);
+                    if notifier.is_none() {
+                        tracing::warn!(channel = %name, "no notifier for a routed channel");
+                    }
+                    notifier.cloned()
+                })
+                .collect()
+        };
+        for notifier in notifiers {
+            if let Err(err) = notifier.notify(&notification).await {
+                tracing::warn!(
+                    channel = notifier.channel(),
+                    event = %notification.event,
+                    "notification failed: {err}"
+                );
+            }
+        }
+    }
+}
+
+// Turns todo mutations into notifications. Delivery runs in the background, so slow channels don't
+// hold up the request.
+pub struct NotifyHook {
+    notifiers: Arc<Notifiers>,
+    dbpool: SqlitePool,
+    // The todos being updated that weren't completed before, so completing one can be told apart
+    // from editing a completed one.
+    completing: Mutex<HashSet<i64>>,
+}
+
+impl NotifyHook {
+    pub fn new(notifiers: Arc<Notifiers>, dbpool: SqlitePool) -> Self {
+        Self {
+            notifiers,
+            dbpool,
+            completing: Mutex::default(),
+        }
+    }
+
+    fn send(&self, notification: Notification) {
+        let notifiers = self.notifiers.clone();
+        let dbpool = self.dbpool.clone();
+        // IDs in the notification are encoded the way the request that caused it had them.
+        tokio::spawn(ids::inherit(async move {
+            notifiers.dispatch(&dbpool, notification).await;
+        }));
+    }
+}
+
+#[async_trait]
+impl TodoHook for NotifyHook {
+    async fn after_create(&self, todo: &Todo) {
+        self.send(Notification::new(
+            NotificationEvent::TodoCreated,
+            todo.id(),
+            Some(todo.clone()),
+        ));
+    }
+
+    async fn before_update(
+        &self,
+        id: i64,
+        _todo: &mut UpdateTodo,
+    ) -> Result<(), crate::error::Error> {
+        if let Ok(before) = Todo::read(self.dbpool.clone(), id).await {
+            if !before.completed() {
+                self.completing.lock().unwrap().insert(id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn after_update(&self, todo: &Todo) {
+        let was_open = self.completing.lock().unwrap().remove(&todo.id());
+        let event = match was_open && todo.completed() {
+            true => NotificationEvent::TodoCompleted,
+            false => NotificationEvent::TodoUpdated,
+        };
+        self.send(Notification::new(event, todo.id(), Some(todo.clone())));
+    }
+
+    async fn after_bulk_update(&self, todo: &Todo, previous: TodoStatus) {
+        let event = match previous != TodoStatus::Done && todo.completed() {
+            true => NotificationEvent::TodoCompleted,
+            false => NotificationEvent::TodoUpdated,
+        };
+        self.send(Notification::new(event, todo.id(), Some(todo.clone())));
+    }
+
+    async fn after_delete(&self, id: i64) {
+        self.send(Notification::new(NotificationEvent::TodoDeleted, id, None));
+    }
+}
+
+// Writes notifications to the service's log, which is handy while setting up routing.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    fn channel(&self) -> &str {
+        "log"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        tracing::info!(event = %notification.event, "{}", notification.summary);
+        Ok(())
+    }
+}
+
+// Posts the notification as JSON to a URL.
+pub struct WebhookNotifier {
+    outbound: Arc<Outbound>,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(outbound: Arc<Outbound>, url: impl Into<String>) -> Self {
+        Self {
+            outbound,
+            url: url.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn channel(&self) -> &str {
+        "webhook"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let request = self
+            .outbound
+            .client()
+            .post(&self.url)
+            .json(notification)
+            .build()
+            .map_err(|err| err.to_string())?;
+        check_status(self.outbound.send(request).await)
+    }
+}
+
+// Posts the summary to a Slack incoming webhook.
+pub struct SlackNotifier {
+    outbound: Arc<Outbound>,
+    url: String,
+}
+
+impl SlackNotifier {
+    pub fn new(outbound: Arc<Outbound>, url: impl Into<String>) -> Self {
+        Self {
+            outbound,
+            url: url.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn channel(&self) -> &str {
+        "slack"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        // Slack treats &, <, and > as markup in message text.
+        let text = notification
+            .summary
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        let request = self
+            .outbound
+            .client()
+            .post(&self.url)
+            .json(&serde_json::json!({ "text": text }))
+            .build()
+            .map_err(|err| err.to_string())?;
+        check_status(self.outbound.send(request).await)
+    }
+}
+
+fn check_status(result: Result<reqwest::Response, reqwest::Error>) -> Result<(), String> {
+    let response = result.map_err(|err| err.to_string())?;
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(format!("the destination answered {}", response.status())),
+    }
+}
+
+// Sends the summary as an email through the local sendmail program, which every mail server
+// provides, so the service doesn't need to speak SMTP or hold mail credentials itself.
+pub struct EmailNotifier {
+    sendmail: PathBuf,
+    from: String,
+    to: String,
+}
+
+impl EmailNotifier {
+    pub fn new(sendmail: PathBuf, from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            sendmail,
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    fn message(&self, notification: &Notification) -> String {
+        let body = match &notification.details {
+            Some(details) => format!(
+                "{}\r\n\r\n{}\r\n",
+                notification.summary,
+                details.lines().collect::<Vec<_>>().join("\r\n")
+            ),
+            None => format!("{}\r\n", notification.summary),
+        };
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+            header_value(&self.from),
+            header_value(&self.to),
+            encode_subject(&header_value(&notification.summary)),
+            body,
+        )
+    }
+}
+
+// Line breaks in a header value would start a new header, so they're replaced.
+fn header_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+// Subjects outside ASCII are encoded as RFC 2047 words, which mail clients decode.
+fn encode_subject(subject: &str) -> String {
+    match subject.is_ascii() {
+        true => subject.to_string(),
+        false => format!("=?utf-8?B?{}?=", STANDARD.encode(subject)),
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn channel(&self) -> &str {
+        "email"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        // -t takes the recipients from the message and -i keeps a line with a single dot from ending
+        // it early.
+        let mut child = tokio::process::Command::new(&self.sendmail)
+            .args(["-t", "-i"])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("can't run {}: {err}", self.sendmail.display()))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin
+            .write_all(self.message(notification).as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+        drop(stdin);
+        let status = child.wait().await.map_err(|err| err.to_string())?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(format!("{} exited with {status}", self.sendmail.display())),
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use crate::config::Config;
+use crate::trace_context;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::RETRY_AFTER;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Request, Response, StatusCode, Url};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// The timeouts for specific destinations, by host, e.g. "hooks.slack.com=3000;fcm.googleapis.com=
+// 10000" in milliseconds. Other hosts get the default timeout.
+#[derive(Clone, Debug, Default)]
+pub struct DestinationTimeouts {
+    timeouts: HashMap<String, Duration>,
+}
+
+impl DestinationTimeouts {
+    fn get(&self, host: &str) -> Option<Duration> {
+        self.timeouts.get(&host.to_ascii_lowercase()).copied()
+    }
+}
+
+impl FromStr for DestinationTimeouts {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut timeouts = HashMap::new();
+        for entry in value
+            .split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (host, millis) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("`{entry}` should look like `host=milliseconds`"))?;
+            let millis: u64 = millis
+                .trim()
+                .parse()
+                .map_err(|_| format!("`{millis}` isn't a number of milliseconds"))?;
+            timeouts.insert(
+                host.trim().to_ascii_lowercase(),
+                Duration::from_millis(millis),
+            );
+        }
+        Ok(Self { timeouts })
+    }
+}
+
+// How often and how patiently a failed call is retried. Only failures that are likely to go away
+// are retried: connection errors, timeouts, 429s, and server errors.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    // Including the first attempt, so 1 disables retries.
+    pub max_attempts: u32,
+    // The delay before the first retry, which doubles with every further retry up to max_delay.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // The delay before the given retry, counting from 1. A Retry-After from the destination wins
+    // as long as it's within max_delay; asking for longer isn't something we wait for.
+    fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_delay);
+        retry_after.map_or(backoff, |after| after.min(self.max_delay))
+    }
+}
+
+#[derive(Default)]
+struct DestinationStats {
+    // Calls by outcome: a status code, or "error" when there was no response at all.
+    outcomes: BTreeMap<String, u64>,
+    retries: u64,
+    seconds: f64,
+    count: u64,
+}
+
+// The HTTP client for calls the service makes to other services, such as webhooks and chat or push
+// integrations. Integrations share it rather than building their own, so they get connection
+// pooling, timeouts, retries, and metrics the same way.
+pub struct Outbound {
+    client: Client,
+    // The client for URLs users typed in, which only connects to public addresses.
+    public: Client,
+    timeout: Duration,
+    timeouts: DestinationTimeouts,
+    retry: RetryPolicy,
+    // Keyed by destination: the host for calls made with send, which only go to hosts we were
+    // configured with, and PUBLIC_DESTINATION for all calls made with send_public, whose hosts come
+    // from users. That keeps the label set small.
+    stats: Mutex<BTreeMap<String, DestinationStats>>,
+    // Calls in progress, including the ones waiting to be retried.
+    in_flight: AtomicU64,
+}
+
+impl Outbound {
+    pub fn new(timeout: Duration, timeouts: DestinationTimeouts, retry: RetryPolicy) -> Self {
+        Self {
+            client: Client::builder()
+                .user_agent(concat!("todo-api-service/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .expect("the outbound HTTP client can always be built"),
+            public: Client::builder()
+                .user_agent(concat!("todo-api-service/", env!("CARGO_PKG_VERSION")))
+                .no_proxy()
+                .dns_resolver(Arc::new(PublicResolver))
+                .redirect(Policy::custom(|attempt| {
+                    if attempt.previous().len() >= MAX_PUBLIC_REDIRECTS {
+                        attempt.error("too many redirects")
+                    } else if !is_public_url(attempt.url()) {
+                        attempt.error("redirected to a non-public address")
+                    } else {
+                        attempt.follow()
+                    }
+                }))
+                .build()
+                .expect("the outbound HTTP client can always be built"),
+            timeout,
+            timeouts,
+            retry,
+            stats: Mutex::default(),
+            in_flight: AtomicU64::new(0),
+        }
+    }
+
+    // The pooled client, for building requests to pass to send or send_public, e.g.
+    // outbound.client().post(url).json(&payload).build().
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // Sends a request, retrying it according to the retry policy. Requests with a streaming body
+    // can't be replayed, so they're only tried once. The last response is returned whatever its
+    // status; only failing to get one at all is an error.
+    pub async fn send(&self, mut request: Request) -> Result<Response, reqwest::Error> {
+        // The call becomes part of the trace of the request that made it. Only destinations we
+        // were configured with get to see our trace IDs, so send_public leaves them out.
+        trace_context::inject(request.headers_mut());
+        self.execute(&self.client, request, false).await
+    }
+
+    // Sends a request to a URL a user typed in, like a link to preview, the way send does. It only
+    // goes to public addresses on the default ports, including after redirects and whatever the
+    // host name resolves to at connection time, so users can't point the service at its own
+    // network.
+    pub async fn send_public(&self, request: Request) -> Result<Response, String> {
+        if !is_public_url(request.url()) {
+            return Err(format!("{} isn't a public URL", request.url()));
+        }
+        self.execute(&self.public, request, true)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn execute(
+        &self,
+        client: &Client,
+        mut request: Request,
+        public: bool,
+    ) -> Result<Response, reqwest::Error> {
+        let host = request.url().host_str().unwrap_or("unknown").to_string();
+        if request.timeout().is_none() {
+            *request.timeout_mut() = Some(self.timeouts.get(&host).unwrap_or(self.timeout));
+        }
+        let started = Instant::now();
+        let _in_flight = InFlight::enter(&self.in_flight);
+        let mut retries = 0;
+        let result = loop {
+            let retry = request
+                .try_clone()
+                .filter(|_| retries + 1 < self.retry.max_attempts);
+            let result = client.execute(request).await;
+            let retry_after = match &result {
+                Ok(response) if retryable_status(response.status()) => Some(retry_after(response)),
+                Ok(_) => None,
+                Err(err) if err.is_connect() || err.is_timeout() => Some(None),
+                Err(_) => None,
+            };
+            match (retry, retry_after) {
+                (Some(next), Some(after)) => {
+                    retries += 1;
+                    let delay = self.retry.delay(retries, after);
+                    tracing::debug!(%host, retries, ?delay, "retrying outbound request");
+                    tokio::time::sleep(delay).await;
+                    request = next;
+                }
+                _ => break result,
+            }
+        };
+        let destination = if public { PUBLIC_DESTINATION } else { &host };
+        self.observe(destination, &host, &result, retries, started.elapsed());
+        result
+    }
+
+    // The number of calls in progress, reported as the webhook backlog by the health metrics.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn observe(
+        &self,
+        destination: &str,
+        host: &str,
+        result: &Result<Response, reqwest::Error>,
+        retries: u32,
+        elapsed: Duration,
+    ) {
+        let outcome = match result {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(err) => {
+                tracing::warn!(%host, error = %err, "outbound request failed");
+                "error".to_string()
+            }
+        };
+        let mut stats = self.stats.lock().expect("outbound stats lock poisoned");
+        let stats = stats.entry(destination.to_string()).or_default();
+        *stats.outcomes.entry(outcome).or_default() += 1;
+        stats.retries += u64::from(retries);
+        stats.seconds += elapsed.as_secs_f64();
+        stats.count += 1;
+    }
+
+    // Renders the outbound metrics in the Prometheus text exposition format, to go with the
+    // request metrics.
+    pub fn render(&self) -> String {
+        let stats = self.stats.lock().expect("outbound stats lock poisoned");
+        let mut out = String::new();
+
+        out.push_str("# HELP outbound_requests_total Outbound calls by destination and outcome.\n");
+        out.push_str("# TYPE outbound_requests_total counter\n");
+        for (destination, stats) in stats.iter() {
+            for (outcome, count) in &stats.outcomes {
+                writeln!(
+                    out,
+                    "outbound_requests_total{{destination=\"{destination}\",outcome=\"{outcome}\"}} {count}"
+                )
+                .ok();
+            }
+        }
+
+        out.push_str("# HELP outbound_retries_total Outbound retries by destination.\n");
+        out.push_str("# TYPE outbound_retries_total counter\n");
+        for (destination, stats) in stats.iter() {
+            writeln!(
+                out,
+                "outbound_retries_total{{destination=\"{destination}\"}} {}",
+                stats.retries
+            )
+            .ok();
+        }
+
+        // The time spent on a call includes its retries, since that's what the caller waits for.
+        out.push_str(
+            "# HELP outbound_request_duration_seconds Time spent on outbound calls, retries included.\n",
+        );
+        out.push_str("# TYPE outbound_request_duration_seconds summary\n");
+        for (destination, stats) in stats.iter() {
+            writeln!(
+                out,
+                "outbound_request_duration_seconds_sum{{destination=\"{destination}\"}} {}",
+                stats.seconds
+            )
+            .ok();
+            writeln!(
+                out,
+                "outbound_request_duration_seconds_count{{destination=\"{destination}\"}} {}",
+                stats.count
+            )
+            .ok();
+        }
+        out
+    }
+}
+
+// Counts a call as in progress until it's dropped, which also covers callers giving up on a call.
+struct InFlight<'a>(&'a AtomicU64);
+
+impl<'a> InFlight<'a> {
+    fn enter(counter: &'a AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+const MAX_PUBLIC_REDIRECTS: usize = 5;
+
+// The destination label of the calls made with send_public.
+const PUBLIC_DESTINATION: &str = "public";
+
+// Resolves host names for send_public, failing for names with any address that isn't public, so
+// a name can't be switched to an internal address after it was checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(format!("{host} doesn't resolve to public addresses").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// Whether a URL is http or https on the default port, and its host isn't an address or name for
+// something local. Host names are checked again once resolved.
+pub fn is_public_url(url: &Url) -> bool {
+    let port = url.port().is_none_or(|port| port == 80 || port == 443);
+    let host = match url.host_str().map(|host| host.trim_matches(['[', ']'])) {
+        Some(host) => match host.parse::<IpAddr>() {
+            Ok(ip) => is_public(ip),
+            Err(_) => {
+                let domain = host.trim_end_matches('.').to_ascii_lowercase();
+                domain != "localhost" && !domain.ends_with(".localhost") && domain.contains('.')
+            }
+        },
+        None => false,
+    };
+    matches!(url.scheme(), "http" | "https") && port && host
+}
+
+// Whether an address is on the public internet, rather than loopback, private, link-local (which
+// includes cloud metadata endpoints), shared, or reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // 0.0.0.0/8, 100.64.0.0/10 (carrier-grade NAT), and 240.0.0.0/4.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            // Addresses with an IPv4 address inside reach that address, so they're as public as it
+            // is: IPv4-mapped (::ffff:a.b.c.d), IPv4-compatible (::a.b.c.d, which includes :: and
+            // ::1), 6to4 (2002:aabb:ccdd::/48), and NAT64 (64:ff9b::a.b.c.d).
+            if let Some(ip) = embedded_ipv4(ip) {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local (fc00::/7) and link-local (fe80::/10) addresses.
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let [.., a, b, c, d] = ip.octets();
+    match segments {
+        [0, 0, 0, 0, 0, 0 | 0xffff, _, _] => Some(Ipv4Addr::new(a, b, c, d)),
+        [0x0064, 0xff9b, 0, 0, 0, 0, _, _] => Some(Ipv4Addr::new(a, b, c, d)),
+        [0x2002, high, low, ..] => {
+            let [a, b] = high.to_be_bytes();
+            let [c, d] = low.to_be_bytes();
+            Some(Ipv4Addr::new(a, b, c, d))
+        }
+        _ => None,
+    }
+}
+
+fn retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// Retry-After in seconds. The HTTP date form is rare enough from APIs that we fall back to our own
+// backoff for it.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+impl From<&Config> for Outbound {
+    fn from(config: &Config) -> Self {
+        Outbound::new(
+            Duration::from_millis(config.outbound_timeout_ms),
+            config.outbound_timeouts.clone(),
+            RetryPolicy {
+                max_attempts: config.outbound_max_attempts.max(1),
+                base_delay: Duration::from_millis(config.outbound_retry_base_ms),
+                max_delay: Duration::from_millis(config.outbound_retry_max_ms),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse().expect("a valid address"))
+    }
+
+    fn public_url(url: &str) -> bool {
+        is_public_url(&Url::parse(url).expect("a valid URL"))
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "2a00:1450:4001::200e",
+        ] {
+            assert!(public(ip), "{ip} is public");
+        }
+    }
+
+    #[test]
+    fn local_addresses() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "192.0.2.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+        ] {
+            assert!(!public(ip), "{ip} isn't public");
+        }
+    }
+
+    #[test]
+    fn embedded_ipv4_addresses() {
+        // IPv4-mapped, IPv4-compatible, 6to4, and NAT64 addresses are as public as the IPv4
+        // address inside them.
+        for ip in [
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!public(ip), "{ip} isn't public");
+        }
+        for ip in [
+            "::ffff:93.184.216.34",
+            "::8.8.8.8",
+            "2002:5db8:d822::1",
+            "64:ff9b::8.8.8.8",
+        ] {
+            assert!(public(ip), "{ip} is public");
+        }
+    }
+
+    #[test]
+    fn public_urls() {
+        for url in [
+            "https://example.com/page",
+            "http://example.com:80/",
+            "https://example.com:443/",
+            "https://8.8.8.8/",
+            "https://[2606:4700::1111]/",
+            "https://example.com./",
+        ] {
+            assert!(public_url(url), "{url} is public");
+        }
+    }
+
+    #[test]
+    fn local_urls() {
+        for url in [
+            // Other schemes and ports.
+            "ftp://example.com/",
+            "file:///etc/passwd",
+            "https://example.com:8443/",
+            "http://example.com:22/",
+            // Local names.
+            "http://localhost/",
+            "http://LOCALHOST./",
+            "http://api.localhost/",
+            "http://intranet/",
+            // Local addresses, however they're written.
+            "http://127.0.0.1/",
+            "http://2130706433/",
+            "http://0x7f.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[2002:a9fe:a9fe::]/",
+            "http://[64:ff9b::10.0.0.1]/",
+            "http://[fe80::1]/",
+        ] {
+            assert!(!public_url(url), "{url} isn't public");
+        }
+    }
+}
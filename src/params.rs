@@ -0,0 +1,181 @@
+use crate::config::Config;
+use crate::error::{Error, RequestError};
+use crate::extract::Query;
+use crate::i18n;
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use std::sync::Arc;
+
+// The query string accepted by the endpoints returning lists of todos, as sent by the client.
+#[derive(Deserialize)]
+struct RawListParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort: Option<String>,
+    q: Option<String>,
+}
+
+// The fields todo lists can be sorted by.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    DueAt,
+}
+
+impl SortField {
+    const NAMES: &'static [&'static str] = &["id", "created_at", "updated_at", "due_at"];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(SortField::Id),
+            "created_at" => Some(SortField::CreatedAt),
+            "updated_at" => Some(SortField::UpdatedAt),
+            "due_at" => Some(SortField::DueAt),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::DueAt => "due_at",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Sort {
+    field: SortField,
+    descending: bool,
+}
+
+impl Sort {
+    // The order by clause for this sort. Ties are broken by ID, so pages are stable. The column
+    // comes from SortField, never from the client, so it's safe to format into the statement.
+    pub fn order_by(&self, table: &str) -> String {
+        let direction = if self.descending { "desc" } else { "asc" };
+        format!(
+            "order by {table}.{column} {direction}, {table}.id {direction}",
+            column = self.field.column()
+        )
+    }
+}
+
+// Validated list parameters shared by the list endpoints. Extracting them rejects bad values such as
+// a negative offset or an unknown sort field with a 400 naming the parameter.
+pub struct ListParams {
+    pub limit: i64,
+    pub offset: i64,
+    // None when the client didn't ask for a particular order, leaving it to the endpoint.
+    pub sort: Option<Sort>,
+    pub q: Option<String>,
+}
+
+impl ListParams {
+    // Identifies the parameters, e.g. for use as a cache key.
+    pub fn fingerprint(&self) -> String {
+        let sort = self
+            .sort
+            .map(|sort| {
+                format!(
+                    "{}{}",
+                    if sort.descending { "-" } else { "" },
+                    sort.field.column()
+                )
+            })
+            .unwrap_or_default();
+        format!(
+            "limit={}&offset={}&sort={sort}&q={}",
+            self.limit,
+            self.offset,
+            self.q.as_deref().unwrap_or_default()
+        )
+    }
+
+    fn validate(raw: RawListParams, config: &Config) -> Result<Self, Error> {
+        let sort = raw.sort.as_deref().map(parse_sort).transpose()?;
+        Ok(Self {
+            limit: page_limit(raw.limit, config)?,
+            offset: page_offset(raw.offset)?,
+            sort,
+            q: raw.q,
+        })
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ListParams
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let Query(raw) = Query::<RawListParams>::from_request_parts(parts, state).await?;
+        ListParams::validate(raw, &config)
+    }
+}
+
+// Parses a sort parameter like "due_at" (ascending) or "-created_at" (descending).
+fn parse_sort(value: &str) -> Result<Sort, Error> {
+    let (name, descending) = match value.strip_prefix('-') {
+        Some(name) => (name, true),
+        None => (value, false),
+    };
+    let field = SortField::parse(name).ok_or_else(|| {
+        invalid_param(
+            "invalid_sort",
+            "sort",
+            i18n::message(
+                "invalid_sort",
+                &[("sort", name), ("fields", &SortField::NAMES.join(", "))],
+            ),
+        )
+    })?;
+    Ok(Sort { field, descending })
+}
+
+// Applies the configured page size limits. An explicit limit above the maximum is an error rather
+// than being silently lowered, so clients don't mistake a truncated page for the full result.
+pub fn page_limit(requested: Option<i64>, config: &Config) -> Result<i64, Error> {
+    match requested {
+        None => Ok(config.default_page_size),
+        Some(limit) if (1..=config.max_page_size).contains(&limit) => Ok(limit),
+        Some(_) => Err(invalid_param(
+            "invalid_limit",
+            "limit",
+            i18n::message(
+                "invalid_limit",
+                &[("max", &config.max_page_size.to_string())],
+            ),
+        )),
+    }
+}
+
+pub fn page_offset(requested: Option<i64>) -> Result<i64, Error> {
+    match requested {
+        None => Ok(0),
+        Some(offset) if offset >= 0 => Ok(offset),
+        Some(_) => Err(invalid_param(
+            "invalid_offset",
+            "offset",
+            i18n::message("invalid_offset", &[]),
+        )),
+    }
+}
+
+pub fn invalid_param(code: &'static str, field: &str, message: String) -> Error {
+    Error::BadRequest(
+        StatusCode::BAD_REQUEST,
+        RequestError::new(code, message).with_field(field),
+    )
+}
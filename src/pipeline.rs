@@ -0,0 +1,210 @@
+use crate::due;
+use chrono::Utc;
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// What the pipeline works on: a todo's body, and its due date as the client wrote it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Draft {
+    pub body: String,
+    pub due: Option<String>,
+}
+
+// A stage of the pipeline. Stages run on every create and update, including on bodies they've
+// processed before, so running one twice has to change nothing the second time.
+pub trait Transform: Send + Sync {
+    // The name the TEXT_PIPELINE setting uses for the stage.
+    fn name(&self) -> &str;
+
+    fn apply(&self, draft: &mut Draft);
+}
+
+// The stages applied to todo bodies on create and update, in order, before the body policy checks
+// the result. Embedders can add their own stages with `with`.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn with(mut self, stage: impl Transform + 'static) -> Self {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn run(&self, body: &str, due: Option<&str>) -> Draft {
+        let mut draft = Draft {
+            body: body.to_string(),
+            due: due.map(String::from),
+        };
+        for stage in &self.stages {
+            stage.apply(&mut draft);
+        }
+        draft
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.stages.iter().map(|stage| stage.name()))
+            .finish()
+    }
+}
+
+// The built-in stages by name, in the order they should run, e.g. "trim,linkify,due_phrases".
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut pipeline = Pipeline::default();
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            pipeline = match name {
+                "trim" => pipeline.with(Trim),
+                "linkify" => pipeline.with(Linkify),
+                "due_phrases" => pipeline.with(DuePhrases),
+                _ => {
+                    return Err(format!(
+                        "`{name}` isn't a stage; the stages are trim, linkify, and due_phrases"
+                    ))
+                }
+            };
+        }
+        Ok(pipeline)
+    }
+}
+
+// Removes whitespace around the body and at the ends of its lines.
+pub struct Trim;
+
+impl Transform for Trim {
+    fn name(&self) -> &str {
+        "trim"
+    }
+
+    fn apply(&self, draft: &mut Draft) {
+        draft.body = draft
+            .body
+            .trim()
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+// Wraps bare http and https URLs in angle brackets, which Markdown renders as links, so exports
+// and clients rendering Markdown link them without guessing where they end. URLs already in angle
+// brackets or in a Markdown link are left alone.
+pub struct Linkify;
+
+impl Transform for Linkify {
+    fn name(&self) -> &str {
+        "linkify"
+    }
+
+    fn apply(&self, draft: &mut Draft) {
+        let body = &draft.body;
+        let mut out = String::with_capacity(body.len());
+        let mut rest = body.as_str();
+        while let Some(start) = find_url(rest) {
+            let (before, from_url) = rest.split_at(start);
+            out.push_str(before);
+            let word_end = from_url.find(char::is_whitespace).unwrap_or(from_url.len());
+            let url = trim_url(&from_url[..word_end]);
+            out.push('<');
+            out.push_str(url);
+            out.push('>');
+            rest = &from_url[url.len()..];
+        }
+        out.push_str(rest);
+        draft.body = out;
+    }
+}
+
+// The start of the next bare URL, one at the start of the text or after whitespace.
+fn find_url(text: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(found) = text[offset..].find("http") {
+        let start = offset + found;
+        let bare = text[..start]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+        let scheme = &text[start..];
+        if bare
+            && (scheme.starts_with("http://") || scheme.starts_with("https://"))
+            && scheme.len() > scheme.find("//").unwrap_or_default() + 2
+        {
+            return Some(start);
+        }
+        offset = start + 4;
+    }
+    None
+}
+
+// Leaves sentence punctuation after a URL out of it, and a closing parenthesis without an opening
+// one, as in "(see https://example.com)".
+pub(crate) fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '"']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(inner) if inner.matches('(').count() < inner.matches(')').count() + 1 => inner,
+            _ => trimmed,
+        };
+        if trimmed == url {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+// Takes a due date written at the end of the body, as in "Pay rent due friday" or "Call Sam, due
+// tomorrow 5pm", out of the body and into the due date, unless the client gave one. Phrases that
+// don't parse as a due date stay in the body.
+pub struct DuePhrases;
+
+impl Transform for DuePhrases {
+    fn name(&self) -> &str {
+        "due_phrases"
+    }
+
+    fn apply(&self, draft: &mut Draft) {
+        if draft.due.is_some() {
+            return;
+        }
+        // ASCII lowercasing keeps byte offsets the same, so they apply to the body too.
+        let lowercase = draft.body.to_ascii_lowercase();
+        let start = match lowercase.rfind("due ") {
+            Some(0) => 0,
+            Some(start) if lowercase[..start].ends_with(char::is_whitespace) => start,
+            _ => return,
+        };
+        let phrase = draft.body[start + 4..].trim().trim_end_matches(['.', '!']);
+        // The phrase only has to parse here; it's resolved in the owner's timezone when stored.
+        if phrase.is_empty() || due::parse(phrase, Utc::now().with_timezone(&Tz::UTC)).is_none() {
+            return;
+        }
+        let body = draft.body[..start]
+            .trim_end()
+            .trim_end_matches([',', ';', '-'])
+            .trim_end();
+        // A body that was nothing but the phrase is kept, since a todo needs a body.
+        if body.is_empty() {
+            return;
+        }
+        draft.due = Some(phrase.to_string());
+        draft.body = body.to_string();
+    }
+}
@@ -0,0 +1,168 @@
+use crate::statement_budget;
+use sqlx::pool::{PoolConnection, PoolConnectionMetadata};
+use sqlx::sqlite::{SqliteConnection, SqlitePoolOptions};
+use sqlx::{Sqlite, SqlitePool};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How long the keeper waits for a connection beyond the idle ones. A pool that can't hand one out
+// that quickly is busy with requests, which keep their own connections warm.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Opens connections until `min_idle` of them are idle in the pool, and pings every idle connection,
+// closing the ones that don't answer so the pool replaces them. Returns the number of connections
+// that answered. We run it once at startup, so the first requests after a deploy don't wait for
+// connections to be opened, and then every ping interval from the keeper.
+pub async fn warm_up(dbpool: &SqlitePool, min_idle: u32) -> usize {
+    let max = dbpool.options().get_max_connections();
+    let wanted = (min_idle.min(max) as usize).max(dbpool.num_idle());
+    // Holding on to each connection until we're done means we see every idle one once, and the
+    // pool opens new ones for the rest.
+    let mut held: Vec<PoolConnection<Sqlite>> = Vec::with_capacity(wanted);
+    while held.len() < wanted {
+        let conn = match dbpool.try_acquire() {
+            Some(conn) => conn,
+            None => match tokio::time::timeout(ACQUIRE_TIMEOUT, dbpool.acquire()).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(err)) => {
+                    tracing::warn!(error = ?err, "failed to open a database connection");
+                    break;
+                }
+                Err(_) => break,
+            },
+        };
+        held.push(conn);
+    }
+    let mut healthy = 0;
+    for mut conn in held {
+        match sqlx::query("select 1").execute(&mut *conn).await {
+            Ok(_) => healthy += 1,
+            Err(err) => {
+                tracing::warn!(error = ?err, "closed a database connection that failed a ping");
+                let _ = conn.close().await;
+            }
+        }
+    }
+    healthy
+}
+
+// Starts keeping `min_idle` connections open and healthy in the background, checking every
+// `interval`. A `min_idle` of 0 turns the keeper off.
+pub fn spawn_keeper(dbpool: SqlitePool, min_idle: u32, interval: Duration) {
+    if min_idle == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if dbpool.is_closed() {
+                break;
+            }
+            warm_up(&dbpool, min_idle).await;
+        }
+    });
+}
+
+// How often a connection handed out by the pool is used to check the schema version.
+const SCHEMA_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Pool options for our databases. Each connection caches its prepared statements along with the
+// columns they return, which go stale when another process changes the schema, e.g.
+// `migrate --phase=cleanup` dropping a column while we run. So every few seconds a connection
+// taken from the pool checks the schema version, and once it has changed, connections opened
+// before that are closed rather than handed out, and replaced with fresh ones. Taking a connection
+// also counts a statement against the budget of the request it's for.
+pub fn options() -> SqlitePoolOptions {
+    let watch = Arc::new(SchemaWatch {
+        started: Instant::now(),
+        version: AtomicI64::new(-1),
+        checked_at: AtomicU64::new(0),
+        changed_at: AtomicU64::new(0),
+    });
+    SqlitePoolOptions::new()
+        .after_connect(|_, _| {
+            statement_budget::count();
+            Box::pin(async { Ok(()) })
+        })
+        .before_acquire(move |conn, meta| {
+            let watch = watch.clone();
+            Box::pin(async move {
+                let usable = watch.check(conn, meta).await?;
+                // A connection that's closed instead is replaced, and the replacement counts.
+                if usable {
+                    statement_budget::count();
+                }
+                Ok(usable)
+            })
+        })
+}
+
+// Whether a DATABASE_URL like sqlite::memory: names an in-memory database.
+pub fn is_in_memory(url: &str) -> bool {
+    url.contains(":memory:") || url.contains("mode=memory")
+}
+
+// Pool options for an in-memory database, e.g. to benchmark the service without a disk in the way.
+// The database only lives as long as one of its connections, so the pool keeps one open for good
+// instead of closing idle and old ones.
+pub fn in_memory(options: SqlitePoolOptions) -> SqlitePoolOptions {
+    options
+        .min_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+}
+
+struct SchemaWatch {
+    started: Instant,
+    // The last schema version seen, or -1 before the first check.
+    version: AtomicI64,
+    // When the schema was last checked and last seen to change, in milliseconds since `started`.
+    // 0 means it hasn't changed.
+    checked_at: AtomicU64,
+    changed_at: AtomicU64,
+}
+
+impl SchemaWatch {
+    fn millis(duration: Duration) -> u64 {
+        duration.as_millis().try_into().unwrap_or(u64::MAX)
+    }
+
+    // Whether the pool may hand out the connection.
+    async fn check(
+        &self,
+        conn: &mut SqliteConnection,
+        meta: PoolConnectionMetadata,
+    ) -> Result<bool, sqlx::Error> {
+        let now = Self::millis(self.started.elapsed());
+        let checked_at = self.checked_at.load(Ordering::Relaxed);
+        let due = now.saturating_sub(checked_at) >= Self::millis(SCHEMA_CHECK_INTERVAL)
+            || self.version.load(Ordering::Relaxed) == -1;
+        // Only one of the connections being handed out at a time does the check.
+        if due
+            && self
+                .checked_at
+                .compare_exchange(checked_at, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let (version,): (i64,) = sqlx::query_as("pragma schema_version")
+                .fetch_one(&mut *conn)
+                .await?;
+            let previous = self.version.swap(version, Ordering::Relaxed);
+            // A new database starts out at version 0, and the statements run before the
+            // migrations created it are the migrations' own.
+            if previous > 0 && previous != version {
+                tracing::info!(
+                    previous,
+                    version,
+                    "the database schema changed, replacing the pooled connections"
+                );
+                self.changed_at.store(now.max(1), Ordering::Relaxed);
+            }
+        }
+        let changed_at = self.changed_at.load(Ordering::Relaxed);
+        let opened_at = now.saturating_sub(Self::millis(meta.age));
+        Ok(changed_at == 0 || opened_at > changed_at)
+    }
+}
@@ -0,0 +1,92 @@
+use crate::cache_control::weak_etag;
+use crate::ids;
+use crate::todo::Todo;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::header::{ETAG, LOCATION};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::convert::Infallible;
+
+const PREFER: HeaderName = HeaderName::from_static("prefer");
+pub(crate) const PREFERENCE_APPLIED: HeaderName = HeaderName::from_static("preference-applied");
+
+// The return preference from the Prefer header (RFC 7240), which lets clients that write a lot skip
+// the representation of what they've just written.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReturnPreference {
+    // The full representation, which is what we send when there's no preference.
+    #[default]
+    Representation,
+    // Just a 204 with the Location and ETag of the todo.
+    Minimal,
+}
+
+impl ReturnPreference {
+    // Responds to a create or update with the written todo, honoring the preference.
+    pub fn respond(self, todo: Todo) -> Response {
+        match self {
+            ReturnPreference::Representation => axum::Json(todo).into_response(),
+            ReturnPreference::Minimal => {
+                let location =
+                    HeaderValue::from_str(&format!("/v1/todos/{}", ids::encode(todo.id())))
+                        .expect("a path with an ID is a valid header value");
+                // The ETag is computed from the representation we would have sent, so it matches
+                // the one a later GET of the todo gets.
+                let etag = serde_json::to_vec(&todo)
+                    .map(|bytes| weak_etag(&bytes))
+                    .ok();
+                let mut response = (
+                    StatusCode::NO_CONTENT,
+                    [
+                        (LOCATION, location),
+                        (
+                            PREFERENCE_APPLIED,
+                            HeaderValue::from_static("return=minimal"),
+                        ),
+                    ],
+                )
+                    .into_response();
+                if let Some(etag) = etag {
+                    response.headers_mut().insert(ETAG, etag);
+                }
+                response
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ReturnPreference
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(if prefers(&parts.headers, "return=minimal") {
+            ReturnPreference::Minimal
+        } else {
+            ReturnPreference::Representation
+        })
+    }
+}
+
+// Whether the request's Prefer headers include a preference, e.g. "return=minimal". Preferences are
+// hints, so anything we don't understand is ignored rather than rejected. A header can list several
+// preferences, and there can be several Prefer headers.
+pub(crate) fn prefers(headers: &HeaderMap, wanted: &str) -> bool {
+    headers
+        .get_all(PREFER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|preference| preference.split(';').next())
+        .any(|preference| {
+            preference
+                .trim()
+                .replace(' ', "")
+                .eq_ignore_ascii_case(wanted)
+        })
+}
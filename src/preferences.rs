@@ -0,0 +1,157 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{query_as, SqlitePool};
+
+// The most notification channels that can be muted, and the longest channel name.
+const MAX_MUTED_CHANNELS: usize = 20;
+const MAX_CHANNEL_CHARS: usize = 32;
+
+// All fields are optional, so clients can change one preference without knowing the others.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdatePreferences {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    muted_channels: Option<Vec<String>>,
+}
+
+impl UpdatePreferences {
+    pub fn with_timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.timezone = Some(timezone.into());
+        self
+    }
+
+    pub fn with_locale(mut self, locale: impl Into<String>) -> Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    pub fn with_muted_channels(mut self, channels: Vec<String>) -> Self {
+        self.muted_channels = Some(channels);
+        self
+    }
+
+    // Rejects values we can't use later, rather than failing when they're needed.
+    fn validate(&self) -> Result<(), Error> {
+        if let Some(timezone) = &self.timezone {
+            if timezone.parse::<Tz>().is_err() {
+                return Err(invalid(
+                    "invalid_timezone",
+                    "timezone",
+                    i18n::message("invalid_timezone", &[("timezone", timezone)]),
+                ));
+            }
+        }
+        if let Some(locale) = &self.locale {
+            // A loose check for BCP 47 language tags like "en" or "pt-BR".
+            let valid = !locale.is_empty()
+                && locale.split('-').all(|part| {
+                    (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+                });
+            if !valid {
+                return Err(invalid(
+                    "invalid_locale",
+                    "locale",
+                    i18n::message("invalid_locale", &[("locale", locale)]),
+                ));
+            }
+        }
+        if let Some(channels) = &self.muted_channels {
+            // Channel names are like "slack" or "web_push". We don't check them against the
+            // registered channels, so muting one that's configured later works too.
+            let invalid_channel = channels.iter().find(|channel| {
+                channel.is_empty()
+                    || channel.len() > MAX_CHANNEL_CHARS
+                    || !channel
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            });
+            if let Some(channel) = invalid_channel {
+                return Err(invalid(
+                    "invalid_channel",
+                    "muted_channels",
+                    i18n::message("invalid_channel", &[("channel", channel)]),
+                ));
+            }
+            if channels.len() > MAX_MUTED_CHANNELS {
+                return Err(invalid(
+                    "too_many_channels",
+                    "muted_channels",
+                    i18n::message(
+                        "too_many_channels",
+                        &[("max", &MAX_MUTED_CHANNELS.to_string())],
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(code: &'static str, field: &str, message: String) -> Error {
+    Error::BadRequest(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RequestError::new(code, message).with_field(field),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct Preferences {
+    // An IANA timezone name such as "Europe/Berlin", used when interpreting dates.
+    timezone: String,
+    locale: String,
+    // The notification channels the owner turned off.
+    muted_channels: Json<Vec<String>>,
+    updated_at: NaiveDateTime,
+}
+
+impl Preferences {
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    pub fn muted_channels(&self) -> &[String] {
+        &self.muted_channels
+    }
+
+    pub fn timezone(&self) -> Tz {
+        // The timezone is validated before it's stored, so this only falls back for rows edited by hand.
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    pub async fn read(dbpool: SqlitePool) -> Result<Preferences, Error> {
+        query_as(
+            "select timezone, locale, muted_channels, updated_at from preferences where id = 1",
+        )
+        .fetch_one(&dbpool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update(
+        dbpool: SqlitePool,
+        updated: UpdatePreferences,
+    ) -> Result<Preferences, Error> {
+        updated.validate()?;
+
+        // coalesce() keeps the current value for any preference the client didn't send.
+        query_as(
+            "update preferences set timezone = coalesce(?, timezone), locale = coalesce(?, locale),
+             muted_channels = coalesce(?, muted_channels), updated_at = datetime('now')
+             where id = 1 returning timezone, locale, muted_channels, updated_at",
+        )
+        .bind(updated.timezone)
+        .bind(updated.locale)
+        .bind(updated.muted_channels.map(Json))
+        .fetch_one(&dbpool)
+        .await
+        .map_err(Into::into)
+    }
+}
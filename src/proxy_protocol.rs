@@ -0,0 +1,226 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// The signature starting every version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// The longest version 1 header the specification allows, CRLF included.
+const V1_MAX_LEN: usize = 107;
+
+// Reads the PROXY protocol header a load balancer sends ahead of the client's bytes, in either
+// version, and returns the client's address. Health checks the load balancer makes itself (LOCAL
+// in version 2, UNKNOWN in version 1) carry no address. We read exactly the header and nothing
+// more, so the HTTP request that follows is left in the stream.
+//
+// A connection without a header is an error rather than a client we'd take at its word: if anyone
+// could skip the header, anyone could also send one claiming to be whoever they like.
+pub async fn read_header<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Option<SocketAddr>, Error> {
+    let mut start = [0; 5];
+    stream.read_exact(&mut start).await?;
+    if &start == b"PROXY" {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..5] {
+        read_v2(stream).await
+    } else {
+        Err(invalid(
+            "the connection didn't start with a PROXY protocol header",
+        ))
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, Error> {
+    // There's no length up front, so we read up to the CRLF a byte at a time.
+    let mut line = b"PROXY".to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("the PROXY protocol header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("the PROXY protocol header isn't text"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("the PROXY protocol header has an invalid source address"))?;
+            let port: u16 = port
+                .parse()
+                .map_err(|_| invalid("the PROXY protocol header has an invalid source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("the PROXY protocol header is malformed")),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, Error> {
+    let mut rest = [0; 11];
+    stream.read_exact(&mut rest).await?;
+    if rest[..7] != V2_SIGNATURE[5..] {
+        return Err(invalid(
+            "the PROXY protocol header has an invalid signature",
+        ));
+    }
+    let (version_command, family) = (rest[7], rest[8]);
+    let len = u16::from_be_bytes([rest[9], rest[10]]) as usize;
+    if version_command >> 4 != 2 {
+        return Err(invalid("the PROXY protocol header has an unknown version"));
+    }
+    // The addresses are followed by optional TLVs, which we read along with them and ignore.
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await?;
+
+    if version_command & 0x0f == 0 {
+        // LOCAL: the load balancer's own connection.
+        return Ok(None);
+    }
+    // The high nibble is the address family, the low one the transport, which doesn't matter here.
+    match family >> 4 {
+        1 if payload.len() >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        2 if payload.len() >= 36 => {
+            let octets: [u8; 16] = payload[..16].try_into().expect("we checked the length");
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // Unix sockets and unspecified families have no IP address to report.
+        0 | 3 => Ok(None),
+        _ => Err(invalid(
+            "the PROXY protocol header has a truncated or unknown address",
+        )),
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads the header from the bytes, returning the address and what's left in the stream.
+    async fn read(bytes: &[u8]) -> (Result<Option<SocketAddr>, Error>, Vec<u8>) {
+        let mut stream = bytes;
+        let header = read_header(&mut stream).await;
+        (header, stream.to_vec())
+    }
+
+    fn v2(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20 | command, family]);
+        header.extend((payload.len() as u16).to_be_bytes());
+        header.extend(payload);
+        header
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (header, rest) = read(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\nGET /").await;
+        assert_eq!(header.unwrap(), Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (header, _) = read(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 443\r\n").await;
+        assert_eq!(
+            header.unwrap(),
+            Some("[2001:db8::7]:51234".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (header, rest) = read(b"PROXY UNKNOWN\r\nGET /").await;
+        assert_eq!(header.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_too_long() {
+        let mut line = b"PROXY TCP4 ".to_vec();
+        line.extend([b'1'; V1_MAX_LEN]);
+        line.extend(b"\r\n");
+        let (header, _) = read(&line).await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v1_malformed() {
+        let (header, _) = read(b"PROXY TCP4 not-an-address 10.0.0.1 1 2\r\n").await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::InvalidData);
+        let (header, _) = read(b"PROXY TCP4 203.0.113.7\r\n").await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_ipv4() {
+        let mut bytes = v2(
+            1,
+            0x11,
+            &[203, 0, 113, 7, 10, 0, 0, 1, 0xc8, 0x22, 0x01, 0xbb],
+        );
+        bytes.extend(b"GET /");
+        let (header, rest) = read(&bytes).await;
+        assert_eq!(header.unwrap(), Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_ipv6() {
+        let source: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        let destination: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut payload = source.octets().to_vec();
+        payload.extend(destination.octets());
+        payload.extend([0xc8, 0x22, 0x01, 0xbb]);
+        let (header, _) = read(&v2(1, 0x21, &payload)).await;
+        assert_eq!(
+            header.unwrap(),
+            Some("[2001:db8::7]:51234".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let mut bytes = v2(0, 0x00, &[]);
+        bytes.extend(b"GET /");
+        let (header, rest) = read(&bytes).await;
+        assert_eq!(header.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_truncated_address() {
+        // An IPv4 address block is 12 bytes long.
+        let (header, _) = read(&v2(1, 0x11, &[203, 0, 113, 7, 10, 0])).await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v2_payload_cut_short() {
+        let mut bytes = v2(
+            1,
+            0x11,
+            &[203, 0, 113, 7, 10, 0, 0, 1, 0xc8, 0x22, 0x01, 0xbb],
+        );
+        bytes.truncate(bytes.len() - 4);
+        let (header, _) = read(&bytes).await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn missing_header() {
+        let (header, _) = read(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::InvalidData);
+        let (header, _) = read(b"GET").await;
+        assert_eq!(header.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
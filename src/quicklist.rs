@@ -0,0 +1,327 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::validation::BodyPolicy;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+use std::time::Duration;
+
+// The most items a quicklist can hold. Anyone can create one, so they're kept small.
+const MAX_ITEMS: i64 = 500;
+// The longest title we accept, in characters.
+const MAX_TITLE_CHARS: usize = 200;
+
+// A throwaway list for someone without an account. The secret token in its path is the only thing
+// granting access, to this list alone, so whoever has the link can read and change it. A quicklist
+// expires a while after it was last changed, and is then deleted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Quicklist {
+    token: String,
+    title: Option<String>,
+    created_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+    // The capability URL's path, for sharing the list.
+    path: String,
+    items: Vec<QuicklistItem>,
+}
+
+#[derive(sqlx::FromRow)]
+struct QuicklistRow {
+    id: i64,
+    token: String,
+    title: Option<String>,
+    created_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct QuicklistItem {
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    body: String,
+    completed: bool,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+impl QuicklistItem {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn completed(&self) -> bool {
+        self.completed
+    }
+}
+
+// The body of POST /v1/quicklists.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CreateQuicklist {
+    title: Option<String>,
+}
+
+impl CreateQuicklist {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateQuicklistItem {
+    body: String,
+}
+
+impl CreateQuicklistItem {
+    pub fn new(body: impl Into<String>) -> Self {
+        Self { body: body.into() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateQuicklistItem {
+    body: Option<String>,
+    completed: Option<bool>,
+}
+
+impl UpdateQuicklistItem {
+    pub fn with_body(mut self, body: impl Into<String>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    pub fn with_completed(mut self, completed: bool) -> Self {
+        self.completed = Some(completed);
+        self
+    }
+}
+
+// See feed.rs for why SQLite generates the tokens.
+const NEW_TOKEN: &str = "lower(hex(randomblob(20)))";
+
+impl Quicklist {
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn expires_at(&self) -> NaiveDateTime {
+        self.expires_at
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn items(&self) -> &[QuicklistItem] {
+        &self.items
+    }
+
+    pub async fn create(
+        dbpool: SqlitePool,
+        ttl: Duration,
+        new: CreateQuicklist,
+    ) -> Result<Quicklist, Error> {
+        let title = new.title.map(|title| title.trim().to_string());
+        if title
+            .as_ref()
+            .is_some_and(|title| title.chars().count() > MAX_TITLE_CHARS)
+        {
+            return Err(Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "title_too_long",
+                    i18n::message("title_too_long", &[("max", &MAX_TITLE_CHARS.to_string())]),
+                )
+                .with_field("title"),
+            ));
+        }
+        let row: QuicklistRow = query_as(&format!(
+            "insert into quicklists (token, title, expires_at)
+             values ({NEW_TOKEN}, ?, datetime('now', '+' || ? || ' seconds')) returning *"
+        ))
+        .bind(title.filter(|title| !title.is_empty()))
+        .bind(ttl.as_secs() as i64)
+        .fetch_one(&dbpool)
+        .await?;
+        Ok(Self::from_row(row, Vec::new()))
+    }
+
+    // Reads a quicklist with its items. Unknown and expired tokens get a 404, like a wrong path.
+    pub async fn read(dbpool: SqlitePool, token: &str) -> Result<Quicklist, Error> {
+        let row = Self::find(&dbpool, token).await?;
+        let items = query_as("select * from quicklist_items where quicklist_id = ? order by id")
+            .bind(row.id)
+            .fetch_all(&dbpool)
+            .await?;
+        Ok(Self::from_row(row, items))
+    }
+
+    pub async fn delete(dbpool: SqlitePool, token: &str) -> Result<(), Error> {
+        let result =
+            query("delete from quicklists where token = ? and expires_at > datetime('now')")
+                .bind(token)
+                .execute(&dbpool)
+                .await?;
+        match result.rows_affected() {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn add_item(
+        dbpool: SqlitePool,
+        ttl: Duration,
+        policy: &BodyPolicy,
+        token: &str,
+        new: CreateQuicklistItem,
+    ) -> Result<QuicklistItem, Error> {
+        let body = policy.apply(&new.body, "body")?;
+        let list = Self::find(&dbpool, token).await?;
+        let mut tx = dbpool.begin().await?;
+        let (count,): (i64,) =
+            query_as("select count(*) from quicklist_items where quicklist_id = ?")
+                .bind(list.id)
+                .fetch_one(&mut *tx)
+                .await?;
+        if count >= MAX_ITEMS {
+            return Err(Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "quicklist_full",
+                    i18n::message("quicklist_full", &[("max", &MAX_ITEMS.to_string())]),
+                ),
+            ));
+        }
+        let item =
+            query_as("insert into quicklist_items (quicklist_id, body) values (?, ?) returning *")
+                .bind(list.id)
+                .bind(body)
+                .fetch_one(&mut *tx)
+                .await?;
+        extend(&mut tx, list.id, ttl).await?;
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    pub async fn update_item(
+        dbpool: SqlitePool,
+        ttl: Duration,
+        policy: &BodyPolicy,
+        token: &str,
+        id: i64,
+        update: UpdateQuicklistItem,
+    ) -> Result<QuicklistItem, Error> {
+        let body = match update.body {
+            Some(body) => Some(policy.apply(&body, "body")?),
+            None => None,
+        };
+        let list = Self::find(&dbpool, token).await?;
+        let mut tx = dbpool.begin().await?;
+        let item = query_as(
+            "update quicklist_items set body = coalesce(?, body),
+             completed = coalesce(?, completed), updated_at = datetime('now')
+             where id = ? and quicklist_id = ? returning *",
+        )
+        .bind(body)
+        .bind(update.completed)
+        .bind(id)
+        .bind(list.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        extend(&mut tx, list.id, ttl).await?;
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    pub async fn delete_item(
+        dbpool: SqlitePool,
+        ttl: Duration,
+        token: &str,
+        id: i64,
+    ) -> Result<(), Error> {
+        let list = Self::find(&dbpool, token).await?;
+        let mut tx = dbpool.begin().await?;
+        let result = query("delete from quicklist_items where id = ? and quicklist_id = ?")
+            .bind(id)
+            .bind(list.id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        extend(&mut tx, list.id, ttl).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Deletes the expired quicklists, with their items, returning how many there were.
+    pub async fn delete_expired(dbpool: &SqlitePool) -> Result<u64, Error> {
+        let result = query("delete from quicklists where expires_at <= datetime('now')")
+            .execute(dbpool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Starts deleting expired quicklists every `interval` in the background.
+    pub fn spawn_cleanup(dbpool: SqlitePool, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if dbpool.is_closed() {
+                    break;
+                }
+                match Self::delete_expired(&dbpool).await {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::info!(deleted, "deleted expired quicklists"),
+                    Err(err) => tracing::warn!(error = ?err, "failed to delete expired quicklists"),
+                }
+            }
+        });
+    }
+
+    async fn find(dbpool: &SqlitePool, token: &str) -> Result<QuicklistRow, Error> {
+        Ok(
+            query_as("select * from quicklists where token = ? and expires_at > datetime('now')")
+                .bind(token)
+                .fetch_one(dbpool)
+                .await?,
+        )
+    }
+
+    fn from_row(row: QuicklistRow, items: Vec<QuicklistItem>) -> Self {
+        Self {
+            path: format!("/v1/quicklists/{}", row.token),
+            token: row.token,
+            title: row.title,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            items,
+        }
+    }
+}
+
+// A quicklist in use stays around: every change pushes its expiry back.
+async fn extend(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    id: i64,
+    ttl: Duration,
+) -> Result<(), sqlx::Error> {
+    query(
+        "update quicklists set expires_at = datetime('now', '+' || ? || ' seconds') where id = ?",
+    )
+    .bind(ttl.as_secs() as i64)
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,486 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::listener::ClientAddr;
+use crate::state::MainDb;
+use crate::tenant::Tenant;
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// The headers telling clients where they stand, as in the IETF RateLimit header fields draft, plus
+// the burst credits they have left.
+pub const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+pub const RATE_LIMIT_BURST_REMAINING: HeaderName =
+    HeaderName::from_static("ratelimit-burst-remaining");
+
+// How long the limiter may use tenant plans read earlier. Changes through the admin API take effect
+// immediately in this process; other processes pick them up within this time.
+const CACHE_TTL: Duration = Duration::from_secs(10);
+// Buckets idle this long have refilled completely, burst credits included, so they're forgotten; a
+// new bucket starts out full, so that changes nothing for the client.
+const BUCKET_IDLE: Duration = Duration::from_secs(3600);
+// At most this many clients have buckets. Past it, the least recently used are forgotten early,
+// which only lets a client make more requests than its plan allows if there are this many busier
+// ones.
+const MAX_BUCKETS: u64 = 100_000;
+
+// The limits of a plan. Reads and writes each have a bucket holding a minute's worth of requests,
+// which refills continuously. Requests beyond that spend burst credits, shared by reads and writes
+// and refilled at burst_per_hour an hour, so a spike goes through and only sustained overuse is
+// turned away.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlanLimits {
+    reads_per_minute: u32,
+    writes_per_minute: u32,
+    burst_per_hour: u32,
+}
+
+impl PlanLimits {
+    pub fn reads_per_minute(&self) -> u32 {
+        self.reads_per_minute
+    }
+
+    pub fn writes_per_minute(&self) -> u32 {
+        self.writes_per_minute
+    }
+
+    pub fn burst_per_hour(&self) -> u32 {
+        self.burst_per_hour
+    }
+}
+
+// A named plan, as listed by the admin API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RatePlan {
+    name: String,
+    #[serde(flatten)]
+    limits: PlanLimits,
+    // Whether tenants without a plan of their own, and clients without a tenant, are on this one.
+    default: bool,
+}
+
+impl RatePlan {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn limits(&self) -> PlanLimits {
+        self.limits
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.default
+    }
+}
+
+// The configured plans, e.g. "free:reads=60,writes=20,burst=100;pro:reads=600,writes=200,burst=2000".
+// The first one is the default. Without any, requests aren't limited.
+#[derive(Clone, Debug, Default)]
+pub struct RatePlans {
+    plans: Vec<(String, PlanLimits)>,
+}
+
+impl RatePlans {
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<PlanLimits> {
+        self.plans
+            .iter()
+            .find(|(plan, _)| plan == name)
+            .map(|(_, limits)| *limits)
+    }
+
+    fn default_plan(&self) -> Option<&(String, PlanLimits)> {
+        self.plans.first()
+    }
+
+    pub fn list(&self) -> Vec<RatePlan> {
+        self.plans
+            .iter()
+            .enumerate()
+            .map(|(index, (name, limits))| RatePlan {
+                name: name.clone(),
+                limits: *limits,
+                default: index == 0,
+            })
+            .collect()
+    }
+}
+
+impl FromStr for RatePlans {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut plans: Vec<(String, PlanLimits)> = Vec::new();
+        for plan in value
+            .split(';')
+            .map(str::trim)
+            .filter(|plan| !plan.is_empty())
+        {
+            let (name, settings) = plan.split_once(':').ok_or_else(|| {
+                format!("`{plan}` should look like `name:reads=60,writes=20,burst=100`")
+            })?;
+            let name = name.trim();
+            if name.is_empty() || plans.iter().any(|(other, _)| other == name) {
+                return Err(format!("`{plan}` needs a name of its own"));
+            }
+            let (mut reads, mut writes, mut burst) = (None, None, 0);
+            for setting in settings.split(',').map(str::trim) {
+                let (key, number) = setting
+                    .split_once('=')
+                    .ok_or_else(|| format!("`{setting}` should look like `reads=60`"))?;
+                let number: u32 = number
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("`{setting}` needs a whole number"))?;
+                match key.trim() {
+                    "reads" => reads = Some(number),
+                    "writes" => writes = Some(number),
+                    "burst" => burst = number,
+                    key => return Err(format!("`{key}` isn't reads, writes, or burst")),
+                }
+            }
+            let (Some(reads_per_minute), Some(writes_per_minute)) = (reads, writes) else {
+                return Err(format!("`{plan}` needs both reads and writes"));
+            };
+            if reads_per_minute == 0 || writes_per_minute == 0 {
+                return Err(format!("`{plan}` has to allow some reads and writes"));
+            }
+            plans.push((
+                name.to_string(),
+                PlanLimits {
+                    reads_per_minute,
+                    writes_per_minute,
+                    burst_per_hour: burst,
+                },
+            ));
+        }
+        Ok(Self { plans })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct TenantPlan {
+    tenant: String,
+    plan: String,
+    updated_at: NaiveDateTime,
+}
+
+impl TenantPlan {
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    pub fn plan(&self) -> &str {
+        &self.plan
+    }
+}
+
+// The body of PUT /v1/admin/tenants/:tenant/plan.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetTenantPlan {
+    plan: String,
+}
+
+impl SetTenantPlan {
+    pub fn new(plan: impl Into<String>) -> Self {
+        Self { plan: plan.into() }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Read,
+    Write,
+}
+
+// A client's buckets, in requests. Tokens are fractional while they refill.
+struct Buckets {
+    reads: f64,
+    writes: f64,
+    burst: f64,
+    updated: Instant,
+    // The plan the buckets were last filled for.
+    limits: PlanLimits,
+}
+
+// What the limiter decided about a request, and the numbers for the headers.
+struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    // Seconds until the bucket is full again.
+    reset: u64,
+    burst_remaining: u32,
+    // Seconds until the next request would go through, when this one didn't.
+    retry_after: u64,
+}
+
+// Limits the rate of requests per tenant, or per client address for requests without a tenant.
+// The buckets are kept in memory per process, so behind a load balancer each instance grants the
+// full limits; the limits are meant to stop runaway clients rather than to meter exactly.
+pub struct RateLimiter {
+    plans: RatePlans,
+    assignments: Cache<(), Arc<HashMap<String, String>>>,
+    buckets: Cache<String, Arc<Mutex<Buckets>>>,
+}
+
+impl RateLimiter {
+    pub fn new(plans: RatePlans) -> Self {
+        Self {
+            plans,
+            assignments: Cache::builder()
+                .max_capacity(1)
+                .time_to_live(CACHE_TTL)
+                .build(),
+            buckets: Cache::builder()
+                .max_capacity(MAX_BUCKETS)
+                .time_to_idle(BUCKET_IDLE)
+                .build(),
+        }
+    }
+
+    pub fn plans(&self) -> Vec<RatePlan> {
+        self.plans.list()
+    }
+
+    pub async fn tenants(&self, dbpool: &SqlitePool) -> Result<Vec<TenantPlan>, Error> {
+        Ok(query_as("select * from tenant_plans order by tenant")
+            .fetch_all(dbpool)
+            .await?)
+    }
+
+    pub async fn set_plan(
+        &self,
+        dbpool: &SqlitePool,
+        tenant: &str,
+        update: SetTenantPlan,
+    ) -> Result<TenantPlan, Error> {
+        if self.plans.get(&update.plan).is_none() {
+            let plans: Vec<&str> = self
+                .plans
+                .plans
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            return Err(Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "unknown_plan",
+                    i18n::message(
+                        "unknown_plan",
+                        &[("plan", &update.plan), ("plans", &plans.join(", "))],
+                    ),
+                )
+                .with_field("plan"),
+            ));
+        }
+        let plan = query_as(
+            "insert into tenant_plans (tenant, plan) values (?, ?)
+             on conflict (tenant) do update set plan = excluded.plan, updated_at = datetime('now')
+             returning *",
+        )
+        .bind(tenant)
+        .bind(&update.plan)
+        .fetch_one(dbpool)
+        .await?;
+        self.assignments.invalidate_all();
+        Ok(plan)
+    }
+
+    // Puts a tenant back on the default plan.
+    pub async fn reset_plan(&self, dbpool: &SqlitePool, tenant: &str) -> Result<(), Error> {
+        let result = query("delete from tenant_plans where tenant = ?")
+            .bind(tenant)
+            .execute(dbpool)
+            .await?;
+        self.assignments.invalidate_all();
+        match result.rows_affected() {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    // The limits for a tenant. A plan that's no longer configured, or plans that can't be read,
+    // fall back to the default plan rather than failing the request.
+    async fn limits_for(&self, dbpool: &SqlitePool, tenant: Option<&str>) -> Option<PlanLimits> {
+        let (_, default) = self.plans.default_plan()?;
+        let Some(tenant) = tenant else {
+            return Some(*default);
+        };
+        let assignments = match self.assignments.get(&()) {
+            Some(assignments) => assignments,
+            None => {
+                let rows: Result<Vec<(String, String)>, _> =
+                    query_as("select tenant, plan from tenant_plans")
+                        .fetch_all(dbpool)
+                        .await;
+                match rows {
+                    Ok(rows) => {
+                        let assignments = Arc::new(rows.into_iter().collect::<HashMap<_, _>>());
+                        self.assignments.insert((), assignments.clone());
+                        assignments
+                    }
+                    Err(err) => {
+                        tracing::warn!(error = ?err, "failed to read tenant plans");
+                        return Some(*default);
+                    }
+                }
+            }
+        };
+        Some(
+            assignments
+                .get(tenant)
+                .and_then(|plan| self.plans.get(plan))
+                .unwrap_or(*default),
+        )
+    }
+
+    fn take(&self, key: String, limits: PlanLimits, kind: Kind) -> Decision {
+        let now = Instant::now();
+        let read_capacity = f64::from(limits.reads_per_minute);
+        let write_capacity = f64::from(limits.writes_per_minute);
+        let burst_capacity = f64::from(limits.burst_per_hour);
+
+        // Each client's buckets have a lock of their own, so clients don't wait for each other.
+        let entry = self.buckets.get_with(key, || {
+            Arc::new(Mutex::new(Buckets {
+                reads: read_capacity,
+                writes: write_capacity,
+                burst: burst_capacity,
+                updated: now,
+                limits,
+            }))
+        });
+        let mut guard = entry.lock().unwrap();
+        let buckets = &mut *guard;
+        // When the client has moved to another plan, what it used so far counts against the new
+        // plan's capacity, so an upgrade helps right away and a downgrade takes effect right away.
+        if buckets.limits != limits {
+            let old = buckets.limits;
+            buckets.reads += read_capacity - f64::from(old.reads_per_minute);
+            buckets.writes += write_capacity - f64::from(old.writes_per_minute);
+            buckets.burst += burst_capacity - f64::from(old.burst_per_hour);
+            buckets.reads = buckets.reads.max(0.0);
+            buckets.writes = buckets.writes.max(0.0);
+            buckets.burst = buckets.burst.max(0.0);
+            buckets.limits = limits;
+        }
+        // Refill for the time since the last request.
+        let elapsed = now.duration_since(buckets.updated).as_secs_f64();
+        buckets.reads = (buckets.reads + elapsed * read_capacity / 60.0).min(read_capacity);
+        buckets.writes = (buckets.writes + elapsed * write_capacity / 60.0).min(write_capacity);
+        buckets.burst = (buckets.burst + elapsed * burst_capacity / 3600.0).min(burst_capacity);
+        buckets.updated = now;
+
+        let (tokens, capacity) = match kind {
+            Kind::Read => (&mut buckets.reads, read_capacity),
+            Kind::Write => (&mut buckets.writes, write_capacity),
+        };
+        let per_second = capacity / 60.0;
+        let allowed = if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else if buckets.burst >= 1.0 {
+            buckets.burst -= 1.0;
+            true
+        } else {
+            false
+        };
+        // The next request goes through once either the bucket or the burst credits have a token.
+        let mut retry_after = (1.0 - *tokens) / per_second;
+        if burst_capacity > 0.0 {
+            retry_after = retry_after.min((1.0 - buckets.burst) / (burst_capacity / 3600.0));
+        }
+        Decision {
+            allowed,
+            limit: capacity as u32,
+            remaining: *tokens as u32,
+            reset: ((capacity - *tokens) / per_second).ceil() as u64,
+            burst_remaining: buckets.burst as u32,
+            retry_after: retry_after.ceil().max(1.0) as u64,
+        }
+    }
+}
+
+// A middleware which limits the rate of API requests by the plan the client is on, with separate
+// limits for reads and writes. Every limited response carries the RateLimit headers for its kind
+// of request; a request over the limit with no burst credits left gets a 429 with Retry-After.
+// Health checks, the admin API, and CORS preflights aren't limited.
+pub async fn limit_rate(
+    State(limiter): State<Arc<RateLimiter>>,
+    State(MainDb(dbpool)): State<MainDb>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if limiter.plans.is_empty()
+        || !path.starts_with("/v1/")
+        || path.starts_with("/v1/admin")
+        || request.method() == Method::OPTIONS
+    {
+        return next.run(request).await;
+    }
+    let kind = match *request.method() {
+        Method::GET | Method::HEAD => Kind::Read,
+        _ => Kind::Write,
+    };
+    // Only a tenant the gateway vouched for gets its plan's limits (see tenant.rs). Clients without
+    // one share the default plan's limits per address, whatever headers they send.
+    let tenant = request.extensions().get::<Tenant>().map(Tenant::as_str);
+    let key = match (tenant, request.extensions().get::<ClientAddr>()) {
+        (Some(tenant), _) => format!("tenant:{tenant}"),
+        (None, Some(client)) => format!("address:{}", client.ip()),
+        (None, None) => "anonymous".to_string(),
+    };
+    let Some(limits) = limiter.limits_for(&dbpool, tenant).await else {
+        return next.run(request).await;
+    };
+    let decision = limiter.take(key, limits, kind);
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        let mut response = Error::BadRequest(
+            StatusCode::TOO_MANY_REQUESTS,
+            RequestError::new(
+                "rate_limited",
+                i18n::message(
+                    "rate_limited",
+                    &[("seconds", &decision.retry_after.to_string())],
+                ),
+            ),
+        )
+        .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(decision.retry_after));
+        response
+    };
+    set_headers(response.headers_mut(), &decision);
+    response
+}
+
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(RATE_LIMIT_RESET, HeaderValue::from(decision.reset));
+    headers.insert(
+        RATE_LIMIT_BURST_REMAINING,
+        HeaderValue::from(decision.burst_remaining),
+    );
+}
@@ -0,0 +1,149 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::todo::Todo;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{SqliteTypeInfo, SqliteValueRef};
+use sqlx::{query, query_as, Decode, Sqlite, SqliteConnection, SqlitePool, Type};
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+
+// Flags and family emoji are sequences of several code points joined together, so a single emoji
+// can take this many.
+const MAX_EMOJI_CHARS: usize = 16;
+
+// The number of reactions on a todo per emoji, e.g. {"👍": 2, "🎉": 1}. The database keeps it up to
+// date in the todo's reactions column as reactions are added and removed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Reactions(BTreeMap<String, i64>);
+
+impl Reactions {
+    pub fn count(&self, emoji: &str) -> i64 {
+        self.0.get(emoji).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, i64)> {
+        self.0.iter().map(|(emoji, count)| (emoji.as_str(), *count))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Type<Sqlite> for Reactions {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <String as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for Reactions {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let json = <&str as Decode<Sqlite>>::decode(value)?;
+        Ok(Reactions(serde_json::from_str(json)?))
+    }
+}
+
+// The body of POST /v1/todos/:id/reactions. The client crate constructs and serializes one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AddReaction {
+    emoji: String,
+}
+
+impl AddReaction {
+    pub fn new(emoji: impl Into<String>) -> Self {
+        Self {
+            emoji: emoji.into(),
+        }
+    }
+
+    pub fn emoji(&self) -> &str {
+        &self.emoji
+    }
+}
+
+// Returns the emoji as we store it, so the same emoji typed on different platforms is counted
+// together, or a 422 when it isn't one. We don't check against the Unicode emoji list, which
+// changes every year, but words and whitespace are turned away.
+fn normalize(emoji: &str) -> Result<String, Error> {
+    let emoji: String = emoji.trim().nfc().collect();
+    let valid = !emoji.is_empty()
+        && emoji.chars().count() <= MAX_EMOJI_CHARS
+        && emoji
+            .chars()
+            .all(|c| !c.is_ascii_alphanumeric() && !c.is_whitespace() && !c.is_control());
+    if !valid {
+        return Err(Error::BadRequest(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            RequestError::new(
+                "invalid_emoji",
+                i18n::message("invalid_emoji", &[("emoji", &emoji)]),
+            )
+            .with_field("emoji"),
+        ));
+    }
+    Ok(emoji)
+}
+
+// Adds a reaction to a todo and returns the todo with the new counts.
+#[tracing::instrument(name = "reaction.add", skip(dbpool, reaction))]
+pub async fn add(dbpool: SqlitePool, id: i64, reaction: AddReaction) -> Result<Todo, Error> {
+    let emoji = normalize(&reaction.emoji)?;
+    // Reading the todo first turns an unknown ID into a 404 rather than a foreign key error.
+    Todo::read(dbpool.clone(), id).await?;
+    query("insert into todo_reactions (todo_id, emoji) values (?, ?)")
+        .bind(id)
+        .bind(&emoji)
+        .execute(&dbpool)
+        .await?;
+    Todo::read(dbpool, id).await
+}
+
+// Removes one reaction with the emoji from a todo, the oldest first, and returns the todo with the
+// new counts. A todo without that reaction is a 404.
+#[tracing::instrument(name = "reaction.remove", skip(dbpool))]
+pub async fn remove(dbpool: SqlitePool, id: i64, emoji: &str) -> Result<Todo, Error> {
+    let emoji: String = emoji.trim().nfc().collect();
+    let removed: Option<(i64,)> = query_as(
+        "delete from todo_reactions where id = (
+             select id from todo_reactions where todo_id = ? and emoji = ? order by id limit 1
+         ) returning id",
+    )
+    .bind(id)
+    .bind(&emoji)
+    .fetch_optional(&dbpool)
+    .await?;
+    if removed.is_none() {
+        return Err(Error::NotFound);
+    }
+    Todo::read(dbpool, id).await
+}
+
+// Adds `count` reactions with the emoji at once, when todos are restored or imported with their
+// reactions.
+pub(crate) async fn insert(
+    conn: &mut SqliteConnection,
+    todo_id: i64,
+    emoji: &str,
+    count: i64,
+) -> Result<(), sqlx::Error> {
+    if count < 1 {
+        return Ok(());
+    }
+    query(
+        "with recursive n (i) as (select 1 union all select i + 1 from n where i < ?)
+         insert into todo_reactions (todo_id, emoji) select ?, ? from n",
+    )
+    .bind(count)
+    .bind(todo_id)
+    .bind(emoji)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
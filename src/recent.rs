@@ -0,0 +1,72 @@
+use crate::error::Error;
+use crate::params::ListParams;
+use crate::todo::Todo;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+
+// Why a todo shows up in the recent listing: whichever happened last.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum RecentReason {
+    Viewed,
+    Modified,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct RecentTodo {
+    #[sqlx(flatten)]
+    todo: Todo,
+    reason: RecentReason,
+    at: NaiveDateTime,
+}
+
+impl RecentTodo {
+    pub fn todo(&self) -> &Todo {
+        &self.todo
+    }
+
+    pub fn reason(&self) -> RecentReason {
+        self.reason
+    }
+
+    pub fn at(&self) -> NaiveDateTime {
+        self.at
+    }
+
+    // Records that a todo was read. This is best effort: failing to record a view must not fail the
+    // read itself, so errors are only logged.
+    pub async fn record_view(dbpool: SqlitePool, id: i64) {
+        let result = query(
+            "insert into todo_views (todo_id) values (?)
+             on conflict (todo_id) do update set viewed_at = datetime('now')",
+        )
+        .bind(id)
+        .execute(&dbpool)
+        .await;
+        if let Err(err) = result {
+            tracing::warn!(todo_id = id, error = %err, "failed to record todo view");
+        }
+    }
+
+    // The order is what makes this listing useful, so the sort parameter is ignored here. Archived
+    // todos are left out, like in the other lists.
+    pub async fn list(dbpool: SqlitePool, params: ListParams) -> Result<Vec<RecentTodo>, Error> {
+        // Timestamps are stored as text in a sortable format, so max() picks the later of the two.
+        query_as(
+            "select todos.*,
+                    case when v.viewed_at > todos.updated_at then 'viewed' else 'modified' end as reason,
+                    max(coalesce(v.viewed_at, ''), todos.updated_at) as at
+             from todos left join todo_views v on v.todo_id = todos.id
+             where not todos.archived
+             order by at desc, todos.id desc
+             limit ? offset ?",
+        )
+        .bind(params.limit)
+        .bind(params.offset)
+        .fetch_all(&dbpool)
+        .await
+        .map_err(Into::into)
+    }
+}
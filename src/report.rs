@@ -0,0 +1,382 @@
+use crate::error::Error;
+use crate::export::{heading, markdown_item};
+use crate::ids;
+use crate::notify::{Notification, Notifiers};
+use crate::preferences::Preferences;
+use crate::status::TodoStatus;
+use crate::todo::Todo;
+use chrono::{
+    Datelike, Duration as ChronoDuration, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// How late a scheduled report may still be generated, e.g. after the service was down at the
+// scheduled time. Later than that, it's skipped rather than sent out of the blue.
+const CATCH_UP: ChronoDuration = ChronoDuration::hours(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ReportKind {
+    // The open todos, with a section per status.
+    OpenByStatus,
+    // The open todos past their due date, most overdue first.
+    Overdue,
+}
+
+impl ReportKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportKind::OpenByStatus => "open_by_status",
+            ReportKind::Overdue => "overdue",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ReportKind::OpenByStatus => "Open todos by status",
+            ReportKind::Overdue => "Overdue todos",
+        }
+    }
+}
+
+impl FromStr for ReportKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "open_by_status" => Ok(ReportKind::OpenByStatus),
+            "overdue" => Ok(ReportKind::Overdue),
+            _ => Err(format!("`{value}` isn't open_by_status or overdue")),
+        }
+    }
+}
+
+// When a report is generated: every day, or on one day of the week, at a time in the owner's
+// timezone.
+#[derive(Clone, Debug)]
+struct Schedule {
+    kind: ReportKind,
+    weekday: Option<Weekday>,
+    time: NaiveTime,
+}
+
+impl Schedule {
+    // The last time the report was due, in UTC. Local times skipped by a daylight saving change
+    // don't happen, so neither does the report.
+    fn last_due(&self, timezone: Tz, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let local_now = timezone.from_utc_datetime(&now).naive_local();
+        let mut date = local_now.date();
+        if date.and_time(self.time) > local_now {
+            date = date.pred_opt()?;
+        }
+        if let Some(weekday) = self.weekday {
+            while date.weekday() != weekday {
+                date = date.pred_opt()?;
+            }
+        }
+        let due = timezone
+            .from_local_datetime(&date.and_time(self.time))
+            .earliest()?;
+        Some(due.naive_utc())
+    }
+}
+
+// The reports to generate, e.g. "open_by_status@mon 09:00;overdue@daily 08:00". Without any, only
+// reports asked for through the API are generated.
+#[derive(Clone, Debug, Default)]
+pub struct ReportSchedules {
+    schedules: Vec<Schedule>,
+}
+
+impl ReportSchedules {
+    pub fn is_empty(&self) -> bool {
+        self.schedules.is_empty()
+    }
+}
+
+impl FromStr for ReportSchedules {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut schedules = Vec::new();
+        for schedule in value
+            .split(';')
+            .map(str::trim)
+            .filter(|schedule| !schedule.is_empty())
+        {
+            let (kind, when) = schedule
+                .split_once('@')
+                .ok_or_else(|| format!("`{schedule}` should look like `kind@mon 09:00`"))?;
+            let (day, time) = when
+                .trim()
+                .split_once(' ')
+                .ok_or_else(|| format!("`{when}` should look like `mon 09:00` or `daily 09:00`"))?;
+            let weekday = match day.trim() {
+                "daily" => None,
+                day => Some(
+                    day.parse::<Weekday>()
+                        .map_err(|_| format!("`{day}` isn't daily or a day of the week"))?,
+                ),
+            };
+            let time = NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("`{time}` should be a time like 09:00"))?;
+            schedules.push(Schedule {
+                kind: kind.trim().parse()?,
+                weekday,
+                time,
+            });
+        }
+        Ok(Self { schedules })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct Report {
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    kind: ReportKind,
+    // The time the report was scheduled for, or None when it was asked for through the API.
+    scheduled_for: Option<NaiveDateTime>,
+    // The number of todos in the report.
+    todos: i64,
+    created_at: NaiveDateTime,
+    // Where to download the report as Markdown.
+    #[sqlx(skip)]
+    download_path: String,
+}
+
+// The body of POST /v1/reports.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateReport {
+    kind: ReportKind,
+}
+
+impl CreateReport {
+    pub fn new(kind: ReportKind) -> Self {
+        Self { kind }
+    }
+}
+
+const COLUMNS: &str = "id, kind, scheduled_for, todos, created_at";
+
+impl Report {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn kind(&self) -> ReportKind {
+        self.kind
+    }
+
+    pub fn scheduled_for(&self) -> Option<NaiveDateTime> {
+        self.scheduled_for
+    }
+
+    pub fn todos(&self) -> i64 {
+        self.todos
+    }
+
+    pub fn download_path(&self) -> &str {
+        &self.download_path
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("report-{}-{}.md", self.kind.as_str(), ids::encode(self.id))
+    }
+
+    fn with_download_path(mut self) -> Self {
+        self.download_path = format!("/v1/reports/{}/download", ids::encode(self.id));
+        self
+    }
+
+    // Generates a report right away.
+    pub async fn create(dbpool: SqlitePool, request: CreateReport) -> Result<Report, Error> {
+        let (body, todos) = render(&dbpool, request.kind).await?;
+        let report: Report = query_as(&format!(
+            "insert into reports (kind, todos, body) values (?, ?, ?) returning {COLUMNS}"
+        ))
+        .bind(request.kind)
+        .bind(todos)
+        .bind(body)
+        .fetch_one(&dbpool)
+        .await?;
+        Ok(report.with_download_path())
+    }
+
+    pub async fn list(dbpool: SqlitePool) -> Result<Vec<Report>, Error> {
+        let reports: Vec<Report> =
+            query_as(&format!("select {COLUMNS} from reports order by id desc"))
+                .fetch_all(&dbpool)
+                .await?;
+        Ok(reports
+            .into_iter()
+            .map(Report::with_download_path)
+            .collect())
+    }
+
+    pub async fn read(dbpool: SqlitePool, id: i64) -> Result<Report, Error> {
+        let report: Report = query_as(&format!("select {COLUMNS} from reports where id = ?"))
+            .bind(id)
+            .fetch_one(&dbpool)
+            .await?;
+        Ok(report.with_download_path())
+    }
+
+    // The report with its Markdown.
+    pub async fn download(dbpool: SqlitePool, id: i64) -> Result<(Report, String), Error> {
+        let report = Self::read(dbpool.clone(), id).await?;
+        let (body,): (String,) = query_as("select body from reports where id = ?")
+            .bind(id)
+            .fetch_one(&dbpool)
+            .await?;
+        Ok((report, body))
+    }
+
+    // Generates the scheduled report due at `due`, unless it has been already, e.g. by another
+    // instance. Returns the report with its Markdown when this call generated it.
+    async fn generate(
+        dbpool: &SqlitePool,
+        kind: ReportKind,
+        due: NaiveDateTime,
+    ) -> Result<Option<(Report, String)>, Error> {
+        let (exists,): (bool,) =
+            query_as("select exists (select 1 from reports where kind = ? and scheduled_for = ?)")
+                .bind(kind)
+                .bind(due)
+                .fetch_one(dbpool)
+                .await?;
+        if exists {
+            return Ok(None);
+        }
+        let (body, todos) = render(dbpool, kind).await?;
+        let report: Option<Report> = query_as(&format!(
+            "insert into reports (kind, scheduled_for, todos, body) values (?, ?, ?, ?)
+             on conflict (kind, scheduled_for) do nothing returning {COLUMNS}"
+        ))
+        .bind(kind)
+        .bind(due)
+        .bind(todos)
+        .bind(&body)
+        .fetch_optional(dbpool)
+        .await?;
+        Ok(report.map(|report| (report.with_download_path(), body)))
+    }
+
+    // Starts generating the scheduled reports in the background, checking every minute whether one
+    // is due. Each one is sent out through the notification channels routed for report.generated,
+    // and kept for download either way.
+    pub fn spawn_scheduler(
+        dbpool: SqlitePool,
+        schedules: ReportSchedules,
+        notifiers: Arc<Notifiers>,
+        encoding: Arc<ids::IdEncoding>,
+    ) {
+        if schedules.is_empty() {
+            return;
+        }
+        tokio::spawn(ids::scope(encoding, async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                ticker.tick().await;
+                if dbpool.is_closed() {
+                    break;
+                }
+                if let Err(err) = run_due(&dbpool, &schedules, &notifiers).await {
+                    tracing::warn!(error = ?err, "failed to generate scheduled reports");
+                }
+            }
+        }));
+    }
+}
+
+async fn run_due(
+    dbpool: &SqlitePool,
+    schedules: &ReportSchedules,
+    notifiers: &Notifiers,
+) -> Result<(), Error> {
+    let timezone = Preferences::read(dbpool.clone()).await?.timezone();
+    let now = Utc::now().naive_utc();
+    for schedule in &schedules.schedules {
+        let Some(due) = schedule.last_due(timezone, now) else {
+            continue;
+        };
+        if now - due > CATCH_UP {
+            continue;
+        }
+        if let Some((report, body)) = Report::generate(dbpool, schedule.kind, due).await? {
+            tracing::info!(
+                kind = schedule.kind.as_str(),
+                "generated a scheduled report"
+            );
+            notifiers
+                .dispatch(dbpool, Notification::for_report(report, body))
+                .await;
+        }
+    }
+    Ok(())
+}
+
+// Renders a report as Markdown, returning it with the number of todos in it. Times are shown in
+// the owner's timezone.
+async fn render(dbpool: &SqlitePool, kind: ReportKind) -> Result<(String, i64), Error> {
+    let timezone = Preferences::read(dbpool.clone()).await?.timezone();
+    let todos: Vec<Todo> = match kind {
+        ReportKind::OpenByStatus => {
+            query_as("select * from todos where not completed and not archived order by id")
+                .fetch_all(dbpool)
+                .await?
+        }
+        ReportKind::Overdue => {
+            query_as(
+                "select * from todos where not completed and not archived
+                 and due_at < datetime('now') order by due_at, id",
+            )
+            .fetch_all(dbpool)
+            .await?
+        }
+    };
+
+    let mut out = format!("# {}\n\n", kind.title());
+    writeln!(
+        out,
+        "Generated {} ({timezone}). {} todos.",
+        timezone
+            .from_utc_datetime(&Utc::now().naive_utc())
+            .format("%Y-%m-%d %H:%M"),
+        todos.len()
+    )
+    .ok();
+    match kind {
+        ReportKind::OpenByStatus => {
+            for status in TodoStatus::ALL {
+                let section: Vec<&Todo> = todos
+                    .iter()
+                    .filter(|todo| todo.status() == status)
+                    .collect();
+                if section.is_empty() {
+                    continue;
+                }
+                writeln!(out, "\n## {} ({})\n", heading(status), section.len()).ok();
+                for todo in section {
+                    markdown_item(&mut out, todo, timezone);
+                }
+            }
+        }
+        ReportKind::Overdue => {
+            if !todos.is_empty() {
+                out.push('\n');
+            }
+            for todo in &todos {
+                markdown_item(&mut out, todo, timezone);
+            }
+        }
+    }
+    Ok((out, todos.len() as i64))
+}
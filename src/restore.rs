@@ -0,0 +1,237 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::migrations::{self, Phase};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{query_as, SqlitePool};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    // The path of the snapshot on the server, e.g. a copy made with `sqlite3 db.sqlite .backup`.
+    snapshot: PathBuf,
+    // Only check that the snapshot could be restored, without touching the live database.
+    #[serde(default)]
+    verify_only: bool,
+}
+
+impl RestoreRequest {
+    pub fn snapshot(&self) -> &Path {
+        &self.snapshot
+    }
+
+    pub fn verify_only(&self) -> bool {
+        self.verify_only
+    }
+}
+
+// What we found out about a snapshot, and, after a restore, what we did with it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotReport {
+    // The migrations the snapshot was taken with.
+    applied_migrations: Vec<i64>,
+    // Expand migrations the snapshot is missing, which are run after restoring it. Like at startup,
+    // the cleanup ones are left for `migrate --phase=cleanup`, since older instances may still be
+    // using what they drop.
+    pending_migrations: Vec<i64>,
+    restored: bool,
+}
+
+// Checks that a snapshot is a healthy database of this service which we know how to bring up to
+// date: every migration it was taken with must be one of ours, with the same checksum. A snapshot
+// from a newer release can't be restored, because we can't undo its migrations.
+pub async fn verify(snapshot: &Path) -> Result<SnapshotReport, String> {
+    let staged = Staged::copy(snapshot)?;
+    let pool = staged.open().await?;
+    let report = check(&pool).await;
+    pool.close().await;
+    report
+}
+
+// Replaces the contents of the live database with the snapshot. The snapshot is copied to a fresh
+// staging database first, where we check it and run any migrations it's missing, so the snapshot
+// itself is never modified and the live database never sees a half-migrated state.
+//
+// The staged database is then copied into the live one with SQLite's online backup API in a single
+// step, which holds a write lock for the duration. Other requests see either the old or the
+// restored database and simply wait (up to the busy timeout) while the copy is in progress.
+pub async fn restore(dbpool: &SqlitePool, snapshot: &Path) -> Result<SnapshotReport, String> {
+    let staged = Staged::copy(snapshot)?;
+    let pool = staged.open().await?;
+    let mut report = check(&pool).await?;
+    migrations::migrator(Phase::Expand)
+        .run(&pool)
+        .await
+        .map_err(|err| format!("can't migrate the snapshot: {err}"))?;
+
+    let mut source = pool
+        .acquire()
+        .await
+        .map_err(|err| format!("can't open the snapshot: {err}"))?;
+
+    let mut destination = dbpool
+        .acquire()
+        .await
+        .map_err(|err| format!("can't acquire a connection: {err}"))?;
+    {
+        let mut source = source.lock_handle().await.map_err(|err| err.to_string())?;
+        let mut destination = destination
+            .lock_handle()
+            .await
+            .map_err(|err| err.to_string())?;
+        copy_database(
+            source.as_raw_handle().as_ptr(),
+            destination.as_raw_handle().as_ptr(),
+        )?;
+    }
+    drop(source);
+    pool.close().await;
+
+    report.restored = true;
+    Ok(report)
+}
+
+async fn check(pool: &SqlitePool) -> Result<SnapshotReport, String> {
+    let (integrity,): (String,) = query_as("pragma quick_check")
+        .fetch_one(pool)
+        .await
+        .map_err(|err| format!("can't check the snapshot: {err}"))?;
+    if integrity != "ok" {
+        return Err(format!("the snapshot is corrupt: {integrity}"));
+    }
+
+    let applied: Vec<(i64, Vec<u8>, bool)> =
+        query_as("select version, checksum, success from _sqlx_migrations order by version")
+            .fetch_all(pool)
+            .await
+            .map_err(|_| "the snapshot isn't a database of this service".to_string())?;
+
+    // Snapshots taken after a cleanup have cleanup migrations applied, which are ours too.
+    let all = migrations::migrator(Phase::Cleanup);
+    let known: HashMap<i64, &[u8]> = all
+        .iter()
+        .map(|migration| (migration.version, migration.checksum.as_ref()))
+        .collect();
+    for (version, checksum, success) in &applied {
+        match known.get(version) {
+            None => {
+                return Err(format!(
+                    "the snapshot has migration {version}, which this release doesn't know about"
+                ))
+            }
+            Some(_) if !success => {
+                return Err(format!("migration {version} failed in the snapshot"))
+            }
+            Some(known) if *known != checksum.as_slice() => {
+                return Err(format!(
+                    "migration {version} in the snapshot differs from this release's"
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let applied_migrations: Vec<i64> = applied.iter().map(|(version, ..)| *version).collect();
+    let pending_migrations = migrations::migrator(Phase::Expand)
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied_migrations.contains(version))
+        .collect();
+    Ok(SnapshotReport {
+        applied_migrations,
+        pending_migrations,
+        restored: false,
+    })
+}
+
+// Turns a failed verification or restore into a response for the admin API.
+pub fn invalid_snapshot(detail: String) -> Error {
+    Error::BadRequest(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RequestError::new(
+            "invalid_snapshot",
+            i18n::message("invalid_snapshot", &[("detail", &detail)]),
+        )
+        .with_field("snapshot"),
+    )
+}
+
+// A working copy of a snapshot in the temp directory, removed when it's dropped. Checking a
+// database with FTS5 tables needs a writable connection, which we don't want to open on the
+// snapshot itself.
+struct Staged {
+    path: PathBuf,
+}
+
+impl Staged {
+    fn copy(snapshot: &Path) -> Result<Self, String> {
+        if !snapshot.is_file() {
+            return Err(format!("{} doesn't exist", snapshot.display()));
+        }
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let path = std::env::temp_dir().join(format!(
+            "todo-restore-{}-{nanos}.sqlite",
+            std::process::id()
+        ));
+        std::fs::copy(snapshot, &path)
+            .map_err(|err| format!("can't copy {}: {err}", snapshot.display()))?;
+        Ok(Self { path })
+    }
+
+    // A pool with a single connection, because that's what the migrator works with.
+    async fn open(&self) -> Result<SqlitePool, String> {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(SqliteConnectOptions::new().filename(&self.path))
+            .await
+            .map_err(|err| format!("can't open the snapshot: {err}"))
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+fn copy_database(
+    source: *mut libsqlite3_sys::sqlite3,
+    destination: *mut libsqlite3_sys::sqlite3,
+) -> Result<(), String> {
+    use libsqlite3_sys::{
+        sqlite3_backup_finish, sqlite3_backup_init, sqlite3_backup_step, sqlite3_errcode,
+        SQLITE_DONE, SQLITE_OK,
+    };
+
+    // SAFETY: both handles are open connections which we hold locked for the duration of the
+    // call, and the backup object is finished before we return.
+    unsafe {
+        let backup = sqlite3_backup_init(destination, c"main".as_ptr(), source, c"main".as_ptr());
+        if backup.is_null() {
+            return Err(format!(
+                "can't start the restore (SQLite error {})",
+                sqlite3_errcode(destination)
+            ));
+        }
+        // A negative page count copies the whole database in one step.
+        let step = sqlite3_backup_step(backup, -1);
+        let finish = sqlite3_backup_finish(backup);
+        if step != SQLITE_DONE {
+            return Err(format!("the restore failed (SQLite error {step})"));
+        }
+        if finish != SQLITE_OK {
+            return Err(format!("the restore failed (SQLite error {finish})"));
+        }
+    }
+    Ok(())
+}
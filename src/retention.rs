@@ -0,0 +1,239 @@
+use crate::cache::ResponseCache;
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+use std::sync::Arc;
+use std::time::Duration;
+
+// The bounds for each rule. Fewer changes than MIN_CHANGES would send sync clients back to the
+// start every time they've been offline for a bit.
+const MAX_DAYS: i64 = 36500;
+const MIN_CHANGES: i64 = 100;
+const MAX_CHANGES: i64 = 1_000_000_000;
+
+// How long completed and archived todos and the change history are kept. Each rule is off while
+// it's null.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct RetentionPolicy {
+    // Completed todos untouched for this many days are moved into the archive.
+    archive_completed_after_days: Option<i64>,
+    // Archived todos are deleted this many days after they were archived.
+    purge_archived_after_days: Option<i64>,
+    // The most entries to keep in the changes feed. The latest change of every todo is kept
+    // regardless, so clients syncing from the start still get every todo.
+    max_changes: Option<i64>,
+    updated_at: NaiveDateTime,
+}
+
+// The body of PUT /v1/admin/retention, which replaces the whole policy. Leaving a rule out turns it
+// off.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateRetentionPolicy {
+    #[serde(default)]
+    archive_completed_after_days: Option<i64>,
+    #[serde(default)]
+    purge_archived_after_days: Option<i64>,
+    #[serde(default)]
+    max_changes: Option<i64>,
+}
+
+impl UpdateRetentionPolicy {
+    pub fn with_archive_completed_after_days(mut self, days: i64) -> Self {
+        self.archive_completed_after_days = Some(days);
+        self
+    }
+
+    pub fn with_purge_archived_after_days(mut self, days: i64) -> Self {
+        self.purge_archived_after_days = Some(days);
+        self
+    }
+
+    pub fn with_max_changes(mut self, max: i64) -> Self {
+        self.max_changes = Some(max);
+        self
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let rules = [
+            (
+                "archive_completed_after_days",
+                self.archive_completed_after_days,
+                1,
+                MAX_DAYS,
+            ),
+            (
+                "purge_archived_after_days",
+                self.purge_archived_after_days,
+                1,
+                MAX_DAYS,
+            ),
+            ("max_changes", self.max_changes, MIN_CHANGES, MAX_CHANGES),
+        ];
+        for (field, value, min, max) in rules {
+            match value {
+                Some(value) if !(min..=max).contains(&value) => {
+                    return Err(Error::BadRequest(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        RequestError::new(
+                            "invalid_retention",
+                            i18n::message(
+                                "invalid_retention",
+                                &[
+                                    ("field", field),
+                                    ("min", &min.to_string()),
+                                    ("max", &max.to_string()),
+                                ],
+                            ),
+                        )
+                        .with_field(field),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+const COLUMNS: &str =
+    "archive_completed_after_days, purge_archived_after_days, max_changes, updated_at";
+
+impl RetentionPolicy {
+    pub fn archive_completed_after_days(&self) -> Option<i64> {
+        self.archive_completed_after_days
+    }
+
+    pub fn purge_archived_after_days(&self) -> Option<i64> {
+        self.purge_archived_after_days
+    }
+
+    pub fn max_changes(&self) -> Option<i64> {
+        self.max_changes
+    }
+
+    pub async fn read(dbpool: SqlitePool) -> Result<RetentionPolicy, Error> {
+        query_as(&format!(
+            "select {COLUMNS} from retention_policy where id = 1"
+        ))
+        .fetch_one(&dbpool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update(
+        dbpool: SqlitePool,
+        updated: UpdateRetentionPolicy,
+    ) -> Result<RetentionPolicy, Error> {
+        updated.validate()?;
+        query_as(&format!(
+            "update retention_policy set archive_completed_after_days = ?,
+             purge_archived_after_days = ?, max_changes = ?, updated_at = datetime('now')
+             where id = 1 returning {COLUMNS}"
+        ))
+        .bind(updated.archive_completed_after_days)
+        .bind(updated.purge_archived_after_days)
+        .bind(updated.max_changes)
+        .fetch_one(&dbpool)
+        .await
+        .map_err(Into::into)
+    }
+
+    // Applies the policy once, returning the number of todos archived and purged and changes
+    // trimmed.
+    async fn enforce(&self, dbpool: &SqlitePool) -> Result<(u64, u64, u64), sqlx::Error> {
+        let mut archived = 0;
+        if let Some(days) = self.archive_completed_after_days {
+            archived = query(
+                "update todos set archived = true, archived_at = datetime('now'), stale_at = null,
+                 updated_at = datetime('now'), version = version + 1
+                 where completed and not archived and updated_at < datetime('now', '-' || ? || ' days')",
+            )
+            .bind(days)
+            .execute(dbpool)
+            .await?
+            .rows_affected();
+        }
+        let mut purged = 0;
+        if let Some(days) = self.purge_archived_after_days {
+            purged = query(
+                "delete from todos where archived and archived_at < datetime('now', '-' || ? || ' days')",
+            )
+            .bind(days)
+            .execute(dbpool)
+            .await?
+            .rows_affected();
+        }
+        let mut trimmed = 0;
+        if let Some(max) = self.max_changes {
+            let mut tx = dbpool.begin().await?;
+            let (cutoff,): (i64,) = query_as("select coalesce(max(seq), 0) - ? from changes")
+                .bind(max)
+                .fetch_one(&mut *tx)
+                .await?;
+            // The latest change of a todo that still exists is what puts it into a sync from the
+            // start, so those stay however old they are.
+            let deleted: Vec<(i64,)> = query_as(
+                "delete from changes where seq <= ?
+                 and not (todo_id in (select id from todos)
+                 and seq in (select max(seq) from changes group by todo_id))
+                 returning seq",
+            )
+            .bind(cutoff)
+            .fetch_all(&mut *tx)
+            .await?;
+            if let Some(through) = deleted.iter().map(|(seq,)| *seq).max() {
+                query(
+                    "update retention_policy
+                     set changes_trimmed_through = max(changes_trimmed_through, ?) where id = 1",
+                )
+                .bind(through)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            trimmed = deleted.len() as u64;
+        }
+        Ok((archived, purged, trimmed))
+    }
+}
+
+// The highest sequence number trimmed from the changes feed. Clients that synced up to an earlier
+// one may have missed deletes.
+pub async fn changes_trimmed_through(dbpool: &SqlitePool) -> Result<i64, Error> {
+    let (through,): (i64,) =
+        query_as("select changes_trimmed_through from retention_policy where id = 1")
+            .fetch_one(dbpool)
+            .await?;
+    Ok(through)
+}
+
+// Starts applying the retention policy every hour in the background. The policy is read each time,
+// so changes through the admin API apply from the next run.
+pub fn spawn(dbpool: SqlitePool, cache: Arc<ResponseCache>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            if dbpool.is_closed() {
+                break;
+            }
+            let result = match RetentionPolicy::read(dbpool.clone()).await {
+                Ok(policy) => policy.enforce(&dbpool).await.map_err(Error::from),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok((0, 0, 0)) => {}
+                Ok((archived, purged, trimmed)) => {
+                    tracing::info!(archived, purged, trimmed, "applied the retention policy");
+                    if archived + purged > 0 {
+                        cache.invalidate_all();
+                    }
+                }
+                Err(err) => tracing::warn!(error = ?err, "failed to apply the retention policy"),
+            }
+        }
+    });
+}
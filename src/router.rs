@@ -1,36 +1,305 @@
+// Which routes a listener serves. Deployments with a private network can keep the admin API off
+// the public listener altogether, rather than relying on the admin token alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routes {
+    // The API and the admin API.
+    All,
+    // The API without /v1/admin.
+    Public,
+    // Only /v1/admin, plus the health checks so the private listener can be probed too.
+    Admin,
+}
+
 pub async fn create_router(
-    // the database pool is passed into the router, which takes ownership
-    dbpool: sqlx::Pool<sqlx::Sqlite>,
+    // the application state, including the database pool, is passed into the router, which takes ownership
+    state: crate::state::AppState,
 ) -> axum::Router {
-    use crate::api::{ping, todo_create, todo_delete, todo_list, todo_read, todo_update};
-    use axum::{routing::get, Router};
+    create_router_for(state, Routes::All).await
+}
+
+pub async fn create_router_for(state: crate::state::AppState, routes: Routes) -> axum::Router {
+    use crate::admin::require_admin;
+    use crate::allow::{answer_options, method_not_allowed};
+    use crate::api::{
+        action_create_todo, changes_list, database_read, export_job_create, export_job_download,
+        export_job_read, feed_calendar, feed_token_create, feed_token_revoke, feed_token_rotate,
+        feed_tokens_list, flag_delete, flag_update, flags_enabled, flags_list, log_level_read,
+        log_level_update, maintenance_read, maintenance_update, metrics_read, migrations_read,
+        ping, preferences_read, preferences_update, push_key, push_subscription_create,
+        push_subscription_delete, push_subscriptions_list, quicklist_create, quicklist_delete,
+        quicklist_item_create, quicklist_item_delete, quicklist_item_update, quicklist_read,
+        rate_plans_list, reaction_add, reaction_remove, report_create, report_download,
+        report_read, reports_list, restore_snapshot, retention_read, retention_update,
+        runtime_read, shared_todo_read, snapshot_export, snapshot_import, sync, tenant_plan_delete,
+        tenant_plan_update, tenant_plans_list, todo_archive, todo_archive_list, todo_board,
+        todo_create, todo_delete, todo_duplicate, todo_export, todo_export_ndjson, todo_import,
+        todo_list, todo_merge, todo_purge, todo_read, todo_recent, todo_search, todo_share_create,
+        todo_share_revoke, todo_shares_list, todo_suggest, todo_unarchive, todo_update,
+        todo_update_where, todo_upsert, trigger_completed_todo, trigger_new_todo, undo,
+        usage_export,
+    };
+    use crate::cache_control::apply_policy;
+    use crate::deprecation;
+    use crate::envelope;
+    use crate::i18n::negotiate_language;
+    use crate::ids::scope_encoding;
+    use crate::listener::ClientAddr;
+    use crate::maintenance::reject_writes;
+    use crate::metering::meter;
+    use crate::metrics::record;
+    use crate::rate_limit::limit_rate;
+    use crate::single_flight::collapse;
+    use crate::statement_budget;
+    use crate::tenant;
+    use crate::trace_context::{propagate, TraceContext};
+    use axum::{
+        middleware,
+        routing::{delete, get, post, put},
+        Router,
+    };
     use tower_http::cors::{Any, CorsLayer};
     use tower_http::trace::TraceLayer;
 
-    Router::new()
+    // The admin routes are grouped in their own router so the admin check only applies to them.
+    let admin = Router::new()
+        // Maintenance mode puts the API into read-only mode; GET reports the current status.
+        .route(
+            "/maintenance",
+            get(maintenance_read).post(maintenance_update),
+        )
+        // The tracing filter, e.g. to turn on sqlx=debug while diagnosing an incident.
+        .route("/log-level", get(log_level_read).put(log_level_update))
+        // Pool statistics, uptime, and build information for operators.
+        .route("/runtime", get(runtime_read))
+        // The applied and pending migrations, to check what a deploy did to the schema.
+        .route("/migrations", get(migrations_read))
+        // The database size and the rows per table, as of the last collection.
+        .route("/database", get(database_read))
+        // Latency histograms, SLO burn rates, and outbound calls in the Prometheus text format.
+        .route("/metrics", get(metrics_read))
+        // Feature flags, which switch risky features on at runtime, optionally for a percentage of
+        // subjects.
+        .route("/flags", get(flags_list))
+        .route("/flags/:name", put(flag_update).delete(flag_delete))
+        // The rate limit plans, and which tenants are on which plan.
+        .route("/plans", get(rate_plans_list))
+        .route("/tenants", get(tenant_plans_list))
+        .route(
+            "/tenants/:tenant/plan",
+            put(tenant_plan_update).delete(tenant_plan_delete),
+        )
+        // API calls per tenant and the storage used, for invoicing, e.g. ?from=2026-10-01&to=2026-11-01.
+        .route("/usage", get(usage_export))
+        // How long completed and archived todos and the change history are kept.
+        .route("/retention", get(retention_read).put(retention_update))
+        // Replaces the database with a backup snapshot, or just checks that it could.
+        .route("/restore", post(restore_snapshot))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin));
+
+    // The API routes, which are nested under the /v1 path.
+    let api = Router::new()
+        // Here, we permit two methods for the /v1/todos path - either GET or POST
+        // which call the todo_list() and todo_create() handlers, respectively.
+        // We can change the methods together using a handy fluent interface.
+        .route("/todos", get(todo_list).post(todo_create))
+        // Deletes completed todos last updated before a cutoff.
+        .route("/todos/purge", post(todo_purge))
+        // A plain-text snapshot of the todos, e.g. ?format=markdown or ?format=org.
+        .route("/todos/export", get(todo_export))
+        // Every todo as newline-delimited JSON, streamed straight from the database.
+        .route("/todos/export.ndjson", get(todo_export_ndjson))
+        // Imports todos from another service's export, e.g. ?format=todoist&dry_run=true.
+        .route("/todos/import", post(todo_import))
+        // Changes every todo matching a filter at once, e.g. moving all stale todos to blocked.
+        .route("/todos/update-where", post(todo_update_where))
+        // Merges duplicate todos into one.
+        .route("/todos/merge", post(todo_merge))
+        // Full-text search over todo bodies. Static segments take precedence over the :id
+        // parameter below, so this doesn't clash with reading a todo.
+        .route("/todos/search", get(todo_search))
+        // Type-ahead suggestions, prefix-matching the word being typed.
+        .route("/todos/suggest", get(todo_suggest))
+        // Archived todos, which the other lists leave out.
+        .route("/todos/archive", get(todo_archive_list))
+        // The todos grouped by status, for kanban boards.
+        .route("/todos/board", get(todo_board))
+        // Recently viewed and recently modified todos, for picking up where the user left off.
+        .route("/todos/recent", get(todo_recent))
+        // The path parameter :id maps to the todo's ID. GET, PUT, or DELETE methods for /v1/todos/:id
+        // map to todo_read(), todo_update(), and todo_delete, respectively.
+        .route(
+            "/todos/:id",
+            get(todo_read).put(todo_update).delete(todo_delete),
+        )
+        // Moves a completed todo into the archive and back out of it.
+        .route("/todos/:id/archive", post(todo_archive))
+        .route("/todos/:id/unarchive", post(todo_unarchive))
+        // Emoji reactions, for acknowledging a todo without editing it. Removing takes away one
+        // reaction with that emoji.
+        .route("/todos/:id/reactions", post(reaction_add))
+        .route("/todos/:id/reactions/:emoji", delete(reaction_remove))
+        // Read-only links to a todo for people without access to the API, and the shared view
+        // itself, which only takes the link's token.
+        .route("/todos/:id/share", post(todo_share_create))
+        .route("/todos/:id/shares", get(todo_shares_list))
+        .route("/todos/:id/shares/:share_id", delete(todo_share_revoke))
+        .route("/shared/todos/:token", get(shared_todo_read))
+        // Web Push: the key browsers subscribe with, and the browsers subscribed to notifications.
+        .route("/push/key", get(push_key))
+        .route(
+            "/push/subscriptions",
+            get(push_subscriptions_list).post(push_subscription_create),
+        )
+        .route("/push/subscriptions/:id", delete(push_subscription_delete))
+        // Copies a todo, optionally moving the copy's due date.
+        .route("/todos/:id/duplicate", post(todo_duplicate))
+        // Creates or updates the todo with a client-supplied key, for idempotent imports.
+        // The key gets its own path segment, so it can't be mistaken for a todo ID.
+        .route("/todos/external/:external_id", put(todo_upsert))
+        // The changes feed lets offline clients fetch everything that happened after the
+        // last sequence number they've seen.
+        .route("/changes", get(changes_list))
+        // Offline clients push the changes they made locally, which we apply unless they
+        // conflict with newer versions on the server.
+        .route("/sync", post(sync))
+        // Deletes, purges, and updates return a token that undoes them for a short while.
+        .route("/undo", post(undo))
+        // The whole account as one file, for moving it to another instance.
+        .route("/me/snapshot", get(snapshot_export).post(snapshot_import))
+        // Throwaway lists for guests. Creating one returns its capability URL, whose secret token
+        // grants access to that list alone.
+        .route("/quicklists", post(quicklist_create))
+        .route(
+            "/quicklists/:token",
+            get(quicklist_read).delete(quicklist_delete),
+        )
+        .route("/quicklists/:token/items", post(quicklist_item_create))
+        .route(
+            "/quicklists/:token/items/:id",
+            put(quicklist_item_update).delete(quicklist_item_delete),
+        )
+        // The owner's timezone and locale, used when interpreting and rendering dates.
+        .route(
+            "/preferences",
+            get(preferences_read).put(preferences_update),
+        )
+        // Polling triggers and actions for automation platforms like Zapier and IFTTT.
+        .route("/triggers/new-todo", get(trigger_new_todo))
+        .route("/triggers/completed-todo", get(trigger_completed_todo))
+        .route("/actions/create-todo", post(action_create_todo))
+        // Exports too large to build within a request are queued as jobs; clients poll the
+        // job and download the file once it's ready.
+        .route("/exports", post(export_job_create))
+        .route("/exports/:id", get(export_job_read))
+        .route("/exports/:id/download", get(export_job_download))
+        // Reports generated on a schedule or on request, downloadable as Markdown.
+        .route("/reports", get(reports_list).post(report_create))
+        .route("/reports/:id", get(report_read))
+        .route("/reports/:id/download", get(report_download))
+        // The feature flags that are on, e.g. ?subject=<client id> during a gradual rollout.
+        .route("/flags", get(flags_enabled))
+        // Tokens for calendar subscriptions to the todos' due dates.
+        .route("/feeds", get(feed_tokens_list).post(feed_token_create))
+        .route("/feeds/:id", delete(feed_token_revoke))
+        .route("/feeds/:id/rotate", post(feed_token_rotate));
+
+    let v1 = match routes {
+        Routes::All => api.nest("/admin", admin),
+        Routes::Public => api,
+        Routes::Admin => Router::new().nest("/admin", admin),
+    };
+
+    let mut router = Router::new()
         // our liveness health check merely returns a 200 status with the body ok.
         .route("/alive", get(|| async { "ok" }))
-        // Our readiness health check makes a GET request with the ping() handler.
-        .route("/ready", get(ping))
-        // The API routes are nested under the /v1 path.
-        .nest(
-            "/v1",
-            Router::new()
-                // Here, we permit two methods for the /v1/todos path - either GET or POST
-                // which call the todo_list() and todo_create() handlers, respectively.
-                // We can change the methods together using a handy fluent interface.
-                .route("/todos", get(todo_list).post(todo_create))
-                // The path parameter :id maps to the todo's ID. GET, PUT, or DELETE methods for /v1/todos/:id
-                // map to todo_read(), todo_update(), and todo_delete, respectively.
-                .route(
-                    "/todos/:id",
-                    get(todo_read).put(todo_update).delete(todo_delete),
-                ),
-        )
-        // We hand the database connection pool off to the router to be passed into handlers as state
-        .with_state(dbpool)
+        // Our readiness health check makes a GET request with the ping() handler, and reports the
+        // schema version in the X-Schema-Version header.
+        .route("/ready", get(ping));
+    if routes != Routes::Admin {
+        // The calendar feed lives outside /v1, since calendar apps can only send the token in the
+        // path and subscription URLs should survive API versions.
+        router = router.route("/feeds/:token/todos.ics", get(feed_calendar));
+    }
+
+    let app = router
+        .nest("/v1", v1)
+        // Requests with a method the route doesn't have. Added before the layers below, so they
+        // apply to it like to any handler.
+        .method_not_allowed_fallback(method_not_allowed)
+        // Statements are counted for the handler alone; the layers below run the same few for
+        // every request.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            statement_budget::enforce,
+        ))
+        // Concurrent identical reads share one run of the handler, and so one database query.
+        .layer(middleware::from_fn_with_state(state.clone(), collapse))
+        // API calls are metered for billing per client request, not per database query.
+        .layer(middleware::from_fn_with_state(state.clone(), meter))
+        // While maintenance mode is on, mutations are rejected before they reach the handlers.
+        .layer(middleware::from_fn_with_state(state.clone(), reject_writes))
+        // Requests over their plan's rate limit are turned away before they cost anything.
+        .layer(middleware::from_fn_with_state(state.clone(), limit_rate))
+        // The rate limits and metering above go by the tenant this layer identifies, so it wraps
+        // them. A tenant header without a valid signature is turned away here.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            tenant::identify,
+        ))
+        // Cache-Control and ETag headers are set on the way out, after everything else has run.
+        .layer(middleware::from_fn_with_state(state.clone(), apply_policy))
+        // Error messages are localized based on the Accept-Language header, so this layer needs to
+        // wrap everything that can produce an error.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            negotiate_language,
+        ))
+        // IDs are encoded and decoded the configured way anywhere in the request, so this layer
+        // needs to wrap everything that serializes or deserializes them, like the one above.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            scope_encoding,
+        ))
+        // Deprecated routes and parameters are announced whatever the response is.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            deprecation::announce,
+        ))
+        // The envelope goes around every response, including the errors of the layers above.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            envelope::wrap,
+        ))
+        // Latency is measured around everything else, so it's what the client experiences.
+        .layer(middleware::from_fn_with_state(state.clone(), record))
+        // We hand the application state off to the router to be passed into handlers
+        .with_state(state);
+
+    // The layers below wrap the router as a whole rather than each route, since OPTIONS requests
+    // have to reach answer_options before the CORS layer, whichever route they're for.
+    Router::new()
+        .fallback_service(app.clone())
         // A CORS layer is added to demonstrate how to apply CORS headers
         .layer(CorsLayer::new().allow_methods(Any).allow_origin(Any))
-        // We need to add the HTTP tracing layer from tower_http to get request traces.
-        .layer(TraceLayer::new_for_http())
+        // OPTIONS requests are answered from the route table, with the CORS layer's headers.
+        .layer(middleware::from_fn_with_state(app, answer_options))
+        // We need to add the HTTP tracing layer from tower_http to get request traces. The span
+        // carries the client's address, taken from the PROXY protocol header when there is one, and
+        // the request's place in the distributed trace.
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    version = ?request.version(),
+                    client = ?request.extensions().get::<ClientAddr>().map(|client| client.addr()),
+                    trace_id = request.extensions().get::<TraceContext>().map(TraceContext::trace_id),
+                    span_id = request.extensions().get::<TraceContext>().map(TraceContext::span_id),
+                )
+            }),
+        )
+        // The W3C trace context is read before the request span is created, so the span can carry
+        // it.
+        .layer(middleware::from_fn(propagate))
 }
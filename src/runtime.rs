@@ -0,0 +1,74 @@
+use crate::backup::BackupStatus;
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PoolStats {
+    // The number of connections currently open, both idle and in use.
+    size: u32,
+    idle: usize,
+    max_connections: u32,
+}
+
+// The async runtime's view of its workload. A growing global queue means the workers can't keep up.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskStats {
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BuildInfo {
+    version: String,
+    git_sha: String,
+}
+
+// A snapshot of the running service, reported by GET /v1/admin/runtime.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RuntimeInfo {
+    uptime_seconds: u64,
+    pool: PoolStats,
+    tasks: TaskStats,
+    build: BuildInfo,
+    backups: BackupStatus,
+}
+
+impl RuntimeInfo {
+    pub fn uptime_seconds(&self) -> u64 {
+        self.uptime_seconds
+    }
+
+    pub fn version(&self) -> &str {
+        &self.build.version
+    }
+
+    pub fn git_sha(&self) -> &str {
+        &self.build.git_sha
+    }
+
+    pub fn collect(state: &AppState) -> Self {
+        Self {
+            uptime_seconds: state.started_at.elapsed().as_secs(),
+            pool: PoolStats {
+                size: state.dbpool.size(),
+                idle: state.dbpool.num_idle(),
+                max_connections: state.dbpool.options().get_max_connections(),
+            },
+            tasks: {
+                let metrics = tokio::runtime::Handle::current().metrics();
+                TaskStats {
+                    workers: metrics.num_workers(),
+                    alive_tasks: metrics.num_alive_tasks(),
+                    global_queue_depth: metrics.global_queue_depth(),
+                }
+            },
+            build: BuildInfo {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                // Set by build.rs from the git checkout the binary was built from.
+                git_sha: env!("GIT_SHA").to_string(),
+            },
+            backups: state.backups.status(),
+        }
+    }
+}
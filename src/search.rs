@@ -0,0 +1,266 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::i18n;
+use crate::params::{invalid_param, ListParams};
+use crate::todo::Todo;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+
+// SQLite marks the matched terms in snippets with these control characters. They can't appear in
+// the markup we produce, so we can safely escape the body and then turn them into <em> tags.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+// Quotes each of the user's words, so FTS5 operators in the input are searched for literally rather
+// than interpreted. Queries must have at least one word, and no more than the configured maximum.
+fn fts_terms(input: &str, config: &Config) -> Result<Vec<String>, Error> {
+    let terms: Vec<String> = input
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return Err(invalid_param(
+            "invalid_query",
+            "q",
+            i18n::message("invalid_query", &[]),
+        ));
+    }
+    if terms.len() > config.max_search_terms {
+        return Err(invalid_param(
+            "too_many_terms",
+            "q",
+            i18n::message(
+                "too_many_terms",
+                &[("max", &config.max_search_terms.to_string())],
+            ),
+        ));
+    }
+    Ok(terms)
+}
+
+// Estimates how many todos a query has to rank, and rejects it when that's more than the configured
+// maximum. Results are sorted before a page is taken, so every todo containing the rarest of the
+// words is ranked, plus the ones skipped for the offset. With `prefix`, the last word is a prefix,
+// which counts every todo containing a word that starts with it; todos with several such words are
+// counted more than once, which is fine for an estimate.
+async fn check_cost(
+    dbpool: &SqlitePool,
+    input: &str,
+    prefix: bool,
+    offset: i64,
+    config: &Config,
+) -> Result<(), Error> {
+    if config.max_search_cost == 0 {
+        return Ok(());
+    }
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let mut matches: Option<i64> = None;
+    for (index, word) in words.iter().enumerate() {
+        // The index lowercases words and splits them at punctuation, so "Re-plan" is looked up
+        // as "re" and "plan".
+        let tokens: Vec<String> = word
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|token| !token.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        for (position, token) in tokens.iter().enumerate() {
+            let sql = match prefix && index + 1 == words.len() && position + 1 == tokens.len() {
+                true => {
+                    "select coalesce(sum(doc), 0) from todos_fts_vocab
+                     where term >= ?1 and term < ?1 || char(1114111)"
+                }
+                false => "select coalesce(sum(doc), 0) from todos_fts_vocab where term = ?1",
+            };
+            let (todos,): (i64,) = query_as(sql).bind(token).fetch_one(dbpool).await?;
+            matches = Some(matches.map_or(todos, |matches| matches.min(todos)));
+        }
+    }
+    let cost = matches.unwrap_or_default() + offset;
+    if cost > config.max_search_cost {
+        return Err(invalid_param(
+            "search_too_expensive",
+            "q",
+            i18n::message(
+                "search_too_expensive",
+                &[
+                    ("cost", &cost.to_string()),
+                    ("max", &config.max_search_cost.to_string()),
+                ],
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    todo: Todo,
+    score: f64,
+    snippet: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchHit {
+    todo: Todo,
+    // The relevance of the hit; higher is better. Scores are only comparable within one search.
+    score: f64,
+    // An HTML-escaped excerpt of the body around the matches, with the matched terms wrapped in <em>.
+    snippet: String,
+}
+
+impl SearchHit {
+    pub fn todo(&self) -> &Todo {
+        &self.todo
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+
+    pub async fn search(
+        dbpool: SqlitePool,
+        params: ListParams,
+        config: &Config,
+    ) -> Result<Vec<SearchHit>, Error> {
+        // Matches todos containing all of the user's words.
+        let input = params.q.as_deref().unwrap_or_default();
+        let fts_query = fts_terms(input, config)?.join(" ");
+        check_cost(&dbpool, input, false, params.offset, config).await?;
+
+        // Hits are ordered by relevance unless the client asked for a particular order. bm25() ranks
+        // better matches lower, so we negate it to get a score where higher is better.
+        let order_by = match params.sort {
+            Some(sort) => sort.order_by("todos"),
+            None => "order by bm25(todos_fts)".to_string(),
+        };
+        let rows: Vec<SearchRow> = query_as(&format!(
+            "select todos.*, -bm25(todos_fts) as score,
+                    snippet(todos_fts, 0, char(2), char(3), '…', 16) as snippet
+             from todos_fts join todos on todos.id = todos_fts.rowid
+             where todos_fts match ?
+             {order_by}
+             limit ? offset ?"
+        ))
+        .bind(fts_query)
+        .bind(params.limit)
+        .bind(params.offset)
+        .fetch_all(&dbpool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| SearchHit {
+                todo: row.todo,
+                score: row.score,
+                snippet: highlight(&row.snippet),
+            })
+            .collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SuggestQuery {
+    q: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+}
+
+impl SuggestQuery {
+    const MAX_LIMIT: i64 = 20;
+
+    pub fn new(q: impl Into<String>, limit: Option<i64>) -> Self {
+        Self { q: q.into(), limit }
+    }
+
+    // Like a search, except that the last word is treated as a prefix, since the user is most
+    // likely still typing it.
+    fn fts_query(&self, config: &Config) -> Result<String, Error> {
+        let mut terms = fts_terms(&self.q, config)?;
+        if let Some(last) = terms.last_mut() {
+            last.push('*');
+        }
+        Ok(terms.join(" "))
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(10).clamp(1, Self::MAX_LIMIT)
+    }
+}
+
+// What a suggestion refers to, so type-ahead boxes can offer different kinds of results.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    #[default]
+    Todo,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct Suggestion {
+    #[sqlx(skip)]
+    kind: SuggestionKind,
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    text: String,
+}
+
+impl Suggestion {
+    pub fn kind(&self) -> SuggestionKind {
+        self.kind
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub async fn suggest(
+        dbpool: SqlitePool,
+        params: SuggestQuery,
+        config: &Config,
+    ) -> Result<Vec<Suggestion>, Error> {
+        let fts_query = params.fts_query(config)?;
+        check_cost(&dbpool, &params.q, true, 0, config).await?;
+
+        // Suggestions are ranked by recency rather than relevance: with only a word or two typed,
+        // the todos the user worked on lately are the likeliest ones they're looking for.
+        query_as(
+            "select todos.id, todos.body as text
+             from todos_fts join todos on todos.id = todos_fts.rowid
+             where todos_fts match ?
+             order by todos.updated_at desc, todos.id desc
+             limit ?",
+        )
+        .bind(fts_query)
+        .bind(params.limit())
+        .fetch_all(&dbpool)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+// The body is user input, so it's escaped before we add our own markup.
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<em>"),
+            MATCH_END => html.push_str("</em>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
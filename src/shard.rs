@@ -0,0 +1,303 @@
+use crate::error::{Error, RequestError};
+use crate::feed;
+use crate::i18n;
+use crate::migrations::{self, Phase};
+use crate::pool;
+use crate::router::{create_router_for, Routes};
+use crate::state::AppState;
+use crate::tenant::Tenant;
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use moka::sync::Cache;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tower_service::Service;
+
+// Each shard gets a small pool, since an install can have many of them open at once.
+const MAX_SHARD_CONNECTIONS: u32 = 4;
+// Tenant names become file names, so they're kept short and plain.
+const MAX_TENANT_CHARS: usize = 64;
+// Shards without requests for this long are closed, and opened again when the tenant is back.
+const SHARD_IDLE: Duration = Duration::from_secs(15 * 60);
+
+// Database-per-tenant mode. Every tenant gets its own SQLite file in the shard directory, created
+// by the provision command, which is opened and migrated the first time a request for the tenant
+// comes in. The tenant is the one the gateway signed (see tenant.rs), so clients can only reach
+// their own shard, and tenants without a shard don't get one just by asking. API requests without a
+// tenant are turned away; the admin API and the health checks use the main database.
+pub struct Shards {
+    dir: PathBuf,
+    main: AppState,
+    // The shards open right now, at most shard_max_open of them. Each tenant has a cell which its
+    // first request opens the shard in while the tenant's other requests wait for it, so the shard
+    // is only opened once and other tenants aren't held up. A shard evicted from here is closed once
+    // the requests still using it are done.
+    open: Cache<String, Arc<OnceCell<Arc<Shard>>>>,
+}
+
+// An open shard: the router with the state for its database, and the database's pool.
+struct Shard {
+    tenant: String,
+    router: Router,
+    dbpool: SqlitePool,
+}
+
+impl Shard {
+    // Routers are always ready, and never fail. The shard stays open until the response is ready,
+    // even if it's evicted in the meantime.
+    async fn call(&self, request: Request) -> Response {
+        match self.router.clone().call(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        }
+    }
+}
+
+impl Drop for Shard {
+    // Closing the pool also ends the shard's background tasks.
+    fn drop(&mut self) {
+        let dbpool = self.dbpool.clone();
+        tokio::spawn(async move { dbpool.close().await });
+        tracing::info!(tenant = self.tenant.as_str(), "closed a shard");
+    }
+}
+
+// Why a tenant's shard can't be used.
+enum Unavailable {
+    // The tenant wasn't provisioned.
+    Unknown,
+    Failed(String),
+}
+
+impl Shards {
+    pub fn new(dir: PathBuf, main: AppState) -> Self {
+        let open = Cache::builder()
+            .max_capacity(main.config.shard_max_open)
+            .time_to_idle(SHARD_IDLE)
+            .build();
+        Self { dir, main, open }
+    }
+
+    async fn shard(&self, tenant: &str) -> Result<Arc<Shard>, Unavailable> {
+        let cell = self.open.get_with(tenant.to_string(), Arc::default);
+        let opened = cell.get_or_try_init(|| self.open_shard(tenant)).await;
+        if opened.is_err() && !cell.initialized() {
+            // Tenants without a shard don't take up room.
+            self.open.invalidate(tenant);
+        }
+        opened.cloned()
+    }
+
+    async fn open_shard(&self, tenant: &str) -> Result<Arc<Shard>, Unavailable> {
+        let path = path(&self.dir, tenant);
+        match tokio::fs::try_exists(&path).await {
+            Ok(true) => {}
+            Ok(false) => return Err(Unavailable::Unknown),
+            Err(err) => return Err(Unavailable::Failed(err.to_string())),
+        }
+        let dbpool = open(
+            &path,
+            Phase::Expand,
+            self.main.config.statement_cache_capacity,
+        )
+        .await
+        .map_err(Unavailable::Failed)?;
+        let state = self.main.for_shard(dbpool.clone());
+        state.spawn_database_tasks();
+        // The admin API only ever runs against the main database.
+        let router = create_router_for(state, Routes::Public).await;
+        tracing::info!(tenant, "opened a shard");
+        Ok(Arc::new(Shard {
+            tenant: tenant.to_string(),
+            router,
+            dbpool,
+        }))
+    }
+}
+
+fn path(dir: &Path, tenant: &str) -> PathBuf {
+    dir.join(format!("{tenant}.sqlite"))
+}
+
+fn valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= MAX_TENANT_CHARS
+        && !tenant.starts_with('.')
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// Opens an existing shard and applies the migrations of the phase.
+pub async fn open(
+    path: &Path,
+    phase: Phase,
+    statement_cache_capacity: usize,
+) -> Result<SqlitePool, String> {
+    connect(path, phase, statement_cache_capacity, false).await
+}
+
+// Creates the shard for a tenant, or migrates it if it exists already, so requests for the tenant
+// can be served. Returns where the shard is.
+pub async fn provision(
+    dir: &Path,
+    tenant: &str,
+    statement_cache_capacity: usize,
+) -> Result<PathBuf, String> {
+    if !valid_tenant(tenant) {
+        return Err(format!(
+            "`{tenant}` isn't a tenant name; names are up to {MAX_TENANT_CHARS} letters, digits, \
+             dots, dashes, and underscores"
+        ));
+    }
+    let path = path(dir, tenant);
+    let dbpool = connect(&path, Phase::Expand, statement_cache_capacity, true).await?;
+    dbpool.close().await;
+    Ok(path)
+}
+
+async fn connect(
+    path: &Path,
+    phase: Phase,
+    statement_cache_capacity: usize,
+    create: bool,
+) -> Result<SqlitePool, String> {
+    let dbpool = pool::options()
+        .max_connections(MAX_SHARD_CONNECTIONS)
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(create)
+                .statement_cache_capacity(statement_cache_capacity),
+        )
+        .await
+        .map_err(|err| format!("can't open the shard at {}: {err}", path.display()))?;
+    migrations::run(&dbpool, phase)
+        .await
+        .map_err(|err| format!("can't migrate the shard at {}: {err}", path.display()))?;
+    Ok(dbpool)
+}
+
+// The shards in a directory, for migrating all of them.
+pub async fn list(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "sqlite")
+        {
+            shards.push(path);
+        }
+    }
+    shards.sort();
+    Ok(shards)
+}
+
+// A middleware which hands API requests to the router of their tenant's shard, which runs them
+// through the whole stack again with the shard's state. It wraps everything else, so no part of the
+// main router sees them, and the main database is never used for a tenant's todos. CORS preflights
+// carry no tenant, and go to the main router, which answers them without the database. Calendar
+// feeds carry their tenant in the path instead of the header, see dispatch_feed.
+pub async fn dispatch(
+    State(shards): State<Arc<Shards>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if path.starts_with("/feeds/") {
+        return dispatch_feed(&shards, request, next).await;
+    }
+    if !path.starts_with("/v1/")
+        || path.starts_with("/v1/admin")
+        || request.method() == Method::OPTIONS
+    {
+        return next.run(request).await;
+    }
+    let tenant = match Tenant::from_headers(&shards.main.config, request.headers()) {
+        Ok(Some(tenant)) => tenant,
+        Ok(None) => {
+            return Error::BadRequest(
+                StatusCode::UNAUTHORIZED,
+                RequestError::new("tenant_required", i18n::message("tenant_required", &[])),
+            )
+            .into_response()
+        }
+        Err(err) => return err.into_response(),
+    };
+    if !valid_tenant(tenant.as_str()) {
+        return Error::BadRequest(
+            StatusCode::BAD_REQUEST,
+            RequestError::new(
+                "invalid_tenant",
+                i18n::message("invalid_tenant", &[("tenant", tenant.as_str())]),
+            ),
+        )
+        .into_response();
+    }
+    request.extensions_mut().insert(tenant.clone());
+    match shards.shard(tenant.as_str()).await {
+        Ok(shard) => shard.call(request).await,
+        Err(Unavailable::Unknown) => Error::BadRequest(
+            StatusCode::FORBIDDEN,
+            RequestError::new(
+                "unknown_tenant",
+                i18n::message("unknown_tenant", &[("tenant", tenant.as_str())]),
+            ),
+        )
+        .into_response(),
+        Err(Unavailable::Failed(problem)) => {
+            tracing::error!(tenant = tenant.as_str(), %problem, "failed to open a shard");
+            Error::ServiceUnavailable("the tenant's database can't be opened right now".to_string())
+                .into_response()
+        }
+    }
+}
+
+// Hands a calendar feed request to the shard its path names, as /feeds/<tenant>~<token>/todos.ics,
+// with the tenant taken out of the path. Feeds are authenticated by their token alone, so there's
+// no tenant header to go by. Paths that don't name a tenant we have go to the main router, whose
+// database has no feed tokens in this mode, so they get the same 404 as an unknown token and
+// don't reveal which tenants exist.
+async fn dispatch_feed(shards: &Shards, mut request: Request, next: Next) -> Response {
+    let Some((tenant, uri)) = feed_uri(request.uri()) else {
+        return next.run(request).await;
+    };
+    if !valid_tenant(&tenant) {
+        return next.run(request).await;
+    }
+    match shards.shard(&tenant).await {
+        Ok(shard) => {
+            // The path doesn't make the request the tenant's the way a signed header does, so the
+            // request doesn't get a Tenant; the token in it is checked by the shard.
+            *request.uri_mut() = uri;
+            shard.call(request).await
+        }
+        Err(Unavailable::Unknown) => next.run(request).await,
+        Err(Unavailable::Failed(problem)) => {
+            tracing::error!(tenant, %problem, "failed to open a shard");
+            Error::ServiceUnavailable("the tenant's database can't be opened right now".to_string())
+                .into_response()
+        }
+    }
+}
+
+// The tenant a feed's URI names, and the URI without it.
+fn feed_uri(uri: &Uri) -> Option<(String, Uri)> {
+    let rest = uri.path().strip_prefix("/feeds/")?;
+    let (token, rest) = rest.split_once('/')?;
+    let (tenant, token) = token.rsplit_once(feed::TENANT_SEPARATOR)?;
+    let path = match uri.query() {
+        Some(query) => format!("/feeds/{token}/{rest}?{query}"),
+        None => format!("/feeds/{token}/{rest}"),
+    };
+    Some((tenant.to_string(), path.parse().ok()?))
+}
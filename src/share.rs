@@ -0,0 +1,120 @@
+use crate::error::Error;
+use crate::reactions::Reactions;
+use crate::status::TodoStatus;
+use crate::todo::Todo;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+
+// A capability link to one todo: whoever has the token can read the todo, and nothing else, until
+// the link is revoked.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct TodoShare {
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    #[serde(with = "crate::ids::id")]
+    todo_id: i64,
+    token: String,
+    created_at: NaiveDateTime,
+    // The path of the shared view, for handing out.
+    #[sqlx(skip)]
+    path: String,
+}
+
+// See feed.rs for why SQLite generates the tokens.
+const NEW_TOKEN: &str = "lower(hex(randomblob(20)))";
+
+impl TodoShare {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn with_path(mut self) -> Self {
+        self.path = format!("/v1/shared/todos/{}", self.token);
+        self
+    }
+
+    pub async fn create(dbpool: SqlitePool, todo_id: i64) -> Result<TodoShare, Error> {
+        // Reading the todo first turns an unknown ID into a 404 rather than a foreign key error.
+        Todo::read(dbpool.clone(), todo_id).await?;
+        let share: TodoShare = query_as(&format!(
+            "insert into todo_shares (todo_id, token) values (?, {NEW_TOKEN}) returning *"
+        ))
+        .bind(todo_id)
+        .fetch_one(&dbpool)
+        .await?;
+        Ok(share.with_path())
+    }
+
+    pub async fn list(dbpool: SqlitePool, todo_id: i64) -> Result<Vec<TodoShare>, Error> {
+        let shares: Vec<TodoShare> =
+            query_as("select * from todo_shares where todo_id = ? order by id")
+                .bind(todo_id)
+                .fetch_all(&dbpool)
+                .await?;
+        Ok(shares.into_iter().map(TodoShare::with_path).collect())
+    }
+
+    pub async fn revoke(dbpool: SqlitePool, todo_id: i64, id: i64) -> Result<(), Error> {
+        let result = query("delete from todo_shares where id = ? and todo_id = ?")
+            .bind(id)
+            .bind(todo_id)
+            .execute(&dbpool)
+            .await?;
+        match result.rows_affected() {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+// What a shared link shows of a todo. The ID, the external ID, and the exact coordinates stay
+// private; the place name is enough to know where.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct SharedTodo {
+    body: String,
+    completed: bool,
+    status: TodoStatus,
+    created_at: NaiveDateTime,
+    due_at: Option<NaiveDateTime>,
+    place: Option<String>,
+    #[serde(default)]
+    reactions: Reactions,
+}
+
+impl SharedTodo {
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn completed(&self) -> bool {
+        self.completed
+    }
+
+    pub fn status(&self) -> TodoStatus {
+        self.status
+    }
+
+    pub fn due_at(&self) -> Option<NaiveDateTime> {
+        self.due_at
+    }
+
+    // Reads the todo of a link. Unknown and revoked tokens get a 404, like a wrong path.
+    pub async fn read(dbpool: SqlitePool, token: &str) -> Result<SharedTodo, Error> {
+        Ok(query_as(
+            "select todos.* from todos join todo_shares on todo_shares.todo_id = todos.id
+             where todo_shares.token = ?",
+        )
+        .bind(token)
+        .fetch_one(&dbpool)
+        .await?)
+    }
+}
@@ -0,0 +1,162 @@
+use crate::cache_control::Streamed;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::header::{ACCEPT, ACCEPT_LANGUAGE, AUTHORIZATION};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// The request headers that can change what a read returns, so requests only share a response when
+// these match too. Authorization keeps one caller from being handed a response meant for another.
+const VARYING_HEADERS: [HeaderName; 4] = [
+    ACCEPT,
+    ACCEPT_LANGUAGE,
+    AUTHORIZATION,
+    HeaderName::from_static("prefer"),
+];
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    uri: String,
+    headers: Vec<Option<HeaderValue>>,
+}
+
+// A buffered response that can be handed to every request waiting on it.
+#[derive(Clone)]
+struct Shared {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Shared {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        *response.version_mut() = self.version;
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+// The reads in flight, so concurrent identical reads can wait for the first one to finish and share
+// its response instead of all querying the database. This protects SQLite when a popular view
+// refreshes for many clients at once. Unlike the response cache, nothing is kept once the read is
+// done, so a read never returns data older than the moment it arrived.
+//
+// The waiters get None when the first read couldn't share its response, e.g. because it was
+// streamed or the client went away before it finished, and then run their own.
+pub struct SingleFlight {
+    enabled: bool,
+    inflight: Mutex<HashMap<Key, broadcast::Sender<Option<Shared>>>>,
+}
+
+impl SingleFlight {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            inflight: Mutex::default(),
+        }
+    }
+}
+
+// The first read's entry, which is removed when the read is done, however it ends, so later reads
+// start afresh. Dropping the sender with it tells the waiters of a read that was cancelled.
+struct Leader<'a> {
+    flights: &'a SingleFlight,
+    key: Option<Key>,
+}
+
+impl Leader<'_> {
+    fn finish(&mut self) -> Option<broadcast::Sender<Option<Shared>>> {
+        let key = self.key.take()?;
+        self.flights
+            .inflight
+            .lock()
+            .expect("single-flight lock poisoned")
+            .remove(&key)
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// A middleware collapsing concurrent identical API reads into one. It sits inside the caching
+// policy, so every request still gets its own ETag check and Cache-Control header.
+pub async fn collapse(
+    State(flights): State<Arc<SingleFlight>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if !flights.enabled
+        || request.method() != Method::GET
+        || !path.starts_with("/v1/")
+        || path.starts_with("/v1/admin")
+    {
+        return next.run(request).await;
+    }
+    let key = Key {
+        uri: request.uri().to_string(),
+        headers: VARYING_HEADERS
+            .iter()
+            .map(|name| request.headers().get(name).cloned())
+            .collect(),
+    };
+
+    let waiting = {
+        let mut inflight = flights
+            .inflight
+            .lock()
+            .expect("single-flight lock poisoned");
+        match inflight.get(&key) {
+            Some(sender) => Some(sender.subscribe()),
+            None => {
+                inflight.insert(key.clone(), broadcast::channel(1).0);
+                None
+            }
+        }
+    };
+    if let Some(mut receiver) = waiting {
+        return match receiver.recv().await {
+            Ok(Some(shared)) => shared.into_response(),
+            _ => next.run(request).await,
+        };
+    }
+
+    let mut leader = Leader {
+        flights: &flights,
+        key: Some(key),
+    };
+    let response = next.run(request).await;
+    let Some(sender) = leader.finish() else {
+        return response;
+    };
+    // There's no need to buffer the response when nobody is waiting, and streamed responses are
+    // left alone.
+    if sender.receiver_count() == 0 || response.extensions().get::<Streamed>().is_some() {
+        sender.send(None).ok();
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(body) = to_bytes(body, usize::MAX).await else {
+        sender.send(None).ok();
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let shared = Shared {
+        status: parts.status,
+        version: parts.version,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+    };
+    sender.send(Some(shared)).ok();
+    Response::from_parts(parts, Body::from(body))
+}
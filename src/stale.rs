@@ -0,0 +1,70 @@
+use crate::cache::ResponseCache;
+use crate::ids::{self, IdEncoding};
+use crate::notify::{Notification, NotificationEvent, Notifiers};
+use crate::todo::Todo;
+use sqlx::{query_as, SqlitePool};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Flags the open todos nobody has touched for `after_days` days as stale, returning them. Todos
+// flagged before aren't returned again until a change has cleared their flag. Flagging leaves the
+// version and updated_at alone: nobody changed the todo, so a client syncing against the version it
+// has mustn't get a conflict for it. The todo as we serve it does change, so the cached copy goes
+// and its ETag changes.
+pub async fn flag(
+    dbpool: &SqlitePool,
+    cache: &ResponseCache,
+    after_days: u64,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    let todos: Vec<Todo> = query_as(
+        "update todos set stale_at = datetime('now')
+         where stale_at is null and not completed and not archived
+         and updated_at < datetime('now', '-' || ? || ' days') returning *",
+    )
+    .bind(after_days as i64)
+    .fetch_all(dbpool)
+    .await?;
+    for todo in &todos {
+        cache.write_through(todo);
+    }
+    Ok(todos)
+}
+
+// Starts looking for stale todos every hour in the background. Each newly stale todo is sent to
+// the notification channels routed for todo.stale, so the owner can finish, reschedule, or delete
+// it.
+pub fn spawn(
+    dbpool: SqlitePool,
+    cache: Arc<ResponseCache>,
+    after_days: u64,
+    notifiers: Arc<Notifiers>,
+    encoding: Arc<IdEncoding>,
+) {
+    if after_days == 0 {
+        return;
+    }
+    tokio::spawn(ids::scope(encoding, async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            if dbpool.is_closed() {
+                break;
+            }
+            let todos = match flag(&dbpool, &cache, after_days).await {
+                Ok(todos) => todos,
+                Err(err) => {
+                    tracing::warn!(error = ?err, "failed to flag stale todos");
+                    continue;
+                }
+            };
+            if !todos.is_empty() {
+                tracing::info!(flagged = todos.len(), "flagged stale todos");
+            }
+            for todo in todos {
+                let notification =
+                    Notification::new(NotificationEvent::TodoStale, todo.id(), Some(todo));
+                notifiers.dispatch(&dbpool, notification).await;
+            }
+        }
+    }));
+}
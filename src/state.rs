@@ -0,0 +1,410 @@
+use crate::backup::{BackupSink, Backups, DirectorySink};
+use crate::cache::ResponseCache;
+use crate::config::Config;
+use crate::db_stats::DbStatsMonitor;
+use crate::deprecation::{Deprecation, DeprecationUsage};
+use crate::export_job::ExportJob;
+use crate::flags::FeatureFlags;
+use crate::heartbeat;
+use crate::hooks::{Hooks, TodoHook};
+use crate::i18n::Catalogs;
+use crate::ids::IdEncoding;
+use crate::links::LinkPreviews;
+use crate::log_level::LogLevel;
+use crate::maintenance::Maintenance;
+use crate::metering::Metering;
+use crate::metrics::Metrics;
+use crate::notify::{
+    EmailNotifier, LogNotifier, Notifier, Notifiers, NotifyHook, SlackNotifier, WebhookNotifier,
+};
+use crate::outbound::Outbound;
+use crate::pool;
+use crate::quicklist::Quicklist;
+use crate::rate_limit::RateLimiter;
+use crate::report::Report;
+use crate::retention;
+use crate::single_flight::SingleFlight;
+use crate::stale;
+use crate::undo::UndoLog;
+use crate::web_push::WebPush;
+use axum::extract::FromRef;
+use chrono::{SubsecRound, Utc};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// The state shared by all handlers. It's cheap to clone because everything is either a pool
+// handle or behind an Arc.
+#[derive(Clone)]
+pub struct AppState {
+    pub dbpool: SqlitePool,
+    // The database for what's shared by all tenants, like rate limit plans and feature flags. It's
+    // dbpool itself unless this is the state of a tenant's shard.
+    pub main_dbpool: SqlitePool,
+    pub config: Arc<Config>,
+    pub maintenance: Arc<Maintenance>,
+    pub catalogs: Arc<Catalogs>,
+    pub cache: Arc<ResponseCache>,
+    pub hooks: Arc<Hooks>,
+    pub backups: Arc<Backups>,
+    pub db_stats: Arc<DbStatsMonitor>,
+    pub metrics: Arc<Metrics>,
+    pub deprecation_usage: Arc<DeprecationUsage>,
+    pub flags: Arc<FeatureFlags>,
+    // The HTTP client for integrations calling other services; hooks can hold on to a clone.
+    pub outbound: Arc<Outbound>,
+    pub log_level: Arc<LogLevel>,
+    pub single_flight: Arc<SingleFlight>,
+    pub ids: Arc<IdEncoding>,
+    pub undo: Arc<UndoLog>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub metering: Arc<Metering>,
+    pub notifiers: Arc<Notifiers>,
+    pub web_push: Arc<WebPush>,
+    // When the service started, for reporting uptime.
+    pub started_at: Instant,
+}
+
+impl AppState {
+    pub fn new(dbpool: SqlitePool, config: Config, catalogs: Catalogs) -> Self {
+        let maintenance = Arc::new(Maintenance::new(config.read_only));
+        let metrics = Arc::new(Metrics::from(&config));
+        let outbound = Arc::new(Outbound::from(&config));
+        let ids = Arc::new(match &config.hashids_salt {
+            Some(salt) => IdEncoding::hashids(salt, config.hashids_min_length),
+            None => IdEncoding::Plain,
+        });
+        let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit_plans.clone()));
+        let metering = Arc::new(Metering::new(config.metering_interval > 0));
+        let database = Database::new(&config, &dbpool, &outbound);
+        let mut backups = Backups::default();
+        if let Some(dir) = &config.backup_dir {
+            backups.register(DirectorySink::new(dir.clone(), config.backup_keep));
+        }
+        Self {
+            main_dbpool: dbpool.clone(),
+            dbpool,
+            config: Arc::new(config),
+            maintenance,
+            catalogs: Arc::new(catalogs),
+            cache: database.cache,
+            hooks: Arc::new(database.hooks),
+            backups: Arc::new(backups),
+            db_stats: Arc::default(),
+            metrics,
+            deprecation_usage: Arc::default(),
+            flags: Arc::default(),
+            outbound,
+            log_level: Arc::default(),
+            single_flight: database.single_flight,
+            ids,
+            undo: database.undo,
+            rate_limiter,
+            metering,
+            notifiers: database.notifiers,
+            web_push: database.web_push,
+            started_at: Instant::now(),
+        }
+    }
+
+    // The state for a tenant's shard in database-per-tenant mode. Everything holding on to todos
+    // or the database, like the response cache and the hooks, is built anew for the shard, while
+    // the rest, like the metrics and the rate limiter, is shared with the main state. Hooks,
+    // notifiers, and backup sinks registered on the main state don't apply to shards.
+    pub fn for_shard(&self, dbpool: SqlitePool) -> Self {
+        let database = Database::new(&self.config, &dbpool, &self.outbound);
+        Self {
+            dbpool,
+            cache: database.cache,
+            hooks: Arc::new(database.hooks),
+            // Snapshots are only taken of the main database.
+            backups: Arc::default(),
+            db_stats: Arc::default(),
+            single_flight: database.single_flight,
+            undo: database.undo,
+            notifiers: database.notifiers,
+            web_push: database.web_push,
+            ..self.clone()
+        }
+    }
+
+    // Registers a hook into the todo mutation lifecycle. Hooks have to be registered before the
+    // state is handed to the router.
+    pub fn with_hook(mut self, hook: impl TodoHook + 'static) -> Self {
+        Arc::make_mut(&mut self.hooks).register(hook);
+        self
+    }
+
+    // Adds a notification channel, or replaces the built-in one with the same name. Routing rules
+    // send events to it by its channel name.
+    pub fn with_notifier(self, notifier: impl Notifier + 'static) -> Self {
+        self.notifiers.register(Arc::new(notifier));
+        self
+    }
+
+    // Registers a sink for database snapshots, e.g. an object storage bucket. Like hooks, sinks have
+    // to be registered before the state is handed to the router.
+    pub fn with_backup_sink(mut self, sink: impl BackupSink + 'static) -> Self {
+        Arc::get_mut(&mut self.backups)
+            .expect("backup sinks must be registered before the state is shared")
+            .register(sink);
+        self
+    }
+
+    // Deprecates a route or parameter on top of the configured ones, e.g. by an embedder replacing
+    // it. Like hooks, deprecations have to be added before the state is handed to the router.
+    pub fn with_deprecation(mut self, deprecation: Deprecation) -> Self {
+        let config = Arc::make_mut(&mut self.config);
+        config.deprecations = std::mem::take(&mut config.deprecations).with(deprecation);
+        self
+    }
+
+    // Lets the admin API change the log filter at runtime. Without it, the log-level routes are
+    // disabled.
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = Arc::new(log_level);
+        self
+    }
+
+    // Starts the background tasks, such as shipping backups.
+    pub fn spawn_tasks(&self) {
+        self.backups.clone().spawn(
+            self.dbpool.clone(),
+            Duration::from_secs(self.config.backup_interval.max(1)),
+        );
+        self.metering.clone().spawn(
+            self.dbpool.clone(),
+            Duration::from_secs(self.config.metering_interval),
+        );
+        if let Some(url) = &self.config.heartbeat_url {
+            heartbeat::spawn(
+                self.dbpool.clone(),
+                self.outbound.clone(),
+                url.clone(),
+                Duration::from_secs(self.config.heartbeat_interval.max(1)),
+            );
+        }
+        self.spawn_database_tasks();
+    }
+
+    // Starts the background tasks working on the state's database, which shards run too. The tasks
+    // end once the pool is closed, which is how a shard closed for being idle stops them.
+    pub fn spawn_database_tasks(&self) {
+        // Timestamps are stored with second precision, so we compare whole seconds.
+        tokio::spawn(ExportJob::fail_interrupted(
+            self.dbpool.clone(),
+            Utc::now().naive_utc().trunc_subsecs(0),
+        ));
+        self.db_stats.clone().spawn(
+            self.dbpool.clone(),
+            Duration::from_secs(self.config.db_stats_interval.max(1)),
+        );
+        pool::spawn_keeper(
+            self.dbpool.clone(),
+            self.config.pool_min_idle,
+            Duration::from_secs(self.config.pool_ping_interval.max(1)),
+        );
+        Quicklist::spawn_cleanup(self.dbpool.clone(), Duration::from_secs(3600));
+        Report::spawn_scheduler(
+            self.dbpool.clone(),
+            self.config.report_schedule.clone(),
+            self.notifiers.clone(),
+            self.ids.clone(),
+        );
+        retention::spawn(self.dbpool.clone(), self.cache.clone());
+        stale::spawn(
+            self.dbpool.clone(),
+            self.cache.clone(),
+            self.config.stale_after_days,
+            self.notifiers.clone(),
+            self.ids.clone(),
+        );
+    }
+}
+
+// The parts of the state tied to one database.
+struct Database {
+    cache: Arc<ResponseCache>,
+    single_flight: Arc<SingleFlight>,
+    undo: Arc<UndoLog>,
+    notifiers: Arc<Notifiers>,
+    web_push: Arc<WebPush>,
+    hooks: Hooks,
+}
+
+impl Database {
+    fn new(config: &Config, dbpool: &SqlitePool, outbound: &Arc<Outbound>) -> Self {
+        let cache = Arc::new(if config.response_cache {
+            ResponseCache::new(
+                config.response_cache_capacity,
+                Duration::from_secs(config.response_cache_ttl),
+            )
+        } else {
+            ResponseCache::disabled()
+        });
+        let notifiers = Arc::new(Notifiers::new(config.notify_routes.clone()));
+        notifiers.register(Arc::new(LogNotifier));
+        if let Some(url) = &config.notify_webhook_url {
+            notifiers.register(Arc::new(WebhookNotifier::new(outbound.clone(), url)));
+        }
+        if let Some(url) = &config.notify_slack_webhook_url {
+            notifiers.register(Arc::new(SlackNotifier::new(outbound.clone(), url)));
+        }
+        if let (Some(from), Some(to)) = (&config.notify_email_from, &config.notify_email_to) {
+            notifiers.register(Arc::new(EmailNotifier::new(
+                config.notify_sendmail.clone(),
+                from,
+                to,
+            )));
+        }
+        let web_push = Arc::new(WebPush::new(
+            config.vapid_private_key.clone(),
+            &config.vapid_subject,
+            dbpool.clone(),
+            outbound.clone(),
+        ));
+        if web_push.enabled() {
+            notifiers.register(web_push.clone());
+        }
+        let mut hooks = Hooks::default();
+        if !notifiers.routes().is_empty() {
+            hooks.register(NotifyHook::new(notifiers.clone(), dbpool.clone()));
+        }
+        if config.link_previews {
+            hooks.register(LinkPreviews::new(
+                dbpool.clone(),
+                cache.clone(),
+                outbound.clone(),
+                Duration::from_secs(config.link_preview_ttl),
+            ));
+        }
+        Self {
+            cache,
+            single_flight: Arc::new(SingleFlight::new(config.single_flight)),
+            undo: Arc::new(UndoLog::new(Duration::from_secs(config.undo_window))),
+            notifiers,
+            web_push,
+            hooks,
+        }
+    }
+}
+
+// The main database, for extracting it with State<MainDb> where the state's own database may be
+// a tenant's shard.
+#[derive(Clone)]
+pub struct MainDb(pub SqlitePool);
+
+impl FromRef<AppState> for MainDb {
+    fn from_ref(state: &AppState) -> Self {
+        MainDb(state.main_dbpool.clone())
+    }
+}
+
+// FromRef lets handlers keep extracting just the piece of state they need, e.g. State<SqlitePool>.
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(state: &AppState) -> Self {
+        state.dbpool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<UndoLog> {
+    fn from_ref(state: &AppState) -> Self {
+        state.undo.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RateLimiter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.rate_limiter.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Metering> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metering.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<WebPush> {
+    fn from_ref(state: &AppState) -> Self {
+        state.web_push.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Maintenance> {
+    fn from_ref(state: &AppState) -> Self {
+        state.maintenance.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Catalogs> {
+    fn from_ref(state: &AppState) -> Self {
+        state.catalogs.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<ResponseCache> {
+    fn from_ref(state: &AppState) -> Self {
+        state.cache.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Hooks> {
+    fn from_ref(state: &AppState) -> Self {
+        state.hooks.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<DbStatsMonitor> {
+    fn from_ref(state: &AppState) -> Self {
+        state.db_stats.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Metrics> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<DeprecationUsage> {
+    fn from_ref(state: &AppState) -> Self {
+        state.deprecation_usage.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<FeatureFlags> {
+    fn from_ref(state: &AppState) -> Self {
+        state.flags.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Outbound> {
+    fn from_ref(state: &AppState) -> Self {
+        state.outbound.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<LogLevel> {
+    fn from_ref(state: &AppState) -> Self {
+        state.log_level.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<SingleFlight> {
+    fn from_ref(state: &AppState) -> Self {
+        state.single_flight.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<IdEncoding> {
+    fn from_ref(state: &AppState) -> Self {
+        state.ids.clone()
+    }
+}
@@ -0,0 +1,63 @@
+use crate::config::Config;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::cell::Cell;
+use std::sync::Arc;
+
+tokio::task_local! {
+    // The statements run for the request being handled on this task.
+    static STATEMENTS: Cell<u32>;
+}
+
+// Counts a statement against the budget of the current request, if there is one. The pool calls it
+// whenever a connection is taken from it, which happens for every statement run against the pool,
+// and once for a transaction with all of its statements. That's the pattern an N+1 leaves behind:
+// one more trip to the pool per item.
+pub(crate) fn count() {
+    let _ = STATEMENTS.try_with(|statements| statements.set(statements.get() + 1));
+}
+
+// A middleware which counts the statements each request runs, and warns about requests running more
+// than statement_budget of them, since that's usually a query in a loop that should have been one
+// query. In strict mode, meant for development and tests, such requests fail with a 500 instead,
+// though whatever they wrote has been written. A budget of 0 turns the counting off.
+pub async fn enforce(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    if config.statement_budget == 0 {
+        return next.run(request).await;
+    }
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let (statements, response) = STATEMENTS
+        .scope(Cell::new(0), async move {
+            let response = next.run(request).await;
+            (STATEMENTS.with(Cell::get), response)
+        })
+        .await;
+    if statements <= config.statement_budget {
+        return response;
+    }
+    tracing::warn!(
+        %method,
+        route,
+        statements,
+        budget = config.statement_budget,
+        "a request ran more statements than its budget"
+    );
+    match config.statement_budget_strict {
+        true => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "{method} {route} ran {statements} statements, more than the budget of {}",
+                config.statement_budget
+            ),
+        )
+            .into_response(),
+        false => response,
+    }
+}
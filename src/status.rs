@@ -0,0 +1,215 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::params::ListParams;
+use crate::todo::{Todo, TodoFilter, NEAR, STALE};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+// Where a todo is in its workflow. `completed` is kept in step with it: a todo is completed exactly
+// when it's done, so clients that only know about `completed` keep working.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum TodoStatus {
+    #[default]
+    Backlog,
+    InProgress,
+    Blocked,
+    Done,
+}
+
+impl TodoStatus {
+    pub const ALL: [TodoStatus; 4] = [
+        TodoStatus::Backlog,
+        TodoStatus::InProgress,
+        TodoStatus::Blocked,
+        TodoStatus::Done,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TodoStatus::Backlog => "backlog",
+            TodoStatus::InProgress => "in_progress",
+            TodoStatus::Blocked => "blocked",
+            TodoStatus::Done => "done",
+        }
+    }
+
+    // The status implied by a `completed` flag, for clients that don't send a status. Completing a
+    // todo makes it done, and reopening a done todo puts it back into the backlog; otherwise the
+    // status stays what it was.
+    pub fn from_completed(completed: bool, current: TodoStatus) -> TodoStatus {
+        match (completed, current) {
+            (true, _) => TodoStatus::Done,
+            (false, TodoStatus::Done) => TodoStatus::Backlog,
+            (false, current) => current,
+        }
+    }
+}
+
+impl fmt::Display for TodoStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TodoStatus {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        TodoStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == name)
+            .ok_or_else(|| format!("unknown status `{name}`"))
+    }
+}
+
+// The status changes allowed on update. Without a configured map, any change is allowed. Staying in
+// the same status is always allowed.
+#[derive(Clone, Debug, Default)]
+pub struct StatusTransitions {
+    allowed: Option<HashMap<TodoStatus, HashSet<TodoStatus>>>,
+}
+
+impl StatusTransitions {
+    pub fn allows(&self, from: TodoStatus, to: TodoStatus) -> bool {
+        from == to
+            || self.allowed.as_ref().is_none_or(|allowed| {
+                allowed
+                    .get(&from)
+                    .is_some_and(|targets| targets.contains(&to))
+            })
+    }
+
+    // Rejects a status change the map doesn't allow with a 422.
+    pub fn check(&self, from: TodoStatus, to: TodoStatus) -> Result<(), Error> {
+        if self.allows(from, to) {
+            return Ok(());
+        }
+        Err(Error::BadRequest(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            RequestError::new(
+                "invalid_transition",
+                i18n::message(
+                    "invalid_transition",
+                    &[("from", from.as_str()), ("to", to.as_str())],
+                ),
+            )
+            .with_field("status"),
+        ))
+    }
+}
+
+// Parses a map like "backlog:in_progress|done;in_progress:blocked|done;blocked:in_progress", listing
+// the statuses each status may move to. Statuses that aren't listed can't be left.
+impl FromStr for StatusTransitions {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut allowed: HashMap<TodoStatus, HashSet<TodoStatus>> = HashMap::new();
+        for rule in value
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            let (from, targets) = rule
+                .split_once(':')
+                .ok_or_else(|| format!("`{rule}` should look like `from:to|to`"))?;
+            let targets = targets
+                .split('|')
+                .map(|target| target.trim().parse::<TodoStatus>())
+                .collect::<Result<HashSet<_>, _>>()?;
+            allowed
+                .entry(from.trim().parse()?)
+                .or_default()
+                .extend(targets);
+        }
+        Ok(Self {
+            allowed: Some(allowed),
+        })
+    }
+}
+
+// One column of the board: a page of the todos in a status, and how many there are in total.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BoardColumn {
+    status: TodoStatus,
+    total: i64,
+    todos: Vec<Todo>,
+}
+
+impl BoardColumn {
+    pub fn status(&self) -> TodoStatus {
+        self.status
+    }
+
+    pub fn total(&self) -> i64 {
+        self.total
+    }
+
+    pub fn todos(&self) -> &[Todo] {
+        &self.todos
+    }
+}
+
+// The todos grouped by status, in workflow order, for rendering a kanban board in one request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Board {
+    columns: Vec<BoardColumn>,
+}
+
+impl Board {
+    // Each column gets its own page of todos, so the list parameters apply per column. With a
+    // status filter, the board has just that column.
+    pub async fn load(
+        dbpool: SqlitePool,
+        params: &ListParams,
+        filter: &TodoFilter,
+    ) -> Result<Board, Error> {
+        let bounds = filter.bounds()?;
+        let totals: Vec<(TodoStatus, i64)> = query_as(&format!(
+            "select status, count(*) from todos where not archived and (?1 is null or updated_at > ?1)
+             and {NEAR} and {STALE} group by status"
+        ))
+        .bind(filter.modified_since())
+        // The ?near= box is bound to ?5 through ?8, like in Todo::list.
+        .bind(None::<i64>)
+        .bind(None::<i64>)
+        .bind(None::<i64>)
+        .bind(bounds.map(|b| b.min_latitude))
+        .bind(bounds.map(|b| b.max_latitude))
+        .bind(bounds.map(|b| b.min_longitude))
+        .bind(bounds.map(|b| b.max_longitude))
+        .bind(filter.stale())
+        .fetch_all(&dbpool)
+        .await?;
+
+        let statuses = match filter.status() {
+            Some(status) => vec![status],
+            None => TodoStatus::ALL.to_vec(),
+        };
+        let mut columns = Vec::with_capacity(statuses.len());
+        for status in statuses {
+            let todos =
+                Todo::list(dbpool.clone(), params, &filter.clone().with_status(status)).await?;
+            let total = totals
+                .iter()
+                .find(|(total_status, _)| *total_status == status)
+                .map_or(0, |(_, total)| *total);
+            columns.push(BoardColumn {
+                status,
+                total,
+                todos,
+            });
+        }
+        Ok(Board { columns })
+    }
+
+    pub fn columns(&self) -> &[BoardColumn] {
+        &self.columns
+    }
+}
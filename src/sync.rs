@@ -0,0 +1,189 @@
+use crate::batch::{BatchItem, BatchReport};
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::ids;
+use crate::status::TodoStatus;
+use crate::todo::Todo;
+use crate::validation::BodyPolicy;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+
+// A change made by a client while it was offline. Updates and deletes carry the version of the
+// todo the client based its edit on, which we compare against the server's copy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SyncChange {
+    Create {
+        // An opaque reference the client uses for its local copy, echoed back so it can map the
+        // todo to the ID we assign.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        client_ref: Option<String>,
+        body: String,
+        #[serde(default)]
+        completed: bool,
+    },
+    Update {
+        #[serde(with = "crate::ids::id")]
+        id: i64,
+        base_version: i64,
+        body: String,
+        completed: bool,
+    },
+    Delete {
+        #[serde(with = "crate::ids::id")]
+        id: i64,
+        base_version: i64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncRequest {
+    changes: Vec<SyncChange>,
+}
+
+// The outcome of each change, in request order. Applied changes carry the todo as stored on the
+// server, except for deletes, and conflicts carry the server's current copy.
+pub type SyncResponse = BatchReport<Todo>;
+
+impl SyncRequest {
+    pub fn new(changes: Vec<SyncChange>) -> Self {
+        Self { changes }
+    }
+
+    pub async fn apply(
+        self,
+        dbpool: SqlitePool,
+        policy: &BodyPolicy,
+    ) -> Result<SyncResponse, Error> {
+        let mut report = SyncResponse::default();
+
+        // The whole batch runs in one transaction, so a database error halfway through doesn't
+        // leave the client guessing which of its changes made it. Invalid changes and conflicts
+        // don't abort the batch; they're reported as failed items for the client to deal with.
+        let mut tx = dbpool.begin().await?;
+
+        for (index, change) in self.changes.into_iter().enumerate() {
+            match change {
+                SyncChange::Create {
+                    client_ref,
+                    body,
+                    completed,
+                } => {
+                    let body = match policy.apply(&body, &format!("changes[{index}].body")) {
+                        Ok(body) => body,
+                        Err(err) => {
+                            report
+                                .push(BatchItem::rejected(index, err)?.with_reference(client_ref));
+                            continue;
+                        }
+                    };
+                    let todo: Todo = query_as(
+                        "insert into todos (body, completed, status) values (?, ?, ?) returning *",
+                    )
+                    .bind(body)
+                    .bind(completed)
+                    .bind(TodoStatus::from_completed(completed, TodoStatus::Backlog))
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    report.push(
+                        BatchItem::succeeded(index, StatusCode::CREATED, Some(todo))
+                            .with_reference(client_ref),
+                    );
+                }
+                SyncChange::Update {
+                    id,
+                    base_version,
+                    body,
+                    completed,
+                } => {
+                    let body = match policy.apply(&body, &format!("changes[{index}].body")) {
+                        Ok(body) => body,
+                        Err(err) => {
+                            report.push(BatchItem::rejected(index, err)?);
+                            continue;
+                        }
+                    };
+                    // The version check is part of the where clause, so a stale edit simply
+                    // doesn't match any row. Offline clients only know about completed, so the
+                    // status follows it the same way TodoStatus::from_completed does.
+                    let updated: Option<Todo> = query_as(
+                        "update todos set body = ?1, completed = ?2, stale_at = null, updated_at = datetime('now'), version = version + 1,
+                         status = case when ?2 then 'done' when status = 'done' then 'backlog' else status end
+                         where id = ?3 and version = ?4 returning *",
+                    )
+                    .bind(body)
+                    .bind(completed)
+                    .bind(id)
+                    .bind(base_version)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+
+                    let item = match updated {
+                        Some(todo) => BatchItem::succeeded(index, StatusCode::OK, Some(todo)),
+                        None => {
+                            let server: Option<Todo> = query_as("select * from todos where id = ?")
+                                .bind(id)
+                                .fetch_optional(&mut *tx)
+                                .await?;
+                            conflict(index, id, base_version, server)
+                        }
+                    };
+                    report.push(item);
+                }
+                SyncChange::Delete { id, base_version } => {
+                    let deleted = query("delete from todos where id = ? and version = ?")
+                        .bind(id)
+                        .bind(base_version)
+                        .execute(&mut *tx)
+                        .await?
+                        .rows_affected();
+
+                    let server: Option<Todo> = if deleted == 0 {
+                        query_as("select * from todos where id = ?")
+                            .bind(id)
+                            .fetch_optional(&mut *tx)
+                            .await?
+                    } else {
+                        None
+                    };
+
+                    let item = match server {
+                        // The todo was modified after the client's base version, so deleting it
+                        // would throw away somebody else's edit.
+                        Some(server) => conflict(index, id, base_version, Some(server)),
+                        // Deleting a todo that's already gone is what the client wanted anyway.
+                        None => BatchItem::succeeded(index, StatusCode::NO_CONTENT, None),
+                    };
+                    report.push(item);
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(report)
+    }
+}
+
+// A change that lost against the server's copy: either somebody else updated the todo since the
+// client's base version, or it has been deleted.
+fn conflict(index: usize, id: i64, base_version: i64, server: Option<Todo>) -> BatchItem<Todo> {
+    let error = match server {
+        Some(_) => RequestError::new(
+            "version_mismatch",
+            i18n::message(
+                "version_mismatch",
+                &[
+                    ("id", &ids::encode(id)),
+                    ("version", &base_version.to_string()),
+                ],
+            ),
+        ),
+        None => RequestError::new(
+            "deleted",
+            i18n::message("deleted", &[("id", &ids::encode(id))]),
+        ),
+    };
+    BatchItem::failed(index, StatusCode::CONFLICT, error).with_entity(server)
+}
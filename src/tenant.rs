@@ -0,0 +1,98 @@
+use crate::config::Config;
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+// The header carrying the signature of the tenant header's value.
+pub const TENANT_SIGNATURE: HeaderName = HeaderName::from_static("tenant-signature");
+
+// The tenant a request is made for, once we know it's really theirs. We don't authenticate clients
+// ourselves: the API gateway in front of us does, and names the client's tenant in the tenant
+// header (RATE_LIMIT_TENANT_HEADER) along with a Tenant-Signature header holding the hex
+// HMAC-SHA256 of the name under TENANT_SECRET, which only the gateway and we know. Clients can send
+// the tenant header themselves, but without the secret they can't sign it, so they can't pass as
+// another tenant. Rate limits, metering, and database-per-tenant mode all go by this tenant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tenant(String);
+
+impl Tenant {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // The signature a gateway sends along with the tenant's name.
+    pub fn sign(secret: &str, tenant: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(tenant.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    // The tenant named in the request's headers, if there is one. A tenant header without a valid
+    // signature is an error rather than being ignored, so a misconfigured gateway is noticed instead
+    // of its clients quietly sharing the anonymous limits. Without a secret, which the service won't
+    // start without when the header is configured, tenants aren't in use.
+    pub fn from_headers(config: &Config, headers: &HeaderMap) -> Result<Option<Self>, Error> {
+        let (Some(header), Some(secret)) =
+            (&config.rate_limit_tenant_header, &config.tenant_secret)
+        else {
+            return Ok(None);
+        };
+        let Some(tenant) = headers
+            .get(header)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|tenant| !tenant.is_empty())
+        else {
+            return Ok(None);
+        };
+        let signature = headers
+            .get(TENANT_SIGNATURE)
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+        let expected = Self::sign(secret, tenant);
+        // The signatures are compared in constant time, so the time a guess takes doesn't reveal
+        // how much of it was right.
+        if !bool::from(expected.as_bytes().ct_eq(signature)) {
+            return Err(Error::BadRequest(
+                StatusCode::UNAUTHORIZED,
+                RequestError::new(
+                    "invalid_tenant_signature",
+                    i18n::message("invalid_tenant_signature", &[]),
+                ),
+            ));
+        }
+        Ok(Some(Self(tenant.to_string())))
+    }
+}
+
+// A middleware which puts the request's tenant in its extensions, for the layers and handlers
+// below, and turns away requests with a tenant header that isn't signed. Requests dispatched to a
+// shard already have theirs.
+pub async fn identify(
+    State(config): State<Arc<Config>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<Tenant>().is_none() {
+        match Tenant::from_headers(&config, request.headers()) {
+            Ok(Some(tenant)) => {
+                request.extensions_mut().insert(tenant);
+            }
+            Ok(None) => {}
+            Err(err) => return err.into_response(),
+        }
+    }
+    next.run(request).await
+}
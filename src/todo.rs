@@ -1,108 +1,920 @@
-use crate::error::Error;
-use chrono::NaiveDateTime;
+use crate::due;
+use crate::error::{Error, RequestError};
+use crate::geo::{self, BoundingBox};
+use crate::i18n;
+use crate::ids;
+use crate::links::Links;
+use crate::params::{invalid_param, ListParams};
+use crate::pipeline::Pipeline;
+use crate::preferences::Preferences;
+use crate::reactions::{self, Reactions};
+use crate::status::{StatusTransitions, TodoStatus};
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, SqlitePool};
 
-#[derive(Deserialize)]
+// The server deserializes a CreateTodo when it receives one in an API call; the client crate
+// constructs and serializes one to send it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CreateTodo {
     body: String,
+    // A due date in natural language ("tomorrow 5pm") or as an RFC 3339 timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    // New todos start in the backlog unless the client puts them somewhere else.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<TodoStatus>,
+    // Where the todo is to be done, in degrees. Latitude and longitude come together.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longitude: Option<f64>,
+    // A free-text place name, with or without coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    place: Option<String>,
 }
 
-// We don't need to construct a CreateTodo; we just need to deserialize it when we receive one in an API call.
 impl CreateTodo {
+    pub fn new(body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            due: None,
+            status: None,
+            latitude: None,
+            longitude: None,
+            place: None,
+        }
+    }
+
+    pub fn with_due(mut self, due: impl Into<String>) -> Self {
+        self.due = Some(due.into());
+        self
+    }
+
+    pub fn with_status(mut self, status: TodoStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_location(mut self, latitude: f64, longitude: f64) -> Self {
+        self.latitude = Some(latitude);
+        self.longitude = Some(longitude);
+        self
+    }
+
+    pub fn with_place(mut self, place: impl Into<String>) -> Self {
+        self.place = Some(place.into());
+        self
+    }
+
     pub fn body(&self) -> &str {
         self.body.as_ref()
     }
+
+    pub fn set_body(&mut self, body: impl Into<String>) {
+        self.body = body.into();
+    }
+
+    // Runs the body and due date through the text-processing pipeline.
+    pub fn process(&mut self, pipeline: &Pipeline) {
+        let draft = pipeline.run(&self.body, self.due.as_deref());
+        self.body = draft.body;
+        self.due = draft.due;
+    }
+
+    pub fn due(&self) -> Option<&str> {
+        self.due.as_deref()
+    }
+
+    pub fn status(&self) -> TodoStatus {
+        self.status.unwrap_or_default()
+    }
+
+    pub fn latitude(&self) -> Option<f64> {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> Option<f64> {
+        self.longitude
+    }
+
+    pub fn place(&self) -> Option<&str> {
+        self.place.as_deref()
+    }
 }
 
-// We don't need to construct a UpdateTodo; we just need to deserialize it when we receive one in an API call.
-#[derive(Deserialize)]
+// Like CreateTodo, the server deserializes an UpdateTodo and the client crate serializes one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UpdateTodo {
     body: String,
-    completed: bool,
+    // Clients that send a status can leave this out, since the status decides it. Leaving out both
+    // keeps the todo's status, rather than reopening a done todo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed: Option<bool>,
+    // Like the other fields, an omitted due date clears the todo's due date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    // Unlike the other fields, an omitted status is derived from completed, so clients that don't
+    // know about statuses keep working, and kept when that's omitted too.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<TodoStatus>,
+    // Like the due date, an omitted location clears the todo's location.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longitude: Option<f64>,
+    // A free-text place name, with or without coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    place: Option<String>,
 }
 
 impl UpdateTodo {
+    pub fn new(body: impl Into<String>, completed: bool) -> Self {
+        Self {
+            body: body.into(),
+            completed: Some(completed),
+            due: None,
+            status: None,
+            latitude: None,
+            longitude: None,
+            place: None,
+        }
+    }
+
+    pub fn with_due(mut self, due: impl Into<String>) -> Self {
+        self.due = Some(due.into());
+        self
+    }
+
+    pub fn with_status(mut self, status: TodoStatus) -> Self {
+        self.completed = Some(status == TodoStatus::Done);
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_location(mut self, latitude: f64, longitude: f64) -> Self {
+        self.latitude = Some(latitude);
+        self.longitude = Some(longitude);
+        self
+    }
+
+    pub fn with_place(mut self, place: impl Into<String>) -> Self {
+        self.place = Some(place.into());
+        self
+    }
+
     pub fn body(&self) -> &str {
         self.body.as_ref()
     }
 
-    pub fn completed(&self) -> bool {
-        self.completed
+    pub fn set_body(&mut self, body: impl Into<String>) {
+        self.body = body.into();
+    }
+
+    // Runs the body and due date through the text-processing pipeline.
+    pub fn process(&mut self, pipeline: &Pipeline) {
+        let draft = pipeline.run(&self.body, self.due.as_deref());
+        self.body = draft.body;
+        self.due = draft.due;
+    }
+
+    // Whether the update completes the todo, or None when it leaves that as it is. A status, when
+    // there is one, wins over the completed flag.
+    pub fn completed(&self) -> Option<bool> {
+        self.status
+            .map(|status| status == TodoStatus::Done)
+            .or(self.completed)
+    }
+
+    pub fn due(&self) -> Option<&str> {
+        self.due.as_deref()
+    }
+
+    pub fn status(&self) -> Option<TodoStatus> {
+        self.status
+    }
+
+    pub fn latitude(&self) -> Option<f64> {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> Option<f64> {
+        self.longitude
+    }
+
+    pub fn place(&self) -> Option<&str> {
+        self.place.as_deref()
+    }
+
+    // The status a todo currently in `current` ends up in after this update.
+    pub fn status_from(&self, current: TodoStatus) -> TodoStatus {
+        match (self.status, self.completed) {
+            (Some(status), _) => status,
+            (None, Some(completed)) => TodoStatus::from_completed(completed, current),
+            (None, None) => current,
+        }
+    }
+}
+
+// An upsert creating a todo runs the before_create hooks, which take a CreateTodo, and hands what
+// they made of it back as an UpdateTodo. A completed todo without a status is a done one.
+impl From<UpdateTodo> for CreateTodo {
+    fn from(todo: UpdateTodo) -> Self {
+        Self {
+            status: todo
+                .status
+                .or_else(|| (todo.completed == Some(true)).then_some(TodoStatus::Done)),
+            body: todo.body,
+            due: todo.due,
+            latitude: todo.latitude,
+            longitude: todo.longitude,
+            place: todo.place,
+        }
+    }
+}
+
+impl From<CreateTodo> for UpdateTodo {
+    fn from(todo: CreateTodo) -> Self {
+        Self {
+            completed: todo.status.map(|status| status == TodoStatus::Done),
+            body: todo.body,
+            due: todo.due,
+            status: todo.status,
+            latitude: todo.latitude,
+            longitude: todo.longitude,
+            place: todo.place,
+        }
+    }
+}
+
+// A due date as the RFC 3339 timestamp resolve_due reads back unchanged. Due dates are stored in UTC.
+pub(crate) fn format_due(due_at: NaiveDateTime) -> String {
+    format!("{}Z", due_at.format("%Y-%m-%dT%H:%M:%S"))
+}
+
+// Filters specific to the todo list, on top of the shared ListParams.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TodoFilter {
+    // Only todos created or updated after this RFC 3339 timestamp, so polling clients can fetch
+    // just what changed since their last poll.
+    modified_since: Option<DateTime<FixedOffset>>,
+    // Only todos in this status, e.g. ?status=in_progress.
+    status: Option<TodoStatus>,
+    // Only todos within radius_km of a point, e.g. ?near=52.52,13.405&radius_km=2.
+    near: Option<String>,
+    radius_km: Option<f64>,
+    // Only todos that are, or with false aren't, stale: ?stale=true.
+    stale: Option<bool>,
+}
+
+impl TodoFilter {
+    pub fn with_status(mut self, status: TodoStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_stale(mut self, stale: bool) -> Self {
+        self.stale = Some(stale);
+        self
+    }
+
+    pub fn status(&self) -> Option<TodoStatus> {
+        self.status
+    }
+
+    pub fn modified_since(&self) -> Option<NaiveDateTime> {
+        self.modified_since.map(|since| since.naive_utc())
+    }
+
+    pub fn stale(&self) -> Option<bool> {
+        self.stale
+    }
+
+    // The box todos have to be in, or None without ?near=.
+    pub fn bounds(&self) -> Result<Option<BoundingBox>, Error> {
+        self.near
+            .as_deref()
+            .map(|near| BoundingBox::from_params(near, self.radius_km))
+            .transpose()
+    }
+
+    // Distinguishes filtered lists in the response cache.
+    pub fn fingerprint(&self) -> String {
+        let mut fingerprint = self
+            .modified_since()
+            .map(|since| format!("modified_since={since}"))
+            .unwrap_or_default();
+        if let Some(status) = self.status {
+            fingerprint.push_str(&format!("&status={status}"));
+        }
+        if let Some(near) = &self.near {
+            fingerprint.push_str(&format!("&near={near}&radius_km={:?}", self.radius_km));
+        }
+        if let Some(stale) = self.stale {
+            fingerprint.push_str(&format!("&stale={stale}"));
+        }
+        fingerprint
+    }
+}
+
+// Options for duplicating a todo, passed in the query string.
+#[derive(Deserialize)]
+pub struct DuplicateOptions {
+    // Moves the copy's due date by this many days, e.g. 7 to repeat a weekly task.
+    #[serde(default)]
+    due_offset_days: i64,
+}
+
+// The cutoff for purging completed todos, as an RFC 3339 timestamp or a date. A date means the
+// start of that day in the owner's timezone.
+#[derive(Deserialize)]
+pub struct PurgeQuery {
+    completed_before: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PurgeResponse {
+    // The number of todos deleted.
+    purged: usize,
+    // Brings the purged todos back with POST /v1/undo for a short while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    undo_token: Option<String>,
+}
+
+impl PurgeResponse {
+    pub fn new(purged: usize) -> Self {
+        Self {
+            purged,
+            undo_token: None,
+        }
+    }
+
+    pub fn with_undo_token(mut self, undo_token: Option<String>) -> Self {
+        self.undo_token = undo_token;
+        self
+    }
+
+    pub fn purged(&self) -> usize {
+        self.purged
+    }
+
+    pub fn undo_token(&self) -> Option<&str> {
+        self.undo_token.as_deref()
     }
 }
 
 // We're deriving the Serialize trait from the serde crate and sqlx::FromRow,
-// which allows us to get a `Todo` from a SQLx query.
-#[derive(Serialize, Clone, sqlx::FromRow)]
+// which allows us to get a `Todo` from a SQLx query. Deserialize is for the client crate.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
 pub struct Todo {
+    #[serde(with = "crate::ids::id")]
     id: i64,
     body: String,
     completed: bool,
+    // Where the todo is in its workflow; a todo is completed exactly when it's done.
+    status: TodoStatus,
     // We use the chrono::NaiveDateTime type to map SQL timestamp into Rust objects.
     created_at: NaiveDateTime,
+    // Bumped on every update, so sync clients can detect concurrent edits.
+    version: i64,
+    // The due date in UTC. Clients can check it to confirm how a natural language due date was understood.
+    due_at: Option<NaiveDateTime>,
+    // The client-supplied key of todos written with PUT /v1/todos/external/:external_id.
+    external_id: Option<String>,
+    // Archived todos only show up in the archive listing, not in the default lists.
+    archived: bool,
+    archived_at: Option<NaiveDateTime>,
+    // When the open todo was found untouched for STALE_AFTER_DAYS days. Any change clears it.
+    #[serde(default)]
+    stale_at: Option<NaiveDateTime>,
+    // Where the todo is to be done: coordinates in degrees and a place name.
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+    #[serde(default)]
+    place: Option<String>,
+    // The number of emoji reactions per emoji, e.g. {"👍": 2}.
+    #[serde(default)]
+    reactions: Reactions,
+    // The links in the body, with the title and description of each page once we've fetched them.
+    #[serde(default)]
+    links: Links,
 }
 
 impl Todo {
-    pub async fn list(dbpool: SqlitePool) -> Result<Vec<Todo>, Error> {
-        // Selects all todos from the todos table
-        query_as("select * from todos")
-            .fetch_all(&dbpool)
-            .await
-            .map_err(Into::into)
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn body(&self) -> &str {
+        self.body.as_ref()
+    }
+
+    pub fn completed(&self) -> bool {
+        self.completed
+    }
+
+    pub fn status(&self) -> TodoStatus {
+        self.status
+    }
+
+    pub fn created_at(&self) -> NaiveDateTime {
+        self.created_at
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn due_at(&self) -> Option<NaiveDateTime> {
+        self.due_at
+    }
+
+    pub fn external_id(&self) -> Option<&str> {
+        self.external_id.as_deref()
+    }
+
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn archived_at(&self) -> Option<NaiveDateTime> {
+        self.archived_at
+    }
+
+    pub fn stale_at(&self) -> Option<NaiveDateTime> {
+        self.stale_at
+    }
+
+    pub fn latitude(&self) -> Option<f64> {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> Option<f64> {
+        self.longitude
+    }
+
+    pub fn place(&self) -> Option<&str> {
+        self.place.as_deref()
+    }
+
+    pub fn reactions(&self) -> &Reactions {
+        &self.reactions
+    }
+
+    pub fn links(&self) -> &Links {
+        &self.links
+    }
+
+    // An update that leaves the todo as it is, for writes that aren't made through an UpdateTodo
+    // to hand to the before_update hooks.
+    pub fn to_update(&self) -> UpdateTodo {
+        let mut update =
+            UpdateTodo::new(self.body.clone(), self.completed).with_status(self.status);
+        update.due = self.due_at.map(format_due);
+        update.latitude = self.latitude;
+        update.longitude = self.longitude;
+        update.place = self.place.clone();
+        update
+    }
+
+    // A copy of the todo to create. The copy starts out not completed in the backlog, and doesn't
+    // take over the external ID, which has to stay unique.
+    pub fn duplicate(&self, options: &DuplicateOptions) -> CreateTodo {
+        CreateTodo {
+            body: self.body.clone(),
+            // Like SQLite's datetime(), an offset that leaves the calendar drops the due date.
+            due: self
+                .due_at
+                .and_then(|due_at| {
+                    due_at.checked_add_signed(Duration::try_days(options.due_offset_days)?)
+                })
+                .map(format_due),
+            status: None,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            place: self.place.clone(),
+        }
+    }
+
+    // Each statement gets its own span with a stable name, so traces show which query inside a
+    // request was slow. The rows field is filled in once we know how many rows the query touched.
+    #[tracing::instrument(name = "todo.list", skip_all, fields(limit = params.limit, offset = params.offset, rows))]
+    pub async fn list(
+        dbpool: SqlitePool,
+        params: &ListParams,
+        filter: &TodoFilter,
+    ) -> Result<Vec<Todo>, Error> {
+        // Selects a page of todos from the todos table, in ID order unless the client asked for
+        // another one. The order has to be stable for paging to work.
+        let order_by = match params.sort {
+            Some(sort) => sort.order_by("todos"),
+            None => "order by todos.id".to_string(),
+        };
+        // Timestamps are stored in UTC, so we compare against the UTC equivalent of the client's
+        // timestamp. Without a filter, the null matches every todo.
+        let bounds = filter.bounds()?;
+        query_as(&format!(
+            "select * from todos where not archived and (?1 is null or updated_at > ?1)
+             and (?2 is null or status = ?2) and {NEAR} and {STALE} {order_by} limit ?3 offset ?4"
+        ))
+        .bind(filter.modified_since())
+        .bind(filter.status)
+        .bind(params.limit)
+        .bind(params.offset)
+        .bind(bounds.map(|b| b.min_latitude))
+        .bind(bounds.map(|b| b.max_latitude))
+        .bind(bounds.map(|b| b.min_longitude))
+        .bind(bounds.map(|b| b.max_longitude))
+        .bind(filter.stale)
+        .fetch_all(&dbpool)
+        .await
+        .inspect(|todos: &Vec<Todo>| record_rows(todos.len() as u64))
+        .map_err(Into::into)
     }
 
+    // Lists archived todos, most recently archived first unless the client asked for another order.
+    #[tracing::instrument(name = "todo.list_archived", skip_all, fields(limit = params.limit, offset = params.offset, rows))]
+    pub async fn list_archived(
+        dbpool: SqlitePool,
+        params: &ListParams,
+    ) -> Result<Vec<Todo>, Error> {
+        let order_by = match params.sort {
+            Some(sort) => sort.order_by("todos"),
+            None => "order by todos.archived_at desc, todos.id desc".to_string(),
+        };
+        query_as(&format!(
+            "select * from todos where archived {order_by} limit ? offset ?"
+        ))
+        .bind(params.limit)
+        .bind(params.offset)
+        .fetch_all(&dbpool)
+        .await
+        .inspect(|todos: &Vec<Todo>| record_rows(todos.len() as u64))
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(name = "todo.read", skip(dbpool), fields(rows))]
     pub async fn read(dbpool: SqlitePool, id: i64) -> Result<Todo, Error> {
         // Selects one todo from the todos table with a matching id field
         query_as("select * from todos where id = ?")
             .bind(id)
             .fetch_one(&dbpool)
             .await
+            .inspect(|_: &Todo| record_rows(1))
             .map_err(Into::into)
     }
 
     // We've added a new type here, CreateTodo, which we haven't defined yet.
     // It contains the todo body, which we need to create a todo.
+    #[tracing::instrument(name = "todo.create", skip_all, fields(rows))]
     pub async fn create(dbpool: SqlitePool, new_todo: CreateTodo) -> Result<Todo, Error> {
+        let due_at = resolve_due(&dbpool, new_todo.due()).await?;
+        geo::check_location(new_todo.latitude, new_todo.longitude, new_todo.place())?;
+
         // We use the returning * SQL cause to retrieve the record immediately after it's inserted.
-        query_as("insert into todos (body) values (?) returning *")
-            .bind(new_todo.body())
-            // We execute the query with fetch_one() because we expect this to return one row.
-            .fetch_one(&dbpool)
-            .await
-            .map_err(Into::into)
+        query_as(
+            "insert into todos (body, due_at, status, completed, latitude, longitude, place)
+             values (?, ?, ?, ?, ?, ?, ?) returning *",
+        )
+        .bind(new_todo.body())
+        .bind(due_at)
+        .bind(new_todo.status())
+        .bind(new_todo.status() == TodoStatus::Done)
+        .bind(new_todo.latitude)
+        .bind(new_todo.longitude)
+        .bind(new_todo.place())
+        // We execute the query with fetch_one() because we expect this to return one row.
+        .fetch_one(&dbpool)
+        .await
+        .inspect(|_: &Todo| record_rows(1))
+        .map_err(Into::into)
     }
 
-    // We've added another new type here, UpdateTodo, which contains the two fields we allow to be updated.
+    // We've added another new type here, UpdateTodo, which contains the fields we allow to be updated.
+    // Status changes have to be allowed by the configured transitions.
+    #[tracing::instrument(
+        name = "todo.update",
+        skip(dbpool, transitions, updated_todo),
+        fields(rows)
+    )]
     pub async fn update(
         dbpool: SqlitePool,
+        transitions: &StatusTransitions,
         id: i64,
         updated_todo: UpdateTodo,
     ) -> Result<Todo, Error> {
+        let due_at = resolve_due(&dbpool, updated_todo.due()).await?;
+        geo::check_location(
+            updated_todo.latitude,
+            updated_todo.longitude,
+            updated_todo.place(),
+        )?;
+
+        // The transaction keeps the status we checked the transition against from changing
+        // underneath us.
+        let mut tx = dbpool.begin().await?;
+        let (current,): (TodoStatus,) = query_as("select status from todos where id = ?")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let status = updated_todo.status_from(current);
+        transitions.check(current, status)?;
+
         // We're using the returning * SQL clause to retrieve the updated record immediately. Notice how we set the updated_at
-        // field to the current date and time.
-        query_as("update todos set body = ?, completed = ?, updated_at = datetime('now') where id = ? returning *")
+        // field to the current date and time, and bump the version.
+        let todo = query_as("update todos set body = ?, completed = ?, status = ?, due_at = ?, latitude = ?, longitude = ?, place = ?, stale_at = null, updated_at = datetime('now'), version = version + 1 where id = ? returning *")
             // Each value is bound in the order they're declared within the SQL statement, using the ? token to bind values.
             // This syntax varies, depending on the SQL implementation.
             // When we use bind() to bind values to the SQL statement, we need to pay attention to the order of the values because
             // they're bound in the order they're specified.
             .bind(updated_todo.body())
-            .bind(updated_todo.completed())
+            .bind(status == TodoStatus::Done)
+            .bind(status)
+            .bind(due_at)
+            .bind(updated_todo.latitude)
+            .bind(updated_todo.longitude)
+            .bind(updated_todo.place())
             .bind(id)
             // We expect to fetch one row when this query is executed.
-            .fetch_one(&dbpool)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        record_rows(1);
+        Ok(todo)
+    }
+
+    // Creates the todo with the given external ID, or updates it if it already exists, so import
+    // pipelines can write the same todo any number of times. Returns whether the todo was created.
+    // Imports mirror another system's state, so the status transitions aren't enforced here.
+    #[tracing::instrument(name = "todo.upsert", skip(dbpool, todo), fields(rows))]
+    pub async fn upsert(
+        dbpool: SqlitePool,
+        external_id: &str,
+        todo: UpdateTodo,
+    ) -> Result<(Todo, bool), Error> {
+        let due_at = resolve_due(&dbpool, todo.due()).await?;
+        geo::check_location(todo.latitude, todo.longitude, todo.place())?;
+
+        let todo: Todo = query_as(
+            "insert into todos (external_id, body, completed, due_at, status, latitude, longitude, place)
+             values (?1, ?2, ?3, ?4, ?5, ?7, ?8, ?9)
+             on conflict (external_id) do update set body = excluded.body,
+             completed = coalesce(?10, todos.completed),
+             due_at = excluded.due_at, latitude = excluded.latitude, longitude = excluded.longitude,
+             place = excluded.place, stale_at = null, updated_at = datetime('now'), version = version + 1,
+             status = case
+                 when ?6 is not null then ?6
+                 when ?10 is null then todos.status
+                 when ?10 then 'done'
+                 when todos.status = 'done' then 'backlog'
+                 else todos.status
+             end
+             returning *",
+        )
+        .bind(external_id)
+        .bind(todo.body())
+        .bind(todo.status_from(TodoStatus::Backlog) == TodoStatus::Done)
+        .bind(due_at)
+        // A new todo's status, and the status an existing one is explicitly moved to. Without the
+        // latter, the existing todo's status is derived from completed like for other updates, or
+        // kept when that's left out too.
+        .bind(todo.status_from(TodoStatus::Backlog))
+        .bind(todo.status())
+        .bind(todo.latitude)
+        .bind(todo.longitude)
+        .bind(todo.place())
+        .bind(todo.completed())
+        .fetch_one(&dbpool)
+        .await?;
+        record_rows(1);
+        // Updates always bump the version, so only a todo we just inserted is at version 1.
+        let created = todo.version == 1;
+        Ok((todo, created))
+    }
+
+    // The ID of the todo with an external ID, if there is one.
+    pub async fn find_external(
+        dbpool: &SqlitePool,
+        external_id: &str,
+    ) -> Result<Option<i64>, Error> {
+        let existing: Option<(i64,)> = query_as("select id from todos where external_id = ?")
+            .bind(external_id)
+            .fetch_optional(dbpool)
+            .await?;
+        Ok(existing.map(|(id,)| id))
+    }
+
+    // Deletes completed todos that haven't been touched since the cutoff, returning them as they
+    // were. We don't record when a todo was completed, so its last update stands in for that.
+    #[tracing::instrument(name = "todo.purge_completed", skip(dbpool, purge), fields(rows))]
+    pub async fn purge_completed(
+        dbpool: SqlitePool,
+        purge: PurgeQuery,
+    ) -> Result<Vec<Todo>, Error> {
+        let cutoff = resolve_cutoff(&dbpool, &purge.completed_before).await?;
+        query_as("delete from todos where completed and updated_at < ? returning *")
+            .bind(cutoff)
+            .fetch_all(&dbpool)
             .await
+            .inspect(|todos: &Vec<Todo>| record_rows(todos.len() as u64))
             .map_err(Into::into)
     }
 
-    pub async fn delete(dbpool: SqlitePool, id: i64) -> Result<(), Error> {
-        // The delete is destructive; nothing is left to return if it succeeds.
-        query("delete from todos where id = ?")
+    // Moves a completed todo into the archive. Archiving an archived todo leaves it as it is.
+    #[tracing::instrument(name = "todo.archive", skip(dbpool), fields(rows))]
+    pub async fn archive(dbpool: SqlitePool, id: i64) -> Result<Todo, Error> {
+        let todo = Self::read(dbpool.clone(), id).await?;
+        if todo.archived {
+            return Ok(todo);
+        }
+        if !todo.completed {
+            return Err(Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new("not_completed", i18n::message("archive_not_completed", &[])),
+            ));
+        }
+        Self::set_archived(dbpool, id, true).await
+    }
+
+    // Brings a todo back from the archive. Unarchiving a todo that isn't archived leaves it as it is.
+    #[tracing::instrument(name = "todo.unarchive", skip(dbpool), fields(rows))]
+    pub async fn unarchive(dbpool: SqlitePool, id: i64) -> Result<Todo, Error> {
+        let todo = Self::read(dbpool.clone(), id).await?;
+        if !todo.archived {
+            return Ok(todo);
+        }
+        Self::set_archived(dbpool, id, false).await
+    }
+
+    // Archiving counts as an update, so sync clients pick it up from the version and changes feed.
+    async fn set_archived(dbpool: SqlitePool, id: i64, archived: bool) -> Result<Todo, Error> {
+        query_as(
+            "update todos set archived = ?1, archived_at = case when ?1 then datetime('now') end,
+             stale_at = null, updated_at = datetime('now'), version = version + 1 where id = ?2 returning *",
+        )
+        .bind(archived)
+        .bind(id)
+        .fetch_one(&dbpool)
+        .await
+        .inspect(|_: &Todo| record_rows(1))
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(name = "todo.delete", skip(dbpool), fields(rows))]
+    pub async fn delete(dbpool: SqlitePool, id: i64) -> Result<Option<Todo>, Error> {
+        // The delete is destructive, so we return the todo as it was, for undoing the delete. Deleting
+        // a todo that doesn't exist isn't an error; there's just nothing to return.
+        let deleted: Option<Todo> = query_as("delete from todos where id = ? returning *")
             .bind(id)
-            // Here, we use execute() to execute the query, which is used for queries that don't return records.
-            .execute(&dbpool)
+            .fetch_optional(&dbpool)
             .await?;
-        // We return unit upon success(i.e., no previous errors).
-        Ok(())
+        record_rows(deleted.is_some() as u64);
+        Ok(deleted)
     }
+
+    // Puts deleted todos back as they were, with their IDs and reactions, for undoing a delete. The
+    // versions are bumped, so sync clients that saw the delete take the todos back.
+    #[tracing::instrument(name = "todo.restore", skip_all, fields(rows))]
+    pub async fn restore(dbpool: SqlitePool, todos: &[Todo]) -> Result<Vec<Todo>, Error> {
+        let mut tx = dbpool.begin().await?;
+        for todo in todos {
+            query(
+                "insert into todos (id, body, completed, status, created_at, version, due_at,
+                 external_id, archived, archived_at, latitude, longitude, place, links)
+                 values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(todo.id)
+            .bind(&todo.body)
+            .bind(todo.completed)
+            .bind(todo.status)
+            .bind(todo.created_at)
+            .bind(todo.version + 1)
+            .bind(todo.due_at)
+            .bind(&todo.external_id)
+            .bind(todo.archived)
+            .bind(todo.archived_at)
+            .bind(todo.latitude)
+            .bind(todo.longitude)
+            .bind(&todo.place)
+            .bind(serde_json::to_string(&todo.links).expect("links always serialize"))
+            .execute(&mut *tx)
+            .await
+            // Another todo may have taken the external ID in the meantime.
+            .map_err(|err| match err.as_database_error() {
+                Some(db) if db.is_unique_violation() => undo_conflict(todo.id),
+                _ => err.into(),
+            })?;
+            for (emoji, count) in todo.reactions.iter() {
+                reactions::insert(&mut tx, todo.id, emoji, count).await?;
+            }
+        }
+        let mut restored = Vec::with_capacity(todos.len());
+        for todo in todos {
+            restored.push(
+                query_as("select * from todos where id = ?")
+                    .bind(todo.id)
+                    .fetch_one(&mut *tx)
+                    .await?,
+            );
+        }
+        tx.commit().await?;
+        record_rows(restored.len() as u64);
+        Ok(restored)
+    }
+
+    // Puts an updated todo back the way it was, for undoing an update. If the todo changed again
+    // after the update, i.e. it's no longer at `version`, undoing would lose that change, so it's a
+    // conflict instead.
+    #[tracing::instrument(name = "todo.revert", skip(dbpool, before), fields(id = before.id, rows))]
+    pub async fn revert(dbpool: SqlitePool, before: &Todo, version: i64) -> Result<Todo, Error> {
+        let reverted: Option<Todo> = query_as(
+            "update todos set body = ?, completed = ?, status = ?, due_at = ?, latitude = ?,
+             longitude = ?, place = ?, stale_at = null, updated_at = datetime('now'), version = version + 1
+             where id = ? and version = ? returning *",
+        )
+        .bind(&before.body)
+        .bind(before.completed)
+        .bind(before.status)
+        .bind(before.due_at)
+        .bind(before.latitude)
+        .bind(before.longitude)
+        .bind(&before.place)
+        .bind(before.id)
+        .bind(version)
+        .fetch_optional(&dbpool)
+        .await?;
+        record_rows(reverted.is_some() as u64);
+        reverted.ok_or_else(|| undo_conflict(before.id))
+    }
+}
+
+// Matches the todos in the box of the ?near= filter, bound to ?5 through ?8, or every todo when
+// they're null. A box across the antimeridian has a min longitude greater than its max longitude.
+pub(crate) const NEAR: &str = "(?5 is null or (latitude between ?5 and ?6 and case
+    when ?7 <= ?8 then longitude between ?7 and ?8
+    else longitude >= ?7 or longitude <= ?8
+end))";
+
+// Matches the todos for the ?stale= filter, bound to ?9, or every todo when it's null.
+pub(crate) const STALE: &str = "(?9 is null or (stale_at is not null) = ?9)";
+
+fn undo_conflict(id: i64) -> Error {
+    Error::BadRequest(
+        StatusCode::CONFLICT,
+        RequestError::new(
+            "undo_conflict",
+            i18n::message("undo_conflict", &[("id", &ids::encode(id))]),
+        ),
+    )
+}
+
+// Records the number of rows a statement returned or changed on the current span.
+fn record_rows(rows: u64) {
+    tracing::Span::current().record("rows", rows);
+}
+
+async fn resolve_cutoff(dbpool: &SqlitePool, cutoff: &str) -> Result<NaiveDateTime, Error> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(cutoff) {
+        return Ok(timestamp.naive_utc());
+    }
+    let date = NaiveDate::parse_from_str(cutoff, "%Y-%m-%d").map_err(|_| {
+        invalid_param(
+            "invalid_cutoff",
+            "completed_before",
+            i18n::message("invalid_cutoff", &[("cutoff", cutoff)]),
+        )
+    })?;
+    let timezone = Preferences::read(dbpool.clone()).await?.timezone();
+    due::to_utc(timezone, date.and_time(NaiveTime::MIN)).ok_or_else(|| {
+        invalid_param(
+            "invalid_cutoff",
+            "completed_before",
+            i18n::message("invalid_cutoff", &[("cutoff", cutoff)]),
+        )
+    })
+}
+
+// Turns the due date a client sent into a UTC timestamp, interpreting natural language relative to
+// the current time in the owner's timezone.
+#[tracing::instrument(name = "todo.resolve_due", skip(dbpool))]
+pub(crate) async fn resolve_due(
+    dbpool: &SqlitePool,
+    due: Option<&str>,
+) -> Result<Option<NaiveDateTime>, Error> {
+    let Some(due) = due else {
+        return Ok(None);
+    };
+    let timezone = Preferences::read(dbpool.clone()).await?.timezone();
+    let now = Utc::now().with_timezone(&timezone);
+
+    due::parse(due, now).map(Some).ok_or_else(|| {
+        Error::BadRequest(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            RequestError::new("invalid_due", i18n::message("invalid_due", &[("due", due)]))
+                .with_field("due"),
+        )
+    })
 }
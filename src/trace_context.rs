@@ -0,0 +1,152 @@
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::fmt::Write;
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+// Longer tracestate headers may be truncated by any hop, so we drop them rather than pass on a
+// broken one.
+const MAX_TRACESTATE_LEN: usize = 512;
+// Whether the caller recorded its trace, in the flags of the traceparent.
+const SAMPLED: u8 = 0x01;
+
+// Where a request sits in a distributed trace, as carried by the W3C Trace Context headers
+// (https://www.w3.org/TR/trace-context/). A request that arrives with a traceparent continues the
+// caller's trace; any other request starts a new one. Either way, the request gets a span ID of
+// its own, which is the parent of the calls we make to other services while handling it, so
+// traces connect across services even though we don't export spans ourselves.
+#[derive(Clone, Debug)]
+pub struct TraceContext {
+    trace_id: String,
+    span_id: String,
+    // The caller's span, when the trace came from a caller.
+    parent_id: Option<String>,
+    flags: u8,
+    // Vendor-specific trace data, which we pass on untouched.
+    tracestate: Option<HeaderValue>,
+}
+
+impl TraceContext {
+    // Continues the trace of the traceparent header, or starts a new one when there's none or it's
+    // malformed, as the specification asks.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some((trace_id, parent_id, flags)) = headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent)
+        else {
+            return Self {
+                trace_id: random_hex(16),
+                span_id: random_hex(8),
+                parent_id: None,
+                flags: SAMPLED,
+                tracestate: None,
+            };
+        };
+        Self {
+            trace_id,
+            span_id: random_hex(8),
+            parent_id: Some(parent_id),
+            flags,
+            tracestate: headers
+                .get(TRACESTATE)
+                .filter(|value| value.len() <= MAX_TRACESTATE_LEN)
+                .cloned(),
+        }
+    }
+
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    pub fn span_id(&self) -> &str {
+        &self.span_id
+    }
+
+    pub fn parent_id(&self) -> Option<&str> {
+        self.parent_id.as_deref()
+    }
+
+    // The headers for a call to another service, naming this request's span as the parent.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let traceparent = format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags);
+        headers.insert(
+            TRACEPARENT,
+            HeaderValue::from_str(&traceparent).expect("hex digits are a valid header value"),
+        );
+        match &self.tracestate {
+            Some(tracestate) => headers.insert(TRACESTATE, tracestate.clone()),
+            None => headers.remove(TRACESTATE),
+        };
+    }
+}
+
+// Returns the trace ID, parent ID, and flags of a traceparent like
+// "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01". Later versions may append fields,
+// which we ignore, as long as the ones we know keep their format.
+fn parse_traceparent(value: &str) -> Option<(String, String, u8)> {
+    let mut fields = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || fields.next().is_none())
+        && is_hex(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && is_hex(parent_id, 16)
+        && parent_id.bytes().any(|b| b != b'0')
+        && is_hex(flags, 2);
+    if !valid {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), parent_id.to_string(), flags))
+}
+
+// Only lowercase hex digits are allowed.
+fn is_hex(field: &str, len: usize) -> bool {
+    field.len() == len
+        && field
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// IDs only have to be unique, not unpredictable, so a fast generator will do.
+fn random_hex(bytes: usize) -> String {
+    let mut id = String::with_capacity(bytes * 2);
+    loop {
+        id.clear();
+        for _ in 0..bytes {
+            write!(id, "{:02x}", fastrand::u8(..)).ok();
+        }
+        // All zeros is reserved as invalid.
+        if id.bytes().any(|b| b != b'0') {
+            return id;
+        }
+    }
+}
+
+tokio::task_local! {
+    // The trace context of the request being handled, for the outbound client to propagate.
+    static CURRENT: TraceContext;
+}
+
+// A middleware which reads the request's trace context and makes it available to the rest of the
+// request: to the request span, through the request's extensions, and to outbound calls.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let context = TraceContext::from_headers(request.headers());
+    request.extensions_mut().insert(context.clone());
+    CURRENT.scope(context, next.run(request)).await
+}
+
+// Adds the current request's trace context to the headers of a call to another service. Outside a
+// request, e.g. in a background task, there's no trace to continue.
+pub fn inject(headers: &mut HeaderMap) {
+    CURRENT.try_with(|context| context.inject(headers)).ok();
+}
@@ -0,0 +1,35 @@
+use crate::error::Error;
+use crate::params::ListParams;
+use crate::todo::Todo;
+use sqlx::{query_as, SqlitePool};
+
+// The polling triggers offered to automation platforms like Zapier and IFTTT. They poll every few
+// minutes and expect a plain array of items, newest first, and remember the ids they've seen to
+// decide which items are new. Todos are flat objects with an id, so they serve as items as they are.
+#[derive(Clone, Copy)]
+pub enum Trigger {
+    // Todos as they're created.
+    NewTodo,
+    // Todos as they're completed. We don't record when a todo was completed, so its last update
+    // stands in for that.
+    CompletedTodo,
+}
+
+impl Trigger {
+    // The most recent items for the trigger. The order is what the platforms rely on, so the sort
+    // parameter is ignored here.
+    pub async fn poll(self, dbpool: SqlitePool, params: &ListParams) -> Result<Vec<Todo>, Error> {
+        let sql = match self {
+            Trigger::NewTodo => "select * from todos order by created_at desc, id desc limit ? offset ?",
+            Trigger::CompletedTodo => {
+                "select * from todos where completed order by updated_at desc, id desc limit ? offset ?"
+            }
+        };
+        query_as(sql)
+            .bind(params.limit)
+            .bind(params.offset)
+            .fetch_all(&dbpool)
+            .await
+            .map_err(Into::into)
+    }
+}
@@ -0,0 +1,149 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::todo::Todo;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The header carrying the token that undoes a delete or an update.
+pub const UNDO_TOKEN: HeaderName = HeaderName::from_static("undo-token");
+
+// How an operation is undone.
+enum Undo {
+    // Puts deleted todos back, with their IDs.
+    Restore(Vec<Todo>),
+    // Puts an updated todo back the way it was, unless it has changed again since.
+    Revert { before: Box<Todo>, version: i64 },
+}
+
+// The destructive operations that can still be undone, by token. A token is valid for the configured
+// window after the operation and can be used once. The log is kept in memory: it's meant for an
+// "Undo" button right after a mistake, so losing it on a restart is fine.
+pub struct UndoLog {
+    window: Duration,
+    entries: Mutex<HashMap<String, (Instant, Undo)>>,
+}
+
+impl UndoLog {
+    // A window of zero turns undo off; destructive operations then return no token.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::default(),
+        }
+    }
+
+    // Returns the token undoing a delete of the todos, if there were any.
+    pub fn record_delete(&self, todos: Vec<Todo>) -> Option<String> {
+        if todos.is_empty() {
+            return None;
+        }
+        self.record(Undo::Restore(todos))
+    }
+
+    // Returns the token undoing an update from `before` to `after`.
+    pub fn record_update(&self, before: Todo, after: &Todo) -> Option<String> {
+        self.record(Undo::Revert {
+            before: Box::new(before),
+            version: after.version(),
+        })
+    }
+
+    fn record(&self, undo: Undo) -> Option<String> {
+        if self.window.is_zero() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (expires, _)| *expires > now);
+        let token = new_token();
+        entries.insert(token.clone(), (now + self.window, undo));
+        Some(token)
+    }
+
+    // Undoes the operation of the token, returning the todos as they are afterwards.
+    pub async fn undo(&self, dbpool: SqlitePool, token: &str) -> Result<Vec<Todo>, Error> {
+        let undo = {
+            let mut entries = self.entries.lock().unwrap();
+            entries
+                .remove(token)
+                .filter(|(expires, _)| *expires > Instant::now())
+                .map(|(_, undo)| undo)
+        };
+        match undo {
+            Some(Undo::Restore(todos)) => Todo::restore(dbpool, &todos).await,
+            Some(Undo::Revert { before, version }) => Todo::revert(dbpool, &before, version)
+                .await
+                .map(|todo| vec![todo]),
+            None => Err(Error::BadRequest(
+                StatusCode::GONE,
+                RequestError::new(
+                    "undo_expired",
+                    i18n::message(
+                        "undo_expired",
+                        &[("seconds", &self.window.as_secs().to_string())],
+                    ),
+                )
+                .with_field("undo_token"),
+            )),
+        }
+    }
+}
+
+// Anyone with the token can undo the operation, so it comes from the operating system's secure
+// random number generator.
+fn new_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("the operating system provides random numbers");
+    let mut token = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(token, "{byte:02x}").ok();
+    }
+    token
+}
+
+// Adds the undo token, if there is one, to the response of a destructive operation.
+pub fn with_token(mut response: Response, token: Option<String>) -> Response {
+    if let Some(token) = token.and_then(|token| HeaderValue::from_str(&token).ok()) {
+        response.headers_mut().insert(UNDO_TOKEN, token);
+    }
+    response
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UndoRequest {
+    undo_token: String,
+}
+
+impl UndoRequest {
+    pub fn new(undo_token: impl Into<String>) -> Self {
+        Self {
+            undo_token: undo_token.into(),
+        }
+    }
+
+    pub fn undo_token(&self) -> &str {
+        &self.undo_token
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UndoResponse {
+    // The restored or reverted todos, as they are now.
+    todos: Vec<Todo>,
+}
+
+impl UndoResponse {
+    pub fn new(todos: Vec<Todo>) -> Self {
+        Self { todos }
+    }
+
+    pub fn todos(&self) -> &[Todo] {
+        &self.todos
+    }
+}
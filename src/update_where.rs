@@ -0,0 +1,169 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use crate::status::{StatusTransitions, TodoStatus};
+use crate::todo::{resolve_due, Todo, TodoFilter, NEAR, STALE};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, SqlitePool};
+use std::collections::HashMap;
+
+// Changes every todo matching a filter at once, e.g. moving all stale todos back to the backlog.
+// The filter takes the same fields as the ?query of GET /v1/todos, and like the list it leaves
+// archived todos out; an empty filter matches every other todo.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateWhere {
+    #[serde(default)]
+    filter: TodoFilter,
+    set: TodoChanges,
+}
+
+// The fields to change. Fields that are left out stay as they are on each todo.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TodoChanges {
+    // Like for single updates, moving todos to done completes them and moving them anywhere else
+    // reopens them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<TodoStatus>,
+    // A due date, in any form creating a todo accepts, e.g. "friday".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    // Removes the due date instead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    clear_due: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateWhereResponse {
+    // The number of todos changed.
+    updated: usize,
+}
+
+impl UpdateWhere {
+    pub fn new(filter: TodoFilter) -> Self {
+        Self {
+            filter,
+            set: TodoChanges::default(),
+        }
+    }
+
+    pub fn with_status(mut self, status: TodoStatus) -> Self {
+        self.set.status = Some(status);
+        self
+    }
+
+    pub fn with_due(mut self, due: impl Into<String>) -> Self {
+        self.set.due = Some(due.into());
+        self
+    }
+
+    pub fn with_cleared_due(mut self) -> Self {
+        self.set.clear_due = true;
+        self
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let set = &self.set;
+        if set.status.is_none() && set.due.is_none() && !set.clear_due {
+            return Err(invalid_update(
+                i18n::message("update_where_empty", &[]),
+                "set",
+            ));
+        }
+        if set.due.is_some() && set.clear_due {
+            return Err(invalid_update(
+                i18n::message("update_where_due_conflict", &[]),
+                "set.clear_due",
+            ));
+        }
+        Ok(())
+    }
+
+    // Applies the changes to the matching todos in one statement, returning them as they are
+    // afterwards along with the status each had before. A status change has to be allowed from the
+    // status of every matching todo, or none of them change.
+    #[tracing::instrument(name = "todo.update_where", skip_all, fields(rows))]
+    pub async fn apply(
+        self,
+        dbpool: SqlitePool,
+        transitions: &StatusTransitions,
+    ) -> Result<Vec<(Todo, TodoStatus)>, Error> {
+        self.validate()?;
+        let due_at = resolve_due(&dbpool, self.set.due.as_deref()).await?;
+        let bounds = self.filter.bounds()?;
+        let filter = format!(
+            "not archived and (?1 is null or updated_at > ?1) and (?2 is null or status = ?2)
+             and {NEAR} and {STALE}"
+        );
+
+        // The transaction keeps the statuses we read from changing underneath us, so they're the
+        // ones the transitions were checked against and the ones the hooks are told about.
+        let mut tx = dbpool.begin().await?;
+        let before: Vec<(i64, TodoStatus)> =
+            query_as(&format!("select id, status from todos where {filter}"))
+                .bind(self.filter.modified_since())
+                .bind(self.filter.status())
+                // ?3 and ?4 are the changes, which this query doesn't use.
+                .bind(None::<TodoStatus>)
+                .bind(false)
+                .bind(bounds.map(|b| b.min_latitude))
+                .bind(bounds.map(|b| b.max_latitude))
+                .bind(bounds.map(|b| b.min_longitude))
+                .bind(bounds.map(|b| b.max_longitude))
+                .bind(self.filter.stale())
+                .fetch_all(&mut *tx)
+                .await?;
+        let before: HashMap<i64, TodoStatus> = before.into_iter().collect();
+        if let Some(status) = self.set.status {
+            for &from in before.values() {
+                if from != status {
+                    transitions.check(from, status)?;
+                }
+            }
+        }
+        let todos: Vec<Todo> = query_as(&format!(
+            "update todos set status = coalesce(?3, status),
+             completed = case when ?3 is null then completed else ?3 = 'done' end,
+             due_at = case when ?4 then ?10 else due_at end,
+             stale_at = null, updated_at = datetime('now'), version = version + 1
+             where {filter} returning *"
+        ))
+        .bind(self.filter.modified_since())
+        .bind(self.filter.status())
+        .bind(self.set.status)
+        .bind(self.set.due.is_some() || self.set.clear_due)
+        .bind(bounds.map(|b| b.min_latitude))
+        .bind(bounds.map(|b| b.max_latitude))
+        .bind(bounds.map(|b| b.min_longitude))
+        .bind(bounds.map(|b| b.max_longitude))
+        .bind(self.filter.stale())
+        .bind(due_at)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        tracing::Span::current().record("rows", todos.len() as u64);
+        Ok(todos
+            .into_iter()
+            .map(|todo| {
+                let previous = before.get(&todo.id()).copied().unwrap_or(todo.status());
+                (todo, previous)
+            })
+            .collect())
+    }
+}
+
+impl UpdateWhereResponse {
+    pub fn new(updated: usize) -> Self {
+        Self { updated }
+    }
+
+    pub fn updated(&self) -> usize {
+        self.updated
+    }
+}
+
+fn invalid_update(message: String, field: &str) -> Error {
+    Error::BadRequest(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RequestError::new("invalid_update_where", message).with_field(field),
+    )
+}
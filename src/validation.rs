@@ -0,0 +1,57 @@
+use crate::error::{Error, RequestError};
+use crate::i18n;
+use axum::http::StatusCode;
+use unicode_normalization::UnicodeNormalization;
+
+// How todo bodies are cleaned up before they're stored, so text from different platforms ends up
+// the same: an "é" typed on macOS and one typed on Windows compare equal, and stray control
+// characters pasted from terminals don't break rendering elsewhere. Every path that writes a body
+// goes through apply.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyPolicy {
+    // The longest body we accept, in characters, after the cleanup.
+    pub max_chars: usize,
+    // Normalizes to NFC, the composed form most platforms produce.
+    pub normalize: bool,
+    // Turns CRLF and lone CR line breaks into LF and drops other control characters, keeping tabs.
+    pub strip_control: bool,
+}
+
+impl Default for BodyPolicy {
+    fn default() -> Self {
+        Self {
+            max_chars: 10_000,
+            normalize: true,
+            strip_control: true,
+        }
+    }
+}
+
+impl BodyPolicy {
+    // Returns the body as it should be stored, or a 422 naming the field when it's too long.
+    pub fn apply(&self, body: &str, field: &str) -> Result<String, Error> {
+        let mut body = body.to_string();
+        if self.strip_control {
+            body = body
+                .replace("\r\n", "\n")
+                .replace('\r', "\n")
+                .chars()
+                .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+                .collect();
+        }
+        if self.normalize {
+            body = body.nfc().collect();
+        }
+        if body.chars().count() > self.max_chars {
+            return Err(Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "body_too_long",
+                    i18n::message("body_too_long", &[("max", &self.max_chars.to_string())]),
+                )
+                .with_field(field),
+            ));
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,390 @@
+use crate::error::{Error, RequestError};
+use crate::hooks::async_trait;
+use crate::i18n;
+use crate::ids;
+use crate::notify::{Notification, Notifier};
+use crate::outbound::{self, Outbound};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes128Gcm, Nonce};
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{NaiveDateTime, Utc};
+use hkdf::Hkdf;
+use p256::ecdh::EphemeralSecret;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::PublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{query, query_as, SqlitePool};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// The record size we announce. Our messages are a single record well below it.
+const RECORD_SIZE: u32 = 4096;
+// How long push services keep a message for a browser that's offline, in seconds.
+const MESSAGE_TTL: u32 = 24 * 3600;
+// How long a VAPID token is valid. Push services reject tokens valid for more than a day.
+const TOKEN_LIFETIME: i64 = 12 * 3600;
+// The summary is cut to this many characters, so the message fits a push service's size limit
+// whatever the todo's body.
+const MAX_SUMMARY_CHARS: usize = 200;
+
+// The key identifying us to push services (VAPID, RFC 8292), as the base64url encoding of a P-256
+// private key's 32 bytes. Browsers subscribe with its public key and push services only accept
+// messages signed with it, so it has to stay the same once browsers have subscribed.
+#[derive(Clone)]
+pub struct VapidKey(SigningKey);
+
+impl VapidKey {
+    // The public key in the form browsers take as applicationServerKey.
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.0.verifying_key().to_encoded_point(false).as_bytes())
+    }
+
+    // A signed token authorizing us at the push service behind `endpoint`.
+    fn authorization(&self, endpoint: &reqwest::Url, subject: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(
+            serde_json::json!({
+                "aud": endpoint.origin().ascii_serialization(),
+                "exp": Utc::now().timestamp() + TOKEN_LIFETIME,
+                "sub": subject,
+            })
+            .to_string(),
+        );
+        let signed = format!("{header}.{claims}");
+        let signature: Signature = self.0.sign(signed.as_bytes());
+        format!(
+            "vapid t={signed}.{}, k={}",
+            URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            self.public_key()
+        )
+    }
+}
+
+// Keeps the private key out of logged configuration.
+impl fmt::Debug for VapidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VapidKey").field(&self.public_key()).finish()
+    }
+}
+
+impl FromStr for VapidKey {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bytes = decode(value).ok_or("the key should be base64url")?;
+        SigningKey::from_slice(&bytes)
+            .map(Self)
+            .map_err(|_| "the key should be the 32 bytes of a P-256 private key".to_string())
+    }
+}
+
+fn decode(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim().trim_end_matches('='))
+        .ok()
+}
+
+// The response of GET /v1/push/key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushKey {
+    public_key: String,
+}
+
+impl PushKey {
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+}
+
+// A browser subscribed to notifications. The keys for encrypting to it aren't returned.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct PushSubscription {
+    #[serde(with = "crate::ids::id")]
+    id: i64,
+    endpoint: String,
+    created_at: NaiveDateTime,
+}
+
+impl PushSubscription {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+// The body of POST /v1/push/subscriptions, which is what a browser's PushSubscription.toJSON()
+// gives.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreatePushSubscription {
+    endpoint: String,
+    #[serde(rename = "expirationTime", default)]
+    expiration_time: Option<i64>,
+    keys: PushSubscriptionKeys,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushSubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+impl CreatePushSubscription {
+    pub fn new(
+        endpoint: impl Into<String>,
+        p256dh: impl Into<String>,
+        auth: impl Into<String>,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            expiration_time: None,
+            keys: PushSubscriptionKeys {
+                p256dh: p256dh.into(),
+                auth: auth.into(),
+            },
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |field: &str| {
+            Error::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RequestError::new(
+                    "invalid_push_subscription",
+                    i18n::message("invalid_push_subscription", &[]),
+                )
+                .with_field(field),
+            )
+        };
+        // The endpoint is where we'll send pushes, so it has to be a push service on the public
+        // internet rather than something on our own network.
+        match reqwest::Url::parse(&self.endpoint) {
+            Ok(url) if url.scheme() == "https" && outbound::is_public_url(&url) => {}
+            _ => return Err(invalid("endpoint")),
+        }
+        match decode(&self.keys.p256dh) {
+            Some(key) if PublicKey::from_sec1_bytes(&key).is_ok() => {}
+            _ => return Err(invalid("keys.p256dh")),
+        }
+        // The auth secret is 16 random bytes.
+        match decode(&self.keys.auth) {
+            Some(secret) if secret.len() == 16 => {}
+            _ => return Err(invalid("keys.auth")),
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SubscriptionRow {
+    id: i64,
+    endpoint: String,
+    p256dh: String,
+    auth: String,
+}
+
+impl PushSubscription {
+    // Subscribes a browser. Browsers subscribe again with the same endpoint when their keys change,
+    // which replaces the keys.
+    pub async fn create(
+        dbpool: SqlitePool,
+        new: CreatePushSubscription,
+    ) -> Result<PushSubscription, Error> {
+        new.validate()?;
+        Ok(query_as(
+            "insert into push_subscriptions (endpoint, p256dh, auth) values (?, ?, ?)
+             on conflict (endpoint) do update set p256dh = excluded.p256dh, auth = excluded.auth
+             returning id, endpoint, created_at",
+        )
+        .bind(new.endpoint)
+        .bind(new.keys.p256dh)
+        .bind(new.keys.auth)
+        .fetch_one(&dbpool)
+        .await?)
+    }
+
+    pub async fn list(dbpool: SqlitePool) -> Result<Vec<PushSubscription>, Error> {
+        Ok(
+            query_as("select id, endpoint, created_at from push_subscriptions order by id")
+                .fetch_all(&dbpool)
+                .await?,
+        )
+    }
+
+    pub async fn delete(dbpool: SqlitePool, id: i64) -> Result<(), Error> {
+        let result = query("delete from push_subscriptions where id = ?")
+            .bind(id)
+            .execute(&dbpool)
+            .await?;
+        match result.rows_affected() {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Sends notifications to the subscribed browsers through their push services (RFC 8030), encrypted
+// for each browser (RFC 8291) and signed with the VAPID key. Without a key, Web Push is off.
+pub struct WebPush {
+    key: Option<VapidKey>,
+    // A mailto: or https: URL push services can reach us at when something's wrong.
+    subject: String,
+    dbpool: SqlitePool,
+    outbound: Arc<Outbound>,
+}
+
+impl WebPush {
+    pub fn new(
+        key: Option<VapidKey>,
+        subject: impl Into<String>,
+        dbpool: SqlitePool,
+        outbound: Arc<Outbound>,
+    ) -> Self {
+        Self {
+            key,
+            subject: subject.into(),
+            dbpool,
+            outbound,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn key(&self) -> Option<PushKey> {
+        self.key.as_ref().map(|key| PushKey {
+            public_key: key.public_key(),
+        })
+    }
+
+    async fn push(
+        &self,
+        key: &VapidKey,
+        subscription: &SubscriptionRow,
+        payload: &[u8],
+    ) -> Result<(), String> {
+        let endpoint =
+            reqwest::Url::parse(&subscription.endpoint).map_err(|err| err.to_string())?;
+        let body = encrypt(subscription, payload)?;
+        let request = self
+            .outbound
+            .client()
+            .post(endpoint.clone())
+            .header("authorization", key.authorization(&endpoint, &self.subject))
+            .header("content-encoding", "aes128gcm")
+            .header("content-type", "application/octet-stream")
+            .header("ttl", MESSAGE_TTL)
+            .body(body)
+            .build()
+            .map_err(|err| err.to_string())?;
+        // Endpoints come from clients, so pushes only go to public addresses, even if the endpoint's
+        // host name has since been pointed somewhere else.
+        let response = self.outbound.send_public(request).await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            // The browser unsubscribed or the subscription expired, so it won't come back.
+            StatusCode::NOT_FOUND | StatusCode::GONE => {
+                PushSubscription::delete(self.dbpool.clone(), subscription.id)
+                    .await
+                    .ok();
+                tracing::info!(
+                    subscription = ids::encode(subscription.id),
+                    "removed an expired push subscription"
+                );
+                Ok(())
+            }
+            status => Err(format!("the push service answered {status}")),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebPush {
+    fn channel(&self) -> &str {
+        "web_push"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let Some(key) = &self.key else {
+            return Ok(());
+        };
+        let subscriptions: Vec<SubscriptionRow> =
+            query_as("select id, endpoint, p256dh, auth from push_subscriptions")
+                .fetch_all(&self.dbpool)
+                .await
+                .map_err(|err| err.to_string())?;
+        // The service worker shows the summary; the ID lets it open the todo, if there is one.
+        let payload = serde_json::json!({
+            "event": notification.event(),
+            "todo_id": notification.todo_id().map(ids::encode),
+            "summary": notification.summary().chars().take(MAX_SUMMARY_CHARS).collect::<String>(),
+        })
+        .to_string();
+        let mut failures = Vec::new();
+        for subscription in &subscriptions {
+            if let Err(err) = self.push(key, subscription, payload.as_bytes()).await {
+                failures.push(format!("{}: {err}", subscription.endpoint));
+            }
+        }
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(failures.join("; ")),
+        }
+    }
+}
+
+// Encrypts a message for a browser with the aes128gcm content encoding, as a single record.
+fn encrypt(subscription: &SubscriptionRow, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let invalid = |_| "the subscription's keys are invalid".to_string();
+    let browser_key = decode(&subscription.p256dh).ok_or("the subscription's keys are invalid")?;
+    let auth = decode(&subscription.auth).ok_or("the subscription's keys are invalid")?;
+    let browser_public = PublicKey::from_sec1_bytes(&browser_key).map_err(invalid)?;
+
+    // A key pair of our own for this message, agreed with the browser's key.
+    let secret = EphemeralSecret::random(&mut OsRng);
+    let server_key = secret.public_key().to_encoded_point(false);
+    let shared = secret.diffie_hellman(&browser_public);
+
+    // Mixes in the browser's auth secret, then derives the content key and nonce from a random salt.
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(browser_public.to_encoded_point(false).as_bytes());
+    key_info.extend_from_slice(server_key.as_bytes());
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&auth), shared.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .map_err(|err| err.to_string())?;
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+    let (mut content_key, mut nonce) = ([0u8; 16], [0u8; 12]);
+    hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut content_key)
+        .map_err(|err| err.to_string())?;
+    hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce)
+        .map_err(|err| err.to_string())?;
+
+    // The last (and only) record ends with a 2 delimiter and no padding.
+    let mut plaintext = payload.to_vec();
+    plaintext.push(2);
+    let ciphertext = Aes128Gcm::new_from_slice(&content_key)
+        .map_err(|err| err.to_string())?
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|err| err.to_string())?;
+
+    let mut body = Vec::with_capacity(86 + ciphertext.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(server_key.as_bytes().len() as u8);
+    body.extend_from_slice(server_key.as_bytes());
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
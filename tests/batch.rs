@@ -0,0 +1,307 @@
+// The per-item results of the batch endpoints, which answer 207 when some items failed, and the
+// version checks of /v1/sync, which must only fail for changes somebody actually made.
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Method, Request};
+use axum::Router;
+use http_rest_api_service::cache::ResponseCache;
+use http_rest_api_service::config::Config;
+use http_rest_api_service::i18n::Catalogs;
+use http_rest_api_service::migrations::{self, Phase};
+use http_rest_api_service::pool;
+use http_rest_api_service::router::create_router;
+use http_rest_api_service::stale;
+use http_rest_api_service::state::AppState;
+use http_rest_api_service::undo::UNDO_TOKEN;
+use serde_json::{json, Value};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::SqlitePool;
+use std::str::FromStr;
+use std::time::Duration;
+use tower::ServiceExt;
+
+// The app with the defaults and the given overrides, whatever the environment says, and its
+// database, for the changes the background tasks make.
+async fn app<'a>(vars: impl IntoIterator<Item = (&'a str, &'a str)>) -> (Router, SqlitePool) {
+    let config = Config::from_vars(vars).expect("the config is valid");
+    let dbpool = pool::in_memory(pool::options())
+        .connect_with(SqliteConnectOptions::from_str("sqlite::memory:").expect("a valid URL"))
+        .await
+        .expect("can open an in-memory database");
+    migrations::run(&dbpool, Phase::Expand)
+        .await
+        .expect("can migrate the database");
+    let catalogs = Catalogs::load(config.locales_dir.as_deref()).expect("can load the catalogs");
+    let app = create_router(AppState::new(dbpool.clone(), config, catalogs)).await;
+    (app, dbpool)
+}
+
+// Sends a request, returning the status, the undo token if there is one, and the JSON body, or
+// null for an empty one.
+async fn send(app: &Router, request: Request<Body>) -> (u16, Option<String>, Value) {
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("the router is infallible");
+    let status = response.status().as_u16();
+    let undo_token = response
+        .headers()
+        .get(UNDO_TOKEN)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("can read the body");
+    let body = match bytes.is_empty() {
+        true => Value::Null,
+        false => serde_json::from_slice(&bytes).expect("the body is JSON"),
+    };
+    (status, undo_token, body)
+}
+
+fn request(method: Method, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("a valid request")
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::builder()
+        .uri(uri)
+        .body(Body::empty())
+        .expect("a valid request")
+}
+
+// The status of each item of a batch report.
+fn statuses(report: &Value) -> Vec<u64> {
+    report["items"]
+        .as_array()
+        .expect("the report has items")
+        .iter()
+        .map(|item| item["status"].as_u64().expect("items have a status"))
+        .collect()
+}
+
+async fn sync(app: &Router, changes: Value) -> (u16, Value) {
+    let (status, _, report) = send(
+        app,
+        request(Method::POST, "/v1/sync", json!({ "changes": changes })),
+    )
+    .await;
+    (status, report)
+}
+
+#[tokio::test]
+async fn import_reports_each_todo() {
+    let (app, _) = app([]).await;
+    let export = json!([
+        {"id": "1", "content": "buy milk"},
+        {"id": "2", "content": "call plumber", "due": {"date": "someday"}},
+        {"id": "3", "content": "water plants", "checked": true},
+    ]);
+
+    let (status, _, report) = send(
+        &app,
+        request(
+            Method::POST,
+            "/v1/todos/import?format=todoist",
+            export.clone(),
+        ),
+    )
+    .await;
+    assert_eq!(status, 207);
+    assert_eq!(
+        (report["created"].as_u64(), report["updated"].as_u64()),
+        (Some(2), Some(0))
+    );
+    assert_eq!(statuses(&report), [201, 422, 201]);
+    assert_eq!(
+        (report["succeeded"].as_u64(), report["failed"].as_u64()),
+        (Some(2), Some(1))
+    );
+    let failed = &report["items"][1];
+    assert_eq!(failed["reference"], "todoist:2");
+    assert_eq!(failed["error"]["code"], "invalid_due");
+
+    // Importing the same export again updates what the first import created.
+    let (status, _, report) = send(
+        &app,
+        request(Method::POST, "/v1/todos/import?format=todoist", export),
+    )
+    .await;
+    assert_eq!(status, 207);
+    assert_eq!(statuses(&report), [200, 422, 200]);
+
+    let (status, _, report) = send(
+        &app,
+        request(
+            Method::POST,
+            "/v1/todos/import?format=todoist",
+            json!([{"id": "1", "content": "buy oat milk"}]),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(statuses(&report), [200]);
+}
+
+#[tokio::test]
+async fn sync_reports_each_change() {
+    let (app, _) = app([("BODY_MAX_CHARS", "20")]).await;
+    let (_, _, kept) = send(
+        &app,
+        request(Method::POST, "/v1/todos", json!({"body": "a"})),
+    )
+    .await;
+    let (_, _, edited) = send(
+        &app,
+        request(Method::POST, "/v1/todos", json!({"body": "b"})),
+    )
+    .await;
+    let (_, _, deleted) = send(
+        &app,
+        request(Method::POST, "/v1/todos", json!({"body": "c"})),
+    )
+    .await;
+    // Somebody else edits the second todo and deletes the third after the client last synced.
+    send(
+        &app,
+        request(
+            Method::PUT,
+            &format!("/v1/todos/{}", edited["id"]),
+            json!({"body": "b, edited"}),
+        ),
+    )
+    .await;
+    send(
+        &app,
+        request(
+            Method::DELETE,
+            &format!("/v1/todos/{}", deleted["id"]),
+            json!({}),
+        ),
+    )
+    .await;
+
+    let (status, report) = sync(
+        &app,
+        json!([
+            {"op": "create", "client_ref": "local-1", "body": "d"},
+            {"op": "create", "client_ref": "local-2", "body": "a body far too long to keep"},
+            {"op": "update", "id": kept["id"], "base_version": 1, "body": "a, synced", "completed": true},
+            {"op": "update", "id": edited["id"], "base_version": 1, "body": "b, synced", "completed": false},
+            {"op": "update", "id": deleted["id"], "base_version": 1, "body": "c, synced", "completed": false},
+            {"op": "delete", "id": 999, "base_version": 1},
+        ]),
+    )
+    .await;
+    assert_eq!(status, 207);
+    assert_eq!(statuses(&report), [201, 422, 200, 409, 409, 204]);
+    let items = &report["items"];
+    assert_eq!(items[0]["reference"], "local-1");
+    assert_eq!(items[1]["reference"], "local-2");
+    assert_eq!(items[1]["error"]["code"], "body_too_long");
+    assert_eq!(items[1]["error"]["field"], "changes[1].body");
+    assert_eq!(items[2]["entity"]["version"], 2);
+    assert_eq!(items[2]["entity"]["status"], "done");
+    // A conflict comes with the server's copy, so the client can resolve it.
+    assert_eq!(items[3]["error"]["code"], "version_mismatch");
+    assert_eq!(items[3]["entity"]["body"], "b, edited");
+    assert_eq!(items[4]["error"]["code"], "deleted");
+    assert!(items[4].get("entity").is_none());
+
+    let (status, report) = sync(&app, json!([{"op": "create", "body": "e"}])).await;
+    assert_eq!(status, 200);
+    assert_eq!(statuses(&report), [201]);
+}
+
+#[tokio::test]
+async fn stale_flags_dont_conflict() {
+    let (app, dbpool) = app([]).await;
+    let (_, _, todo) = send(
+        &app,
+        request(Method::POST, "/v1/todos", json!({"body": "a"})),
+    )
+    .await;
+    sqlx::query("update todos set updated_at = datetime('now', '-30 days')")
+        .execute(&dbpool)
+        .await
+        .expect("can age the todo");
+
+    let flagged = stale::flag(&dbpool, &ResponseCache::disabled(), 14)
+        .await
+        .expect("can flag stale todos");
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].version(), 1);
+
+    // The client edited the todo offline without knowing it went stale.
+    let (status, report) = sync(
+        &app,
+        json!([{"op": "update", "id": todo["id"], "base_version": 1, "body": "a, synced", "completed": false}]),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(statuses(&report), [200]);
+    assert_eq!(report["items"][0]["entity"]["stale_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn link_previews_dont_conflict() {
+    // Link previews are only fetched from public addresses, so previewing a local one fails
+    // without going anywhere, and the link is stored without a title.
+    let (app, _) = app([("LINK_PREVIEWS", "true")]).await;
+    let (_, _, todo) = send(
+        &app,
+        request(Method::POST, "/v1/todos", json!({"body": "a"})),
+    )
+    .await;
+    let uri = format!("/v1/todos/{}", todo["id"]);
+    let (status, undo_token, _) = send(
+        &app,
+        request(
+            Method::PUT,
+            &uri,
+            json!({"body": "read http://localhost/page"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let undo_token = undo_token.expect("updates can be undone");
+
+    // The previews are stored in the background.
+    let mut links = Value::Null;
+    for _ in 0..100 {
+        let (_, _, todo) = send(&app, get(&uri)).await;
+        if todo["links"]
+            .as_array()
+            .is_some_and(|links| !links.is_empty())
+        {
+            links = todo["links"].clone();
+            assert_eq!(todo["version"], 2);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(
+        links,
+        json!([{"url": "http://localhost/page", "title": null, "description": null}])
+    );
+
+    // The client that added the link can still undo its update, and sync an edit based on it.
+    let (status, _, _) = send(
+        &app,
+        request(Method::POST, "/v1/undo", json!({"undo_token": undo_token})),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, report) = sync(
+        &app,
+        json!([{"op": "update", "id": todo["id"], "base_version": 3, "body": "b", "completed": false}]),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(statuses(&report), [200]);
+}
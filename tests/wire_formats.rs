@@ -0,0 +1,166 @@
+// Snapshots of the JSON our clients depend on: todos, error bodies, the change feed's cursor, the
+// response envelope, and webhook payloads. A change to any of them fails here with a diff, so a
+// breaking change to the wire format shows up in review. When the change is intended, update the
+// snapshots with `cargo insta review` (or INSTA_UPDATE=always cargo test) and commit them with it.
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Method, Request};
+use axum::Router;
+use http_rest_api_service::config::Config;
+use http_rest_api_service::envelope::EnvelopeMode;
+use http_rest_api_service::i18n::Catalogs;
+use http_rest_api_service::migrations::{self, Phase};
+use http_rest_api_service::notify::{Notification, NotificationEvent};
+use http_rest_api_service::pool;
+use http_rest_api_service::router::create_router;
+use http_rest_api_service::state::AppState;
+use http_rest_api_service::todo::Todo;
+use insta::assert_json_snapshot;
+use serde_json::Value;
+use sqlx::sqlite::SqliteConnectOptions;
+use std::str::FromStr;
+use tower::ServiceExt;
+
+// The defaults, whatever the environment the tests run in says, so the snapshots don't change with
+// it.
+fn config() -> Config {
+    Config::from_vars([]).expect("the defaults are valid")
+}
+
+async fn app(config: Config) -> Router {
+    let dbpool = pool::in_memory(pool::options())
+        .connect_with(SqliteConnectOptions::from_str("sqlite::memory:").expect("a valid URL"))
+        .await
+        .expect("can open an in-memory database");
+    migrations::run(&dbpool, Phase::Expand)
+        .await
+        .expect("can migrate the database");
+    let catalogs = Catalogs::load(config.locales_dir.as_deref()).expect("can load the catalogs");
+    create_router(AppState::new(dbpool, config, catalogs)).await
+}
+
+// Sends a request, returning the status and the JSON body, or null for an empty one.
+async fn send(app: &Router, request: Request<Body>) -> (u16, Value) {
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("the router is infallible");
+    let status = response.status().as_u16();
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("can read the body");
+    let body = match bytes.is_empty() {
+        true => Value::Null,
+        false => serde_json::from_slice(&bytes).expect("the body is JSON"),
+    };
+    (status, body)
+}
+
+fn post(uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("a valid request")
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::builder()
+        .uri(uri)
+        .body(Body::empty())
+        .expect("a valid request")
+}
+
+#[tokio::test]
+async fn todo() {
+    let app = app(config()).await;
+    let (status, created) = send(
+        &app,
+        post(
+            "/v1/todos",
+            r#"{"body": "renew passport https://example.com", "status": "in_progress",
+                "due": "2030-01-31T09:00:00Z", "latitude": 52.52, "longitude": 13.405}"#,
+        ),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_json_snapshot!("created", created, {".created_at" => "[timestamp]"});
+
+    let (_, list) = send(&app, get("/v1/todos")).await;
+    assert_json_snapshot!("list", list, {"[].created_at" => "[timestamp]"});
+}
+
+#[tokio::test]
+async fn errors() {
+    let app = app(config()).await;
+    let (status, malformed) = send(&app, post("/v1/todos", r#"{"body": "#)).await;
+    assert_eq!(status, 400);
+    assert_json_snapshot!("malformed_json", malformed);
+
+    let (status, invalid) = send(&app, get("/v1/todos?sort=priority")).await;
+    assert_eq!(status, 400);
+    assert_json_snapshot!("invalid_param", invalid);
+
+    let (status, unprocessable) =
+        send(&app, post("/v1/todos/update-where", r#"{"set": {}}"#)).await;
+    assert_eq!(status, 422);
+    assert_json_snapshot!("unprocessable", unprocessable);
+
+    // Todos that don't exist are a 404 without a body.
+    let (status, not_found) = send(&app, get("/v1/todos/999")).await;
+    assert_eq!((status, not_found), (404, Value::Null));
+}
+
+#[tokio::test]
+async fn change_feed_pagination() {
+    let app = app(config()).await;
+    for body in ["buy milk", "call plumber", "water plants"] {
+        send(&app, post("/v1/todos", &format!(r#"{{"body": "{body}"}}"#))).await;
+    }
+    // The client passes last_seq back as since to get the next page.
+    let (_, first) = send(&app, get("/v1/changes?limit=2")).await;
+    assert_json_snapshot!("first_page", first, {
+        ".changes[].changed_at" => "[timestamp]",
+        ".changes[].todo.created_at" => "[timestamp]",
+    });
+    let since = first["last_seq"].as_i64().expect("a cursor");
+    let (_, last) = send(&app, get(&format!("/v1/changes?since={since}&limit=2"))).await;
+    assert_json_snapshot!("last_page", last, {
+        ".changes[].changed_at" => "[timestamp]",
+        ".changes[].todo.created_at" => "[timestamp]",
+    });
+}
+
+#[tokio::test]
+async fn envelope() {
+    let mut config = config();
+    config.response_envelope = EnvelopeMode::Always;
+    let app = app(config).await;
+    let (_, created) = send(&app, post("/v1/todos", r#"{"body": "buy milk"}"#)).await;
+    assert_json_snapshot!("data", created, {
+        ".data.created_at" => "[timestamp]",
+        ".meta.trace_id" => "[trace_id]",
+    });
+
+    let (_, malformed) = send(&app, post("/v1/todos", r#"{"body": "#)).await;
+    assert_json_snapshot!("error", malformed, {".meta.trace_id" => "[trace_id]"});
+
+    let (_, not_found) = send(&app, get("/v1/todos/999")).await;
+    assert_json_snapshot!("empty_error", not_found, {".meta.trace_id" => "[trace_id]"});
+}
+
+#[tokio::test]
+async fn webhook_payloads() {
+    let app = app(config()).await;
+    let (_, created) = send(&app, post("/v1/todos", r#"{"body": "buy milk"}"#)).await;
+    let todo: Todo = serde_json::from_value(created).expect("a todo");
+
+    let completed = Notification::new(NotificationEvent::TodoCompleted, todo.id(), Some(todo));
+    assert_json_snapshot!("completed", completed, {
+        ".at" => "[timestamp]",
+        ".todo.created_at" => "[timestamp]",
+    });
+    let deleted = Notification::new(NotificationEvent::TodoDeleted, 1, None);
+    assert_json_snapshot!("deleted", deleted, {".at" => "[timestamp]"});
+}
@@ -0,0 +1,818 @@
+// A typed async client for the todo service. The request and response types are the ones the
+// service itself uses, so the client can't drift out of sync with the API.
+use http_rest_api_service::ids;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+
+pub use http_rest_api_service::account::{AccountSnapshot, SnapshotImportReport, SnapshotTodo};
+pub use http_rest_api_service::batch::{BatchItem, BatchReport};
+pub use http_rest_api_service::change::{Change, ChangeFeed, ChangeOp};
+pub use http_rest_api_service::db_stats::DbStats;
+pub use http_rest_api_service::error::RequestError;
+pub use http_rest_api_service::export::ExportFormat;
+pub use http_rest_api_service::export_job::{
+    CreateExportJob, ExportJob, ExportJobFormat, ExportJobStatus,
+};
+pub use http_rest_api_service::feed::FeedToken;
+pub use http_rest_api_service::flags::{FeatureFlag, UpdateFeatureFlag};
+pub use http_rest_api_service::ids::IdEncoding;
+pub use http_rest_api_service::import::{ImportFormat, ImportReport, ImportedTodo};
+pub use http_rest_api_service::links::{Link, Links};
+pub use http_rest_api_service::log_level::LogLevelStatus;
+pub use http_rest_api_service::maintenance::MaintenanceStatus;
+pub use http_rest_api_service::merge::{MergeRequest, MergeResponse};
+pub use http_rest_api_service::metering::{TenantUsage, UsageFormat, UsageQuery, UsageReport};
+pub use http_rest_api_service::migrations::{AppliedMigration, MigrationStatus, PendingMigration};
+pub use http_rest_api_service::preferences::{Preferences, UpdatePreferences};
+pub use http_rest_api_service::quicklist::{
+    CreateQuicklist, CreateQuicklistItem, Quicklist, QuicklistItem, UpdateQuicklistItem,
+};
+pub use http_rest_api_service::rate_limit::{PlanLimits, RatePlan, SetTenantPlan, TenantPlan};
+pub use http_rest_api_service::reactions::{AddReaction, Reactions};
+pub use http_rest_api_service::recent::{RecentReason, RecentTodo};
+pub use http_rest_api_service::report::{CreateReport, Report, ReportKind};
+pub use http_rest_api_service::retention::{RetentionPolicy, UpdateRetentionPolicy};
+pub use http_rest_api_service::runtime::RuntimeInfo;
+pub use http_rest_api_service::search::{SearchHit, SuggestQuery, Suggestion, SuggestionKind};
+pub use http_rest_api_service::share::{SharedTodo, TodoShare};
+pub use http_rest_api_service::status::{Board, BoardColumn, TodoStatus};
+pub use http_rest_api_service::sync::{SyncChange, SyncRequest, SyncResponse};
+pub use http_rest_api_service::todo::{CreateTodo, PurgeResponse, Todo, TodoFilter, UpdateTodo};
+pub use http_rest_api_service::undo::{UndoRequest, UndoResponse, UNDO_TOKEN};
+pub use http_rest_api_service::update_where::{UpdateWhere, UpdateWhereResponse};
+pub use http_rest_api_service::web_push::{CreatePushSubscription, PushKey, PushSubscription};
+
+#[derive(Debug)]
+pub enum ClientError {
+    // The request didn't make it to the service, or the response couldn't be read.
+    Http(reqwest::Error),
+    // The response wasn't the JSON we expected, e.g. because the service encodes IDs differently.
+    Decode(serde_json::Error),
+    // The service answered with an error status. Most errors carry a structured body; the raw body
+    // is kept for the ones that don't, like a 404 for an unknown todo.
+    Api {
+        status: StatusCode,
+        error: Option<RequestError>,
+        body: String,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "request failed: {err}"),
+            ClientError::Decode(err) => write!(f, "can't decode the response: {err}"),
+            ClientError::Api {
+                status,
+                error: Some(error),
+                ..
+            } => write!(f, "{status}: {} ({})", error.message(), error.code()),
+            ClientError::Api { status, body, .. } => write!(f, "{status}: {body}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(err) => Some(err),
+            ClientError::Decode(err) => Some(err),
+            ClientError::Api { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+// The paging, sorting, and filtering parameters shared by the list endpoints. Anything left unset
+// falls back to the service's defaults.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ListOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified_since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<TodoStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    near: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    radius_km: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stale: Option<bool>,
+}
+
+impl ListOptions {
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    // A field name such as "created_at", prefixed with "-" for descending order.
+    pub fn sort(mut self, sort: impl Into<String>) -> Self {
+        self.sort = Some(sort.into());
+        self
+    }
+
+    pub fn q(mut self, q: impl Into<String>) -> Self {
+        self.q = Some(q.into());
+        self
+    }
+
+    // An RFC 3339 timestamp. Only list_todos supports it.
+    pub fn modified_since(mut self, timestamp: impl Into<String>) -> Self {
+        self.modified_since = Some(timestamp.into());
+        self
+    }
+
+    pub fn status(mut self, status: TodoStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    // Only todos within radius_km of a point given in degrees. Only list_todos and board support
+    // it.
+    pub fn near(mut self, latitude: f64, longitude: f64, radius_km: f64) -> Self {
+        self.near = Some(format!("{latitude},{longitude}"));
+        self.radius_km = Some(radius_km);
+        self
+    }
+
+    // Only todos that are, or aren't, stale. Only list_todos and board support it.
+    pub fn stale(mut self, stale: bool) -> Self {
+        self.stale = Some(stale);
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    admin_token: Option<String>,
+    ids: Arc<IdEncoding>,
+}
+
+impl Client {
+    // `base_url` is where the service is mounted, e.g. "http://localhost:3000".
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    // For callers that need to configure timeouts, proxies, and so on themselves.
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            admin_token: None,
+            ids: Arc::default(),
+        }
+    }
+
+    // The token sent to the /v1/admin endpoints.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    // For services with HASHIDS_SALT set, the same encoding, e.g. IdEncoding::hashids(salt, 8). IDs
+    // are still passed to and returned from the client as integers.
+    pub fn with_id_encoding(mut self, encoding: IdEncoding) -> Self {
+        self.ids = Arc::new(encoding);
+        self
+    }
+
+    pub async fn alive(&self) -> Result<String, ClientError> {
+        self.text(self.request(Method::GET, "/alive")).await
+    }
+
+    pub async fn ready(&self) -> Result<String, ClientError> {
+        self.text(self.request(Method::GET, "/ready")).await
+    }
+
+    pub async fn list_todos(&self, options: &ListOptions) -> Result<Vec<Todo>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/todos").query(options))
+            .await
+    }
+
+    // The todos grouped by status. The options' paging applies to each column.
+    pub async fn board(&self, options: &ListOptions) -> Result<Board, ClientError> {
+        self.json(self.request(Method::GET, "/v1/todos/board").query(options))
+            .await
+    }
+
+    pub async fn archived_todos(&self, options: &ListOptions) -> Result<Vec<Todo>, ClientError> {
+        self.json(
+            self.request(Method::GET, "/v1/todos/archive")
+                .query(options),
+        )
+        .await
+    }
+
+    // The todos as a Markdown or org-mode document.
+    pub async fn export_todos(&self, format: ExportFormat) -> Result<String, ClientError> {
+        self.text(
+            self.request(Method::GET, "/v1/todos/export")
+                .query(&[("format", format)]),
+        )
+        .await
+    }
+
+    pub async fn read_todo(&self, id: i64) -> Result<Todo, ClientError> {
+        self.json(self.request(Method::GET, &format!("/v1/todos/{}", self.ids.encode(id))))
+            .await
+    }
+
+    pub async fn create_todo(&self, todo: &CreateTodo) -> Result<Todo, ClientError> {
+        self.json(self.request(Method::POST, "/v1/todos").json(todo))
+            .await
+    }
+
+    pub async fn update_todo(&self, id: i64, todo: &UpdateTodo) -> Result<Todo, ClientError> {
+        self.json(
+            self.request(Method::PUT, &format!("/v1/todos/{}", self.ids.encode(id)))
+                .json(todo),
+        )
+        .await
+    }
+
+    // Creates or updates the todo with the given external ID.
+    pub async fn upsert_todo(
+        &self,
+        external_id: &str,
+        todo: &UpdateTodo,
+    ) -> Result<Todo, ClientError> {
+        self.json(
+            self.request(Method::PUT, &format!("/v1/todos/external/{external_id}"))
+                .json(todo),
+        )
+        .await
+    }
+
+    // Copies a todo, moving the copy's due date by `due_offset_days`.
+    pub async fn duplicate_todo(&self, id: i64, due_offset_days: i64) -> Result<Todo, ClientError> {
+        self.json(
+            self.request(
+                Method::POST,
+                &format!("/v1/todos/{}/duplicate", self.ids.encode(id)),
+            )
+            .query(&[("due_offset_days", due_offset_days)]),
+        )
+        .await
+    }
+
+    // Imports the todos from another service's export. With `dry_run`, nothing is written and the
+    // report says what would have been. Todos that couldn't be imported are failed items in the
+    // report rather than an error, so the rest of the import still counts.
+    pub async fn import_todos(
+        &self,
+        format: ImportFormat,
+        dry_run: bool,
+        export: &serde_json::Value,
+    ) -> Result<ImportReport, ClientError> {
+        #[derive(Serialize)]
+        struct Params {
+            format: ImportFormat,
+            dry_run: bool,
+        }
+        self.json(
+            self.request(Method::POST, "/v1/todos/import")
+                .query(&Params { format, dry_run })
+                .json(export),
+        )
+        .await
+    }
+
+    pub async fn merge_todos(&self, request: &MergeRequest) -> Result<MergeResponse, ClientError> {
+        self.json(self.body(self.request(Method::POST, "/v1/todos/merge"), request))
+            .await
+    }
+
+    pub async fn update_where(
+        &self,
+        request: &UpdateWhere,
+    ) -> Result<UpdateWhereResponse, ClientError> {
+        self.json(self.body(
+            self.request(Method::POST, "/v1/todos/update-where"),
+            request,
+        ))
+        .await
+    }
+
+    // Deletes completed todos last updated before `completed_before`, a date or RFC 3339 timestamp.
+    pub async fn purge_completed(
+        &self,
+        completed_before: &str,
+    ) -> Result<PurgeResponse, ClientError> {
+        self.json(
+            self.request(Method::POST, "/v1/todos/purge")
+                .query(&[("completed_before", completed_before)]),
+        )
+        .await
+    }
+
+    pub async fn archive_todo(&self, id: i64) -> Result<Todo, ClientError> {
+        self.json(self.request(
+            Method::POST,
+            &format!("/v1/todos/{}/archive", self.ids.encode(id)),
+        ))
+        .await
+    }
+
+    pub async fn unarchive_todo(&self, id: i64) -> Result<Todo, ClientError> {
+        self.json(self.request(
+            Method::POST,
+            &format!("/v1/todos/{}/unarchive", self.ids.encode(id)),
+        ))
+        .await
+    }
+
+    // Adds an emoji reaction to a todo, returning the todo with the new counts.
+    pub async fn add_reaction(&self, id: i64, emoji: &str) -> Result<Todo, ClientError> {
+        self.json(
+            self.request(
+                Method::POST,
+                &format!("/v1/todos/{}/reactions", self.ids.encode(id)),
+            )
+            .json(&AddReaction::new(emoji)),
+        )
+        .await
+    }
+
+    // Removes one reaction with the emoji from a todo.
+    pub async fn remove_reaction(&self, id: i64, emoji: &str) -> Result<Todo, ClientError> {
+        self.json(self.request(
+            Method::DELETE,
+            &format!("/v1/todos/{}/reactions/{emoji}", self.ids.encode(id)),
+        ))
+        .await
+    }
+
+    // Creates a read-only link to the todo, for someone without access to the API.
+    pub async fn share_todo(&self, id: i64) -> Result<TodoShare, ClientError> {
+        let path = format!("/v1/todos/{}/share", self.ids.encode(id));
+        self.json(self.request(Method::POST, &path)).await
+    }
+
+    pub async fn todo_shares(&self, id: i64) -> Result<Vec<TodoShare>, ClientError> {
+        let path = format!("/v1/todos/{}/shares", self.ids.encode(id));
+        self.json(self.request(Method::GET, &path)).await
+    }
+
+    pub async fn revoke_todo_share(&self, id: i64, share_id: i64) -> Result<(), ClientError> {
+        let path = format!(
+            "/v1/todos/{}/shares/{}",
+            self.ids.encode(id),
+            self.ids.encode(share_id)
+        );
+        self.send(self.request(Method::DELETE, &path))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn shared_todo(&self, token: &str) -> Result<SharedTodo, ClientError> {
+        self.json(self.request(Method::GET, &format!("/v1/shared/todos/{token}")))
+            .await
+    }
+
+    // The key a browser subscribes to Web Push with.
+    pub async fn push_key(&self) -> Result<PushKey, ClientError> {
+        self.json(self.request(Method::GET, "/v1/push/key")).await
+    }
+
+    pub async fn subscribe_push(
+        &self,
+        subscription: &CreatePushSubscription,
+    ) -> Result<PushSubscription, ClientError> {
+        self.json(
+            self.request(Method::POST, "/v1/push/subscriptions")
+                .json(subscription),
+        )
+        .await
+    }
+
+    pub async fn push_subscriptions(&self) -> Result<Vec<PushSubscription>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/push/subscriptions"))
+            .await
+    }
+
+    pub async fn unsubscribe_push(&self, id: i64) -> Result<(), ClientError> {
+        let path = format!("/v1/push/subscriptions/{}", self.ids.encode(id));
+        self.send(self.request(Method::DELETE, &path))
+            .await
+            .map(|_| ())
+    }
+
+    // Returns the token for undoing the delete, unless undo is turned off or there was no such todo.
+    pub async fn delete_todo(&self, id: i64) -> Result<Option<String>, ClientError> {
+        let response = self
+            .send(self.request(
+                Method::DELETE,
+                &format!("/v1/todos/{}", self.ids.encode(id)),
+            ))
+            .await?;
+        Ok(response
+            .headers()
+            .get(UNDO_TOKEN.as_str())
+            .and_then(|token| token.to_str().ok())
+            .map(str::to_string))
+    }
+
+    // Everything the account has, for importing into another instance with import_snapshot().
+    pub async fn snapshot(&self) -> Result<AccountSnapshot, ClientError> {
+        self.json(self.request(Method::GET, "/v1/me/snapshot"))
+            .await
+    }
+
+    pub async fn import_snapshot(
+        &self,
+        snapshot: &AccountSnapshot,
+    ) -> Result<SnapshotImportReport, ClientError> {
+        self.json(self.request(Method::POST, "/v1/me/snapshot").json(snapshot))
+            .await
+    }
+
+    // Undoes a delete, purge, or update with the token it returned.
+    pub async fn undo(&self, undo_token: &str) -> Result<UndoResponse, ClientError> {
+        self.json(
+            self.request(Method::POST, "/v1/undo")
+                .json(&UndoRequest::new(undo_token)),
+        )
+        .await
+    }
+
+    pub async fn search_todos(&self, options: &ListOptions) -> Result<Vec<SearchHit>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/todos/search").query(options))
+            .await
+    }
+
+    pub async fn suggest(&self, query: &SuggestQuery) -> Result<Vec<Suggestion>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/todos/suggest").query(query))
+            .await
+    }
+
+    pub async fn recent_todos(
+        &self,
+        options: &ListOptions,
+    ) -> Result<Vec<RecentTodo>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/todos/recent").query(options))
+            .await
+    }
+
+    // Changes after `since`, the last_seq of the previous feed (0 for the first call).
+    pub async fn changes(&self, since: i64, limit: Option<i64>) -> Result<ChangeFeed, ClientError> {
+        let mut request = self
+            .request(Method::GET, "/v1/changes")
+            .query(&[("since", since)]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        self.json(request).await
+    }
+
+    // Conflicts and invalid changes are failed items in the response, not errors.
+    pub async fn sync(&self, request: &SyncRequest) -> Result<SyncResponse, ClientError> {
+        self.json(self.body(self.request(Method::POST, "/v1/sync"), request))
+            .await
+    }
+
+    // Queues a background export; poll it with export_job() until it has succeeded.
+    pub async fn create_export(&self, format: ExportJobFormat) -> Result<ExportJob, ClientError> {
+        self.json(
+            self.request(Method::POST, "/v1/exports")
+                .json(&CreateExportJob::new(format)),
+        )
+        .await
+    }
+
+    pub async fn export_job(&self, id: i64) -> Result<ExportJob, ClientError> {
+        self.json(self.request(Method::GET, &format!("/v1/exports/{}", self.ids.encode(id))))
+            .await
+    }
+
+    pub async fn download_export(&self, id: i64) -> Result<String, ClientError> {
+        self.text(self.request(
+            Method::GET,
+            &format!("/v1/exports/{}/download", self.ids.encode(id)),
+        ))
+        .await
+    }
+
+    pub async fn reports(&self) -> Result<Vec<Report>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/reports")).await
+    }
+
+    pub async fn create_report(&self, kind: ReportKind) -> Result<Report, ClientError> {
+        self.json(
+            self.request(Method::POST, "/v1/reports")
+                .json(&CreateReport::new(kind)),
+        )
+        .await
+    }
+
+    pub async fn report(&self, id: i64) -> Result<Report, ClientError> {
+        self.json(self.request(Method::GET, &format!("/v1/reports/{}", self.ids.encode(id))))
+            .await
+    }
+
+    // The report as Markdown.
+    pub async fn download_report(&self, id: i64) -> Result<String, ClientError> {
+        self.text(self.request(
+            Method::GET,
+            &format!("/v1/reports/{}/download", self.ids.encode(id)),
+        ))
+        .await
+    }
+
+    pub async fn feed_tokens(&self) -> Result<Vec<FeedToken>, ClientError> {
+        self.json(self.request(Method::GET, "/v1/feeds")).await
+    }
+
+    pub async fn create_feed_token(&self) -> Result<FeedToken, ClientError> {
+        self.json(self.request(Method::POST, "/v1/feeds")).await
+    }
+
+    pub async fn rotate_feed_token(&self, id: i64) -> Result<FeedToken, ClientError> {
+        self.json(self.request(
+            Method::POST,
+            &format!("/v1/feeds/{}/rotate", self.ids.encode(id)),
+        ))
+        .await
+    }
+
+    pub async fn revoke_feed_token(&self, id: i64) -> Result<(), ClientError> {
+        self.send(self.request(
+            Method::DELETE,
+            &format!("/v1/feeds/{}", self.ids.encode(id)),
+        ))
+        .await
+        .map(|_| ())
+    }
+
+    // Creates a guest quicklist. Its token is all it takes to use it, with the methods below.
+    pub async fn create_quicklist(&self, new: &CreateQuicklist) -> Result<Quicklist, ClientError> {
+        self.json(self.request(Method::POST, "/v1/quicklists").json(new))
+            .await
+    }
+
+    pub async fn quicklist(&self, token: &str) -> Result<Quicklist, ClientError> {
+        self.json(self.request(Method::GET, &format!("/v1/quicklists/{token}")))
+            .await
+    }
+
+    pub async fn delete_quicklist(&self, token: &str) -> Result<(), ClientError> {
+        self.send(self.request(Method::DELETE, &format!("/v1/quicklists/{token}")))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn add_quicklist_item(
+        &self,
+        token: &str,
+        new: &CreateQuicklistItem,
+    ) -> Result<QuicklistItem, ClientError> {
+        self.json(
+            self.request(Method::POST, &format!("/v1/quicklists/{token}/items"))
+                .json(new),
+        )
+        .await
+    }
+
+    pub async fn update_quicklist_item(
+        &self,
+        token: &str,
+        id: i64,
+        update: &UpdateQuicklistItem,
+    ) -> Result<QuicklistItem, ClientError> {
+        let path = format!("/v1/quicklists/{token}/items/{}", self.ids.encode(id));
+        self.json(self.request(Method::PUT, &path).json(update))
+            .await
+    }
+
+    pub async fn delete_quicklist_item(&self, token: &str, id: i64) -> Result<(), ClientError> {
+        let path = format!("/v1/quicklists/{token}/items/{}", self.ids.encode(id));
+        self.send(self.request(Method::DELETE, &path))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn preferences(&self) -> Result<Preferences, ClientError> {
+        self.json(self.request(Method::GET, "/v1/preferences"))
+            .await
+    }
+
+    pub async fn update_preferences(
+        &self,
+        preferences: &UpdatePreferences,
+    ) -> Result<Preferences, ClientError> {
+        self.json(
+            self.request(Method::PUT, "/v1/preferences")
+                .json(preferences),
+        )
+        .await
+    }
+
+    // The names of the feature flags that are on for `subject`.
+    pub async fn enabled_flags(&self, subject: Option<&str>) -> Result<Vec<String>, ClientError> {
+        let mut request = self.request(Method::GET, "/v1/flags");
+        if let Some(subject) = subject {
+            request = request.query(&[("subject", subject)]);
+        }
+        self.json(request).await
+    }
+
+    pub async fn feature_flags(&self) -> Result<Vec<FeatureFlag>, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/flags"))
+            .await
+    }
+
+    pub async fn set_feature_flag(
+        &self,
+        name: &str,
+        update: &UpdateFeatureFlag,
+    ) -> Result<FeatureFlag, ClientError> {
+        self.json(
+            self.admin_request(Method::PUT, &format!("/v1/admin/flags/{name}"))
+                .json(update),
+        )
+        .await
+    }
+
+    pub async fn delete_feature_flag(&self, name: &str) -> Result<(), ClientError> {
+        self.send(self.admin_request(Method::DELETE, &format!("/v1/admin/flags/{name}")))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn rate_plans(&self) -> Result<Vec<RatePlan>, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/plans"))
+            .await
+    }
+
+    pub async fn tenant_plans(&self) -> Result<Vec<TenantPlan>, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/tenants"))
+            .await
+    }
+
+    pub async fn set_tenant_plan(
+        &self,
+        tenant: &str,
+        plan: &str,
+    ) -> Result<TenantPlan, ClientError> {
+        self.json(
+            self.admin_request(Method::PUT, &format!("/v1/admin/tenants/{tenant}/plan"))
+                .json(&SetTenantPlan::new(plan)),
+        )
+        .await
+    }
+
+    // Puts the tenant back on the default plan.
+    pub async fn reset_tenant_plan(&self, tenant: &str) -> Result<(), ClientError> {
+        self.send(self.admin_request(Method::DELETE, &format!("/v1/admin/tenants/{tenant}/plan")))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn usage(&self, query: &UsageQuery) -> Result<UsageReport, ClientError> {
+        let query = query.clone().with_format(UsageFormat::Json);
+        self.json(
+            self.admin_request(Method::GET, "/v1/admin/usage")
+                .query(&query),
+        )
+        .await
+    }
+
+    pub async fn usage_csv(&self, query: &UsageQuery) -> Result<String, ClientError> {
+        let query = query.clone().with_format(UsageFormat::Csv);
+        self.text(
+            self.admin_request(Method::GET, "/v1/admin/usage")
+                .query(&query),
+        )
+        .await
+    }
+
+    pub async fn maintenance(&self) -> Result<MaintenanceStatus, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/maintenance"))
+            .await
+    }
+
+    pub async fn set_maintenance(
+        &self,
+        status: &MaintenanceStatus,
+    ) -> Result<MaintenanceStatus, ClientError> {
+        self.json(
+            self.admin_request(Method::POST, "/v1/admin/maintenance")
+                .json(status),
+        )
+        .await
+    }
+
+    pub async fn log_level(&self) -> Result<LogLevelStatus, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/log-level"))
+            .await
+    }
+
+    pub async fn set_log_level(
+        &self,
+        status: &LogLevelStatus,
+    ) -> Result<LogLevelStatus, ClientError> {
+        self.json(
+            self.admin_request(Method::PUT, "/v1/admin/log-level")
+                .json(status),
+        )
+        .await
+    }
+
+    pub async fn runtime(&self) -> Result<RuntimeInfo, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/runtime"))
+            .await
+    }
+
+    pub async fn migrations(&self) -> Result<MigrationStatus, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/migrations"))
+            .await
+    }
+
+    pub async fn database_stats(&self) -> Result<DbStats, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/database"))
+            .await
+    }
+
+    pub async fn retention_policy(&self) -> Result<RetentionPolicy, ClientError> {
+        self.json(self.admin_request(Method::GET, "/v1/admin/retention"))
+            .await
+    }
+
+    // Replaces the retention policy; rules left out of `policy` are turned off.
+    pub async fn set_retention_policy(
+        &self,
+        policy: &UpdateRetentionPolicy,
+    ) -> Result<RetentionPolicy, ClientError> {
+        self.json(
+            self.admin_request(Method::PUT, "/v1/admin/retention")
+                .json(policy),
+        )
+        .await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base_url))
+    }
+
+    fn admin_request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.request(method, path);
+        match &self.admin_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    // Sends the request, turning error statuses into ClientError::Api.
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await?;
+        Err(ClientError::Api {
+            status,
+            error: serde_json::from_str(&body).ok(),
+            body,
+        })
+    }
+
+    // Serializes a request body with the service's ID encoding.
+    fn body<T: Serialize>(&self, request: RequestBuilder, body: &T) -> RequestBuilder {
+        ids::with_encoding(self.ids.clone(), || request.json(body))
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        let body = self.send(request).await?.bytes().await?;
+        ids::with_encoding(self.ids.clone(), || serde_json::from_slice(&body))
+            .map_err(ClientError::Decode)
+    }
+
+    async fn text(&self, request: RequestBuilder) -> Result<String, ClientError> {
+        Ok(self.send(request).await?.text().await?)
+    }
+}